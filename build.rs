@@ -0,0 +1,80 @@
+// Compiles the cxx bridges in src/rdkit_bridge, which bind the parts of RDKit that rdkit-sys does
+// not. Every bridge <name>.rs comes with wrapper/include/<name>.h and wrapper/src/<name>.cc. The
+// RDKit libraries rdkit-sys already links are not repeated here
+const BRIDGES: [&str; 9] = [
+    "filter_catalog",
+    "fingerprint",
+    "fmcs",
+    "inchi",
+    "mol_draw",
+    "mol_graph",
+    "mol_ops",
+    "rgroup_decomposition",
+    "smarts",
+];
+
+const RDKIT_LIBS: [&str; 8] = [
+    "Catalogs",
+    "Depictor",
+    "FMCS",
+    "FilterCatalog",
+    "Inchi",
+    "MolDraw2D",
+    "RDInchiLib",
+    "RGroupDecomposition",
+];
+
+fn main() {
+    let mut include_paths = vec![];
+    let mut lib_paths = vec![];
+
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("macos", "aarch64") => {
+            include_paths.push("/opt/homebrew/include".to_string());
+            include_paths.push("/opt/homebrew/include/rdkit".to_string());
+            lib_paths.push("/opt/homebrew/lib".to_string());
+        }
+        ("macos", _) => {
+            include_paths.push("/usr/local/include".to_string());
+            include_paths.push("/usr/local/include/rdkit".to_string());
+        }
+        _ => {
+            include_paths.push("/usr/local/include".to_string());
+            include_paths.push("/usr/local/include/rdkit".to_string());
+            include_paths.push("/usr/include".to_string());
+            include_paths.push("/usr/include/rdkit".to_string());
+        }
+    }
+
+    let bridge_files = BRIDGES
+        .iter()
+        .map(|bridge| format!("src/rdkit_bridge/{bridge}.rs"))
+        .collect::<Vec<_>>();
+    let wrapper_files = BRIDGES
+        .iter()
+        .map(|bridge| format!("wrapper/src/{bridge}.cc"))
+        .collect::<Vec<_>>();
+
+    for bridge in BRIDGES {
+        println!("cargo:rerun-if-changed=src/rdkit_bridge/{bridge}.rs");
+        println!("cargo:rerun-if-changed=wrapper/include/{bridge}.h");
+        println!("cargo:rerun-if-changed=wrapper/src/{bridge}.cc");
+    }
+
+    cxx_build::bridges(bridge_files)
+        .files(wrapper_files)
+        .includes(include_paths)
+        .include(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+        .flag("-std=c++17")
+        // RDKit's own headers do not compile warning-free
+        .warnings(false)
+        .compile("cheminee-rdkit");
+
+    for path in lib_paths {
+        println!("cargo:rustc-link-search=native={path}");
+    }
+
+    for lib in RDKIT_LIBS {
+        println!("cargo:rustc-link-lib=dylib=RDKit{lib}");
+    }
+}
@@ -42,6 +42,16 @@ pub fn command() -> Command {
                 .long("create-or-reset-index")
                 .num_args(0),
         )
+        .arg(
+            Arg::new("schema-name")
+                .required(false)
+                .long("schema-name")
+                .short('n')
+                .help(
+                    "Set to descriptor_v1 by default; only used when the index is created or reset",
+                )
+                .num_args(1),
+        )
         .arg(
             Arg::new("commit")
                 .required(false)
@@ -61,6 +71,7 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
     let chunksize = matches.get_one::<String>("chunk-size");
     let reset_index: bool = matches.get_flag("create-or-reset-index");
     let commit: bool = matches.get_flag("commit");
+    let schema_name = matches.get_one::<String>("schema-name");
 
     let chunksize = if let Some(chunksize) = chunksize {
         chunksize.parse::<usize>()?
@@ -84,8 +95,14 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
         Box::new(mol_iter)
     };
 
+    let schema_name = if let Some(schema_name) = schema_name {
+        schema_name.as_str()
+    } else {
+        "descriptor_v1"
+    };
+
     let schema = crate::schema::LIBRARY
-        .get("descriptor_v1")
+        .get(schema_name)
         .ok_or(eyre::eyre!("Failed to extract schema"))?;
 
     let index = if reset_index {
@@ -103,6 +120,9 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
         tantivy::Index::open(mmap_directory)?
     };
 
+    // An existing index keeps whatever schema it was created with
+    let schema = &index.schema();
+
     let mut index_writer = index.writer(50 * 1024 * 1024)?;
 
     let mut counter = 0;
@@ -1,4 +1,5 @@
 use crate::command_line::prelude::*;
+use crate::search::formula::expand_formula_queries;
 use crate::search::{aggregate_query_hits, basic_search::basic_search};
 
 pub const NAME: &str = "basic-search";
@@ -47,8 +48,9 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
     let reader = index.reader()?;
     let searcher = reader.searcher();
 
-    let results = basic_search(&searcher, query, limit)?;
-    let final_results = aggregate_query_hits(searcher, results, query)?;
+    let query = expand_formula_queries(query)?;
+    let results = basic_search(&searcher, &query, limit)?;
+    let final_results = aggregate_query_hits(searcher, results, &query)?;
 
     log::info!("{:#?}", final_results);
 
@@ -1,6 +1,12 @@
 use crate::command_line::prelude::*;
+use crate::search::cancellation::CancellationToken;
+use crate::search::excluded_patterns::parse_excluded_patterns;
+use crate::search::formula::expand_formula_queries;
 use crate::search::structure_search::structure_search;
-use crate::search::{compound_processing::*, sort_results, validate_structure, StructureSearchHit};
+use crate::search::{
+    compound_processing::*, prepare_mol_block_query, prepare_smarts_query, sort_results,
+    validate_structure, StructureSearchHit,
+};
 use rayon::iter::ParallelIterator;
 use rayon::prelude::IntoParallelIterator;
 use std::cmp::min;
@@ -9,13 +15,16 @@ pub fn cli_structure_search(method: &str, matches: &ArgMatches) -> eyre::Result<
     let index_path = matches
         .get_one::<String>("index")
         .ok_or(eyre::eyre!("Failed to extract index path"))?;
-    let query_smiles = matches
-        .get_one::<String>("smiles")
-        .ok_or(eyre::eyre!("Failed to extract SMILES"))?;
+    let query_smiles = matches.get_one::<String>("smiles");
+    let query_smarts = matches.try_get_one::<String>("smarts").unwrap_or(None);
+    let query_mol_block = matches.try_get_one::<String>("mol-block").unwrap_or(None);
     let use_chirality = matches.get_one::<String>("use-chirality");
     let result_limit = matches.get_one::<String>("result-limit");
     let tautomer_limit = matches.get_one::<String>("tautomer-limit");
     let extra_query = matches.get_one::<String>("extra-query");
+    let exclude_smarts = matches
+        .try_get_many::<String>("exclude-smarts")
+        .unwrap_or(None);
     let use_scaffolds = matches.get_one::<String>("use-scaffolds");
 
     let use_chirality = if let Some(use_chirality) = use_chirality {
@@ -37,11 +46,18 @@ pub fn cli_structure_search(method: &str, matches: &ArgMatches) -> eyre::Result<
     };
 
     let extra_query = if let Some(extra_query) = extra_query {
-        extra_query.clone()
+        expand_formula_queries(extra_query)?
     } else {
         "".to_string()
     };
 
+    let exclude_smarts = if let Some(exclude_smarts) = exclude_smarts {
+        exclude_smarts.cloned().collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+    let excluded_patterns = parse_excluded_patterns(&exclude_smarts)?;
+
     // by default, we will use scaffold-based indexing
     let use_scaffolds = if let Some(use_scaffolds) = use_scaffolds {
         matches!(use_scaffolds.as_str(), "true")
@@ -53,14 +69,34 @@ pub fn cli_structure_search(method: &str, matches: &ArgMatches) -> eyre::Result<
     let reader = index.reader()?;
     let searcher = reader.searcher();
 
-    let problems = validate_structure(query_smiles)?;
-    if !problems.is_empty() {
-        return Err(eyre::eyre!("Failed structure validation"));
-    };
+    let (query, query_canon_taut, generic_query) =
+        match (query_smiles, query_smarts, query_mol_block) {
+            (Some(query_smiles), None, None) => {
+                let problems = validate_structure(query_smiles)?;
+                if !problems.is_empty() {
+                    return Err(eyre::eyre!("Failed structure validation"));
+                };
 
-    let query_canon_taut = standardize_smiles(query_smiles, false)?;
+                let query_canon_taut = standardize_smiles(query_smiles, false)?;
+                (query_smiles.clone(), query_canon_taut, false)
+            }
+            (None, Some(query_smarts), None) => {
+                let query_mol = prepare_smarts_query(query_smarts)?;
+                (query_smarts.clone(), query_mol, true)
+            }
+            (None, None, Some(query_mol_block)) => {
+                let mol_block = std::fs::read_to_string(query_mol_block)?;
+                let query_mol = prepare_mol_block_query(&mol_block)?;
+                (mol_block, query_mol, true)
+            }
+            _ => {
+                return Err(eyre::eyre!(
+                    "Exactly one of smiles, smarts or mol-block must be provided"
+                ))
+            }
+        };
 
-    let mut results = structure_search(
+    let (mut results, mut truncated) = structure_search(
         &searcher,
         &query_canon_taut,
         method,
@@ -68,12 +104,17 @@ pub fn cli_structure_search(method: &str, matches: &ArgMatches) -> eyre::Result<
         result_limit,
         use_chirality,
         &extra_query,
+        generic_query,
+        &excluded_patterns,
+        None,
+        None,
+        &CancellationToken::default(),
     )?;
 
     let mut used_tautomers = false;
     let before_tauts_result_count = results.len();
 
-    if before_tauts_result_count < result_limit && tautomer_limit > 0 {
+    if before_tauts_result_count < result_limit && tautomer_limit > 0 && !generic_query {
         let mut tautomers = get_tautomers(&query_canon_taut);
 
         let tautomer_limit = min(tautomers.len(), tautomer_limit);
@@ -90,14 +131,20 @@ pub fn cli_structure_search(method: &str, matches: &ArgMatches) -> eyre::Result<
                         result_limit,
                         use_chirality,
                         &extra_query,
+                        false,
+                        &excluded_patterns,
+                        None,
+                        None,
+                        &CancellationToken::default(),
                     )
                     .ok()
                 })
                 .collect::<Vec<_>>();
 
-            for results_set in tautomer_results {
+            for (results_set, tautomer_truncated) in tautomer_results {
                 if results.len() < result_limit {
                     results.extend(results_set);
+                    truncated |= tautomer_truncated;
                 }
             }
 
@@ -115,16 +162,24 @@ pub fn cli_structure_search(method: &str, matches: &ArgMatches) -> eyre::Result<
             extra_data,
             smiles,
             score: 1.0,
-            query: query_smiles.into(),
+            query: query.clone(),
             used_tautomers,
+            atom_matches: None,
+            svg: None,
+            rgroup_decomposition: None,
         })
         .collect::<Vec<_>>();
 
     if final_results.len() > result_limit {
         log::info!("{:#?}", &final_results[..result_limit]);
+        truncated = true;
     } else {
         log::info!("{:#?}", final_results)
     }
 
+    if truncated {
+        log::info!("Results were truncated at a result limit of {result_limit}");
+    }
+
     Ok(())
 }
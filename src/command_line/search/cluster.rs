@@ -0,0 +1,83 @@
+use crate::command_line::prelude::*;
+use crate::search::cancellation::CancellationToken;
+use crate::search::clustering::cluster_index;
+
+pub const NAME: &str = "cluster";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .arg(
+            Arg::new("index")
+                .required(true)
+                .long("index")
+                .short('i')
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("query")
+                .required(false)
+                .long("query")
+                .short('q')
+                .help("Only cluster the compounds matching this basic search query (e.g. \"exactmw:[50 TO 300]\"); all compounds by default")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("distance-threshold")
+                .required(false)
+                .long("distance-threshold")
+                .short('d')
+                .help("Set to 0.35 by default; compounds within this Tanimoto distance of each other are neighbors")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("compound-limit")
+                .required(false)
+                .long("compound-limit")
+                .short('l')
+                .help("Set to 10000 by default; only the first this many matching compounds are clustered")
+                .num_args(1),
+        )
+}
+
+pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
+    let index_path = matches
+        .get_one::<String>("index")
+        .ok_or(eyre::eyre!("Failed to extract index path"))?;
+    let query = matches.get_one::<String>("query");
+    let distance_threshold = matches.get_one::<String>("distance-threshold");
+    let compound_limit = matches.get_one::<String>("compound-limit");
+
+    let query = if let Some(query) = query {
+        query.clone()
+    } else {
+        "*".to_string()
+    };
+
+    let distance_threshold = if let Some(distance_threshold) = distance_threshold {
+        distance_threshold.parse::<f32>()?
+    } else {
+        0.35
+    };
+
+    let compound_limit = if let Some(compound_limit) = compound_limit {
+        compound_limit.parse::<usize>()?
+    } else {
+        10000
+    };
+
+    let index = open_index(index_path)?;
+    let reader = index.reader()?;
+    let searcher = reader.searcher();
+
+    let clustering = cluster_index(
+        &searcher,
+        &query,
+        distance_threshold,
+        compound_limit,
+        &CancellationToken::default(),
+    )?;
+
+    log::info!("{:#?}", clustering);
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use crate::command_line::prelude::*;
+use crate::search::cancellation::CancellationToken;
+use crate::search::clustering::diversity_pick;
+
+pub const NAME: &str = "diversity-pick";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .arg(
+            Arg::new("index")
+                .required(true)
+                .long("index")
+                .short('i')
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("count")
+                .required(false)
+                .long("count")
+                .short('n')
+                .help("Set to 10 by default; the number of compounds to pick")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("query")
+                .required(false)
+                .long("query")
+                .short('q')
+                .help("Only pick from the compounds matching this basic search query (e.g. \"exactmw:[50 TO 300]\"); all compounds by default")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("compound-limit")
+                .required(false)
+                .long("compound-limit")
+                .short('l')
+                .help("Set to 10000 by default; only the first this many matching compounds are picked from")
+                .num_args(1),
+        )
+}
+
+pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
+    let index_path = matches
+        .get_one::<String>("index")
+        .ok_or(eyre::eyre!("Failed to extract index path"))?;
+    let count = matches.get_one::<String>("count");
+    let query = matches.get_one::<String>("query");
+    let compound_limit = matches.get_one::<String>("compound-limit");
+
+    let count = if let Some(count) = count {
+        count.parse::<usize>()?
+    } else {
+        10
+    };
+
+    let query = if let Some(query) = query {
+        query.clone()
+    } else {
+        "*".to_string()
+    };
+
+    let compound_limit = if let Some(compound_limit) = compound_limit {
+        compound_limit.parse::<usize>()?
+    } else {
+        10000
+    };
+
+    let index = open_index(index_path)?;
+    let reader = index.reader()?;
+    let searcher = reader.searcher();
+
+    let picks = diversity_pick(
+        &searcher,
+        &query,
+        count,
+        compound_limit,
+        &CancellationToken::default(),
+    )?;
+
+    log::info!("{:#?}", picks);
+
+    Ok(())
+}
@@ -1,4 +1,6 @@
 use crate::command_line::prelude::*;
+use crate::search::cancellation::CancellationToken;
+use crate::search::formula::expand_formula_queries;
 use crate::search::scaffold_search::{scaffold_search, PARSED_SCAFFOLDS};
 use crate::search::{
     identity_search::identity_search, prepare_query_structure, sort_results, StructureSearchHit,
@@ -67,7 +69,7 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
     };
 
     let extra_query = if let Some(extra_query) = extra_query {
-        extra_query.clone()
+        expand_formula_queries(extra_query)?
     } else {
         "".to_string()
     };
@@ -104,6 +106,7 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
         &descriptors,
         use_chirality,
         &extra_query,
+        &CancellationToken::default(),
     )?;
 
     let final_results = sort_results(&mut data_results)
@@ -114,6 +117,9 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
             score: 1.0,
             query: query_smiles.into(),
             used_tautomers: false,
+            atom_matches: None,
+            svg: None,
+            rgroup_decomposition: None,
         })
         .collect::<Vec<_>>();
 
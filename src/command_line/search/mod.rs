@@ -1,6 +1,8 @@
 pub mod basic_search;
 pub mod basic_search_count;
 pub mod cli_structure_search;
+pub mod cluster;
+pub mod diversity_pick;
 pub mod identity_search;
 pub mod similarity_search;
 pub mod substructure_search;
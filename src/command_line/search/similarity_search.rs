@@ -1,5 +1,9 @@
 use crate::command_line::prelude::*;
-use crate::search::similarity_search::{neighbor_search, similarity_search};
+use crate::search::cancellation::CancellationToken;
+use crate::search::exact_similarity_search::exact_similarity_search;
+use crate::search::fingerprints::get_fingerprint;
+use crate::search::formula::expand_formula_queries;
+use crate::search::similarity_search::{neighbor_search, similarity_search, SimilarityMetric};
 use crate::search::{compound_processing::*, validate_structure};
 use std::cmp::min;
 
@@ -45,11 +49,12 @@ pub fn command() -> Command {
                 .num_args(1),
         )
         .arg(
-            Arg::new("tanimoto-minimum")
+            Arg::new("similarity-minimum")
                 .required(false)
-                .long("tanimoto-minimum")
+                .long("similarity-minimum")
+                .alias("tanimoto-minimum")
                 .short('m')
-                .help("Set to 0.4 by default; Cheminee will ignore compounds with Tanimoto scores below this")
+                .help("Set to 0.4 by default; Cheminee will ignore compounds scoring below this with the chosen metric")
                 .num_args(1),
         )
         .arg(
@@ -60,6 +65,43 @@ pub fn command() -> Command {
                 .help("An extra query (e.g. \"exactmw:[50 TO 100]\") may be helpful in case you want to further restrict the kinds of similarity matches that are returned")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("fingerprint")
+                .required(false)
+                .long("fingerprint")
+                .short('f')
+                .help("Set to morgan by default; one of morgan, morgan_r2_1024, morgan_r2_2048, morgan_r3_4096, feature_morgan_r2_2048, atom_pair, torsion or maccs. All but morgan require an index built with the descriptor_v2 schema")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("metric")
+                .required(false)
+                .long("metric")
+                .short('M')
+                .help("Set to tanimoto by default; one of tanimoto, dice, cosine or tversky. The similarity minimum applies to whichever metric is chosen")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("tversky-alpha")
+                .required(false)
+                .long("tversky-alpha")
+                .help("Set to 0.5 by default; Tversky weight of the bits only set in the query")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("tversky-beta")
+                .required(false)
+                .long("tversky-beta")
+                .help("Set to 0.5 by default; Tversky weight of the bits only set in the indexed compound")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("exact")
+                .required(false)
+                .long("exact")
+                .help("Scan every fingerprint in the index for the exact top hits instead of searching the predicted similarity clusters; requires an index built with the descriptor_v2 schema")
+                .num_args(0),
+        )
 }
 
 pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
@@ -72,8 +114,13 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
     let result_limit = matches.get_one::<String>("result-limit");
     let tautomer_limit = matches.get_one::<String>("tautomer-limit");
     let search_percent_limit = matches.get_one::<String>("search-percent-limit");
-    let tanimoto_minimum = matches.get_one::<String>("tanimoto-minimum");
+    let similarity_minimum = matches.get_one::<String>("similarity-minimum");
     let extra_query = matches.get_one::<String>("extra-query");
+    let fingerprint = matches.get_one::<String>("fingerprint");
+    let metric = matches.get_one::<String>("metric");
+    let tversky_alpha = matches.get_one::<String>("tversky-alpha");
+    let tversky_beta = matches.get_one::<String>("tversky-beta");
+    let exact: bool = matches.get_flag("exact");
 
     let result_limit = if let Some(result_limit) = result_limit {
         result_limit.parse::<usize>()?
@@ -93,18 +140,42 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
         0.1
     };
 
-    let tanimoto_minimum = if let Some(tanimoto_minimum) = tanimoto_minimum {
-        tanimoto_minimum.parse::<f32>()?
+    let similarity_minimum = if let Some(similarity_minimum) = similarity_minimum {
+        similarity_minimum.parse::<f32>()?
     } else {
         0.4
     };
 
     let extra_query = if let Some(extra_query) = extra_query {
-        extra_query.clone()
+        expand_formula_queries(extra_query)?
     } else {
         "".to_string()
     };
 
+    let fingerprint = if let Some(fingerprint) = fingerprint {
+        fingerprint.clone()
+    } else {
+        "morgan".to_string()
+    };
+
+    let tversky_alpha = if let Some(tversky_alpha) = tversky_alpha {
+        tversky_alpha.parse::<f32>()?
+    } else {
+        0.5
+    };
+
+    let tversky_beta = if let Some(tversky_beta) = tversky_beta {
+        tversky_beta.parse::<f32>()?
+    } else {
+        0.5
+    };
+
+    let metric = SimilarityMetric::from_name(
+        metric.map(|m| m.as_str()).unwrap_or("tanimoto"),
+        tversky_alpha,
+        tversky_beta,
+    )?;
+
     let index = open_index(index_path)?;
     let reader = index.reader()?;
     let searcher = reader.searcher();
@@ -131,20 +202,49 @@ pub fn action(matches: &ArgMatches) -> eyre::Result<()> {
         .map(|m| m.morgan_fingerprint().0)
         .collect::<Vec<_>>();
 
-    let results = neighbor_search(
-        &searcher,
-        &taut_morgan_fingerprints,
-        &extra_query,
-        search_percent_limit,
-    )?;
+    let taut_fingerprints = tautomers[..tautomer_limit]
+        .iter()
+        .map(|m| get_fingerprint(m, &fingerprint))
+        .collect::<eyre::Result<Vec<_>>>()?;
 
-    let final_results = similarity_search(
-        &searcher,
-        results,
-        &taut_morgan_fingerprints,
-        tanimoto_minimum,
-        query_smiles,
-    )?;
+    let final_results = if exact {
+        exact_similarity_search(
+            &searcher,
+            &taut_fingerprints,
+            &fingerprint,
+            metric,
+            similarity_minimum,
+            &extra_query,
+            query_smiles,
+            result_limit,
+            None,
+            &CancellationToken::default(),
+        )?
+    } else {
+        let results = neighbor_search(
+            &searcher,
+            &taut_morgan_fingerprints,
+            &extra_query,
+            search_percent_limit,
+        )?;
+
+        similarity_search(
+            &searcher,
+            results,
+            &taut_fingerprints,
+            &fingerprint,
+            metric,
+            similarity_minimum,
+            query_smiles,
+            None,
+            &CancellationToken::default(),
+        )?
+    };
+
+    let final_results = final_results
+        .into_iter()
+        .map(|(hit, _)| hit)
+        .collect::<Vec<_>>();
 
     if final_results.len() > result_limit {
         log::info!("{:#?}", &final_results[..result_limit]);
@@ -14,11 +14,29 @@ pub fn command() -> Command {
         )
         .arg(
             Arg::new("smiles")
-                .required(true)
+                .required_unless_present_any(["smarts", "mol-block"])
                 .long("smiles")
                 .short('s')
                 .num_args(1),
         )
+        .arg(
+            Arg::new("smarts")
+                .required(false)
+                .long("smarts")
+                .short('a')
+                .help("A SMARTS query (e.g. \"c1ccccc1[F,Cl,Br,I]\"); it is used as-is, without standardization or tautomers")
+                .conflicts_with_all(["smiles", "mol-block"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("mol-block")
+                .required(false)
+                .long("mol-block")
+                .short('m')
+                .help("Path to a molfile whose MDL query features (e.g. atom lists) should be used for the query")
+                .conflicts_with_all(["smiles", "smarts"])
+                .num_args(1),
+        )
         .arg(
             Arg::new("use-chirality")
                 .required(false)
@@ -50,6 +68,14 @@ pub fn command() -> Command {
                 .help("An extra query (e.g. \"exactmw:[50 TO 100]\") may be helpful in case you want to further restrict the kinds of substructure matches that are returned")
                 .num_args(1),
         )
+        .arg(
+            Arg::new("exclude-smarts")
+                .required(false)
+                .long("exclude-smarts")
+                .short('x')
+                .help("One or more SMARTS (e.g. \"[N+](=O)[O-]\" \"C(=O)[OH]\"); hits containing any of them are left out")
+                .num_args(1..),
+        )
         .arg(
             Arg::new("use-scaffolds")
                 .required(false)
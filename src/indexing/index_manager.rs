@@ -116,4 +116,64 @@ impl IndexManager {
 
         Ok(paths)
     }
+
+    // The indexes named by `patterns`, in pattern order and without duplicates. Patterns may use *
+    // and ? wildcards; a pattern without wildcards must name an existing index
+    pub fn resolve(&self, patterns: &[String]) -> eyre::Result<Vec<String>> {
+        let mut names = self.list()?;
+        names.sort();
+
+        let mut resolved: Vec<String> = Vec::new();
+        for pattern in patterns {
+            if !pattern.contains(['*', '?']) {
+                if !names.contains(pattern) {
+                    return Err(eyre::eyre!("Index {pattern} does not exist"));
+                }
+
+                if !resolved.contains(pattern) {
+                    resolved.push(pattern.clone());
+                }
+                continue;
+            }
+
+            for name in &names {
+                if glob_match(pattern, name) && !resolved.contains(name) {
+                    resolved.push(name.clone());
+                }
+            }
+        }
+
+        if resolved.is_empty() {
+            return Err(eyre::eyre!("No index matches {}", patterns.join(", ")));
+        }
+
+        Ok(resolved)
+    }
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    // Backtracks to the last * whenever the rest of the pattern stops matching
+    let (mut p, mut n) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = last_star {
+            p = star_p + 1;
+            n = star_n + 1;
+            last_star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
 }
@@ -1,10 +1,15 @@
 use crate::search::compound_processing::process_cpd;
+use crate::search::fingerprints::{fingerprint_field, get_fingerprints, FINGERPRINTS};
+use crate::search::formula::{element_counts, hill_formula, ELEMENT_THRESHOLD_LIMIT};
+use crate::search::identity_layers::identity_layers;
+use crate::search::inchikey_search::{inchikey_connectivity, standard_inchi};
 use crate::search::scaffold_search::{scaffold_search, PARSED_SCAFFOLDS};
 use crate::search::similarity_search::encode_fingerprints;
+use crate::search::structural_alerts::{alert_search, PARSED_ALERTS};
 use bitvec::prelude::BitVec;
 use rayon::prelude::*;
-use rdkit::Fingerprint;
-use std::collections::HashMap;
+use rdkit::{Fingerprint, ROMol};
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 pub use tantivy::doc;
 use tantivy::{directory::MmapDirectory, schema::*, Index, IndexBuilder, TantivyError};
@@ -89,8 +94,15 @@ pub struct CompoundDocAttributes {
     pub extra_data: Option<serde_json::Value>,
     pub pattern_fingerprint: Fingerprint,
     pub morgan_fingerprint: Fingerprint,
+    pub extra_fingerprints: HashMap<String, BitVec<u8>>,
     pub descriptors: HashMap<String, f64>,
     pub scaffold_ids: Vec<i64>,
+    pub alert_ids: Vec<i64>,
+    pub formula: String,
+    pub element_counts: BTreeMap<String, u32>,
+    pub identity_layers: HashMap<String, String>,
+    pub inchi: Option<String>,
+    pub inchikey: Option<String>,
     pub status: String,
 }
 
@@ -98,9 +110,14 @@ pub struct CompoundDocFields {
     pub smiles: Field,
     pub pattern_fingerprint: Field,
     pub morgan_fingerprint: Field,
+    pub extra_fingerprints: HashMap<String, Field>,
     pub descriptors: HashMap<String, Field>,
     pub extra_data: Field,
     pub other_descriptors: Field,
+    // Only schemas from descriptor_v3 on store InChI identifiers
+    pub inchi: Option<Field>,
+    pub inchikey: Option<Field>,
+    pub inchikey_connectivity: Option<Field>,
 }
 
 pub fn batch_doc_creation(
@@ -112,21 +129,47 @@ pub fn batch_doc_creation(
         .map(|kd| (kd.to_string(), schema.get_field(kd).unwrap()))
         .collect::<HashMap<String, Field>>();
 
+    // Alternative fingerprints are only computed when the index schema has a field to store them
+    let extra_fingerprint_fields = FINGERPRINTS
+        .iter()
+        .filter(|fp| **fp != "morgan")
+        .filter_map(|fp| {
+            schema
+                .get_field(&fingerprint_field(fp))
+                .ok()
+                .map(|field| (fp.to_string(), field))
+        })
+        .collect::<HashMap<String, Field>>();
+
+    let extra_fingerprints = extra_fingerprint_fields
+        .keys()
+        .map(|fp| fp.as_str())
+        .collect::<Vec<_>>();
+
     let compound_doc_fields = CompoundDocFields {
         smiles: schema.get_field("smiles")?,
         extra_data: schema.get_field("extra_data")?,
         pattern_fingerprint: schema.get_field("pattern_fingerprint")?,
         morgan_fingerprint: schema.get_field("morgan_fingerprint")?,
+        extra_fingerprints: extra_fingerprint_fields.clone(),
         descriptors: descriptor_fields,
         other_descriptors: schema.get_field("other_descriptors")?,
+        inchi: schema.get_field("inchi").ok(),
+        inchikey: schema.get_field("inchikey").ok(),
+        inchikey_connectivity: schema.get_field("inchikey_connectivity").ok(),
     };
 
-    let placeholder_attributes = get_compound_doc_attributes("c1ccccc1", &None)?;
+    // InChI identifiers are likewise only generated for schemas that store them
+    let with_inchi = compound_doc_fields.inchi.is_some() || compound_doc_fields.inchikey.is_some();
+
+    let placeholder_attributes =
+        get_compound_doc_attributes("c1ccccc1", &None, &extra_fingerprints, false)?;
 
     let mol_attributes = compounds
         .into_par_iter()
         .map(|(smiles, extra_data)| {
-            let attributes_result = get_compound_doc_attributes(smiles, extra_data);
+            let attributes_result =
+                get_compound_doc_attributes(smiles, extra_data, &extra_fingerprints, with_inchi);
             match attributes_result {
                 Ok(attributes) => attributes,
                 Err(e) => {
@@ -187,6 +230,8 @@ pub fn batch_doc_creation(
 pub fn get_compound_doc_attributes(
     raw_smiles: &str,
     extra_data: &Option<serde_json::Value>,
+    extra_fingerprints: &[&str],
+    with_inchi: bool,
 ) -> eyre::Result<CompoundDocAttributes> {
     let initial_attributes = process_cpd(raw_smiles, false)?;
     let mut scaffold_ids = scaffold_search(
@@ -199,17 +244,52 @@ pub fn get_compound_doc_attributes(
         scaffold_ids.push(-1);
     }
 
+    let mut alert_ids = alert_search(&initial_attributes.0, &PARSED_ALERTS)?;
+
+    if alert_ids.is_empty() {
+        alert_ids.push(-1);
+    }
+
+    let (element_counts, charge) = element_counts(&initial_attributes.0);
+    let formula = hill_formula(&element_counts, charge);
+
+    let identity_layers = identity_layers(&ROMol::from_smiles(raw_smiles)?, &initial_attributes.0);
+    let (inchi, inchikey) = if with_inchi {
+        get_inchi_identifiers(&initial_attributes.0)
+    } else {
+        (None, None)
+    };
+
     Ok(CompoundDocAttributes {
         smiles: initial_attributes.0.as_smiles(),
         pattern_fingerprint: initial_attributes.1,
         morgan_fingerprint: initial_attributes.0.morgan_fingerprint(),
+        extra_fingerprints: get_fingerprints(&initial_attributes.0, extra_fingerprints)?,
         descriptors: initial_attributes.2,
         extra_data: extra_data.clone(),
         scaffold_ids,
+        alert_ids,
+        formula,
+        element_counts,
+        identity_layers,
+        inchi,
+        inchikey,
         status: "Passed".to_string(),
     })
 }
 
+// The standard InChI and InChIKey of the standardized compound. The InChI library rejects some
+// structures RDKit accepts (e.g. ones with dummy atoms), which only leaves those identifiers out
+pub fn get_inchi_identifiers(mol: &ROMol) -> (Option<String>, Option<String>) {
+    match standard_inchi(mol) {
+        Ok((inchi, inchikey)) => (Some(inchi), Some(inchikey)),
+        Err(e) => {
+            log::warn!("Skipping InChI for {}: {e}", mol.as_smiles());
+            (None, None)
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn create_tantivy_doc(
     compound_doc_attributes: CompoundDocAttributes,
@@ -222,9 +302,42 @@ pub fn create_tantivy_doc(
         compound_doc_fields.morgan_fingerprint => compound_doc_attributes.morgan_fingerprint.0.as_raw_slice(),
     );
 
+    for (fingerprint, bits) in &compound_doc_attributes.extra_fingerprints {
+        if let Some(field) = compound_doc_fields.extra_fingerprints.get(fingerprint) {
+            doc.add_field_value(*field, bits.as_raw_slice());
+        }
+    }
+
     let scaffold_json = serde_json::json!({"scaffolds": compound_doc_attributes.scaffold_ids});
     let cluster_json = serde_json::json!({"similarity_cluster": similarity_cluster});
-    let other_descriptors_json = combine_json_objects(Some(scaffold_json), Some(cluster_json));
+    let alerts_json = serde_json::json!({"alerts": compound_doc_attributes.alert_ids});
+    let identity_json = serde_json::json!({"identity": compound_doc_attributes.identity_layers});
+    // Besides the exact count, every element gets the counts 1..=n (up to ELEMENT_THRESHOLD_LIMIT)
+    // as "at least" thresholds, which formula queries use in place of range queries (not supported
+    // on JSON fields)
+    let element_thresholds = compound_doc_attributes
+        .element_counts
+        .iter()
+        .map(|(symbol, count)| {
+            let thresholds = 1..=(*count).min(ELEMENT_THRESHOLD_LIMIT);
+            (symbol.clone(), thresholds.collect::<Vec<_>>())
+        })
+        .collect::<BTreeMap<_, _>>();
+    let formula_json = serde_json::json!({
+        "formula": compound_doc_attributes.formula,
+        "element_counts": compound_doc_attributes.element_counts,
+        "element_thresholds": element_thresholds,
+    });
+    let other_descriptors_json = combine_json_objects(
+        combine_json_objects(
+            combine_json_objects(
+                combine_json_objects(Some(scaffold_json), Some(cluster_json)),
+                Some(alerts_json),
+            ),
+            Some(formula_json),
+        ),
+        Some(identity_json),
+    );
 
     if let Some(other_descriptors_json) = other_descriptors_json {
         doc.add_field_value(
@@ -237,6 +350,20 @@ pub fn create_tantivy_doc(
         doc.add_field_value(compound_doc_fields.extra_data, extra_data);
     }
 
+    if let (Some(field), Some(inchi)) = (compound_doc_fields.inchi, compound_doc_attributes.inchi) {
+        doc.add_field_value(field, inchi);
+    }
+
+    if let Some(inchikey) = compound_doc_attributes.inchikey {
+        if let Some(field) = compound_doc_fields.inchikey_connectivity {
+            doc.add_field_value(field, inchikey_connectivity(&inchikey));
+        }
+
+        if let Some(field) = compound_doc_fields.inchikey {
+            doc.add_field_value(field, inchikey);
+        }
+    }
+
     for field in KNOWN_DESCRIPTORS {
         if let Some(val) = compound_doc_attributes.descriptors.get(field) {
             if field.starts_with("Num") || field.starts_with("lipinski") {
@@ -3,6 +3,7 @@
 pub mod command_line;
 pub mod indexing;
 pub mod pubchem;
+pub mod rdkit_bridge;
 pub mod rest_api;
 pub mod schema;
 pub mod search;
@@ -29,6 +29,8 @@ async fn main() -> eyre::Result<()> {
         .subcommand(command_line::search::superstructure_search::command())
         .subcommand(command_line::search::similarity_search::command())
         .subcommand(command_line::search::identity_search::command())
+        .subcommand(command_line::search::cluster::command())
+        .subcommand(command_line::search::diversity_pick::command())
         .subcommand(rest_api::command());
 
     let matches = app.get_matches();
@@ -75,6 +77,12 @@ async fn main() -> eyre::Result<()> {
         (command_line::search::identity_search::NAME, matches) => {
             command_line::search::identity_search::action(matches)
         }
+        (command_line::search::cluster::NAME, matches) => {
+            command_line::search::cluster::action(matches)
+        }
+        (command_line::search::diversity_pick::NAME, matches) => {
+            command_line::search::diversity_pick::action(matches)
+        }
         (rest_api::NAME, matches) => rest_api::action(matches).await,
         (unknown, _) => panic!("🤨: {}", unknown),
     };
@@ -0,0 +1,23 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("wrapper/include/filter_catalog.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+        #[namespace = "RDKit"]
+        pub type FilterCatalog;
+
+        pub fn new_filter_catalog(catalog_name: &CxxString) -> Result<SharedPtr<FilterCatalog>>;
+        pub fn filter_catalog_descriptions(catalog: &SharedPtr<FilterCatalog>) -> Vec<String>;
+        pub fn filter_catalog_matches(
+            catalog: &SharedPtr<FilterCatalog>,
+            mol: &SharedPtr<ROMol>,
+        ) -> Vec<u32>;
+    }
+}
+
+// A catalog is never modified once built and matching is const, which is how RDKit itself shares
+// one catalog across threads
+unsafe impl Send for ffi::FilterCatalog {}
+unsafe impl Sync for ffi::FilterCatalog {}
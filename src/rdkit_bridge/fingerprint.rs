@@ -0,0 +1,28 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("wrapper/include/fingerprint.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+        #[namespace = "RDKit"]
+        pub type ExplicitBitVect = rdkit_sys::fingerprint_ffi::ExplicitBitVect;
+
+        pub fn morgan_fingerprint_mol_with_params(
+            mol: &SharedPtr<ROMol>,
+            radius: u32,
+            num_bits: u32,
+            use_features: bool,
+        ) -> SharedPtr<ExplicitBitVect>;
+        pub fn atom_pair_fingerprint_mol(
+            mol: &SharedPtr<ROMol>,
+            num_bits: u32,
+        ) -> SharedPtr<ExplicitBitVect>;
+        pub fn topological_torsion_fingerprint_mol(
+            mol: &SharedPtr<ROMol>,
+            num_bits: u32,
+        ) -> SharedPtr<ExplicitBitVect>;
+        pub fn maccs_fingerprint_mol(mol: &SharedPtr<ROMol>) -> SharedPtr<ExplicitBitVect>;
+        pub fn get_num_bits(bitvect: &SharedPtr<ExplicitBitVect>) -> u32;
+    }
+}
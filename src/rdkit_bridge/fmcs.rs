@@ -0,0 +1,31 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    pub struct McsResult {
+        pub smarts: String,
+        pub num_atoms: u32,
+        pub num_bonds: u32,
+        // The search was stopped at the deadline before it finished
+        pub canceled: bool,
+    }
+
+    unsafe extern "C++" {
+        include!("wrapper/include/fmcs.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+
+        pub type MolList;
+
+        pub fn new_mol_list() -> UniquePtr<MolList>;
+        pub fn mol_list_push(mols: Pin<&mut MolList>, mol: &SharedPtr<ROMol>);
+
+        pub fn find_mcs(
+            mols: &MolList,
+            timeout_ms: u64,
+            atom_compare: &CxxString,
+            bond_compare: &CxxString,
+            ring_matches_ring_only: bool,
+            complete_rings_only: bool,
+        ) -> Result<McsResult>;
+    }
+}
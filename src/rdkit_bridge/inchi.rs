@@ -0,0 +1,12 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("wrapper/include/inchi.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+
+        pub fn mol_to_inchi(mol: &SharedPtr<ROMol>) -> Result<String>;
+        pub fn inchi_to_inchi_key(inchi: &CxxString) -> Result<String>;
+    }
+}
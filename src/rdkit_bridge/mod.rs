@@ -0,0 +1,28 @@
+// Bindings for the parts of RDKit that rdkit-sys does not cover, laid out like rdkit-sys's own
+// bridges so that they can move there as they are. Molecules are rdkit-sys's types
+mod filter_catalog;
+pub use filter_catalog::ffi as filter_catalog_ffi;
+
+mod fingerprint;
+pub use fingerprint::ffi as fingerprint_ffi;
+
+mod fmcs;
+pub use fmcs::ffi as fmcs_ffi;
+
+mod inchi;
+pub use inchi::ffi as inchi_ffi;
+
+mod mol_draw;
+pub use mol_draw::ffi as mol_draw_ffi;
+
+mod mol_graph;
+pub use mol_graph::ffi as mol_graph_ffi;
+
+mod mol_ops;
+pub use mol_ops::ffi as mol_ops_ffi;
+
+mod rgroup_decomposition;
+pub use rgroup_decomposition::ffi as rgroup_decomposition_ffi;
+
+mod smarts;
+pub use smarts::ffi as smarts_ffi;
@@ -0,0 +1,17 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("wrapper/include/mol_draw.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+
+        pub fn draw_svg(
+            mol: &SharedPtr<ROMol>,
+            width: u32,
+            height: u32,
+            highlight_atoms: &[i32],
+            highlight_bonds: &[i32],
+        ) -> Result<String>;
+    }
+}
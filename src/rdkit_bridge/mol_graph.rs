@@ -0,0 +1,19 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    // 1, 2 and 3 for single, double and triple bonds, 4 for aromatic ones and 0 for anything else
+    pub struct MolBond {
+        pub begin: u32,
+        pub end: u32,
+        pub order: u8,
+    }
+
+    unsafe extern "C++" {
+        include!("wrapper/include/mol_graph.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+
+        pub fn mol_bonds(mol: &SharedPtr<ROMol>) -> Vec<MolBond>;
+        pub fn atom_isotopes(mol: &SharedPtr<ROMol>) -> Vec<u32>;
+    }
+}
@@ -0,0 +1,12 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("wrapper/include/mol_ops.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+
+        pub fn remove_stereochemistry(mol: &SharedPtr<ROMol>) -> SharedPtr<ROMol>;
+        pub fn remove_isotopes(mol: &SharedPtr<ROMol>) -> SharedPtr<ROMol>;
+    }
+}
@@ -0,0 +1,27 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    pub struct RGroupSmiles {
+        pub label: String,
+        pub smiles: String,
+    }
+
+    pub struct RGroupRow {
+        pub core: String,
+        // The molecule's atoms matching the core's own atoms, in core atom order
+        pub core_atoms: Vec<u32>,
+        pub rgroups: Vec<RGroupSmiles>,
+    }
+
+    unsafe extern "C++" {
+        include!("wrapper/include/rgroup_decomposition.h");
+
+        #[namespace = "RDKit"]
+        pub type ROMol = rdkit_sys::ro_mol_ffi::ROMol;
+
+        pub fn rgroup_core_query(core: &SharedPtr<ROMol>) -> Result<SharedPtr<ROMol>>;
+        pub fn rgroup_decompose(
+            core: &SharedPtr<ROMol>,
+            mol: &SharedPtr<ROMol>,
+        ) -> Result<RGroupRow>;
+    }
+}
@@ -0,0 +1,8 @@
+#[cxx::bridge(namespace = "cheminee")]
+pub mod ffi {
+    unsafe extern "C++" {
+        include!("wrapper/include/smarts.h");
+
+        pub fn check_smarts(smarts: &CxxString) -> Result<()>;
+    }
+}
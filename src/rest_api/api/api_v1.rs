@@ -1,25 +1,47 @@
 use crate::indexing::index_manager::IndexManager;
 use crate::rest_api::api::{
-    v1_convert_mol_block_to_smiles, v1_convert_smiles_to_mol_block, v1_delete_index,
-    v1_delete_index_bulk, v1_get_index, v1_index_search_basic, v1_index_search_identity,
-    v1_index_search_similarity, v1_index_search_structure, v1_list_indexes, v1_list_schemas,
-    v1_merge_segments, v1_post_index, v1_post_index_bulk, v1_standardize, BulkRequest,
+    run_cancellable, v1_alerts, v1_convert_mol_block_to_smiles, v1_convert_smiles_to_mol_block,
+    v1_delete_index, v1_delete_index_bulk, v1_depict, v1_get_index, v1_index_cluster,
+    v1_index_diversity_pick, v1_index_search_basic, v1_index_search_batch, v1_index_search_explain,
+    v1_index_search_facets, v1_index_search_identity, v1_index_search_inchikey,
+    v1_index_search_mass, v1_index_search_mcs, v1_index_search_query, v1_index_search_similarity,
+    v1_index_search_structure, v1_list_indexes, v1_list_schemas, v1_mcs, v1_merge_segments,
+    v1_post_index, v1_post_index_bulk, v1_rgroup_decomposition, v1_search_federated,
+    v1_standardize, wants_ndjson, AlertsResponse, BatchSearchRequest, BulkRequest,
     ConvertedMolBlockResponse, ConvertedSmilesResponse, DeleteIndexResponse,
-    DeleteIndexesBulkDeleteResponse, GetIndexResponse, GetQuerySearchResponse,
-    GetStructureSearchResponse, ListIndexesResponse, ListSchemasResponse, MergeSegmentsResponse,
-    PostIndexResponse, PostIndexesBulkIndexResponse, StandardizeResponse,
+    DeleteIndexesBulkDeleteResponse, DepictResponse, DepictResponseError, FederatedSearchRequest,
+    GetClusteringResponse, GetDescriptorFacetsResponse, GetDiversityPickResponse, GetIndexResponse,
+    GetMassSearchResponse, GetQuerySearchResponse, GetResultCacheStatsResponse,
+    GetStructureSearchExplanationResponse, GetStructureSearchResponse, ListIndexesResponse,
+    ListSchemasResponse, McsResponse, MergeSegmentsResponse, PostBatchSearchResponse,
+    PostFederatedSearchResponse, PostIndexResponse, PostIndexesBulkIndexResponse,
+    QueryResponseError, RGroupDecompositionRequest, RGroupDecompositionResponse, ResultCache,
+    StandardizeResponse, StructureResponseError, StructuredSearchRequest,
 };
 use crate::rest_api::models::{MolBlock, Smiles};
+use crate::search::cancellation::CancellationToken;
+use crate::search::depiction::DEFAULT_DEPICTION_SIZE;
+use crate::search::excluded_patterns::parse_excluded_patterns;
+use crate::search::formula::{build_formula_query, expand_formula_queries};
+use crate::search::mcs::McsParams;
+use crate::search::structural_alerts::{add_alert_filters, check_alerts_indexed};
+use std::sync::Arc;
+use std::time::Duration;
 
 use poem::web::Data;
 use poem_openapi::{
-    param::{Path, Query},
+    param::{Header, Path, Query},
     payload::Json,
     OpenApi,
 };
 
 #[derive(Default)]
-pub struct ApiV1 {}
+pub struct ApiV1 {
+    /// Applies to searches that do not set their own timeout_ms; no timeout when unset
+    pub search_timeout_ms: Option<u64>,
+    /// Pages of substructure, superstructure and similarity hits; disabled by default
+    pub result_cache: Arc<ResultCache>,
+}
 
 #[OpenApi]
 impl ApiV1 {
@@ -33,6 +55,13 @@ impl ApiV1 {
         v1_standardize(mol, attempt_fix.0.as_deref()).await
     }
 
+    #[oai(path = "/v1/alerts", method = "post")]
+    /// Report the structural alerts (PAINS A/B/C, Brenk and reactive groups) each standardized
+    /// SMILES hits. Searches take the same alerts through exclude_alerts and only_alerts
+    pub async fn v1_alerts(&self, mol: Json<Vec<Smiles>>) -> AlertsResponse {
+        v1_alerts(mol).await
+    }
+
     #[oai(path = "/v1/convert/mol_block_to_smiles", method = "post")]
     /// Convert a list of SMILES to molblocks
     pub async fn v1_convert_mol_block_to_smiles(
@@ -52,12 +81,92 @@ impl ApiV1 {
         v1_convert_smiles_to_mol_block(smiles_vec).await
     }
 
+    #[oai(path = "/v1/mcs", method = "post")]
+    /// Find the maximum common substructure of a list of SMILES with RDKit's FMCS. Returns it as
+    /// SMARTS along with the matching atoms and bonds of every molecule, in input order. Atoms are
+    /// compared by atom_compare ("elements" by default, "any" or "isotopes") and bonds by
+    /// bond_compare ("order" by default, "order_exact" or "any"); ring_matches_ring_only and
+    /// complete_rings_only (both false by default) restrict how rings may be matched. If the
+    /// search takes longer than timeout_ms (10000 by default), the largest common substructure
+    /// found so far is returned and timed_out is set
+    pub async fn v1_mcs(
+        &self,
+        smiles_vec: Json<Vec<Smiles>>,
+        timeout_ms: Query<Option<u64>>,
+        atom_compare: Query<Option<String>>,
+        bond_compare: Query<Option<String>>,
+        ring_matches_ring_only: Query<Option<bool>>,
+        complete_rings_only: Query<Option<bool>>,
+    ) -> McsResponse {
+        let mcs_params = McsParams {
+            timeout: Duration::from_millis(timeout_ms.0.unwrap_or(10_000)),
+            atom_compare: atom_compare.0.unwrap_or_else(|| "elements".to_string()),
+            bond_compare: bond_compare.0.unwrap_or_else(|| "order".to_string()),
+            ring_matches_ring_only: ring_matches_ring_only.0.unwrap_or(false),
+            complete_rings_only: complete_rings_only.0.unwrap_or(false),
+        };
+
+        v1_mcs(smiles_vec, &mcs_params).await
+    }
+
+    #[oai(path = "/v1/rgroup_decomposition", method = "post")]
+    /// Split each SMILES into the given core (a SMILES with attachment points like [*:1]) and the
+    /// R-groups on it, in input order. Substituents on core atoms without an attachment point get
+    /// new labels, and attachment points without a substituent get [H]
+    pub async fn v1_rgroup_decomposition(
+        &self,
+        request: Json<RGroupDecompositionRequest>,
+    ) -> RGroupDecompositionResponse {
+        v1_rgroup_decomposition(request).await
+    }
+
+    #[oai(path = "/v1/depict", method = "get")]
+    /// Render a molecule given as SMILES or as a molblock (exactly one of them should be provided)
+    /// to SVG. width and height default to 300 pixels. Set explicit_hydrogens to draw all hydrogens
+    /// as atoms, and pass highlight_smarts to highlight every match of that substructure query
+    pub async fn v1_depict(
+        &self,
+        smiles: Query<Option<String>>,
+        mol_block: Query<Option<String>>,
+        width: Query<Option<u32>>,
+        height: Query<Option<u32>>,
+        explicit_hydrogens: Query<Option<bool>>,
+        highlight_smarts: Query<Option<String>>,
+    ) -> DepictResponse {
+        let (structure, structure_format) = match (smiles.0, mol_block.0) {
+            (Some(smiles), None) => (smiles, "smiles"),
+            (None, Some(mol_block)) => (mol_block, "mol_block"),
+            _ => {
+                return DepictResponse::Err(Json(DepictResponseError {
+                    error: "Exactly one of smiles or mol_block must be provided".to_string(),
+                }))
+            }
+        };
+
+        v1_depict(
+            &structure,
+            structure_format,
+            width.0.unwrap_or(DEFAULT_DEPICTION_SIZE),
+            height.0.unwrap_or(DEFAULT_DEPICTION_SIZE),
+            explicit_hydrogens.0.unwrap_or(false),
+            highlight_smarts.0.as_deref(),
+        )
+    }
+
     #[oai(path = "/v1/schemas", method = "get")]
     /// List schemas available for creating indexes
     pub async fn v1_list_schemas(&self) -> ListSchemasResponse {
         v1_list_schemas().await
     }
 
+    #[oai(path = "/v1/cache", method = "get")]
+    /// Hit and miss counts of the search result cache, which keeps the most recently used pages of
+    /// substructure, superstructure and similarity hits until the index changes. Its size is set
+    /// with the server's --result-cache-size option
+    pub async fn v1_result_cache_stats(&self) -> GetResultCacheStatsResponse {
+        GetResultCacheStatsResponse::Ok(Json(self.result_cache.stats()))
+    }
+
     #[oai(path = "/v1/indexes", method = "get")]
     /// List indexes
     pub async fn v1_list_indexes(&self, index_manager: Data<&IndexManager>) -> ListIndexesResponse {
@@ -130,57 +239,411 @@ impl ApiV1 {
         v1_delete_index_bulk(index_manager.0, index.to_string(), bulk_request.0).await
     }
 
+    #[allow(clippy::too_many_arguments)]
     #[oai(path = "/v1/indexes/:index/search/basic", method = "get")]
-    /// Perform basic query search against index
+    /// Perform basic query search against index. Send "Accept: application/x-ndjson" to have the
+    /// hits streamed as newline-delimited JSON while the search runs. Set sort_by to a descriptor
+    /// (only exactmw unless the index uses descriptor_v4) or to extra_data.{key} to get the top
+    /// hits by that field instead of in index order; order is asc (default) or desc. Repeat
+    /// exclude_smarts to drop every hit containing any of those substructures. After timeout_ms
+    /// (or the server's default) the search stops and returns the hits found so far
     pub async fn v1_index_search_basic(
         &self,
         index: Path<String>,
         query: Query<String>,
         limit: Query<Option<usize>>,
+        exclude_alerts: Query<Option<String>>,
+        only_alerts: Query<Option<String>>,
+        exclude_smarts: Query<Option<Vec<String>>>,
+        sort_by: Query<Option<String>>,
+        order: Query<Option<String>>,
+        timeout_ms: Query<Option<u64>>,
+        #[oai(name = "accept")] accept: Header<Option<String>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetQuerySearchResponse {
+        let limit = limit.0.unwrap_or(1000);
+        let query = match expand_formula_queries(&query.0) {
+            Ok(query) => query,
+            Err(e) => {
+                return GetQuerySearchResponse::Err(Json(QueryResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
+        let query = if exclude_alerts.0.is_some() || only_alerts.0.is_some() {
+            match add_alert_filters(
+                &query,
+                exclude_alerts.0.as_deref(),
+                only_alerts.0.as_deref(),
+            ) {
+                Ok(query) => query,
+                Err(e) => {
+                    return GetQuerySearchResponse::Err(Json(QueryResponseError {
+                        error: e.to_string(),
+                    }))
+                }
+            }
+        } else {
+            query
+        };
+        let excluded_patterns = match parse_excluded_patterns(&exclude_smarts.0.unwrap_or_default())
+        {
+            Ok(excluded_patterns) => excluded_patterns,
+            Err(e) => {
+                return GetQuerySearchResponse::Err(Json(QueryResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
+        let order = order.0.unwrap_or_else(|| "asc".to_string());
+        let stream = wants_ndjson(accept.0.as_deref());
+        let index = index_manager.0.open(&index).and_then(|index| {
+            check_alerts_indexed(index, exclude_alerts.0.is_some() || only_alerts.0.is_some())
+        });
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, stream, move || {
+            v1_index_search_basic(
+                index,
+                query,
+                excluded_patterns,
+                limit,
+                sort_by.0,
+                order,
+                stream,
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/v1/indexes/:index/search/formula", method = "get")]
+    /// Search by molecular formula, e.g. "C6-8 H* N1-2 Cl0": every element takes an exact count,
+    /// a range (C6-8), an open range (N2- for at least two) or * for any count; a bare symbol
+    /// means one atom. Elements that are not mentioned can have any count. The same syntax works
+    /// in the extra_query of every search as formula:"...". extra_query, sort_by, order,
+    /// timeout_ms and "Accept: application/x-ndjson" work as for basic search
+    pub async fn v1_index_search_formula(
+        &self,
+        index: Path<String>,
+        formula: Query<String>,
+        extra_query: Query<Option<String>>,
+        limit: Query<Option<usize>>,
+        sort_by: Query<Option<String>>,
+        order: Query<Option<String>>,
+        timeout_ms: Query<Option<u64>>,
+        #[oai(name = "accept")] accept: Header<Option<String>>,
         index_manager: Data<&IndexManager>,
     ) -> GetQuerySearchResponse {
         let limit = limit.0.unwrap_or(1000);
+        let formula_query = build_formula_query(&formula.0).and_then(|formula_query| {
+            match extra_query.0.filter(|extra_query| !extra_query.is_empty()) {
+                Some(extra_query) => Ok(format!(
+                    "{formula_query} AND ({})",
+                    expand_formula_queries(&extra_query)?
+                )),
+                None => Ok(formula_query),
+            }
+        });
+        let query = match formula_query {
+            Ok(query) => query,
+            Err(e) => {
+                return GetQuerySearchResponse::Err(Json(QueryResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
+        let order = order.0.unwrap_or_else(|| "asc".to_string());
+        let stream = wants_ndjson(accept.0.as_deref());
+        let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, stream, move || {
+            v1_index_search_basic(
+                index,
+                query,
+                Vec::new(),
+                limit,
+                sort_by.0,
+                order,
+                stream,
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/v1/indexes/:index/search/mass", method = "get")]
+    /// Accurate-mass search: find the compounds whose exactmw explains the observed mz as one of
+    /// the given adducts (repeat adduct for several, e.g. [M+H]+ and [M+Na]+; every known adduct
+    /// of the charge state when unset). charge is the charge state (default 1, negative for
+    /// negative mode) and every adduct must carry it. tolerance (default 5) applies to the mz,
+    /// in tolerance_unit ppm (default) or mda. Hits are ranked by their absolute mass error and
+    /// name the adduct that explains them. Stops with an error after timeout_ms (or the server's
+    /// default)
+    pub async fn v1_index_search_mass(
+        &self,
+        index: Path<String>,
+        mz: Query<f64>,
+        tolerance: Query<Option<f64>>,
+        tolerance_unit: Query<Option<String>>,
+        charge: Query<Option<i32>>,
+        #[oai(name = "adduct")] adducts: Query<Option<Vec<String>>>,
+        extra_query: Query<Option<String>>,
+        result_limit: Query<Option<usize>>,
+        timeout_ms: Query<Option<u64>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetMassSearchResponse {
+        let extra_query = match expand_formula_queries(&extra_query.0.unwrap_or_default()) {
+            Ok(extra_query) => extra_query,
+            Err(e) => {
+                return GetMassSearchResponse::Err(Json(QueryResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
+        let tolerance_unit = tolerance_unit.0.unwrap_or_else(|| "ppm".to_string());
         let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
 
-        v1_index_search_basic(index, query.0, limit)
+        run_cancellable(&token, false, move || {
+            v1_index_search_mass(
+                index,
+                mz.0,
+                tolerance.0.unwrap_or(5.0),
+                &tolerance_unit,
+                charge.0.unwrap_or(1),
+                &adducts.0.unwrap_or_default(),
+                &extra_query,
+                result_limit.0.unwrap_or(1000),
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/v1/indexes/:index/search/basic/facets", method = "get")]
+    /// Aggregate the documents matching a basic search query (needs a descriptor_v4 index).
+    /// Integer descriptors (e.g. NumRings) and extra_data keys are counted per value, keeping the
+    /// terms_limit (default 100) most frequent; float descriptors (e.g. exactmw) get min, max,
+    /// mean and a histogram with the given number of bins (default 10). Repeat descriptor and
+    /// extra_data_key to aggregate several fields at once. Stops with an error after timeout_ms
+    /// (or the server's default)
+    pub async fn v1_index_search_facets(
+        &self,
+        index: Path<String>,
+        query: Query<String>,
+        #[oai(name = "descriptor")] descriptors: Query<Option<Vec<String>>>,
+        #[oai(name = "extra_data_key")] extra_data_keys: Query<Option<Vec<String>>>,
+        bins: Query<Option<usize>>,
+        terms_limit: Query<Option<usize>>,
+        timeout_ms: Query<Option<u64>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetDescriptorFacetsResponse {
+        let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, false, move || {
+            v1_index_search_facets(
+                index,
+                query.0,
+                descriptors.0.unwrap_or_default(),
+                extra_data_keys.0.unwrap_or_default(),
+                bins.0.unwrap_or(10),
+                terms_limit.0.unwrap_or(100),
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[oai(path = "/v1/indexes/:index/cluster", method = "get")]
+    /// Butina clustering of the stored Morgan fingerprints of the compounds matching query (every
+    /// compound when unset). Compounds within distance_threshold (Tanimoto distance, default 0.35)
+    /// of each other are neighbors; the compounds with the most neighbors become the centroids.
+    /// Only the first compound_limit (default 10000) matches are clustered, since every pair is
+    /// compared. Stops with an error after timeout_ms (or the server's default)
+    pub async fn v1_index_cluster(
+        &self,
+        index: Path<String>,
+        query: Query<Option<String>>,
+        distance_threshold: Query<Option<f32>>,
+        compound_limit: Query<Option<usize>>,
+        timeout_ms: Query<Option<u64>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetClusteringResponse {
+        let query = query.0.unwrap_or_else(|| "*".to_string());
+        let distance_threshold = distance_threshold.0.unwrap_or(0.35);
+        let compound_limit = compound_limit.0.unwrap_or(10000);
+        let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let cluster_token = token.clone();
+
+        run_cancellable(&token, false, move || {
+            v1_index_cluster(
+                index,
+                &query,
+                distance_threshold,
+                compound_limit,
+                &cluster_token,
+            )
+        })
+        .await
+    }
+
+    #[oai(path = "/v1/indexes/:index/diversity_pick", method = "get")]
+    /// MaxMin diversity picking: returns count (default 10) compounds out of those matching query
+    /// (every compound when unset), each picked as far as possible, by Tanimoto distance between
+    /// stored Morgan fingerprints, from the ones picked before it. Only the first compound_limit
+    /// (default 10000) matches are considered. Stops with an error after timeout_ms (or the
+    /// server's default)
+    pub async fn v1_index_diversity_pick(
+        &self,
+        index: Path<String>,
+        count: Query<Option<usize>>,
+        query: Query<Option<String>>,
+        compound_limit: Query<Option<usize>>,
+        timeout_ms: Query<Option<u64>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetDiversityPickResponse {
+        let count = count.0.unwrap_or(10);
+        let query = query.0.unwrap_or_else(|| "*".to_string());
+        let compound_limit = compound_limit.0.unwrap_or(10000);
+        let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let pick_token = token.clone();
+
+        run_cancellable(&token, false, move || {
+            v1_index_diversity_pick(index, &query, count, compound_limit, &pick_token)
+        })
+        .await
     }
 
     #[allow(clippy::too_many_arguments)]
     #[oai(path = "/v1/indexes/:index/search/substructure", method = "get")]
-    /// Perform substructure search against index
+    /// Perform substructure search against index. The query can be given as SMILES, as SMARTS, or
+    /// as a molblock with MDL query features; exactly one of them should be provided. Pass the
+    /// x-cheminee-next-cursor header of a response as cursor to fetch the next page, or send
+    /// "Accept: application/x-ndjson" to have the hits streamed as newline-delimited JSON. Set
+    /// include_atom_matches to get the atom and bond indices of every mapping of the query onto
+    /// each hit, and depict to embed an SVG of each hit with the matched atoms highlighted. With
+    /// rgroup_decomposition, the smiles query is a core with attachment points (e.g.
+    /// c1ccc([*:1])cc1[*:2]) and every hit is split into the core and its R-groups. Repeat
+    /// exclude_smarts to drop every hit that also contains any of those substructures. Set sort_by
+    /// (and order, asc or desc) to get the top hits by a descriptor or extra_data.{key} field, as
+    /// in basic search; sorted searches cannot be paged. After timeout_ms (or the server's
+    /// default) the search stops and returns the hits found so far, flagged by the
+    /// x-cheminee-timed-out header
     pub async fn v1_index_search_substructure(
         &self,
         index: Path<String>,
-        smiles: Query<String>,
+        smiles: Query<Option<String>>,
+        smarts: Query<Option<String>>,
+        mol_block: Query<Option<String>>,
         use_chirality: Query<Option<bool>>,
         result_limit: Query<Option<usize>>,
         tautomer_limit: Query<Option<usize>>,
         extra_query: Query<Option<String>>,
+        exclude_alerts: Query<Option<String>>,
+        only_alerts: Query<Option<String>>,
+        exclude_smarts: Query<Option<Vec<String>>>,
         use_scaffolds: Query<Option<bool>>,
+        cursor: Query<Option<String>>,
+        include_atom_matches: Query<Option<bool>>,
+        depict: Query<Option<bool>>,
+        rgroup_decomposition: Query<Option<bool>>,
+        sort_by: Query<Option<String>>,
+        order: Query<Option<String>>,
+        timeout_ms: Query<Option<u64>>,
+        #[oai(name = "accept")] accept: Header<Option<String>>,
         index_manager: Data<&IndexManager>,
     ) -> GetStructureSearchResponse {
         let use_chirality = use_chirality.0.unwrap_or(false);
         let result_limit = result_limit.0.unwrap_or(1000);
         let tautomer_limit = tautomer_limit.0.unwrap_or(0);
         let extra_query = extra_query.0.unwrap_or_default();
+        let extra_query = match expand_formula_queries(&extra_query).and_then(|extra_query| {
+            add_alert_filters(
+                &extra_query,
+                exclude_alerts.0.as_deref(),
+                only_alerts.0.as_deref(),
+            )
+        }) {
+            Ok(extra_query) => extra_query,
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
         let use_scaffolds = use_scaffolds.0.unwrap_or(true);
-        let index = index_manager.0.open(&index);
+        let stream = wants_ndjson(accept.0.as_deref());
 
-        v1_index_search_structure(
-            index,
-            smiles.0,
-            use_chirality,
-            "substructure",
-            result_limit,
-            tautomer_limit,
-            &extra_query,
-            use_scaffolds,
-        )
+        let (query, query_format) = match (smiles.0, smarts.0, mol_block.0) {
+            (Some(smiles), None, None) => (smiles, "smiles"),
+            (None, Some(smarts), None) => (smarts, "smarts"),
+            (None, None, Some(mol_block)) => (mol_block, "mol_block"),
+            _ => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: "Exactly one of smiles, smarts or mol_block must be provided"
+                        .to_string(),
+                }))
+            }
+        };
+
+        let index_name = index.0;
+        let index = index_manager.0.open(&index_name).and_then(|index| {
+            check_alerts_indexed(index, exclude_alerts.0.is_some() || only_alerts.0.is_some())
+        });
+        let result_cache = self.result_cache.clone();
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, stream, move || {
+            v1_index_search_structure(
+                index,
+                &index_name,
+                query,
+                query_format,
+                use_chirality,
+                "substructure",
+                result_limit,
+                tautomer_limit,
+                &extra_query,
+                &exclude_smarts.0.unwrap_or_default(),
+                use_scaffolds,
+                cursor.0.as_deref(),
+                stream,
+                include_atom_matches.0.unwrap_or(false),
+                depict.0.unwrap_or(false),
+                rgroup_decomposition.0.unwrap_or(false),
+                sort_by.0.as_deref(),
+                &order.0.unwrap_or_else(|| "asc".to_string()),
+                &result_cache,
+                &search_token,
+            )
+        })
+        .await
     }
 
     #[allow(clippy::too_many_arguments)]
     #[oai(path = "/v1/indexes/:index/search/superstructure", method = "get")]
-    /// Perform superstructure search against index
+    /// Perform superstructure search against index. Pass the x-cheminee-next-cursor header of a
+    /// response as cursor to fetch the next page, or send "Accept: application/x-ndjson" to have the
+    /// hits streamed as newline-delimited JSON. Set include_atom_matches to get, for each hit, the
+    /// atom and bond indices of the query molecule that every mapping of the hit covers, and depict
+    /// to embed an SVG of each hit. Repeat exclude_smarts to drop every hit containing any of those
+    /// substructures. Set sort_by (and order, asc or desc) to get the top hits by a descriptor or
+    /// extra_data.{key} field, as in basic search; sorted searches cannot be paged. After
+    /// timeout_ms (or the server's default) the search stops and returns the hits found so far,
+    /// flagged by the x-cheminee-timed-out header
     pub async fn v1_index_search_superstructure(
         &self,
         index: Path<String>,
@@ -189,31 +652,286 @@ impl ApiV1 {
         result_limit: Query<Option<usize>>,
         tautomer_limit: Query<Option<usize>>,
         extra_query: Query<Option<String>>,
+        exclude_alerts: Query<Option<String>>,
+        only_alerts: Query<Option<String>>,
+        exclude_smarts: Query<Option<Vec<String>>>,
         use_scaffolds: Query<Option<bool>>,
+        cursor: Query<Option<String>>,
+        include_atom_matches: Query<Option<bool>>,
+        depict: Query<Option<bool>>,
+        sort_by: Query<Option<String>>,
+        order: Query<Option<String>>,
+        timeout_ms: Query<Option<u64>>,
+        #[oai(name = "accept")] accept: Header<Option<String>>,
         index_manager: Data<&IndexManager>,
     ) -> GetStructureSearchResponse {
         let use_chirality = use_chirality.0.unwrap_or(false);
         let result_limit = result_limit.0.unwrap_or(1000);
         let tautomer_limit = tautomer_limit.0.unwrap_or(0);
         let extra_query = extra_query.0.unwrap_or_default();
+        let extra_query = match expand_formula_queries(&extra_query).and_then(|extra_query| {
+            add_alert_filters(
+                &extra_query,
+                exclude_alerts.0.as_deref(),
+                only_alerts.0.as_deref(),
+            )
+        }) {
+            Ok(extra_query) => extra_query,
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
         let use_scaffolds = use_scaffolds.0.unwrap_or(true);
+        let stream = wants_ndjson(accept.0.as_deref());
+        let index_name = index.0;
+        let index = index_manager.0.open(&index_name).and_then(|index| {
+            check_alerts_indexed(index, exclude_alerts.0.is_some() || only_alerts.0.is_some())
+        });
+        let result_cache = self.result_cache.clone();
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, stream, move || {
+            v1_index_search_structure(
+                index,
+                &index_name,
+                smiles.0,
+                "smiles",
+                use_chirality,
+                "superstructure",
+                result_limit,
+                tautomer_limit,
+                &extra_query,
+                &exclude_smarts.0.unwrap_or_default(),
+                use_scaffolds,
+                cursor.0.as_deref(),
+                stream,
+                include_atom_matches.0.unwrap_or(false),
+                depict.0.unwrap_or(false),
+                false,
+                sort_by.0.as_deref(),
+                &order.0.unwrap_or_else(|| "asc".to_string()),
+                &result_cache,
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/v1/indexes/:index/search/explain", method = "get")]
+    /// Explain a substructure (default) or superstructure search instead of returning its hits:
+    /// the generated tantivy query, the scaffolds found in the query, and the candidates left and
+    /// time spent after each stage. Takes the query like the search itself; stops confirming
+    /// candidates once result_limit (default 1000) hits are found, or after timeout_ms (or the
+    /// server's default), which sets timed_out
+    pub async fn v1_index_search_explain(
+        &self,
+        index: Path<String>,
+        method: Query<Option<String>>,
+        smiles: Query<Option<String>>,
+        smarts: Query<Option<String>>,
+        mol_block: Query<Option<String>>,
+        use_chirality: Query<Option<bool>>,
+        result_limit: Query<Option<usize>>,
+        extra_query: Query<Option<String>>,
+        use_scaffolds: Query<Option<bool>>,
+        timeout_ms: Query<Option<u64>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetStructureSearchExplanationResponse {
+        let method = method.0.unwrap_or_else(|| "substructure".to_string());
+        let use_chirality = use_chirality.0.unwrap_or(false);
+        let result_limit = result_limit.0.unwrap_or(1000);
+        let extra_query = match expand_formula_queries(&extra_query.0.unwrap_or_default()) {
+            Ok(extra_query) => extra_query,
+            Err(e) => {
+                return GetStructureSearchExplanationResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
+        let use_scaffolds = use_scaffolds.0.unwrap_or(true);
+
+        let (query, query_format) = match (smiles.0, smarts.0, mol_block.0) {
+            (Some(smiles), None, None) => (smiles, "smiles"),
+            (None, Some(smarts), None) => (smarts, "smarts"),
+            (None, None, Some(mol_block)) => (mol_block, "mol_block"),
+            _ => {
+                return GetStructureSearchExplanationResponse::Err(Json(StructureResponseError {
+                    error: "Exactly one of smiles, smarts or mol_block must be provided"
+                        .to_string(),
+                }))
+            }
+        };
+
         let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
 
-        v1_index_search_structure(
-            index,
-            smiles.0,
-            use_chirality,
-            "superstructure",
-            result_limit,
-            tautomer_limit,
-            &extra_query,
-            use_scaffolds,
-        )
+        run_cancellable(&token, false, move || {
+            v1_index_search_explain(
+                index,
+                query,
+                query_format,
+                &method,
+                use_chirality,
+                result_limit,
+                &extra_query,
+                use_scaffolds,
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/v1/indexes/:index/search/mcs", method = "get")]
+    /// Rank the nearest neighbours of the query (the same candidates a similarity search looks
+    /// at) by the size of their maximum common substructure with it. The score is the number of
+    /// MCS bonds, with ties going to the hit with more MCS atoms. mcs_timeout_ms (100 by default)
+    /// limits the MCS search for each candidate, and atom_compare, bond_compare,
+    /// ring_matches_ring_only and complete_rings_only work as for /v1/mcs. Set include_atom_matches to get the MCS atoms and
+    /// bonds within each hit, and depict to embed an SVG of each hit with the MCS highlighted.
+    /// timeout_ms (or the server's default) bounds the whole search, which then ranks the
+    /// candidates it got to
+    pub async fn v1_index_search_mcs(
+        &self,
+        index: Path<String>,
+        smiles: Query<String>,
+        result_limit: Query<Option<usize>>,
+        search_percent_limit: Query<Option<f32>>,
+        extra_query: Query<Option<String>>,
+        mcs_timeout_ms: Query<Option<u64>>,
+        atom_compare: Query<Option<String>>,
+        bond_compare: Query<Option<String>>,
+        ring_matches_ring_only: Query<Option<bool>>,
+        complete_rings_only: Query<Option<bool>>,
+        include_atom_matches: Query<Option<bool>>,
+        depict: Query<Option<bool>>,
+        timeout_ms: Query<Option<u64>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetStructureSearchResponse {
+        let result_limit = result_limit.0.unwrap_or(1000);
+        let search_percent_limit = search_percent_limit.0.unwrap_or(0.1);
+        let mcs_params = McsParams {
+            timeout: Duration::from_millis(mcs_timeout_ms.0.unwrap_or(100)),
+            atom_compare: atom_compare.0.unwrap_or_else(|| "elements".to_string()),
+            bond_compare: bond_compare.0.unwrap_or_else(|| "order".to_string()),
+            ring_matches_ring_only: ring_matches_ring_only.0.unwrap_or(false),
+            complete_rings_only: complete_rings_only.0.unwrap_or(false),
+        };
+        let extra_query = match expand_formula_queries(&extra_query.0.unwrap_or_default()) {
+            Ok(extra_query) => extra_query,
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
+        let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, false, move || {
+            v1_index_search_mcs(
+                index,
+                smiles.0,
+                result_limit,
+                search_percent_limit,
+                &extra_query,
+                &mcs_params,
+                include_atom_matches.0.unwrap_or(false),
+                depict.0.unwrap_or(false),
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[oai(path = "/v1/indexes/:index/search/batch", method = "post")]
+    /// Run a list of substructure, superstructure, identity, similarity and MCS queries, each with
+    /// its own parameters, against one view of the index. Results come back in query order; a
+    /// query that fails carries its own error instead of failing the whole batch. Each query's
+    /// timeout_ms (or the server's default) applies to that query alone
+    pub async fn v1_index_search_batch(
+        &self,
+        index: Path<String>,
+        batch_request: Json<BatchSearchRequest>,
+        index_manager: Data<&IndexManager>,
+    ) -> PostBatchSearchResponse {
+        let index = index_manager.0.open(&index);
+        let token = CancellationToken::default();
+        let search_token = token.clone();
+        let search_timeout_ms = self.search_timeout_ms;
+
+        run_cancellable(&token, false, move || {
+            v1_index_search_batch(index, batch_request.0, search_timeout_ms, &search_token)
+        })
+        .await
+    }
+
+    #[oai(path = "/v1/indexes/:index/search/query", method = "post")]
+    /// Run a structured query that combines substructure, superstructure, identity, similarity,
+    /// descriptor and tantivy clauses with and, or and not. Descriptor and tantivy clauses, and the
+    /// descriptor and scaffold bounds of structure clauses, narrow down the candidates in the
+    /// index; the chemistry clauses are then evaluated on those, cheapest first. Hits come in index
+    /// order; their score is the highest similarity among the similar clauses they match (1 when
+    /// there are none). timeout_ms (or the server's default) applies as for the other searches
+    pub async fn v1_index_search_query(
+        &self,
+        index: Path<String>,
+        query_request: Json<StructuredSearchRequest>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetStructureSearchResponse {
+        let index = index_manager.0.open(&index);
+        let token =
+            CancellationToken::with_timeout(query_request.timeout_ms.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, false, move || {
+            v1_index_search_query(index, query_request.0, &search_token)
+        })
+        .await
+    }
+
+    #[oai(path = "/v1/search/federated", method = "post")]
+    /// Run one basic, substructure, superstructure or similarity query against several indexes,
+    /// given by name or by a pattern with * and ? wildcards (e.g. "vendor_*"). Every hit is tagged
+    /// with the index it came from. result_limit applies to the merged hits, which are ordered by
+    /// score for similarity searches, by sort_by when it is set, and index by index otherwise.
+    /// timeout_ms (or the server's default) applies to the whole search
+    pub async fn v1_search_federated(
+        &self,
+        federated_request: Json<FederatedSearchRequest>,
+        index_manager: Data<&IndexManager>,
+    ) -> PostFederatedSearchResponse {
+        let index_manager = index_manager.0.clone();
+        let token = CancellationToken::with_timeout(
+            federated_request.timeout_ms.or(self.search_timeout_ms),
+        );
+        let search_token = token.clone();
+
+        run_cancellable(&token, false, move || {
+            v1_search_federated(&index_manager, federated_request.0, &search_token)
+        })
+        .await
     }
 
     #[allow(clippy::too_many_arguments)]
     #[oai(path = "/v1/indexes/:index/search/similarity", method = "get")]
-    /// Perform similarity search against index
+    /// Perform similarity search against index. Pass the x-cheminee-next-cursor header of a
+    /// response as cursor to fetch the next page. fingerprint is one of morgan (default),
+    /// morgan_r2_1024, morgan_r2_2048, morgan_r3_4096, feature_morgan_r2_2048, atom_pair, torsion
+    /// or maccs (all but morgan need a descriptor_v2 index); metric is one of tanimoto (default),
+    /// dice, cosine or tversky, and similarity_minimum applies to whichever metric is chosen
+    /// (tanimoto_minimum is its deprecated former name). Set
+    /// exact to scan every fingerprint for the exact top hits instead of the predicted similarity
+    /// clusters (needs a descriptor_v2 index; search_percent_limit is ignored). Repeat
+    /// exclude_smarts to drop every hit containing any of those substructures. Set depict to embed
+    /// an SVG of each hit. After timeout_ms (or the server's default) the search ranks the
+    /// candidates it got to, flagged by the x-cheminee-timed-out header, and issues no cursor
     pub async fn v1_index_search_similarity(
         &self,
         index: Path<String>,
@@ -221,44 +939,178 @@ impl ApiV1 {
         result_limit: Query<Option<usize>>,
         tautomer_limit: Query<Option<usize>>,
         search_percent_limit: Query<Option<f32>>,
-        tanimoto_minimum: Query<Option<f32>>,
+        similarity_minimum: Query<Option<f32>>,
+        #[oai(deprecated)] tanimoto_minimum: Query<Option<f32>>,
         extra_query: Query<Option<String>>,
+        exclude_alerts: Query<Option<String>>,
+        only_alerts: Query<Option<String>>,
+        exclude_smarts: Query<Option<Vec<String>>>,
+        cursor: Query<Option<String>>,
+        fingerprint: Query<Option<String>>,
+        metric: Query<Option<String>>,
+        tversky_alpha: Query<Option<f32>>,
+        tversky_beta: Query<Option<f32>>,
+        exact: Query<Option<bool>>,
+        depict: Query<Option<bool>>,
+        timeout_ms: Query<Option<u64>>,
         index_manager: Data<&IndexManager>,
     ) -> GetStructureSearchResponse {
         let result_limit = result_limit.0.unwrap_or(1000);
         let tautomer_limit = tautomer_limit.0.unwrap_or(0);
         let search_percent_limit = search_percent_limit.0.unwrap_or(0.1);
-        let tanimoto_minimum = tanimoto_minimum.0.unwrap_or(0.4);
+        let similarity_minimum = similarity_minimum.0.or(tanimoto_minimum.0).unwrap_or(0.4);
         let extra_query = extra_query.0.unwrap_or_default();
-        let index = index_manager.0.open(&index);
+        let extra_query = match expand_formula_queries(&extra_query).and_then(|extra_query| {
+            add_alert_filters(
+                &extra_query,
+                exclude_alerts.0.as_deref(),
+                only_alerts.0.as_deref(),
+            )
+        }) {
+            Ok(extra_query) => extra_query,
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
+        let fingerprint = fingerprint.0.unwrap_or_else(|| "morgan".to_string());
+        let metric = metric.0.unwrap_or_else(|| "tanimoto".to_string());
+        let tversky_alpha = tversky_alpha.0.unwrap_or(0.5);
+        let tversky_beta = tversky_beta.0.unwrap_or(0.5);
+        let exact = exact.0.unwrap_or(false);
+        let index_name = index.0;
+        let index = index_manager.0.open(&index_name).and_then(|index| {
+            check_alerts_indexed(index, exclude_alerts.0.is_some() || only_alerts.0.is_some())
+        });
+        let result_cache = self.result_cache.clone();
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
 
-        v1_index_search_similarity(
-            index,
-            smiles.0,
-            result_limit,
-            tautomer_limit,
-            search_percent_limit,
-            tanimoto_minimum,
-            &extra_query,
-        )
+        run_cancellable(&token, false, move || {
+            v1_index_search_similarity(
+                index,
+                &index_name,
+                smiles.0,
+                result_limit,
+                tautomer_limit,
+                search_percent_limit,
+                similarity_minimum,
+                &extra_query,
+                &exclude_smarts.0.unwrap_or_default(),
+                cursor.0.as_deref(),
+                &fingerprint,
+                &metric,
+                tversky_alpha,
+                tversky_beta,
+                exact,
+                depict.0.unwrap_or(false),
+                &result_cache,
+                &search_token,
+            )
+        })
+        .await
     }
 
+    #[allow(clippy::too_many_arguments)]
     #[oai(path = "/v1/indexes/:index/search/identity", method = "get")]
-    /// Perform identity search (i.e. exact match) against index
+    /// Perform identity search (i.e. exact match) against index. Set identity_level to one of
+    /// full, tautomer, stereo, isotope or connectivity to match on that layer only (use_chirality
+    /// is then ignored). Send "Accept: application/x-ndjson" to have the hits streamed as
+    /// newline-delimited JSON, and set depict to embed an SVG of each hit. sort_by and order sort
+    /// the hits by a descriptor or extra_data.{key} field, as in basic search. After timeout_ms
+    /// (or the server's default) the search stops and returns the hits found so far, flagged by
+    /// the x-cheminee-timed-out header
     pub async fn v1_index_search_identity(
         &self,
         index: Path<String>,
         smiles: Query<String>,
         use_chirality: Query<Option<bool>>,
         extra_query: Query<Option<String>>,
+        exclude_alerts: Query<Option<String>>,
+        only_alerts: Query<Option<String>>,
         use_scaffolds: Query<Option<bool>>,
+        identity_level: Query<Option<String>>,
+        depict: Query<Option<bool>>,
+        sort_by: Query<Option<String>>,
+        order: Query<Option<String>>,
+        timeout_ms: Query<Option<u64>>,
+        #[oai(name = "accept")] accept: Header<Option<String>>,
         index_manager: Data<&IndexManager>,
     ) -> GetStructureSearchResponse {
         let use_chirality = use_chirality.0.unwrap_or(false);
         let extra_query = extra_query.0.unwrap_or_default();
+        let extra_query = match expand_formula_queries(&extra_query).and_then(|extra_query| {
+            add_alert_filters(
+                &extra_query,
+                exclude_alerts.0.as_deref(),
+                only_alerts.0.as_deref(),
+            )
+        }) {
+            Ok(extra_query) => extra_query,
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
         let use_scaffolds = use_scaffolds.0.unwrap_or(true);
+        let stream = wants_ndjson(accept.0.as_deref());
+        let index = index_manager.0.open(&index).and_then(|index| {
+            check_alerts_indexed(index, exclude_alerts.0.is_some() || only_alerts.0.is_some())
+        });
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
+
+        run_cancellable(&token, stream, move || {
+            v1_index_search_identity(
+                index,
+                smiles.0,
+                use_chirality,
+                &extra_query,
+                use_scaffolds,
+                identity_level.0.as_deref(),
+                stream,
+                depict.0.unwrap_or(false),
+                sort_by.0.as_deref(),
+                &order.0.unwrap_or_else(|| "asc".to_string()),
+                &search_token,
+            )
+        })
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[oai(path = "/v1/indexes/:index/search/inchikey", method = "get")]
+    /// Look up compounds by standard InChIKey (needs a descriptor_v3 index). Set connectivity to
+    /// match on the first block only, which ignores stereochemistry, isotopes and protonation; the
+    /// first block may then be passed on its own. result_limit defaults to 1000, and depict embeds
+    /// an SVG of each hit. After timeout_ms (or the server's default) the search stops and
+    /// returns the hits found so far
+    pub async fn v1_index_search_inchikey(
+        &self,
+        index: Path<String>,
+        inchikey: Query<String>,
+        connectivity: Query<Option<bool>>,
+        result_limit: Query<Option<usize>>,
+        depict: Query<Option<bool>>,
+        timeout_ms: Query<Option<u64>>,
+        index_manager: Data<&IndexManager>,
+    ) -> GetStructureSearchResponse {
         let index = index_manager.0.open(&index);
+        let token = CancellationToken::with_timeout(timeout_ms.0.or(self.search_timeout_ms));
+        let search_token = token.clone();
 
-        v1_index_search_identity(index, smiles.0, use_chirality, &extra_query, use_scaffolds)
+        run_cancellable(&token, false, move || {
+            v1_index_search_inchikey(
+                index,
+                &inchikey.0,
+                connectivity.0.unwrap_or(false),
+                result_limit.0.unwrap_or(1000),
+                depict.0.unwrap_or(false),
+                &search_token,
+            )
+        })
+        .await
     }
 }
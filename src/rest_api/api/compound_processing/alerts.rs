@@ -0,0 +1,31 @@
+use crate::rest_api::api::{AlertedSmiles, AlertsResponse};
+use crate::search::structural_alerts::{alert_hits, alert_search, PARSED_ALERTS};
+use crate::{rest_api::models::Smiles, search::compound_processing::standardize_smiles};
+use poem_openapi::payload::Json;
+use rayon::prelude::*;
+
+// Alerts are matched against the standardized molecule, as they are at index time
+pub async fn v1_alerts(smiles_vec: Json<Vec<Smiles>>) -> AlertsResponse {
+    let alerted_smiles = smiles_vec
+        .0
+        .into_par_iter()
+        .map(|s| {
+            match standardize_smiles(&s.smiles, false)
+                .and_then(|romol| alert_search(&romol, &PARSED_ALERTS))
+            {
+                Ok(alert_ids) => AlertedSmiles {
+                    smiles: s.smiles,
+                    alerts: Some(alert_hits(&alert_ids, &PARSED_ALERTS)),
+                    error: None,
+                },
+                Err(e) => AlertedSmiles {
+                    smiles: s.smiles,
+                    alerts: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    AlertsResponse::Ok(Json(alerted_smiles))
+}
@@ -0,0 +1,54 @@
+use crate::rest_api::api::{DepictResponse, DepictResponseError};
+use crate::search::depiction::depict_svg;
+use crate::search::prepare_smarts_query;
+use crate::search::structure_matching::map_atoms;
+use poem_openapi::payload::{Json, PlainText};
+use rdkit::{ROMol, RWMol};
+
+pub fn v1_depict(
+    structure: &str,
+    structure_format: &str,
+    width: u32,
+    height: u32,
+    explicit_hydrogens: bool,
+    highlight_smarts: Option<&str>,
+) -> DepictResponse {
+    match depict_structure(
+        structure,
+        structure_format,
+        width,
+        height,
+        explicit_hydrogens,
+        highlight_smarts,
+    ) {
+        Ok(svg) => DepictResponse::Ok(PlainText(svg)),
+        Err(e) => DepictResponse::Err(Json(DepictResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
+
+// The structure is drawn as given (no standardization), keeping the coordinates and any explicit
+// hydrogens of a molblock
+fn depict_structure(
+    structure: &str,
+    structure_format: &str,
+    width: u32,
+    height: u32,
+    explicit_hydrogens: bool,
+    highlight_smarts: Option<&str>,
+) -> eyre::Result<String> {
+    let mol = match structure_format {
+        "mol_block" => RWMol::from_mol_block(structure, true, false, false)
+            .ok_or(eyre::eyre!("Could not parse molblock"))?
+            .to_ro_mol(),
+        _ => ROMol::from_smiles(structure)?,
+    };
+
+    let highlights = match highlight_smarts {
+        Some(smarts) => map_atoms(&mol, &prepare_smarts_query(smarts)?, false)?,
+        None => Vec::new(),
+    };
+
+    depict_svg(&mol, width, height, explicit_hydrogens, &highlights)
+}
@@ -0,0 +1,24 @@
+use crate::rest_api::api::{McsResponse, McsResponseError};
+use crate::rest_api::models::Smiles;
+use crate::search::mcs::{find_mcs, McsParams};
+use poem_openapi::payload::Json;
+use rdkit::ROMol;
+
+pub async fn v1_mcs(smiles_vec: Json<Vec<Smiles>>, mcs_params: &McsParams) -> McsResponse {
+    let mols = smiles_vec
+        .0
+        .iter()
+        .map(|s| {
+            ROMol::from_smiles(&s.smiles)
+                .map_err(|_| eyre::eyre!("Could not convert smiles\n{}\n", s.smiles))
+        })
+        .collect::<eyre::Result<Vec<_>>>()
+        .and_then(|mols| find_mcs(&mols, mcs_params));
+
+    match mols {
+        Ok(mcs) => McsResponse::Ok(Json(mcs)),
+        Err(e) => McsResponse::Err(Json(McsResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
@@ -1,8 +1,20 @@
+mod alerts;
+pub use alerts::*;
+
 mod convert_mol_block_to_smiles;
 pub use convert_mol_block_to_smiles::*;
 
 mod convert_smiles_to_mol_block;
 pub use convert_smiles_to_mol_block::*;
 
+mod depict;
+pub use depict::*;
+
+mod mcs;
+pub use mcs::*;
+
+mod rgroup_decomposition;
+pub use rgroup_decomposition::*;
+
 mod standardize;
 pub use standardize::*;
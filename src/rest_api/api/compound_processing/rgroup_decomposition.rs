@@ -0,0 +1,40 @@
+use crate::rest_api::api::{
+    DecomposedSmiles, RGroupDecompositionRequest, RGroupDecompositionResponse,
+    RGroupDecompositionResponseError,
+};
+use crate::search::rgroup_decomposition::RGroupCore;
+use poem_openapi::payload::Json;
+use rayon::prelude::*;
+
+pub async fn v1_rgroup_decomposition(
+    request: Json<RGroupDecompositionRequest>,
+) -> RGroupDecompositionResponse {
+    let core = match RGroupCore::from_smiles(&request.0.core) {
+        Ok(core) => core,
+        Err(e) => {
+            return RGroupDecompositionResponse::Err(Json(RGroupDecompositionResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let decompositions = request
+        .0
+        .smiles
+        .into_par_iter()
+        .map(|smiles| match core.decompose(&smiles) {
+            Ok(decomposition) => DecomposedSmiles {
+                smiles,
+                decomposition: Some(decomposition),
+                error: None,
+            },
+            Err(e) => DecomposedSmiles {
+                smiles,
+                decomposition: None,
+                error: Some(e.to_string()),
+            },
+        })
+        .collect::<Vec<_>>();
+
+    RGroupDecompositionResponse::Ok(Json(decompositions))
+}
@@ -3,13 +3,16 @@ use crate::schema::LIBRARY;
 use poem_openapi::payload::Json;
 
 pub async fn v1_list_schemas() -> ListSchemasResponse {
-    let schema_descriptions = LIBRARY
+    let mut schema_descriptions = LIBRARY
         .iter()
         .map(|(name, schema)| Schema {
             name: name.to_string(),
             schema: serde_json::to_value(schema).unwrap(),
         })
-        .collect();
+        .collect::<Vec<_>>();
+
+    // LIBRARY is a HashMap, so sort to keep the listing stable
+    schema_descriptions.sort_by(|a, b| a.name.cmp(&b.name));
 
     ListSchemasResponse::Ok(Json(schema_descriptions))
 }
@@ -1,8 +1,25 @@
+use crate::search::clustering::{Clustering, DiversityPick};
+use crate::search::facets::DescriptorFacets;
+use crate::search::mass_search::MassSearchHit;
+use crate::search::mcs::Mcs;
+use crate::search::rgroup_decomposition::RGroupDecomposition;
+use crate::search::structural_alerts::AlertHit;
+use crate::search::structure_explain::StructureSearchExplanation;
 use crate::search::{QuerySearchHit, StructureSearchHit};
-use poem_openapi::{payload::Json, ApiResponse, Object};
+use poem::Body;
+use poem_openapi::{
+    payload::{Binary, Json, PlainText},
+    ApiResponse, Object, ResponseContent,
+};
 use tantivy::Opstamp;
 
 // Response types
+#[derive(ApiResponse, Debug)]
+pub enum AlertsResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<Vec<AlertedSmiles>>),
+}
+
 #[derive(ApiResponse, Debug)]
 pub enum StandardizeResponse {
     #[oai(status = "200", content_type = "application/json")]
@@ -55,6 +72,12 @@ pub enum ListSchemasResponse {
     Ok(Json<Vec<crate::rest_api::api::Schema>>),
 }
 
+#[derive(ApiResponse, Debug)]
+pub enum GetResultCacheStatsResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<crate::rest_api::api::ResultCacheStats>),
+}
+
 #[derive(ApiResponse, Debug)]
 pub enum PostIndexesBulkIndexResponse {
     #[oai(status = "200", content_type = "application/json")]
@@ -97,10 +120,66 @@ pub enum DeleteIndexResponse {
     Err(Json<crate::rest_api::api::DeleteIndexError>),
 }
 
+// Search hits are returned as a JSON array, or as newline-delimited JSON streamed while the search
+// is still running when the client sends "Accept: application/x-ndjson"
+#[derive(ResponseContent, Debug)]
+pub enum QuerySearchContent {
+    Json(Json<Vec<QuerySearchHit>>),
+    #[oai(content_type = "application/x-ndjson")]
+    Ndjson(Binary<Body>),
+}
+
+#[derive(ResponseContent, Debug)]
+pub enum StructureSearchContent {
+    Json(Json<Vec<StructureSearchHit>>),
+    #[oai(content_type = "application/x-ndjson")]
+    Ndjson(Binary<Body>),
+}
+
 #[derive(ApiResponse, Debug)]
 pub enum GetQuerySearchResponse {
+    #[oai(status = "200")]
+    Ok(
+        QuerySearchContent,
+        /// Set when the search ran past timeout_ms and the hits are the ones found until then;
+        /// streamed responses end with an error line instead
+        #[oai(header = "x-cheminee-timed-out")]
+        Option<bool>,
+    ),
+    #[oai(status = "404")]
+    IndexDoesNotExist,
+    #[oai(status = "500", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::QueryResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum GetClusteringResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<Clustering>),
+    #[oai(status = "500", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::QueryResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum GetMassSearchResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<Vec<MassSearchHit>>),
+    #[oai(status = "500", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::QueryResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum GetDiversityPickResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<DiversityPick>),
+    #[oai(status = "500", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::QueryResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum GetDescriptorFacetsResponse {
     #[oai(status = "200", content_type = "application/json")]
-    Ok(Json<Vec<QuerySearchHit>>),
+    Ok(Json<DescriptorFacets>),
     #[oai(status = "404")]
     IndexDoesNotExist,
     #[oai(status = "500", content_type = "application/json")]
@@ -109,14 +188,79 @@ pub enum GetQuerySearchResponse {
 
 #[derive(ApiResponse, Debug)]
 pub enum GetStructureSearchResponse {
+    #[oai(status = "200")]
+    Ok(
+        StructureSearchContent,
+        /// Set when the hits were cut off at result_limit and more matches may exist; not sent
+        /// with streamed responses, since it is only known once the last hit has been written
+        #[oai(header = "x-cheminee-truncated")]
+        Option<bool>,
+        /// Opaque cursor for fetching the next page of hits; only valid until the index is modified
+        #[oai(header = "x-cheminee-next-cursor")]
+        Option<String>,
+        /// Set when the search ran past timeout_ms and the hits are the ones found until then;
+        /// streamed responses end with an error line instead
+        #[oai(header = "x-cheminee-timed-out")]
+        Option<bool>,
+    ),
+    #[oai(status = "404")]
+    IndexDoesNotExist,
+    #[oai(status = "500", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::StructureResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum GetStructureSearchExplanationResponse {
     #[oai(status = "200", content_type = "application/json")]
-    Ok(Json<Vec<StructureSearchHit>>),
+    Ok(Json<StructureSearchExplanation>),
     #[oai(status = "404")]
     IndexDoesNotExist,
     #[oai(status = "500", content_type = "application/json")]
     Err(Json<crate::rest_api::api::StructureResponseError>),
 }
 
+#[derive(ApiResponse, Debug)]
+pub enum PostBatchSearchResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<Vec<crate::rest_api::api::BatchSearchResult>>),
+    #[oai(status = "404")]
+    IndexDoesNotExist,
+    #[oai(status = "500", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::StructureResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum PostFederatedSearchResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<crate::rest_api::api::FederatedSearchResult>),
+    #[oai(status = "500", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::StructureResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum DepictResponse {
+    #[oai(status = "200", content_type = "image/svg+xml")]
+    Ok(PlainText<String>),
+    #[oai(status = "400", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::DepictResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum McsResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<Mcs>),
+    #[oai(status = "400", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::McsResponseError>),
+}
+
+#[derive(ApiResponse, Debug)]
+pub enum RGroupDecompositionResponse {
+    #[oai(status = "200", content_type = "application/json")]
+    Ok(Json<Vec<DecomposedSmiles>>),
+    #[oai(status = "400", content_type = "application/json")]
+    Err(Json<crate::rest_api::api::RGroupDecompositionResponseError>),
+}
+
 // Relevant structs
 #[derive(Object, Debug)]
 pub struct StandardizedSmiles {
@@ -126,6 +270,16 @@ pub struct StandardizedSmiles {
     pub error: Option<String>,
 }
 
+#[derive(Object, Debug)]
+pub struct AlertedSmiles {
+    pub smiles: String,
+    /// The structural alerts the standardized compound hits; not set when it failed standardization
+    #[oai(skip_serializing_if_is_none)]
+    pub alerts: Option<Vec<AlertHit>>,
+    #[oai(skip_serializing_if_is_none)]
+    pub error: Option<String>,
+}
+
 #[derive(Object, Debug)]
 pub struct ConvertedSmiles {
     #[oai(skip_serializing_if_is_none)]
@@ -142,6 +296,22 @@ pub struct ConvertedMolBlock {
     pub error: Option<String>,
 }
 
+#[derive(Object, Debug)]
+pub struct DecomposedSmiles {
+    pub smiles: String,
+    #[oai(skip_serializing_if_is_none)]
+    pub decomposition: Option<RGroupDecomposition>,
+    #[oai(skip_serializing_if_is_none)]
+    pub error: Option<String>,
+}
+
+#[derive(Object, Debug)]
+pub struct RGroupDecompositionRequest {
+    /// The core with its attachment points, e.g. c1ccc([*:1])cc1[*:2]
+    pub core: String,
+    pub smiles: Vec<String>,
+}
+
 #[derive(Object, Debug)]
 pub struct BulkRequest {
     pub docs: Vec<crate::rest_api::api::BulkRequestDoc>,
@@ -154,6 +324,145 @@ pub struct BulkRequestDoc {
     pub extra_data: Option<serde_json::Value>,
 }
 
+#[derive(Object, Debug)]
+pub struct BatchSearchRequest {
+    pub queries: Vec<crate::rest_api::api::BatchSearchQuery>,
+}
+
+/// A single query of a batch search. Parameters mirror those of the individual search endpoints
+/// and fall back to the same defaults; the ones that do not apply to the method are ignored
+#[derive(Object, Debug)]
+pub struct BatchSearchQuery {
+    /// One of "substructure", "superstructure", "identity", "similarity" or "mcs"
+    pub method: String,
+    pub smiles: String,
+    /// "smiles" (default), or "smarts" or "mol_block" for substructure queries
+    pub query_format: Option<String>,
+    pub use_chirality: Option<bool>,
+    pub result_limit: Option<usize>,
+    pub tautomer_limit: Option<usize>,
+    pub extra_query: Option<String>,
+    /// Drop substructure, superstructure and similarity hits containing any of these SMARTS
+    pub exclude_smarts: Option<Vec<String>>,
+    pub use_scaffolds: Option<bool>,
+    pub cursor: Option<String>,
+    pub search_percent_limit: Option<f32>,
+    pub similarity_minimum: Option<f32>,
+    pub fingerprint: Option<String>,
+    pub metric: Option<String>,
+    pub tversky_alpha: Option<f32>,
+    pub tversky_beta: Option<f32>,
+    pub exact: Option<bool>,
+    pub include_atom_matches: Option<bool>,
+    /// Embed an SVG depiction in every hit
+    pub depict: Option<bool>,
+    pub mcs_timeout_ms: Option<u64>,
+    /// For MCS queries: "elements", "any" or "isotopes"
+    pub atom_compare: Option<String>,
+    /// For MCS queries: "order", "order_exact" or "any"
+    pub bond_compare: Option<String>,
+    pub ring_matches_ring_only: Option<bool>,
+    pub complete_rings_only: Option<bool>,
+    /// Decompose substructure hits into the core given as smiles (with attachment points like
+    /// [*:1]) and their R-groups
+    pub rgroup_decomposition: Option<bool>,
+    /// For identity queries, the layer that must match: "full", "tautomer", "stereo", "isotope"
+    /// or "connectivity"
+    pub identity_level: Option<String>,
+    /// Stop the query after this many milliseconds and return the hits found until then
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Object, Debug)]
+pub struct BatchSearchResult {
+    pub method: String,
+    pub smiles: String,
+    #[oai(skip_serializing_if_is_none)]
+    pub hits: Option<Vec<StructureSearchHit>>,
+    #[oai(skip_serializing_if_is_none)]
+    pub truncated: Option<bool>,
+    #[oai(skip_serializing_if_is_none)]
+    pub next_cursor: Option<String>,
+    #[oai(skip_serializing_if_is_none)]
+    pub timed_out: Option<bool>,
+    #[oai(skip_serializing_if_is_none)]
+    pub error: Option<String>,
+}
+
+/// One query run against several indexes. Parameters mirror those of the individual search
+/// endpoints and fall back to the same defaults; the ones that do not apply to the method are
+/// ignored
+#[derive(Object, Debug)]
+pub struct FederatedSearchRequest {
+    /// Index names, each of which may use * and ? wildcards (e.g. "vendor_*")
+    pub indexes: Vec<String>,
+    /// One of "basic", "substructure", "superstructure" or "similarity"
+    pub method: String,
+    /// The query molecule; required for every method but basic
+    pub smiles: Option<String>,
+    /// The tantivy query of a basic search
+    pub query: Option<String>,
+    /// "smiles" (default), or "smarts" or "mol_block" for substructure queries
+    pub query_format: Option<String>,
+    pub use_chirality: Option<bool>,
+    /// Applies to the merged hits of all indexes
+    pub result_limit: Option<usize>,
+    pub tautomer_limit: Option<usize>,
+    pub extra_query: Option<String>,
+    /// Drop every hit containing any of these SMARTS
+    pub exclude_smarts: Option<Vec<String>>,
+    pub use_scaffolds: Option<bool>,
+    pub search_percent_limit: Option<f32>,
+    pub similarity_minimum: Option<f32>,
+    pub fingerprint: Option<String>,
+    pub metric: Option<String>,
+    pub tversky_alpha: Option<f32>,
+    pub tversky_beta: Option<f32>,
+    pub exact: Option<bool>,
+    /// Order the merged hits of a basic or structure search by this descriptor or
+    /// extra_data.{key} field; similarity hits are always ordered by score
+    pub sort_by: Option<String>,
+    /// "asc" (default) or "desc"
+    pub order: Option<String>,
+    /// Stop searching all indexes after this many milliseconds and merge the hits found until then
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Object, Debug)]
+pub struct FederatedSearchHit {
+    /// The index the hit was found in
+    pub index: String,
+    #[oai(flatten)]
+    pub hit: StructureSearchHit,
+}
+
+#[derive(Object, Debug)]
+pub struct FederatedSearchResult {
+    pub hits: Vec<FederatedSearchHit>,
+    /// Set when more than result_limit hits were found, in total or in a single index
+    pub truncated: bool,
+    pub timed_out: bool,
+}
+
+/// A structured query: clauses combined with "and", "or" and "not", e.g.
+/// {"and": [{"substructure": {"smarts": "c1ccccc1"}}, {"not": {"substructure": {"smarts":
+/// "[N+](=O)[O-]"}}}, {"similar": {"smiles": "CCOc1ccccc1", "min": 0.7}}, {"descriptor":
+/// {"name": "exactmw", "min": 100, "max": 500}}]}
+#[derive(Object, Debug)]
+pub struct StructuredSearchRequest {
+    /// Clauses are "substructure" (smiles or smarts), "superstructure" and "identity" (smiles),
+    /// "similar" (smiles, min, and optionally fingerprint and metric), "descriptor" (name and min
+    /// and/or max) and "query" (any tantivy query, as in extra_query)
+    pub query: serde_json::Value,
+    pub result_limit: Option<usize>,
+    pub use_chirality: Option<bool>,
+    pub use_scaffolds: Option<bool>,
+    /// Embed an SVG depiction in every hit
+    pub depict: Option<bool>,
+    /// Stop the search after this many milliseconds and return the hits found until then
+    pub timeout_ms: Option<u64>,
+}
+
 #[derive(Object, Debug)]
 pub struct CreateIndexError {
     pub error: String,
@@ -224,6 +533,15 @@ pub struct Schema {
     pub schema: serde_json::Value,
 }
 
+#[derive(Object, Debug)]
+pub struct ResultCacheStats {
+    /// Maximum number of cached result pages; 0 when the cache is disabled
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
 #[derive(Object, Debug)]
 pub struct QueryResponseError {
     pub error: String,
@@ -234,6 +552,21 @@ pub struct StructureResponseError {
     pub error: String,
 }
 
+#[derive(Object, Debug)]
+pub struct DepictResponseError {
+    pub error: String,
+}
+
+#[derive(Object, Debug)]
+pub struct McsResponseError {
+    pub error: String,
+}
+
+#[derive(Object, Debug)]
+pub struct RGroupDecompositionResponseError {
+    pub error: String,
+}
+
 #[derive(ApiResponse, Debug)]
 pub enum MergeSegmentsResponse {
     #[oai(status = "200", content_type = "application/json")]
@@ -1,12 +1,23 @@
-use crate::rest_api::api::{GetQuerySearchResponse, QueryResponseError};
-use crate::search::{aggregate_query_hits, basic_search::basic_search};
-use poem_openapi::payload::Json;
-use tantivy::Index;
+use crate::rest_api::api::{
+    ndjson_body, ndjson_timeout, GetQuerySearchResponse, QueryResponseError, QuerySearchContent,
+};
+use crate::search::aggregate_query_hits;
+use crate::search::basic_search::{basic_search, basic_search_sorted, basic_search_stream};
+use crate::search::cancellation::CancellationToken;
+use crate::search::excluded_patterns::{retain_unexcluded, ExcludedPattern};
+use poem_openapi::payload::{Binary, Json};
+use tantivy::{DocAddress, Index, Searcher};
 
+#[allow(clippy::too_many_arguments)]
 pub fn v1_index_search_basic(
     index: eyre::Result<Index>,
     query: String,
+    excluded_patterns: Vec<ExcludedPattern>,
     limit: usize,
+    sort_by: Option<String>,
+    order: String,
+    stream: bool,
+    token: &CancellationToken,
 ) -> GetQuerySearchResponse {
     let index = match index {
         Ok(index) => index,
@@ -30,7 +41,77 @@ pub fn v1_index_search_basic(
     let searcher = reader.searcher();
 
     let tantivy_limit = 10 * limit;
-    let results = basic_search(&searcher, &query, tantivy_limit);
+
+    // Excluded documents can only be dropped once they are read, so with exclusions the candidates
+    // are not cut off at tantivy_limit but streamed until `limit` of them are kept
+    let keep = if excluded_patterns.is_empty() {
+        tantivy_limit
+    } else {
+        limit
+    };
+
+    if stream {
+        let token = token.clone();
+        let body = ndjson_body(move |emit| {
+            let mut candidates = basic_candidates(
+                &searcher,
+                &query,
+                sort_by.as_deref(),
+                &order,
+                tantivy_limit,
+                !excluded_patterns.is_empty(),
+                true,
+            )?
+            .take_while(|_| !token.is_cancelled());
+            let mut kept = 0;
+
+            while kept < keep {
+                let chunk = candidates.by_ref().take(1000).collect::<Vec<_>>();
+                if chunk.is_empty() {
+                    break;
+                }
+
+                let chunk = retain_unexcluded(
+                    &searcher,
+                    chunk,
+                    |doc| *doc,
+                    &excluded_patterns,
+                    keep - kept,
+                )?;
+                kept += chunk.len();
+
+                for hit in aggregate_query_hits(searcher.clone(), chunk, &query)? {
+                    if !emit(hit) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            ndjson_timeout(&token)
+        });
+
+        return GetQuerySearchResponse::Ok(QuerySearchContent::Ndjson(Binary(body)), None);
+    }
+
+    // A search that runs past its deadline returns the hits kept until then
+    let results = basic_candidates(
+        &searcher,
+        &query,
+        sort_by.as_deref(),
+        &order,
+        tantivy_limit,
+        !excluded_patterns.is_empty(),
+        false,
+    )
+    .and_then(|candidates| {
+        retain_unexcluded(
+            &searcher,
+            candidates.take_while(|_| !token.is_cancelled()),
+            |doc| *doc,
+            &excluded_patterns,
+            keep,
+        )
+    });
 
     let results = match results {
         Ok(results) => results,
@@ -52,5 +133,31 @@ pub fn v1_index_search_basic(
         }
     };
 
-    GetQuerySearchResponse::Ok(Json(final_results))
+    GetQuerySearchResponse::Ok(
+        QuerySearchContent::Json(Json(final_results)),
+        Some(token.timed_out()),
+    )
+}
+
+// The candidates in the order they are returned: by sort_by when it is set, otherwise in index
+// order. Without exclusions these are the first tantivy_limit matches (the top tantivy_limit by
+// tantivy's score for a plain, unstreamed search); with them every match is a candidate
+fn basic_candidates(
+    searcher: &Searcher,
+    query: &str,
+    sort_by: Option<&str>,
+    order: &str,
+    tantivy_limit: usize,
+    excluding: bool,
+    stream: bool,
+) -> eyre::Result<Box<dyn Iterator<Item = DocAddress>>> {
+    let limit = if excluding { usize::MAX } else { tantivy_limit };
+
+    Ok(match sort_by {
+        Some(sort_by) => {
+            Box::new(basic_search_sorted(searcher, query, limit, sort_by, order)?.into_iter())
+        }
+        None if excluding || stream => Box::new(basic_search_stream(searcher, query)?.take(limit)),
+        None => Box::new(basic_search(searcher, &query.to_string(), limit)?.into_iter()),
+    })
 }
@@ -0,0 +1,209 @@
+use crate::rest_api::api::{
+    identity_search_hits, mcs_search_page, prepare_identity_query, prepare_rgroup_query,
+    prepare_structure_query, similarity_search_page, structure_search_page, BatchSearchQuery,
+    BatchSearchRequest, BatchSearchResult, PostBatchSearchResponse, StructureResponseError,
+    StructureSearchPage,
+};
+use crate::search::cancellation::CancellationToken;
+use crate::search::depiction::embed_depictions;
+use crate::search::excluded_patterns::parse_excluded_patterns;
+use crate::search::formula::expand_formula_queries;
+use crate::search::mcs::McsParams;
+use poem_openapi::payload::Json;
+use rayon::prelude::*;
+use std::time::Duration;
+use tantivy::{Index, Searcher};
+
+// Every query runs against the same searcher, so the whole batch sees one consistent view of the
+// index. A failing query only sets the error on its own result. Every query gets its own
+// timeout (falling back to `default_timeout_ms`), while cancelling `token` stops them all
+pub fn v1_index_search_batch(
+    index: eyre::Result<Index>,
+    batch_request: BatchSearchRequest,
+    default_timeout_ms: Option<u64>,
+    token: &CancellationToken,
+) -> PostBatchSearchResponse {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return PostBatchSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return PostBatchSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    let results = batch_request
+        .queries
+        .into_par_iter()
+        .map(|query| {
+            let query_token = token.child(query.timeout_ms.or(default_timeout_ms));
+
+            match batch_query_page(&searcher, &query, &query_token) {
+                Ok((hits, truncated, next_cursor)) => BatchSearchResult {
+                    method: query.method,
+                    smiles: query.smiles,
+                    hits: Some(hits),
+                    truncated: Some(truncated),
+                    next_cursor,
+                    timed_out: Some(query_token.timed_out()),
+                    error: None,
+                },
+                Err(e) => BatchSearchResult {
+                    method: query.method,
+                    smiles: query.smiles,
+                    hits: None,
+                    truncated: None,
+                    next_cursor: None,
+                    timed_out: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    PostBatchSearchResponse::Ok(Json(results))
+}
+
+fn batch_query_page(
+    searcher: &Searcher,
+    query: &BatchSearchQuery,
+    token: &CancellationToken,
+) -> eyre::Result<StructureSearchPage> {
+    let use_chirality = query.use_chirality.unwrap_or(false);
+    let result_limit = query.result_limit.unwrap_or(1000);
+    let tautomer_limit = query.tautomer_limit.unwrap_or(0);
+    let extra_query = &expand_formula_queries(query.extra_query.as_deref().unwrap_or_default())?;
+    let excluded_patterns =
+        parse_excluded_patterns(query.exclude_smarts.as_deref().unwrap_or_default())?;
+    let use_scaffolds = query.use_scaffolds.unwrap_or(true);
+    let cursor = query.cursor.as_deref();
+    let depict = query.depict.unwrap_or(false);
+
+    match query.method.as_str() {
+        "substructure" | "superstructure" => {
+            let query_format = query.query_format.as_deref().unwrap_or("smiles");
+            if query.method == "superstructure" && query_format != "smiles" {
+                return Err(eyre::eyre!(
+                    "Superstructure queries must be given as SMILES"
+                ));
+            }
+
+            let (query_canon_taut, generic_query, rgroup_core) =
+                if query.rgroup_decomposition.unwrap_or(false) {
+                    let (core, query_mol) =
+                        prepare_rgroup_query(&query.smiles, query_format, &query.method)?;
+                    (query_mol, true, Some(core))
+                } else {
+                    let (query_mol, generic_query) =
+                        prepare_structure_query(&query.smiles, query_format)?;
+                    (query_mol, generic_query, None)
+                };
+
+            structure_search_page(
+                searcher,
+                &query_canon_taut,
+                &query.smiles,
+                &query.method,
+                result_limit,
+                tautomer_limit,
+                extra_query,
+                use_scaffolds,
+                use_chirality,
+                generic_query,
+                &excluded_patterns,
+                cursor,
+                query.include_atom_matches.unwrap_or(false),
+                depict,
+                rgroup_core.as_ref(),
+                None,
+                token,
+            )
+        }
+        "identity" => {
+            let identity_query = prepare_identity_query(
+                searcher,
+                &query.smiles,
+                use_scaffolds,
+                query.identity_level.as_deref(),
+            )?;
+            let mut hits = identity_search_hits(
+                searcher,
+                &identity_query,
+                &query.smiles,
+                use_chirality,
+                extra_query,
+                None,
+                token,
+            )?;
+
+            if depict {
+                embed_depictions(&mut hits, false)?;
+            }
+
+            Ok((hits, token.stopped(), None))
+        }
+        "similarity" => {
+            let (query_canon_taut, _) = prepare_structure_query(&query.smiles, "smiles")?;
+            let (mut hits, truncated, next_cursor) = similarity_search_page(
+                searcher,
+                query_canon_taut,
+                &query.smiles,
+                result_limit,
+                tautomer_limit,
+                query.search_percent_limit.unwrap_or(0.1),
+                query.similarity_minimum.unwrap_or(0.4),
+                extra_query,
+                &excluded_patterns,
+                cursor,
+                query.fingerprint.as_deref().unwrap_or("morgan"),
+                query.metric.as_deref().unwrap_or("tanimoto"),
+                query.tversky_alpha.unwrap_or(0.5),
+                query.tversky_beta.unwrap_or(0.5),
+                query.exact.unwrap_or(false),
+                token,
+            )?;
+
+            if depict {
+                embed_depictions(&mut hits, false)?;
+            }
+
+            Ok((hits, truncated, next_cursor))
+        }
+        "mcs" => mcs_search_page(
+            searcher,
+            &query.smiles,
+            result_limit,
+            query.search_percent_limit.unwrap_or(0.1),
+            extra_query,
+            &McsParams {
+                timeout: Duration::from_millis(query.mcs_timeout_ms.unwrap_or(100)),
+                atom_compare: query
+                    .atom_compare
+                    .clone()
+                    .unwrap_or_else(|| "elements".to_string()),
+                bond_compare: query
+                    .bond_compare
+                    .clone()
+                    .unwrap_or_else(|| "order".to_string()),
+                ring_matches_ring_only: query.ring_matches_ring_only.unwrap_or(false),
+                complete_rings_only: query.complete_rings_only.unwrap_or(false),
+            },
+            query.include_atom_matches.unwrap_or(false),
+            depict,
+            token,
+        ),
+        other => Err(eyre::eyre!("Unknown search method: {other}")),
+    }
+}
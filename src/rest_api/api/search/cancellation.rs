@@ -0,0 +1,21 @@
+use crate::search::cancellation::CancellationToken;
+
+// Runs a search on the blocking thread pool. Poem drops the handler future when the client goes
+// away, which cancels `token` and so stops the search at its next check. Streamed searches outlive
+// the handler and stop through their emit callback instead, so they pass stream = true
+pub async fn run_cancellable<T, F>(token: &CancellationToken, stream: bool, search: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let _cancel_on_drop = if stream {
+        None
+    } else {
+        Some(token.cancel_on_drop())
+    };
+
+    match tokio::task::spawn_blocking(search).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
@@ -0,0 +1,77 @@
+use crate::rest_api::api::{GetClusteringResponse, GetDiversityPickResponse, QueryResponseError};
+use crate::search::cancellation::CancellationToken;
+use crate::search::clustering::{cluster_index, diversity_pick};
+use poem_openapi::payload::Json;
+use tantivy::Index;
+
+pub fn v1_index_cluster(
+    index: eyre::Result<Index>,
+    query: &str,
+    distance_threshold: f32,
+    compound_limit: usize,
+    token: &CancellationToken,
+) -> GetClusteringResponse {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetClusteringResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetClusteringResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    match cluster_index(&searcher, query, distance_threshold, compound_limit, token) {
+        Ok(clustering) => GetClusteringResponse::Ok(Json(clustering)),
+        Err(e) => GetClusteringResponse::Err(Json(QueryResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
+
+pub fn v1_index_diversity_pick(
+    index: eyre::Result<Index>,
+    query: &str,
+    count: usize,
+    compound_limit: usize,
+    token: &CancellationToken,
+) -> GetDiversityPickResponse {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetDiversityPickResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetDiversityPickResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    match diversity_pick(&searcher, query, count, compound_limit, token) {
+        Ok(picks) => GetDiversityPickResponse::Ok(Json(picks)),
+        Err(e) => GetDiversityPickResponse::Err(Json(QueryResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
@@ -0,0 +1,59 @@
+use crate::rest_api::api::{GetDescriptorFacetsResponse, QueryResponseError};
+use crate::search::cancellation::CancellationToken;
+use crate::search::facets::descriptor_facets;
+use poem_openapi::payload::Json;
+use tantivy::Index;
+
+pub fn v1_index_search_facets(
+    index: eyre::Result<Index>,
+    query: String,
+    descriptors: Vec<String>,
+    extra_data_keys: Vec<String>,
+    bins: usize,
+    terms_limit: usize,
+    token: &CancellationToken,
+) -> GetDescriptorFacetsResponse {
+    if bins == 0 {
+        return GetDescriptorFacetsResponse::Err(Json(QueryResponseError {
+            error: "bins must be at least 1".to_string(),
+        }));
+    }
+
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetDescriptorFacetsResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetDescriptorFacetsResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    let facets = descriptor_facets(
+        &searcher,
+        &query,
+        &descriptors,
+        &extra_data_keys,
+        bins,
+        terms_limit,
+        token,
+    );
+
+    match facets {
+        Ok(facets) => GetDescriptorFacetsResponse::Ok(Json(facets)),
+        Err(e) => GetDescriptorFacetsResponse::Err(Json(QueryResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
@@ -0,0 +1,254 @@
+use crate::indexing::index_manager::IndexManager;
+use crate::rest_api::api::{
+    prepare_structure_query, similarity_search_page, structure_search_docs_page,
+    FederatedSearchHit, FederatedSearchRequest, FederatedSearchResult, PostFederatedSearchResponse,
+    StructureResponseError,
+};
+use crate::search::basic_search::basic_search_stream;
+use crate::search::cancellation::CancellationToken;
+use crate::search::excluded_patterns::{parse_excluded_patterns, retain_unexcluded};
+use crate::search::formula::expand_formula_queries;
+use crate::search::sorting::{RankedValue, SortKeys};
+use crate::search::{get_smiles_and_extra_data, StructureSearchHit};
+use poem_openapi::payload::Json;
+use rayon::prelude::*;
+use tantivy::{DocAddress, Searcher};
+
+// A hit and, for sorted searches, its rank under the sort field
+type RankedHit = (StructureSearchHit, Option<(bool, RankedValue)>);
+
+// Runs the query against every index in parallel, each through its own searcher, and merges the
+// hits: similarity hits by score, sorted searches by their sort field, and everything else index
+// by index in the order the indexes were resolved. Every index is asked for result_limit hits, so
+// the merged top result_limit are exact. Any index failing fails the whole search
+pub fn v1_search_federated(
+    index_manager: &IndexManager,
+    request: FederatedSearchRequest,
+    token: &CancellationToken,
+) -> PostFederatedSearchResponse {
+    if !matches!(
+        request.method.as_str(),
+        "basic" | "substructure" | "superstructure" | "similarity"
+    ) {
+        return PostFederatedSearchResponse::Err(Json(StructureResponseError {
+            error: format!(
+                "Unknown search method: {}; expected basic, substructure, superstructure or similarity",
+                request.method
+            ),
+        }));
+    }
+
+    let index_names = match index_manager.resolve(&request.indexes) {
+        Ok(index_names) => index_names,
+        Err(e) => {
+            return PostFederatedSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let result_limit = request.result_limit.unwrap_or(1000);
+
+    let index_results = index_names
+        .par_iter()
+        .map(|index_name| {
+            let searcher = index_manager.open(index_name)?.reader()?.searcher();
+
+            federated_index_hits(&searcher, &request, result_limit, token)
+                .map_err(|e| eyre::eyre!("{index_name}: {e}"))
+        })
+        .collect::<eyre::Result<Vec<_>>>();
+
+    let index_results = match index_results {
+        Ok(index_results) => index_results,
+        Err(e) => {
+            return PostFederatedSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let mut truncated = false;
+    let mut hits = Vec::new();
+    for (index_name, (index_hits, index_truncated)) in index_names.iter().zip(index_results) {
+        truncated |= index_truncated;
+        hits.extend(index_hits.into_iter().map(|(hit, rank)| {
+            (
+                FederatedSearchHit {
+                    index: index_name.clone(),
+                    hit,
+                },
+                rank,
+            )
+        }));
+    }
+
+    // Both sorts are stable, so ties keep index order
+    if request.method == "similarity" {
+        hits.sort_by(|a, b| b.0.hit.score.total_cmp(&a.0.hit.score));
+    } else if request.sort_by.is_some() {
+        hits.sort_by_key(|(_, rank)| *rank);
+    }
+
+    if hits.len() > result_limit {
+        truncated = true;
+        hits.truncate(result_limit);
+    }
+
+    PostFederatedSearchResponse::Ok(Json(FederatedSearchResult {
+        hits: hits.into_iter().map(|(hit, _)| hit).collect(),
+        truncated,
+        timed_out: token.timed_out(),
+    }))
+}
+
+fn federated_index_hits(
+    searcher: &Searcher,
+    request: &FederatedSearchRequest,
+    result_limit: usize,
+    token: &CancellationToken,
+) -> eyre::Result<(Vec<RankedHit>, bool)> {
+    let extra_query = &expand_formula_queries(request.extra_query.as_deref().unwrap_or_default())?;
+    let excluded_patterns =
+        parse_excluded_patterns(request.exclude_smarts.as_deref().unwrap_or_default())?;
+
+    let sort_keys = match (&request.sort_by, request.method.as_str()) {
+        (Some(sort_by), "basic" | "substructure" | "superstructure") => Some(SortKeys::new(
+            searcher,
+            sort_by,
+            request.order.as_deref().unwrap_or("asc"),
+        )?),
+        _ => None,
+    };
+
+    let rank = |doc: DocAddress| sort_keys.as_ref().map(|sort_keys| sort_keys.rank_doc(doc));
+
+    let query_smiles = || match &request.smiles {
+        Some(smiles) => Ok(smiles.as_str()),
+        None => Err(eyre::eyre!("{} searches need smiles", request.method)),
+    };
+
+    match request.method.as_str() {
+        "basic" => {
+            let query = match &request.query {
+                Some(query) => query,
+                None => return Err(eyre::eyre!("basic searches need a query")),
+            };
+
+            // One more than result_limit tells whether the index holds more matches. Excluded
+            // documents can only be dropped once ranked, so then every candidate is ranked;
+            // unsorted candidates are streamed until enough of them are kept
+            let fetch_limit = if excluded_patterns.is_empty() {
+                result_limit + 1
+            } else {
+                usize::MAX
+            };
+            let candidates = basic_search_stream(searcher, query)?;
+            let docs: Box<dyn Iterator<Item = DocAddress>> = match &sort_keys {
+                Some(sort_keys) => {
+                    Box::new(sort_keys.top_docs(candidates, fetch_limit).into_iter())
+                }
+                None => Box::new(candidates),
+            };
+            let mut docs = retain_unexcluded(
+                searcher,
+                docs,
+                |doc| *doc,
+                &excluded_patterns,
+                result_limit + 1,
+            )?;
+
+            let truncated = docs.len() > result_limit;
+            docs.truncate(result_limit);
+
+            let schema = searcher.schema();
+            let smiles_field = schema.get_field("smiles")?;
+            let extra_data_field = schema.get_field("extra_data")?;
+
+            let hits = docs
+                .into_iter()
+                .map(|doc| {
+                    let (smiles, extra_data) =
+                        get_smiles_and_extra_data(doc, searcher, smiles_field, extra_data_field)?;
+
+                    let hit = StructureSearchHit {
+                        extra_data,
+                        smiles,
+                        score: 1.0,
+                        query: query.clone(),
+                        used_tautomers: false,
+                        atom_matches: None,
+                        svg: None,
+                        rgroup_decomposition: None,
+                    };
+
+                    Ok((hit, rank(doc)))
+                })
+                .collect::<eyre::Result<Vec<_>>>()?;
+
+            Ok((hits, truncated))
+        }
+        "substructure" | "superstructure" => {
+            let query_smiles = query_smiles()?;
+            let query_format = request.query_format.as_deref().unwrap_or("smiles");
+            if request.method == "superstructure" && query_format != "smiles" {
+                return Err(eyre::eyre!(
+                    "Superstructure queries must be given as SMILES"
+                ));
+            }
+
+            let (query_mol, generic_query) = prepare_structure_query(query_smiles, query_format)?;
+
+            let (hits, truncated, _) = structure_search_docs_page(
+                searcher,
+                &query_mol,
+                query_smiles,
+                &request.method,
+                result_limit,
+                request.tautomer_limit.unwrap_or(0),
+                extra_query,
+                request.use_scaffolds.unwrap_or(true),
+                request.use_chirality.unwrap_or(false),
+                generic_query,
+                &excluded_patterns,
+                None,
+                false,
+                false,
+                None,
+                sort_keys.as_ref(),
+                token,
+            )?;
+
+            let hits = hits
+                .into_iter()
+                .map(|(hit, doc)| (hit, rank(doc)))
+                .collect();
+
+            Ok((hits, truncated))
+        }
+        _ => {
+            let query_smiles = query_smiles()?;
+            let (query_canon_taut, _) = prepare_structure_query(query_smiles, "smiles")?;
+            let (hits, truncated, _) = similarity_search_page(
+                searcher,
+                query_canon_taut,
+                query_smiles,
+                result_limit,
+                request.tautomer_limit.unwrap_or(0),
+                request.search_percent_limit.unwrap_or(0.1),
+                request.similarity_minimum.unwrap_or(0.4),
+                extra_query,
+                &excluded_patterns,
+                None,
+                request.fingerprint.as_deref().unwrap_or("morgan"),
+                request.metric.as_deref().unwrap_or("tanimoto"),
+                request.tversky_alpha.unwrap_or(0.5),
+                request.tversky_beta.unwrap_or(0.5),
+                request.exact.unwrap_or(false),
+                token,
+            )?;
+
+            Ok((hits.into_iter().map(|hit| (hit, None)).collect(), truncated))
+        }
+    }
+}
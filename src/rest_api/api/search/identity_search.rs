@@ -1,17 +1,32 @@
-use crate::rest_api::api::{GetStructureSearchResponse, StructureResponseError};
-use crate::search::scaffold_search::{scaffold_search, PARSED_SCAFFOLDS};
-use crate::search::{
-    identity_search::identity_search, prepare_query_structure, sort_results, StructureSearchHit,
+use crate::rest_api::api::{
+    ndjson_body, ndjson_timeout, GetStructureSearchResponse, StructureResponseError,
+    StructureSearchContent,
 };
-use poem_openapi::payload::Json;
-use tantivy::Index;
+use crate::search::cancellation::CancellationToken;
+use crate::search::depiction::{depict_hit, embed_depictions};
+use crate::search::identity_layers::identity_layer;
+use crate::search::identity_search::{identity_layer_search_streaming, identity_search_streaming};
+use crate::search::scaffold_search::{scaffold_search, PARSED_SCAFFOLDS};
+use crate::search::sorting::SortKeys;
+use crate::search::{json_path_indexed, prepare_query_structure, StructureSearchHit};
+use poem_openapi::payload::{Binary, Json};
+use rdkit::{Fingerprint, ROMol};
+use std::collections::HashMap;
+use tantivy::{DocAddress, DocId, Index, Searcher, SegmentOrdinal};
 
+#[allow(clippy::too_many_arguments)]
 pub fn v1_index_search_identity(
     index: eyre::Result<Index>,
     query_smiles: String,
     use_chirality: bool,
     extra_query: &str,
     use_scaffolds: bool,
+    identity_level: Option<&str>,
+    stream: bool,
+    depict: bool,
+    sort_by: Option<&str>,
+    order: &str,
+    token: &CancellationToken,
 ) -> GetStructureSearchResponse {
     let index = match index {
         Ok(index) => index,
@@ -34,18 +49,160 @@ pub fn v1_index_search_identity(
 
     let searcher = reader.searcher();
 
-    let query_attributes = prepare_query_structure(&query_smiles);
+    let identity_query =
+        match prepare_identity_query(&searcher, &query_smiles, use_scaffolds, identity_level) {
+            Ok(identity_query) => identity_query,
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        };
 
-    let query_attributes = match query_attributes {
-        Ok(query_attributes) => query_attributes,
-        Err(e) => {
-            return GetStructureSearchResponse::Err(Json(StructureResponseError {
-                error: e.to_string(),
-            }))
-        }
+    let sort_keys = match sort_by {
+        Some(sort_by) => match SortKeys::new(&searcher, sort_by, order) {
+            Ok(sort_keys) => Some(sort_keys),
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        },
+        None => None,
     };
 
-    let (query_canon_taut, pattern_fingerprint, descriptors) = query_attributes;
+    // Sorted hits can only be written once all of them are known
+    if stream && sort_keys.is_none() {
+        let extra_query = extra_query.to_string();
+        let token = token.clone();
+
+        let body = ndjson_body(move |emit| {
+            let mut failure = None;
+
+            search_identity_query(
+                &searcher,
+                &identity_query,
+                use_chirality,
+                &extra_query,
+                &token,
+                |results_subset| {
+                    results_subset
+                        .into_iter()
+                        .all(|(smiles, extra_data, _, _)| {
+                            let mut hit = StructureSearchHit {
+                                extra_data,
+                                smiles,
+                                score: 1.0,
+                                query: query_smiles.clone(),
+                                used_tautomers: false,
+                                atom_matches: None,
+                                svg: None,
+                                rgroup_decomposition: None,
+                            };
+
+                            if depict {
+                                match depict_hit(&hit, false) {
+                                    Ok(svg) => hit.svg = Some(svg),
+                                    Err(e) => {
+                                        failure = Some(e);
+                                        return false;
+                                    }
+                                }
+                            }
+
+                            emit(hit)
+                        })
+                },
+            )?;
+
+            match failure {
+                Some(e) => Err(e),
+                None => ndjson_timeout(&token),
+            }
+        });
+
+        return GetStructureSearchResponse::Ok(
+            StructureSearchContent::Ndjson(Binary(body)),
+            None,
+            None,
+            None,
+        );
+    }
+
+    let hits = identity_search_hits(
+        &searcher,
+        &identity_query,
+        &query_smiles,
+        use_chirality,
+        extra_query,
+        sort_keys.as_ref(),
+        token,
+    )
+    .and_then(|mut hits| {
+        if depict {
+            embed_depictions(&mut hits, false)?;
+        }
+        Ok(hits)
+    });
+
+    if stream {
+        let token = token.clone();
+        let body = ndjson_body(move |emit| {
+            for hit in hits? {
+                if !emit(hit) {
+                    return Ok(());
+                }
+            }
+
+            ndjson_timeout(&token)
+        });
+
+        return GetStructureSearchResponse::Ok(
+            StructureSearchContent::Ndjson(Binary(body)),
+            None,
+            None,
+            None,
+        );
+    }
+
+    match hits {
+        Ok(final_results) => GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(final_results)),
+            Some(token.stopped()),
+            None,
+            Some(token.timed_out()),
+        ),
+        Err(e) => GetStructureSearchResponse::Err(Json(StructureResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
+
+// The standardized query, its pattern fingerprint and descriptors, the scaffolds it contains, and
+// the requested identity level with the query's layer at that level
+pub type IdentityQuery = (
+    ROMol,
+    Fingerprint,
+    HashMap<String, f64>,
+    Option<Vec<i64>>,
+    Option<(String, String)>,
+);
+
+pub fn prepare_identity_query(
+    searcher: &Searcher,
+    query_smiles: &str,
+    use_scaffolds: bool,
+    identity_level: Option<&str>,
+) -> eyre::Result<IdentityQuery> {
+    // Indexes built before identity layers were added would find nothing at any level
+    if identity_level.is_some() && !json_path_indexed(searcher, "other_descriptors", "identity")? {
+        return Err(eyre::eyre!(
+            "Index has no identity layers; re-index it to search by identity level"
+        ));
+    }
+
+    let (query_canon_taut, pattern_fingerprint, descriptors) =
+        prepare_query_structure(query_smiles)?;
 
     let matching_scaffolds = if use_scaffolds {
         scaffold_search(&pattern_fingerprint.0, &query_canon_taut, &PARSED_SCAFFOLDS).ok()
@@ -53,33 +210,106 @@ pub fn v1_index_search_identity(
         None
     };
 
-    let data_results = identity_search(
-        &searcher,
-        &query_canon_taut,
-        &matching_scaffolds,
-        pattern_fingerprint.0.as_bitslice(),
-        &descriptors,
-        use_chirality,
-        extra_query,
-    );
-
-    let final_results = match data_results {
-        Ok(mut data_results) => sort_results(&mut data_results)
-            .into_iter()
-            .map(|(smiles, extra_data)| StructureSearchHit {
-                extra_data,
-                smiles,
-                score: 1.0,
-                query: query_smiles.clone(),
-                used_tautomers: false,
-            })
-            .collect::<Vec<_>>(),
-        Err(e) => {
-            return GetStructureSearchResponse::Err(Json(StructureResponseError {
-                error: e.to_string(),
-            }))
+    // The "full" layer is taken from the query as given, the same way it is at indexing time
+    let identity_layer = match identity_level {
+        Some(level) => {
+            let layer =
+                identity_layer(level, &ROMol::from_smiles(query_smiles)?, &query_canon_taut)?;
+            Some((level.to_string(), layer))
         }
+        None => None,
     };
 
-    GetStructureSearchResponse::Ok(Json(final_results))
+    Ok((
+        query_canon_taut,
+        pattern_fingerprint,
+        descriptors,
+        matching_scaffolds,
+        identity_layer,
+    ))
+}
+
+// With an identity level the stored layers decide the match (use_chirality is then ignored; only
+// connectivity hits are confirmed against the query's skeleton), otherwise the query is confirmed by an exact structure match
+fn search_identity_query<F>(
+    searcher: &Searcher,
+    identity_query: &IdentityQuery,
+    use_chirality: bool,
+    extra_query: &str,
+    token: &CancellationToken,
+    on_results: F,
+) -> eyre::Result<()>
+where
+    F: FnMut(Vec<(String, serde_json::Value, SegmentOrdinal, DocId)>) -> bool,
+{
+    let (query_canon_taut, pattern_fingerprint, descriptors, matching_scaffolds, identity_layer) =
+        identity_query;
+
+    match identity_layer {
+        Some((level, layer)) => identity_layer_search_streaming(
+            searcher,
+            query_canon_taut,
+            level,
+            layer,
+            extra_query,
+            token,
+            on_results,
+        ),
+        None => identity_search_streaming(
+            searcher,
+            query_canon_taut,
+            matching_scaffolds,
+            pattern_fingerprint.0.as_bitslice(),
+            descriptors,
+            use_chirality,
+            extra_query,
+            token,
+            on_results,
+        ),
+    }
+}
+
+pub fn identity_search_hits(
+    searcher: &Searcher,
+    identity_query: &IdentityQuery,
+    query_smiles: &str,
+    use_chirality: bool,
+    extra_query: &str,
+    sort_keys: Option<&SortKeys>,
+    token: &CancellationToken,
+) -> eyre::Result<Vec<StructureSearchHit>> {
+    let mut data_results = Vec::new();
+
+    search_identity_query(
+        searcher,
+        identity_query,
+        use_chirality,
+        extra_query,
+        token,
+        |results_subset| {
+            data_results.extend(results_subset);
+            true
+        },
+    )?;
+
+    data_results.sort_by_key(|result| (result.2, result.3));
+    if let Some(sort_keys) = sort_keys {
+        sort_keys.sort(&mut data_results, |result| {
+            DocAddress::new(result.2, result.3)
+        });
+    }
+
+    Ok(data_results
+        .into_iter()
+        .map(|(smiles, extra_data, _, _)| StructureSearchHit {
+            extra_data,
+            smiles,
+            score: 1.0,
+            query: query_smiles.to_string(),
+            used_tautomers: false,
+            atom_matches: None,
+            svg: None,
+            rgroup_decomposition: None,
+        })
+        .collect::<Vec<_>>())
 }
@@ -0,0 +1,60 @@
+use crate::rest_api::api::{
+    GetStructureSearchResponse, StructureResponseError, StructureSearchContent,
+};
+use crate::search::cancellation::CancellationToken;
+use crate::search::depiction::embed_depictions;
+use crate::search::inchikey_search::inchikey_search;
+use poem_openapi::payload::Json;
+use tantivy::Index;
+
+pub fn v1_index_search_inchikey(
+    index: eyre::Result<Index>,
+    inchikey: &str,
+    connectivity: bool,
+    result_limit: usize,
+    depict: bool,
+    token: &CancellationToken,
+) -> GetStructureSearchResponse {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    let results = inchikey_search(&searcher, inchikey, connectivity, result_limit, token).and_then(
+        |(mut hits, truncated)| {
+            if depict {
+                embed_depictions(&mut hits, false)?;
+            }
+
+            Ok((hits, truncated))
+        },
+    );
+
+    match results {
+        Ok((hits, truncated)) => GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(hits)),
+            Some(truncated || token.stopped()),
+            None,
+            Some(token.timed_out()),
+        ),
+        Err(e) => GetStructureSearchResponse::Err(Json(StructureResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
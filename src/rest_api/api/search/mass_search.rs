@@ -0,0 +1,56 @@
+use crate::rest_api::api::{GetMassSearchResponse, QueryResponseError};
+use crate::search::cancellation::CancellationToken;
+use crate::search::mass_search::mass_search;
+use poem_openapi::payload::Json;
+use tantivy::Index;
+
+#[allow(clippy::too_many_arguments)]
+pub fn v1_index_search_mass(
+    index: eyre::Result<Index>,
+    mz: f64,
+    tolerance: f64,
+    tolerance_unit: &str,
+    charge: i32,
+    adducts: &[String],
+    extra_query: &str,
+    result_limit: usize,
+    token: &CancellationToken,
+) -> GetMassSearchResponse {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetMassSearchResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetMassSearchResponse::Err(Json(QueryResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    match mass_search(
+        &searcher,
+        mz,
+        tolerance,
+        tolerance_unit,
+        charge,
+        adducts,
+        extra_query,
+        result_limit,
+        token,
+    ) {
+        Ok(hits) => GetMassSearchResponse::Ok(Json(hits)),
+        Err(e) => GetMassSearchResponse::Err(Json(QueryResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
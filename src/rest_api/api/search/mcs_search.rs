@@ -0,0 +1,138 @@
+use crate::rest_api::api::{
+    GetStructureSearchResponse, StructureResponseError, StructureSearchContent, StructureSearchPage,
+};
+use crate::search::cancellation::CancellationToken;
+use crate::search::compound_processing::standardize_smiles;
+use crate::search::depiction::embed_depictions;
+use crate::search::mcs::McsParams;
+use crate::search::mcs_search::mcs_search;
+use crate::search::similarity_search::neighbor_search;
+use crate::search::validate_structure;
+use poem_openapi::payload::Json;
+use std::collections::HashSet;
+use tantivy::{Index, Searcher};
+
+#[allow(clippy::too_many_arguments)]
+pub fn v1_index_search_mcs(
+    index: eyre::Result<Index>,
+    query_smiles: String,
+    result_limit: usize,
+    search_percent_limit: f32,
+    extra_query: &str,
+    mcs_params: &McsParams,
+    include_atom_matches: bool,
+    depict: bool,
+    token: &CancellationToken,
+) -> GetStructureSearchResponse {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    let page = mcs_search_page(
+        &searcher,
+        &query_smiles,
+        result_limit,
+        search_percent_limit,
+        extra_query,
+        mcs_params,
+        include_atom_matches,
+        depict,
+        token,
+    );
+
+    match page {
+        Ok((hits, truncated, next_cursor)) => GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(hits)),
+            Some(truncated),
+            next_cursor,
+            Some(token.timed_out()),
+        ),
+        Err(e) => GetStructureSearchResponse::Err(Json(StructureResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
+
+// MCS ranking has no cursor support, so the page never comes with a next cursor
+#[allow(clippy::too_many_arguments)]
+pub fn mcs_search_page(
+    searcher: &Searcher,
+    query_smiles: &str,
+    result_limit: usize,
+    search_percent_limit: f32,
+    extra_query: &str,
+    mcs_params: &McsParams,
+    include_atom_matches: bool,
+    depict: bool,
+    token: &CancellationToken,
+) -> eyre::Result<StructureSearchPage> {
+    let problems_exist = match validate_structure(query_smiles) {
+        Ok(problems) => !problems.is_empty(),
+        Err(_) => true,
+    };
+
+    if problems_exist {
+        return Err(eyre::eyre!("Failed structure validation"));
+    };
+
+    let query_canon_taut = standardize_smiles(query_smiles, false)
+        .map_err(|e| eyre::eyre!("Failed structure standardization: {e}"))?;
+
+    let results = neighbor_search(
+        searcher,
+        &[query_canon_taut.morgan_fingerprint().0],
+        extra_query,
+        search_percent_limit,
+    )
+    .unwrap_or_else(|e| {
+        log::warn!("Encountered a failed search: {e}");
+        HashSet::new()
+    });
+
+    let final_results = mcs_search(
+        searcher,
+        results,
+        &query_canon_taut,
+        query_smiles,
+        mcs_params,
+        token,
+    )?;
+
+    let truncated = final_results.len() > result_limit || token.stopped();
+
+    let mut final_results = final_results
+        .into_iter()
+        .take(result_limit)
+        .map(|(hit, _)| hit)
+        .collect::<Vec<_>>();
+
+    // The atom matches are what highlights the MCS in the depictions
+    if depict {
+        embed_depictions(&mut final_results, true)?;
+    }
+
+    if !include_atom_matches {
+        for hit in final_results.iter_mut() {
+            hit.atom_matches = None;
+        }
+    }
+
+    Ok((final_results, truncated, None))
+}
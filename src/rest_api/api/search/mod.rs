@@ -1,11 +1,47 @@
 mod basic_search;
 pub use basic_search::*;
 
+mod batch_search;
+pub use batch_search::*;
+
+mod cancellation;
+pub use cancellation::*;
+
+mod clustering;
+pub use clustering::*;
+
+mod facets;
+pub use facets::*;
+
+mod federated_search;
+pub use federated_search::*;
+
 mod identity_search;
 pub use identity_search::*;
 
+mod inchikey_search;
+pub use inchikey_search::*;
+
+mod mass_search;
+pub use mass_search::*;
+
+mod mcs_search;
+pub use mcs_search::*;
+
+mod ndjson;
+pub use ndjson::*;
+
+mod result_cache;
+pub use result_cache::*;
+
+mod structure_explain;
+pub use structure_explain::*;
+
 mod structure_search;
 pub use structure_search::*;
 
+mod structured_search;
+pub use structured_search::*;
+
 mod similarity_search;
 pub use similarity_search::*;
@@ -0,0 +1,59 @@
+use crate::search::cancellation::CancellationToken;
+use poem::Body;
+use poem_openapi::types::ToJSON;
+use tokio::io::AsyncWriteExt;
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+pub fn wants_ndjson(accept: Option<&str>) -> bool {
+    match accept {
+        Some(accept) => accept
+            .split(',')
+            .any(|media_type| media_type.trim().starts_with(NDJSON_CONTENT_TYPE)),
+        None => false,
+    }
+}
+
+// Runs the search on the blocking thread pool and writes every hit it emits as one line of JSON.
+// The duplex pipe only buffers a limited number of bytes, so a slow client throttles the search
+// instead of letting hits pile up in memory. The emit callback returns false once the client has
+// gone away, which searches should treat as a signal to stop
+pub fn ndjson_body<T, F>(search: F) -> Body
+where
+    T: ToJSON + 'static,
+    F: FnOnce(&mut dyn FnMut(T) -> bool) -> eyre::Result<()> + Send + 'static,
+{
+    let (mut writer, reader) = tokio::io::duplex(64 * 1024);
+    let handle = tokio::runtime::Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        let mut write_line = |line: serde_json::Value| {
+            let mut line = line.to_string().into_bytes();
+            line.push(b'\n');
+            handle.block_on(writer.write_all(&line)).is_ok()
+        };
+
+        // Hits go through the same ToJSON conversion as the regular JSON array responses
+        let mut emit = |hit: T| write_line(hit.to_json().unwrap_or_default());
+
+        // Headers are long gone by the time a search fails mid-stream, so the error becomes the
+        // last line of the body
+        if let Err(e) = search(&mut emit) {
+            write_line(serde_json::json!({"error": e.to_string()}));
+        }
+    });
+
+    Body::from_async_read(reader)
+}
+
+// Streamed responses have no header left to flag a timeout with, so it is reported as the last
+// line, like any other failure
+pub fn ndjson_timeout(token: &CancellationToken) -> eyre::Result<()> {
+    if token.timed_out() {
+        Err(eyre::eyre!(
+            "Search timed out; the hits above are incomplete"
+        ))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use crate::rest_api::api::{ResultCacheStats, StructureSearchPage};
+use crate::search::pagination::searcher_generation;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tantivy::Searcher;
+
+// (index name, searcher generation, method, canonical query and parameters)
+type ResultCacheKey = (String, u64, String);
+
+// Least recently used pages of search results, shared by every request. Keys carry the generation
+// of the searcher the page came from, so a commit, delete or merge makes every page cached for the
+// index unreachable; the first lookup that sees the new generation drops them. A capacity of 0
+// disables the cache, and then lookups count neither as hits nor as misses
+#[derive(Default)]
+pub struct ResultCache {
+    capacity: usize,
+    entries: Mutex<ResultCacheEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct ResultCacheEntries {
+    pages: HashMap<ResultCacheKey, (u64, StructureSearchPage)>,
+    // Last use of every key, oldest first
+    recency: BTreeMap<u64, ResultCacheKey>,
+    generations: HashMap<String, u64>,
+    tick: u64,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> Self {
+        ResultCache {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(
+        &self,
+        index_name: &str,
+        searcher: &Searcher,
+        query_key: &str,
+    ) -> Option<StructureSearchPage> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        let generation = searcher_generation(searcher);
+        let key = (index_name.to_string(), generation, query_key.to_string());
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.invalidate_stale(index_name, generation);
+
+        let page = entries.touch(&key);
+        match page {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        page
+    }
+
+    pub fn insert(
+        &self,
+        index_name: &str,
+        searcher: &Searcher,
+        query_key: String,
+        page: &StructureSearchPage,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let generation = searcher_generation(searcher);
+        let key = (index_name.to_string(), generation, query_key);
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.invalidate_stale(index_name, generation);
+
+        if entries.touch(&key).is_some() {
+            return;
+        }
+
+        while entries.pages.len() >= self.capacity {
+            match entries.recency.pop_first() {
+                Some((_, oldest_key)) => {
+                    entries.pages.remove(&oldest_key);
+                }
+                None => break,
+            }
+        }
+
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.recency.insert(tick, key.clone());
+        entries.pages.insert(key, (tick, page.clone()));
+    }
+
+    pub fn stats(&self) -> ResultCacheStats {
+        ResultCacheStats {
+            capacity: self.capacity,
+            entries: self.entries.lock().unwrap().pages.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ResultCacheEntries {
+    // Marks the page as just used and returns a copy of it
+    fn touch(&mut self, key: &ResultCacheKey) -> Option<StructureSearchPage> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let (last_used, page) = self.pages.get_mut(key)?;
+        self.recency.remove(last_used);
+        self.recency.insert(tick, key.clone());
+        *last_used = tick;
+
+        Some(page.clone())
+    }
+
+    // Readers move on to newer generations, so pages of any other generation than the one just
+    // seen are dead weight. A request still holding an older searcher only costs the newer pages
+    // their spot, since keys never match across generations
+    fn invalidate_stale(&mut self, index_name: &str, generation: u64) {
+        let previous = self.generations.insert(index_name.to_string(), generation);
+
+        if matches!(previous, Some(previous) if previous != generation) {
+            self.pages.retain(|(name, page_generation, _), _| {
+                name != index_name || *page_generation == generation
+            });
+            self.recency.retain(|_, (name, page_generation, _)| {
+                name != index_name || *page_generation == generation
+            });
+        }
+    }
+}
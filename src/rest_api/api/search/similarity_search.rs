@@ -1,20 +1,44 @@
-use crate::rest_api::api::{GetStructureSearchResponse, StructureResponseError};
-use crate::search::compound_processing::{get_tautomers, standardize_smiles};
-use crate::search::similarity_search::neighbor_search;
-use crate::search::{similarity_search::similarity_search, validate_structure};
+use crate::rest_api::api::{
+    prepare_structure_query, GetStructureSearchResponse, ResultCache, StructureResponseError,
+    StructureSearchContent, StructureSearchPage,
+};
+use crate::search::cancellation::CancellationToken;
+use crate::search::compound_processing::get_tautomers;
+use crate::search::depiction::embed_depictions;
+use crate::search::exact_similarity_search::exact_similarity_search;
+use crate::search::excluded_patterns::{
+    parse_excluded_patterns, retain_unexcluded, ExcludedPattern,
+};
+use crate::search::fingerprints::get_fingerprint;
+use crate::search::pagination::{query_hash, resume_cursor, SearchCursor};
+use crate::search::similarity_search::similarity_search;
+use crate::search::similarity_search::{neighbor_search, SimilarityMetric};
 use poem_openapi::payload::Json;
+use rdkit::ROMol;
 use std::cmp::min;
 use std::collections::HashSet;
-use tantivy::Index;
+use tantivy::{Index, Searcher};
 
+#[allow(clippy::too_many_arguments)]
 pub fn v1_index_search_similarity(
     index: eyre::Result<Index>,
+    index_name: &str,
     query_smiles: String,
     result_limit: usize,
     tautomer_limit: usize,
     search_percent_limit: f32,
-    tanimoto_minimum: f32,
+    similarity_minimum: f32,
     extra_query: &str,
+    exclude_smarts: &[String],
+    cursor: Option<&str>,
+    fingerprint: &str,
+    metric: &str,
+    tversky_alpha: f32,
+    tversky_beta: f32,
+    exact: bool,
+    depict: bool,
+    result_cache: &ResultCache,
+    token: &CancellationToken,
 ) -> GetStructureSearchResponse {
     let index = match index {
         Ok(index) => index,
@@ -37,28 +61,153 @@ pub fn v1_index_search_similarity(
 
     let searcher = reader.searcher();
 
-    let problems_result = validate_structure(&query_smiles);
-
-    let problems_exist = match problems_result {
-        Ok(problems) => !problems.is_empty(),
-        Err(_) => true,
+    let (query_canon_taut, _) = match prepare_structure_query(&query_smiles, "smiles") {
+        Ok(query) => query,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
     };
 
-    if problems_exist {
-        return GetStructureSearchResponse::Err(Json(StructureResponseError {
-            error: "Failed structure validation".to_string(),
-        }));
-    };
+    // Keyed on the standardized query molecule, so different spellings of the same query share
+    // their pages. Hits echo the query as it was given, which is restored on a hit
+    let query_key = serde_json::json!([
+        "similarity",
+        query_canon_taut.as_smiles(),
+        {
+            "result_limit": result_limit,
+            "tautomer_limit": tautomer_limit,
+            "search_percent_limit": search_percent_limit,
+            "similarity_minimum": similarity_minimum,
+            "extra_query": extra_query,
+            "exclude_smarts": exclude_smarts,
+            "cursor": cursor,
+            "fingerprint": fingerprint,
+            "metric": metric,
+            "tversky_alpha": tversky_alpha,
+            "tversky_beta": tversky_beta,
+            "exact": exact,
+            "depict": depict,
+        }
+    ])
+    .to_string();
 
-    let query_canon_taut = match standardize_smiles(&query_smiles, false) {
-        Ok(romol) => romol,
-        Err(e) => {
-            return GetStructureSearchResponse::Err(Json(StructureResponseError {
-                error: format!("Failed structure standardization: {e}"),
-            }));
+    if let Some((mut hits, truncated, next_cursor)) =
+        result_cache.get(index_name, &searcher, &query_key)
+    {
+        for hit in &mut hits {
+            hit.query = query_smiles.clone();
+        }
+
+        return GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(hits)),
+            Some(truncated),
+            next_cursor,
+            Some(false),
+        );
+    }
+
+    let page = parse_excluded_patterns(exclude_smarts)
+        .and_then(|excluded_patterns| {
+            similarity_search_page(
+                &searcher,
+                query_canon_taut,
+                &query_smiles,
+                result_limit,
+                tautomer_limit,
+                search_percent_limit,
+                similarity_minimum,
+                extra_query,
+                &excluded_patterns,
+                cursor,
+                fingerprint,
+                metric,
+                tversky_alpha,
+                tversky_beta,
+                exact,
+                token,
+            )
+        })
+        .and_then(|(mut hits, truncated, next_cursor)| {
+            if depict {
+                embed_depictions(&mut hits, false)?;
+            }
+            Ok((hits, truncated, next_cursor))
+        });
+
+    if let Ok(page) = &page {
+        if !token.stopped() {
+            result_cache.insert(index_name, &searcher, query_key, page);
         }
+    }
+
+    match page {
+        Ok((hits, truncated, next_cursor)) => GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(hits)),
+            Some(truncated),
+            next_cursor,
+            Some(token.timed_out()),
+        ),
+        Err(e) => GetStructureSearchResponse::Err(Json(StructureResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn similarity_search_page(
+    searcher: &Searcher,
+    query_canon_taut: ROMol,
+    query_smiles: &str,
+    result_limit: usize,
+    tautomer_limit: usize,
+    search_percent_limit: f32,
+    similarity_minimum: f32,
+    extra_query: &str,
+    excluded_patterns: &[ExcludedPattern],
+    cursor: Option<&str>,
+    fingerprint: &str,
+    metric: &str,
+    tversky_alpha: f32,
+    tversky_beta: f32,
+    exact: bool,
+    token: &CancellationToken,
+) -> eyre::Result<StructureSearchPage> {
+    // Everything that decides which hits are found and how they rank. Keyed on the standardized
+    // query molecule, so a cursor also resumes another spelling of the same query
+    let query_hash = query_hash(
+        "similarity",
+        &query_canon_taut.as_smiles(),
+        &serde_json::json!({
+            "tautomer_limit": tautomer_limit,
+            "search_percent_limit": search_percent_limit,
+            "similarity_minimum": similarity_minimum,
+            "extra_query": extra_query,
+            "exclude_smarts": excluded_patterns
+                .iter()
+                .map(|pattern| pattern.smarts.as_str())
+                .collect::<Vec<_>>(),
+            "fingerprint": fingerprint,
+            "metric": metric,
+            "tversky_alpha": tversky_alpha,
+            "tversky_beta": tversky_beta,
+            "exact": exact,
+        }),
+    );
+
+    let after = match resume_cursor(cursor, searcher, query_hash)? {
+        Some(SearchCursor {
+            last_doc,
+            last_score: Some(last_score),
+            ..
+        }) => Some((last_score, last_doc)),
+        Some(_) => return Err(eyre::eyre!("Cursor was not issued by a similarity search")),
+        None => None,
     };
 
+    let metric = SimilarityMetric::from_name(metric, tversky_alpha, tversky_beta)?;
+
     let tautomers = if tautomer_limit > 0 {
         let mut tauts = get_tautomers(&query_canon_taut);
         tauts.insert(0, query_canon_taut);
@@ -74,35 +223,100 @@ pub fn v1_index_search_similarity(
         .map(|m| m.morgan_fingerprint().0)
         .collect::<Vec<_>>();
 
-    let results = neighbor_search(
-        &searcher,
-        &taut_morgan_fingerprints,
-        extra_query,
-        search_percent_limit,
-    )
-    .unwrap_or_else(|e| {
-        log::warn!("Encountered a failed search: {e}");
-        HashSet::new()
-    });
-
-    let final_results = match similarity_search(
-        &searcher,
-        results,
-        &taut_morgan_fingerprints,
-        tanimoto_minimum,
-        &query_smiles,
-    ) {
-        Ok(final_results) => final_results,
-        Err(e) => {
-            return GetStructureSearchResponse::Err(Json(StructureResponseError {
-                error: e.to_string(),
-            }))
+    // Candidates always come from the Morgan-based similarity clusters; only the scoring uses
+    // the requested fingerprint
+    let taut_fingerprints = tautomers[..tautomer_limit]
+        .iter()
+        .map(|m| get_fingerprint(m, fingerprint))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let final_results = if exact {
+        // One extra hit tells us whether the results were cut off at result_limit. Excluded hits
+        // leave the page short, so the scan resumes after the last ranked hit until it is full
+        let mut kept = Vec::new();
+        let mut after = after;
+
+        loop {
+            let batch = exact_similarity_search(
+                searcher,
+                &taut_fingerprints,
+                fingerprint,
+                metric,
+                similarity_minimum,
+                extra_query,
+                query_smiles,
+                result_limit.saturating_add(1),
+                after,
+                token,
+            )?;
+
+            let exhausted = batch.len() <= result_limit;
+            after = batch.last().map(|(hit, last_doc)| (hit.score, *last_doc));
+
+            kept.extend(retain_unexcluded(
+                searcher,
+                batch,
+                |(_, doc)| *doc,
+                excluded_patterns,
+                usize::MAX,
+            )?);
+
+            if exhausted || kept.len() > result_limit || token.stopped() {
+                break;
+            }
         }
-    };
 
-    if final_results.len() > result_limit {
-        GetStructureSearchResponse::Ok(Json(final_results[..result_limit].into()))
+        kept
     } else {
-        GetStructureSearchResponse::Ok(Json(final_results))
-    }
+        let results = neighbor_search(
+            searcher,
+            &taut_morgan_fingerprints,
+            extra_query,
+            search_percent_limit,
+        )
+        .unwrap_or_else(|e| {
+            log::warn!("Encountered a failed search: {e}");
+            HashSet::new()
+        });
+
+        let hits = similarity_search(
+            searcher,
+            results,
+            &taut_fingerprints,
+            fingerprint,
+            metric,
+            similarity_minimum,
+            query_smiles,
+            after,
+            token,
+        )?;
+
+        retain_unexcluded(
+            searcher,
+            hits,
+            |(_, doc)| *doc,
+            excluded_patterns,
+            result_limit.saturating_add(1),
+        )?
+    };
+
+    // Candidates skipped after the search was stopped may outrank the hits that were found, so the
+    // ranking is only good for this page
+    let stopped = token.stopped();
+    let truncated = final_results.len() > result_limit || stopped;
+
+    let next_cursor = match final_results[..min(final_results.len(), result_limit)].last() {
+        Some((hit, last_doc)) if truncated && !stopped => {
+            Some(SearchCursor::new(searcher, query_hash, *last_doc, Some(hit.score)).encode())
+        }
+        _ => None,
+    };
+
+    let final_results = final_results
+        .into_iter()
+        .take(result_limit)
+        .map(|(hit, _)| hit)
+        .collect::<Vec<_>>();
+
+    Ok((final_results, truncated, next_cursor))
 }
@@ -0,0 +1,78 @@
+use crate::rest_api::api::{
+    prepare_structure_query, GetStructureSearchExplanationResponse, StructureResponseError,
+};
+use crate::search::cancellation::CancellationToken;
+use crate::search::structure_explain::explain_structure_search;
+use poem_openapi::payload::Json;
+use tantivy::Index;
+
+#[allow(clippy::too_many_arguments)]
+pub fn v1_index_search_explain(
+    index: eyre::Result<Index>,
+    query_smiles: String,
+    query_format: &str,
+    method: &str,
+    use_chirality: bool,
+    result_limit: usize,
+    extra_query: &str,
+    use_scaffolds: bool,
+    token: &CancellationToken,
+) -> GetStructureSearchExplanationResponse {
+    if !matches!(method, "substructure" | "superstructure") {
+        return GetStructureSearchExplanationResponse::Err(Json(StructureResponseError {
+            error: format!(
+                "Cannot explain {method} searches; expected substructure or superstructure"
+            ),
+        }));
+    }
+
+    if method == "superstructure" && query_format != "smiles" {
+        return GetStructureSearchExplanationResponse::Err(Json(StructureResponseError {
+            error: "Superstructure queries must be given as SMILES".to_string(),
+        }));
+    }
+
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetStructureSearchExplanationResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetStructureSearchExplanationResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    let explanation = prepare_structure_query(&query_smiles, query_format).and_then(
+        |(query_mol, generic_query)| {
+            explain_structure_search(
+                &searcher,
+                &query_mol,
+                method,
+                use_scaffolds,
+                result_limit,
+                use_chirality,
+                extra_query,
+                generic_query,
+                token,
+            )
+        },
+    );
+
+    match explanation {
+        Ok(explanation) => GetStructureSearchExplanationResponse::Ok(Json(explanation)),
+        Err(e) => GetStructureSearchExplanationResponse::Err(Json(StructureResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
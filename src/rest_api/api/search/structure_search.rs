@@ -1,24 +1,50 @@
-use crate::rest_api::api::{GetStructureSearchResponse, StructureResponseError};
+use crate::rest_api::api::{
+    ndjson_body, ndjson_timeout, GetStructureSearchResponse, ResultCache, StructureResponseError,
+    StructureSearchContent,
+};
+use crate::search::cancellation::CancellationToken;
 use crate::search::compound_processing::standardize_smiles;
-use crate::search::structure_search::structure_search;
+use crate::search::depiction::depict_hit;
+use crate::search::excluded_patterns::{parse_excluded_patterns, ExcludedPattern};
+use crate::search::pagination::{query_hash, resume_cursor, SearchCursor};
+use crate::search::rgroup_decomposition::RGroupCore;
+use crate::search::sorting::SortKeys;
+use crate::search::structure_matching::get_atom_matches;
+use crate::search::structure_search::{structure_search, structure_search_streaming};
 use crate::search::{
-    compound_processing::get_tautomers, sort_results, validate_structure, StructureSearchHit,
+    compound_processing::get_tautomers, prepare_mol_block_query, prepare_smarts_query,
+    validate_structure, AtomMatch, StructureSearchHit,
 };
-use poem_openapi::payload::Json;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use poem::Body;
+use poem_openapi::payload::{Binary, Json};
+use rayon::iter::{IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rdkit::ROMol;
 use std::cmp::min;
-use tantivy::Index;
+use std::collections::HashSet;
+use tantivy::{DocAddress, Index, Searcher};
 
 #[allow(clippy::too_many_arguments)]
 pub fn v1_index_search_structure(
     index: eyre::Result<Index>,
+    index_name: &str,
     query_smiles: String,
+    query_format: &str,
     use_chirality: bool,
     method: &str,
     result_limit: usize,
     tautomer_limit: usize,
     extra_query: &str,
+    exclude_smarts: &[String],
     use_scaffolds: bool,
+    cursor: Option<&str>,
+    stream: bool,
+    include_atom_matches: bool,
+    depict: bool,
+    rgroup_decomposition: bool,
+    sort_by: Option<&str>,
+    order: &str,
+    result_cache: &ResultCache,
+    token: &CancellationToken,
 ) -> GetStructureSearchResponse {
     let index = match index {
         Ok(index) => index,
@@ -41,40 +67,48 @@ pub fn v1_index_search_structure(
 
     let searcher = reader.searcher();
 
-    let problems_result = validate_structure(&query_smiles);
-
-    let problems_exist = match problems_result {
-        Ok(problems) => !problems.is_empty(),
-        Err(_) => true,
+    // R-group decomposition searches on the core without its attachment points
+    let prepared_query = if rgroup_decomposition {
+        prepare_rgroup_query(&query_smiles, query_format, method)
+            .map(|(core, query_mol)| (query_mol, true, Some(core)))
+    } else {
+        prepare_structure_query(&query_smiles, query_format)
+            .map(|(query_mol, generic_query)| (query_mol, generic_query, None))
     };
 
-    if problems_exist {
-        return GetStructureSearchResponse::Err(Json(StructureResponseError {
-            error: "Failed structure validation".to_string(),
-        }));
+    let (query_canon_taut, generic_query, rgroup_core) = match prepared_query {
+        Ok(query) => query,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }));
+        }
     };
 
-    let query_canon_taut = match standardize_smiles(&query_smiles, false) {
-        Ok(romol) => romol,
+    let excluded_patterns = match parse_excluded_patterns(exclude_smarts) {
+        Ok(excluded_patterns) => excluded_patterns,
         Err(e) => {
             return GetStructureSearchResponse::Err(Json(StructureResponseError {
-                error: format!("Failed structure standardization: {e}"),
+                error: e.to_string(),
             }));
         }
     };
 
-    let results = structure_search(
-        &searcher,
+    let query_hash = structure_query_hash(
         &query_canon_taut,
+        &query_smiles,
         method,
+        tautomer_limit,
+        extra_query,
         use_scaffolds,
-        result_limit,
         use_chirality,
-        extra_query,
+        generic_query,
+        &excluded_patterns,
+        rgroup_core.is_some(),
     );
 
-    let mut results = match results {
-        Ok(results) => results,
+    let after = match resume_cursor(cursor, &searcher, query_hash) {
+        Ok(cursor) => cursor.map(|c| c.last_doc),
         Err(e) => {
             return GetStructureSearchResponse::Err(Json(StructureResponseError {
                 error: e.to_string(),
@@ -82,35 +116,364 @@ pub fn v1_index_search_structure(
         }
     };
 
+    // Cursors resume from a doc address, which says nothing about where a sorted page ended
+    let sort_keys = match sort_by {
+        Some(_) if after.is_some() => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: "cursor cannot be combined with sort_by".to_string(),
+            }))
+        }
+        Some(sort_by) => match SortKeys::new(&searcher, sort_by, order) {
+            Ok(sort_keys) => Some(sort_keys),
+            Err(e) => {
+                return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                    error: e.to_string(),
+                }))
+            }
+        },
+        None => None,
+    };
+
+    if stream {
+        let body = stream_structure_search(
+            searcher,
+            query_canon_taut,
+            query_smiles,
+            method.to_string(),
+            result_limit,
+            tautomer_limit,
+            extra_query.to_string(),
+            use_scaffolds,
+            use_chirality,
+            generic_query,
+            excluded_patterns,
+            after,
+            include_atom_matches,
+            depict,
+            rgroup_core,
+            sort_keys,
+            token.clone(),
+        );
+
+        return GetStructureSearchResponse::Ok(
+            StructureSearchContent::Ndjson(Binary(body)),
+            None,
+            None,
+            None,
+        );
+    }
+
+    // SMILES queries are keyed on the standardized query molecule, so different spellings of the
+    // same query share their pages. Hits echo the query as it was given, which is restored on a hit
+    let canonical_query = if query_format == "smiles" && rgroup_core.is_none() {
+        query_canon_taut.as_smiles()
+    } else {
+        query_smiles.clone()
+    };
+
+    let query_key = serde_json::json!([
+        method,
+        canonical_query,
+        {
+            "query_format": query_format,
+            "use_chirality": use_chirality,
+            "result_limit": result_limit,
+            "tautomer_limit": tautomer_limit,
+            "extra_query": extra_query,
+            "exclude_smarts": exclude_smarts,
+            "use_scaffolds": use_scaffolds,
+            "cursor": cursor,
+            "include_atom_matches": include_atom_matches,
+            "depict": depict,
+            "rgroup_decomposition": rgroup_decomposition,
+            "sort_by": sort_by,
+            "order": order,
+        }
+    ])
+    .to_string();
+
+    if let Some((mut hits, truncated, next_cursor)) =
+        result_cache.get(index_name, &searcher, &query_key)
+    {
+        for hit in &mut hits {
+            hit.query = query_smiles.clone();
+        }
+
+        return GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(hits)),
+            Some(truncated),
+            next_cursor,
+            Some(false),
+        );
+    }
+
+    let page = structure_search_page(
+        &searcher,
+        &query_canon_taut,
+        &query_smiles,
+        method,
+        result_limit,
+        tautomer_limit,
+        extra_query,
+        use_scaffolds,
+        use_chirality,
+        generic_query,
+        &excluded_patterns,
+        cursor,
+        include_atom_matches,
+        depict,
+        rgroup_core.as_ref(),
+        sort_keys.as_ref(),
+        token,
+    );
+
+    // A search that was stopped early returns whatever it found so far, which must not be served
+    // again
+    if let Ok(page) = &page {
+        if !token.stopped() {
+            result_cache.insert(index_name, &searcher, query_key, page);
+        }
+    }
+
+    match page {
+        Ok((hits, truncated, next_cursor)) => GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(hits)),
+            Some(truncated),
+            next_cursor,
+            Some(token.timed_out()),
+        ),
+        Err(e) => GetStructureSearchResponse::Err(Json(StructureResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
+
+// One page of hits, whether the hits were cut off at result_limit, and the cursor for the next page
+pub type StructureSearchPage = (Vec<StructureSearchHit>, bool, Option<String>);
+
+// Everything that decides which hits a structure search finds and in which order, so that a cursor
+// only resumes the query it was issued for. SMILES queries are keyed on the standardized query
+// molecule, so a cursor also resumes another spelling of the same query
+#[allow(clippy::too_many_arguments)]
+pub fn structure_query_hash(
+    query_canon_taut: &ROMol,
+    query_smiles: &str,
+    method: &str,
+    tautomer_limit: usize,
+    extra_query: &str,
+    use_scaffolds: bool,
+    use_chirality: bool,
+    generic_query: bool,
+    excluded_patterns: &[ExcludedPattern],
+    rgroup_decomposition: bool,
+) -> u64 {
+    let query_key = if generic_query {
+        query_smiles.to_string()
+    } else {
+        query_canon_taut.as_smiles()
+    };
+
+    query_hash(
+        method,
+        &query_key,
+        &serde_json::json!({
+            "tautomer_limit": tautomer_limit,
+            "extra_query": extra_query,
+            "use_scaffolds": use_scaffolds,
+            "use_chirality": use_chirality,
+            "generic_query": generic_query,
+            "exclude_smarts": excluded_patterns
+                .iter()
+                .map(|pattern| pattern.smarts.as_str())
+                .collect::<Vec<_>>(),
+            "rgroup_decomposition": rgroup_decomposition,
+        }),
+    )
+}
+
+// Returns the query molecule and whether it is a generic (SMARTS or molblock) query
+pub fn prepare_structure_query(
+    query_smiles: &str,
+    query_format: &str,
+) -> eyre::Result<(ROMol, bool)> {
+    let generic_query = matches!(query_format, "smarts" | "mol_block");
+
+    let query_mol = match query_format {
+        "smarts" => prepare_smarts_query(query_smiles)?,
+        "mol_block" => prepare_mol_block_query(query_smiles)?,
+        _ => {
+            let problems_exist = match validate_structure(query_smiles) {
+                Ok(problems) => !problems.is_empty(),
+                Err(_) => true,
+            };
+
+            if problems_exist {
+                return Err(eyre::eyre!("Failed structure validation"));
+            };
+
+            standardize_smiles(query_smiles, false)
+                .map_err(|e| eyre::eyre!("Failed structure standardization: {e}"))?
+        }
+    };
+
+    Ok((query_mol, generic_query))
+}
+
+// The core (e.g. c1ccc([*:1])cc1[*:2]) and the SMARTS query molecule that finds it
+pub fn prepare_rgroup_query(
+    query_smiles: &str,
+    query_format: &str,
+    method: &str,
+) -> eyre::Result<(RGroupCore, ROMol)> {
+    if method != "substructure" || query_format != "smiles" {
+        return Err(eyre::eyre!(
+            "R-group decomposition needs a substructure query given as SMILES"
+        ));
+    }
+
+    let core = RGroupCore::from_smiles(query_smiles)?;
+    let query_mol = core.search_mol()?;
+
+    Ok((core, query_mol))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn structure_search_page(
+    searcher: &Searcher,
+    query_canon_taut: &ROMol,
+    query_smiles: &str,
+    method: &str,
+    result_limit: usize,
+    tautomer_limit: usize,
+    extra_query: &str,
+    use_scaffolds: bool,
+    use_chirality: bool,
+    generic_query: bool,
+    excluded_patterns: &[ExcludedPattern],
+    cursor: Option<&str>,
+    include_atom_matches: bool,
+    depict: bool,
+    rgroup_core: Option<&RGroupCore>,
+    sort_keys: Option<&SortKeys>,
+    token: &CancellationToken,
+) -> eyre::Result<StructureSearchPage> {
+    let (hits, truncated, next_cursor) = structure_search_docs_page(
+        searcher,
+        query_canon_taut,
+        query_smiles,
+        method,
+        result_limit,
+        tautomer_limit,
+        extra_query,
+        use_scaffolds,
+        use_chirality,
+        generic_query,
+        excluded_patterns,
+        cursor,
+        include_atom_matches,
+        depict,
+        rgroup_core,
+        sort_keys,
+        token,
+    )?;
+
+    Ok((
+        hits.into_iter().map(|(hit, _)| hit).collect(),
+        truncated,
+        next_cursor,
+    ))
+}
+
+// Same as `structure_search_page`, but every hit comes with its doc address
+#[allow(clippy::too_many_arguments)]
+pub fn structure_search_docs_page(
+    searcher: &Searcher,
+    query_canon_taut: &ROMol,
+    query_smiles: &str,
+    method: &str,
+    result_limit: usize,
+    tautomer_limit: usize,
+    extra_query: &str,
+    use_scaffolds: bool,
+    use_chirality: bool,
+    generic_query: bool,
+    excluded_patterns: &[ExcludedPattern],
+    cursor: Option<&str>,
+    include_atom_matches: bool,
+    depict: bool,
+    rgroup_core: Option<&RGroupCore>,
+    sort_keys: Option<&SortKeys>,
+    token: &CancellationToken,
+) -> eyre::Result<(Vec<(StructureSearchHit, DocAddress)>, bool, Option<String>)> {
+    let query_hash = structure_query_hash(
+        query_canon_taut,
+        query_smiles,
+        method,
+        tautomer_limit,
+        extra_query,
+        use_scaffolds,
+        use_chirality,
+        generic_query,
+        excluded_patterns,
+        rgroup_core.is_some(),
+    );
+    let after = resume_cursor(cursor, searcher, query_hash)?.map(|c| c.last_doc);
+
+    let (mut results, mut truncated) = structure_search(
+        searcher,
+        query_canon_taut,
+        method,
+        use_scaffolds,
+        result_limit,
+        use_chirality,
+        extra_query,
+        generic_query,
+        excluded_patterns,
+        after,
+        sort_keys,
+        token,
+    )?;
+
     let mut used_tautomers = false;
     let before_tauts_result_count = results.len();
+    let mut tautomers = Vec::new();
 
-    if before_tauts_result_count < result_limit && tautomer_limit > 0 {
-        let mut tautomers = get_tautomers(&query_canon_taut);
+    // Every page searches the tautomers too, even when the query alone fills it; otherwise a later
+    // page could turn up tautomer hits that sort before the cursor and would never be shown
+    if tautomer_limit > 0 && !generic_query {
+        tautomers = get_tautomers(query_canon_taut);
 
         let tautomer_limit = min(tautomers.len(), tautomer_limit);
+        tautomers.truncate(tautomer_limit);
 
         if !tautomers.is_empty() {
-            let tautomer_results = tautomers[..tautomer_limit]
-                .into_par_iter()
+            let tautomer_results = tautomers
+                .par_iter()
                 .filter_map(|taut| {
                     structure_search(
-                        &searcher,
+                        searcher,
                         taut,
                         method,
                         use_scaffolds,
                         result_limit,
                         use_chirality,
                         extra_query,
+                        false,
+                        excluded_patterns,
+                        after,
+                        sort_keys,
+                        token,
                     )
                     .ok()
                 })
                 .collect::<Vec<_>>();
 
-            for results_set in tautomer_results {
-                if results.len() < result_limit {
-                    results.extend(results_set);
-                }
+            // Each set holds the first result_limit hits of its own query, so the first
+            // result_limit hits of their union are the right ones. A hit found by several queries
+            // is kept once
+            for (results_set, tautomer_truncated) in tautomer_results {
+                results.extend(results_set);
+                truncated |= tautomer_truncated;
             }
 
             if results.len() > before_tauts_result_count {
@@ -119,22 +482,269 @@ pub fn v1_index_search_structure(
         }
     }
 
+    // Ties in a sorted search keep doc address order
     let mut data_results = results.into_iter().collect::<Vec<_>>();
+    data_results.sort_by_key(|result| (result.2, result.3));
+    if let Some(sort_keys) = sort_keys {
+        sort_keys.sort(&mut data_results, |result| {
+            DocAddress::new(result.2, result.3)
+        });
+    }
+
+    // Hits are only kept when RDKit can decompose them, which is decided before the page is cut
+    // so that a later page cannot start with hits that sort before the cursor
+    let mut decompositions = Vec::new();
+    if let Some(rgroup_core) = rgroup_core {
+        let decomposed = data_results
+            .par_iter()
+            .map(|(smiles, ..)| rgroup_core.decompose(smiles).ok())
+            .collect::<Vec<_>>();
+
+        (data_results, decompositions) = data_results
+            .into_iter()
+            .zip(decomposed)
+            .filter_map(|(result, decomposition)| Some((result, Some(decomposition?))))
+            .unzip();
+    }
+
+    if data_results.len() > result_limit {
+        truncated = true;
+    }
+    data_results.truncate(result_limit);
+    decompositions.resize(data_results.len(), None);
 
-    let final_results = sort_results(&mut data_results)
-        .into_iter()
-        .map(|(smiles, extra_data)| StructureSearchHit {
-            extra_data,
-            smiles,
-            score: 1.0,
-            query: query_smiles.clone(),
-            used_tautomers,
-        })
+    let mut final_results = data_results
+        .iter()
+        .zip(decompositions)
+        .map(
+            |((smiles, extra_data, segment_ord, doc_id), rgroup_decomposition)| {
+                (
+                    StructureSearchHit {
+                        extra_data: extra_data.clone(),
+                        smiles: smiles.clone(),
+                        score: 1.0,
+                        query: query_smiles.to_string(),
+                        used_tautomers,
+                        atom_matches: None,
+                        svg: None,
+                        rgroup_decomposition,
+                    },
+                    DocAddress::new(*segment_ord, *doc_id),
+                )
+            },
+        )
         .collect::<Vec<_>>();
 
-    if final_results.len() > result_limit {
-        GetStructureSearchResponse::Ok(Json(final_results[..result_limit].into()))
+    let page_size = final_results.len();
+
+    // data_results lines up with final_results. A search that was stopped early may have skipped
+    // candidates before its last hit, and a sorted one is not in doc address order, so neither can
+    // be resumed
+    let next_cursor = if truncated && page_size > 0 && !token.stopped() && sort_keys.is_none() {
+        let (_, _, segment_ord, doc_id) = &data_results[page_size - 1];
+        let last_doc = DocAddress::new(*segment_ord, *doc_id);
+        Some(SearchCursor::new(searcher, query_hash, last_doc, None).encode())
     } else {
-        GetStructureSearchResponse::Ok(Json(final_results))
+        None
+    };
+
+    if include_atom_matches || depict {
+        let query_mols = std::iter::once(query_canon_taut)
+            .chain(tautomers.iter())
+            .collect::<Vec<_>>();
+
+        final_results.par_iter_mut().try_for_each(|(hit, _)| {
+            annotate_hit(
+                hit,
+                &query_mols,
+                method,
+                use_chirality,
+                include_atom_matches,
+                depict,
+            )
+        })?;
     }
+
+    Ok((final_results, truncated, next_cursor))
+}
+
+// Streams the hits for the query first and then those for its tautomers, skipping documents that
+// were already sent (so sorted hits are only in order within each of those searches). Hits are
+// written as soon as they are confirmed, so used_tautomers can only be set on the hits that were
+// actually found through a tautomer
+#[allow(clippy::too_many_arguments)]
+fn stream_structure_search(
+    searcher: Searcher,
+    query_canon_taut: ROMol,
+    query_smiles: String,
+    method: String,
+    result_limit: usize,
+    tautomer_limit: usize,
+    extra_query: String,
+    use_scaffolds: bool,
+    use_chirality: bool,
+    generic_query: bool,
+    excluded_patterns: Vec<ExcludedPattern>,
+    after: Option<DocAddress>,
+    include_atom_matches: bool,
+    depict: bool,
+    rgroup_core: Option<RGroupCore>,
+    sort_keys: Option<SortKeys>,
+    token: CancellationToken,
+) -> Body {
+    ndjson_body(move |emit| {
+        let tautomers = if tautomer_limit > 0 && !generic_query {
+            get_tautomers(&query_canon_taut)
+        } else {
+            Vec::new()
+        };
+
+        let mut query_mols = vec![(query_canon_taut, generic_query, false)];
+        query_mols.extend(
+            tautomers
+                .into_iter()
+                .take(tautomer_limit)
+                .map(|taut| (taut, false, true)),
+        );
+
+        let mut sent = HashSet::new();
+        let mut failure = None;
+
+        for (query_mol, generic_query, used_tautomers) in query_mols {
+            let mut keep_going = true;
+
+            structure_search_streaming(
+                &searcher,
+                &query_mol,
+                &method,
+                use_scaffolds,
+                use_chirality,
+                &extra_query,
+                generic_query,
+                &excluded_patterns,
+                after,
+                sort_keys.as_ref(),
+                None,
+                &token,
+                |results_subset| {
+                    for (smiles, extra_data, segment_ord, doc_id) in results_subset {
+                        if sent.len() >= result_limit {
+                            break;
+                        }
+
+                        let doc_address = DocAddress::new(segment_ord, doc_id);
+                        if sent.contains(&doc_address) {
+                            continue;
+                        }
+
+                        // Hits RDKit cannot decompose are skipped, as on a page
+                        let rgroup_decomposition = match &rgroup_core {
+                            Some(rgroup_core) => match rgroup_core.decompose(&smiles) {
+                                Ok(decomposition) => Some(decomposition),
+                                Err(_) => continue,
+                            },
+                            None => None,
+                        };
+
+                        sent.insert(doc_address);
+
+                        let mut hit = StructureSearchHit {
+                            extra_data,
+                            smiles,
+                            score: 1.0,
+                            query: query_smiles.clone(),
+                            used_tautomers,
+                            atom_matches: None,
+                            svg: None,
+                            rgroup_decomposition,
+                        };
+
+                        if let Err(e) = annotate_hit(
+                            &mut hit,
+                            &[&query_mol],
+                            &method,
+                            use_chirality,
+                            include_atom_matches,
+                            depict,
+                        ) {
+                            failure = Some(e);
+                            keep_going = false;
+                            return false;
+                        }
+
+                        if !emit(hit) {
+                            keep_going = false;
+                            return false;
+                        }
+                    }
+
+                    if sent.len() >= result_limit {
+                        keep_going = false;
+                    }
+
+                    keep_going
+                },
+            )?;
+
+            if let Some(e) = failure {
+                return Err(e);
+            }
+
+            if !keep_going || token.stopped() {
+                break;
+            }
+        }
+
+        ndjson_timeout(&token)
+    })
+}
+
+// Depictions of substructure hits highlight the matched atoms, so the matches are computed for
+// those even when the caller did not ask for them
+fn annotate_hit(
+    hit: &mut StructureSearchHit,
+    query_mols: &[&ROMol],
+    method: &str,
+    use_chirality: bool,
+    include_atom_matches: bool,
+    depict: bool,
+) -> eyre::Result<()> {
+    let highlight = depict && method == "substructure";
+
+    if include_atom_matches || highlight {
+        hit.atom_matches = Some(find_atom_matches(
+            query_mols,
+            &hit.smiles,
+            method,
+            use_chirality,
+        )?);
+    }
+
+    if depict {
+        hit.svg = Some(depict_hit(hit, highlight)?);
+    }
+
+    if !include_atom_matches {
+        hit.atom_matches = None;
+    }
+
+    Ok(())
+}
+
+// A page mixes hits for the query and for its tautomers without recording which one matched, so
+// the mappings come from the first query molecule that maps onto the hit at all
+fn find_atom_matches(
+    query_mols: &[&ROMol],
+    hit_smiles: &str,
+    method: &str,
+    use_chirality: bool,
+) -> eyre::Result<Vec<AtomMatch>> {
+    for query_mol in query_mols {
+        let atom_matches = get_atom_matches(query_mol, hit_smiles, method, use_chirality)?;
+        if !atom_matches.is_empty() {
+            return Ok(atom_matches);
+        }
+    }
+
+    Ok(Vec::new())
 }
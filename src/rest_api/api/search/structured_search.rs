@@ -0,0 +1,79 @@
+use crate::rest_api::api::{
+    GetStructureSearchResponse, StructureResponseError, StructureSearchContent,
+    StructuredSearchRequest,
+};
+use crate::search::cancellation::CancellationToken;
+use crate::search::depiction::embed_depictions;
+use crate::search::structured_query::{structured_search, QueryNode, QueryPlan};
+use poem_openapi::payload::Json;
+use tantivy::Index;
+
+pub fn v1_index_search_query(
+    index: eyre::Result<Index>,
+    request: StructuredSearchRequest,
+    token: &CancellationToken,
+) -> GetStructureSearchResponse {
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let reader = index.reader();
+    let reader = match reader {
+        Ok(reader) => reader,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: e.to_string(),
+            }))
+        }
+    };
+
+    let searcher = reader.searcher();
+
+    let query = match serde_json::from_value::<QueryNode>(request.query.clone()) {
+        Ok(query) => query,
+        Err(e) => {
+            return GetStructureSearchResponse::Err(Json(StructureResponseError {
+                error: format!("Invalid structured query: {e}"),
+            }))
+        }
+    };
+
+    let results = QueryPlan::new(
+        &searcher,
+        &query,
+        request.use_chirality.unwrap_or(false),
+        request.use_scaffolds.unwrap_or(true),
+    )
+    .and_then(|plan| {
+        structured_search(
+            &searcher,
+            &plan,
+            &request.query.to_string(),
+            request.result_limit.unwrap_or(1000),
+            token,
+        )
+    })
+    .and_then(|(mut hits, truncated)| {
+        if request.depict.unwrap_or(false) {
+            embed_depictions(&mut hits, false)?;
+        }
+        Ok((hits, truncated))
+    });
+
+    match results {
+        Ok((hits, truncated)) => GetStructureSearchResponse::Ok(
+            StructureSearchContent::Json(Json(hits)),
+            Some(truncated),
+            None,
+            Some(token.timed_out()),
+        ),
+        Err(e) => GetStructureSearchResponse::Err(Json(StructureResponseError {
+            error: e.to_string(),
+        })),
+    }
+}
@@ -14,6 +14,10 @@ pub fn command() -> clap::Command {
         Arg::new("index-storage-directory").num_args(1).required(false).short('s').long("index-storage-directory").default_value("/tmp/cheminee")
     ).arg(
         Arg::new("index-storage-directory-create-if-missing").required(false).long("index-storage-directory-create-if-missing").action(ArgAction::SetTrue)
+    ).arg(
+        Arg::new("search-timeout-ms").num_args(1).required(false).long("search-timeout-ms").help("Default timeout for searches that do not pass timeout_ms")
+    ).arg(
+        Arg::new("result-cache-size").num_args(1).required(false).long("result-cache-size").default_value("1000").help("Number of search result pages to keep in memory; 0 disables the cache")
     ).subcommand(
         clap::Command::new("spec").arg(
             clap::Arg::new("output")
@@ -35,12 +39,22 @@ pub async fn action(matches: &clap::ArgMatches) -> eyre::Result<()> {
                 matches.get_one("index-storage-directory").unwrap();
             let index_storage_directory_create_if_missing: bool =
                 matches.get_flag("index-storage-directory-create-if-missing");
+            let search_timeout_ms = match matches.get_one::<String>("search-timeout-ms") {
+                Some(search_timeout_ms) => Some(search_timeout_ms.parse::<u64>()?),
+                None => None,
+            };
+            let result_cache_size = matches
+                .get_one::<String>("result-cache-size")
+                .unwrap()
+                .parse::<usize>()?;
 
             openapi_server::run_api_service(
                 bind,
                 server_url,
                 index_storage_directory.into(),
                 index_storage_directory_create_if_missing,
+                search_timeout_ms,
+                result_cache_size,
             )
             .await?
         }
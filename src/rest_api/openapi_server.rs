@@ -3,13 +3,21 @@ use crate::indexing::index_manager::IndexManager;
 use poem::{listener::TcpListener, EndpointExt, Route, Server};
 use poem_openapi::{ContactObject, OpenApiService};
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use super::api::ApiV1;
+use super::api::{ApiV1, ResultCache};
 
 pub const API_PREFIX: &str = "/api";
 
 pub fn api_service(server_url: &str, api_prefix: &str) -> eyre::Result<OpenApiService<ApiV1, ()>> {
-    let api = ApiV1::default();
+    configured_api_service(ApiV1::default(), server_url, api_prefix)
+}
+
+pub fn configured_api_service(
+    api: ApiV1,
+    server_url: &str,
+    api_prefix: &str,
+) -> eyre::Result<OpenApiService<ApiV1, ()>> {
     let openapi_service = OpenApiService::new(api, "Cheminée", "1.0")
         .server(format!("{}{}", server_url, api_prefix))
         .description("Cheminée: The Chemical Structure Search Engine")
@@ -22,9 +30,15 @@ pub async fn run_api_service(
     server_url: &str,
     index_path: PathBuf,
     create_storage_dir_if_missing: bool,
+    search_timeout_ms: Option<u64>,
+    result_cache_size: usize,
 ) -> eyre::Result<()> {
     let index_manager = IndexManager::new(index_path, create_storage_dir_if_missing)?;
-    let api_service = api_service(server_url, API_PREFIX)?;
+    let api = ApiV1 {
+        search_timeout_ms,
+        result_cache: Arc::new(ResultCache::new(result_cache_size)),
+    };
+    let api_service = configured_api_service(api, server_url, API_PREFIX)?;
     let ui = api_service.swagger_ui();
 
     let spec = api_service.spec();
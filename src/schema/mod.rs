@@ -1,37 +1,103 @@
 use std::collections::HashMap;
 
 use tantivy::schema::{
-    JsonObjectOptions, Schema, SchemaBuilder, FAST, INDEXED, STORED, STRING, TEXT,
+    BytesOptions, JsonObjectOptions, NumericOptions, Schema, SchemaBuilder, FAST, INDEXED, STORED,
+    STRING, TEXT,
 };
 
 use crate::indexing::KNOWN_DESCRIPTORS;
+use crate::search::fingerprints::{fingerprint_field, FINGERPRINTS};
 
 lazy_static::lazy_static! {
-    pub static ref LIBRARY: HashMap<&'static str, Schema> = [("descriptor_v1", descriptor_v1_schema())].into_iter().collect();
+    pub static ref LIBRARY: HashMap<&'static str, Schema> = [
+        ("descriptor_v1", descriptor_v1_schema()),
+        ("descriptor_v2", descriptor_v2_schema()),
+        ("descriptor_v3", descriptor_v3_schema()),
+        ("descriptor_v4", descriptor_v4_schema()),
+    ]
+    .into_iter()
+    .collect();
 }
 
 fn descriptor_v1_schema() -> Schema {
+    descriptor_schema_builder(STORED.into(), false).build()
+}
+
+// Same as descriptor_v1, plus stored bytes for every alternative similarity fingerprint. All
+// similarity fingerprints are also fast fields, which exact similarity search scans
+fn descriptor_v2_schema() -> Schema {
+    descriptor_v2_schema_builder(false).build()
+}
+
+// Same as descriptor_v2, plus the standard InChI and InChIKey. The InChIKey and its first
+// (connectivity) block are indexed as raw strings for exact lookups
+fn descriptor_v3_schema() -> Schema {
+    descriptor_v3_schema_builder(false).build()
+}
+
+// Same as descriptor_v3, but every descriptor and the extra_data keys are also fast fields, which
+// descriptor facets aggregate over
+fn descriptor_v4_schema() -> Schema {
+    descriptor_v3_schema_builder(true).build()
+}
+
+fn descriptor_v3_schema_builder(fast_descriptors: bool) -> SchemaBuilder {
+    let mut builder = descriptor_v2_schema_builder(fast_descriptors);
+    builder.add_text_field("inchi", STRING | STORED);
+    builder.add_text_field("inchikey", STRING | STORED);
+    builder.add_text_field("inchikey_connectivity", STRING);
+
+    builder
+}
+
+fn descriptor_v2_schema_builder(fast_descriptors: bool) -> SchemaBuilder {
+    let similarity_fingerprint_options: BytesOptions = (STORED | FAST).into();
+    let mut builder =
+        descriptor_schema_builder(similarity_fingerprint_options.clone(), fast_descriptors);
+    for fingerprint in FINGERPRINTS {
+        if fingerprint != "morgan" {
+            builder.add_bytes_field(
+                &fingerprint_field(fingerprint),
+                similarity_fingerprint_options.clone(),
+            );
+        }
+    }
+
+    builder
+}
+
+fn descriptor_schema_builder(
+    morgan_fingerprint_options: BytesOptions,
+    fast_descriptors: bool,
+) -> SchemaBuilder {
     let mut builder = SchemaBuilder::new();
     builder.add_text_field("smiles", STRING | STORED);
     for field in KNOWN_DESCRIPTORS {
+        let descriptor_options: NumericOptions = if fast_descriptors || field == "exactmw" {
+            (INDEXED | FAST).into()
+        } else {
+            INDEXED.into()
+        };
+
         if field.starts_with("Num") || field.starts_with("lipinski") {
-            builder.add_i64_field(field, INDEXED);
-        } else if field == "exactmw" {
-            builder.add_f64_field(field, INDEXED | FAST);
+            builder.add_i64_field(field, descriptor_options);
         } else {
-            builder.add_f64_field(field, INDEXED);
+            builder.add_f64_field(field, descriptor_options);
         }
     }
     builder.add_bytes_field("pattern_fingerprint", STORED);
-    builder.add_bytes_field("morgan_fingerprint", STORED);
+    builder.add_bytes_field("morgan_fingerprint", morgan_fingerprint_options);
 
-    let extra_data_options: JsonObjectOptions =
+    let mut extra_data_options: JsonObjectOptions =
         JsonObjectOptions::from(TEXT | STORED).set_expand_dots_enabled();
+    if fast_descriptors {
+        extra_data_options = extra_data_options.set_fast(None);
+    }
     builder.add_json_field("extra_data", extra_data_options);
 
     let other_descriptors_options: JsonObjectOptions =
         JsonObjectOptions::from(TEXT).set_expand_dots_enabled();
     builder.add_json_field("other_descriptors", other_descriptors_options.clone());
 
-    builder.build()
+    builder
 }
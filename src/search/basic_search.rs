@@ -1,7 +1,25 @@
-use crate::search::sort_docs;
+use crate::search::sorting::SortKeys;
+use crate::search::{json_path_indexed, sort_docs};
 use rayon::prelude::*;
 use tantivy::collector::{Count, TopDocs};
-use tantivy::{query::QueryParser, DocAddress, Searcher};
+use tantivy::fastfield::AliveBitSet;
+use tantivy::query::{EnableScoring, Query, Scorer};
+use tantivy::{query::QueryParser, DocAddress, DocSet, Searcher, SegmentOrdinal, TERMINATED};
+
+// Formula clauses expand into element_thresholds terms, which indexes built before formulas were
+// indexed lack. A formula query would then silently match nothing (or, negated, everything), so
+// it is refused instead
+pub fn parse_search_query(searcher: &Searcher, query: &str) -> eyre::Result<Box<dyn Query>> {
+    if query.contains("other_descriptors.element_thresholds")
+        && !json_path_indexed(searcher, "other_descriptors", "element_thresholds")?
+    {
+        return Err(eyre::eyre!(
+            "Index has no element counts; re-index it to search by formula"
+        ));
+    }
+
+    Ok(QueryParser::for_index(searcher.index(), vec![]).parse_query(query)?)
+}
 
 #[allow(clippy::ptr_arg)]
 pub fn basic_search(
@@ -9,9 +27,7 @@ pub fn basic_search(
     query: &String,
     limit: usize,
 ) -> eyre::Result<Vec<DocAddress>> {
-    let index = searcher.index();
-    let query_parser = QueryParser::for_index(index, vec![]);
-    let query = query_parser.parse_query(query)?;
+    let query = parse_search_query(searcher, query)?;
     let results = searcher.search(&query, &TopDocs::with_limit(limit))?;
     let mut final_results = results
         .into_par_iter()
@@ -22,12 +38,98 @@ pub fn basic_search(
     Ok(final_results)
 }
 
+// The first `limit` matches ordered by `sort_by`, rather than the first `limit` in index order.
+// Every match is ranked, but only through its fast field, so no stored document is read here
+pub fn basic_search_sorted(
+    searcher: &Searcher,
+    query: &str,
+    limit: usize,
+    sort_by: &str,
+    order: &str,
+) -> eyre::Result<Vec<DocAddress>> {
+    let sort_keys = SortKeys::new(searcher, sort_by, order)?;
+    let candidates = basic_search_stream(searcher, query)?;
+
+    Ok(sort_keys.top_docs(candidates, limit))
+}
+
 #[allow(clippy::ptr_arg)]
 pub fn basic_search_count(searcher: &Searcher, query: &String) -> eyre::Result<usize> {
-    let index = searcher.index();
-    let query_parser = QueryParser::for_index(index, vec![]);
-    let query = query_parser.parse_query(query)?;
+    let query = parse_search_query(searcher, query)?;
     let result_count = searcher.search(&query, &Count)?;
 
     Ok(result_count)
 }
+
+// Lazily enumerates every live document matching the query, segment by segment and in doc id
+// order (i.e. the same order `sort_docs` produces), so callers are not bound to a TopDocs limit
+pub fn basic_search_stream(
+    searcher: &Searcher,
+    query: &str,
+) -> eyre::Result<impl Iterator<Item = DocAddress>> {
+    basic_search_stream_after(searcher, query, None)
+}
+
+// Same as `basic_search_stream`, but only yields documents that come strictly after `after`
+pub fn basic_search_stream_after(
+    searcher: &Searcher,
+    query: &str,
+    after: Option<DocAddress>,
+) -> eyre::Result<impl Iterator<Item = DocAddress>> {
+    let query = parse_search_query(searcher, query)?;
+    let weight = query.weight(EnableScoring::disabled_from_searcher(searcher))?;
+
+    let mut segment_streams = Vec::with_capacity(searcher.segment_readers().len());
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        let segment_ord = segment_ord as SegmentOrdinal;
+        let mut scorer = weight.scorer(segment_reader, 1.0)?;
+
+        if let Some(after) = after {
+            if segment_ord < after.segment_ord {
+                continue;
+            }
+
+            if segment_ord == after.segment_ord && scorer.doc() <= after.doc_id {
+                scorer.seek(after.doc_id + 1);
+            }
+        }
+
+        segment_streams.push(SegmentDocStream {
+            segment_ord,
+            scorer,
+            alive_bitset: segment_reader.alive_bitset().cloned(),
+        });
+    }
+
+    Ok(segment_streams.into_iter().flatten())
+}
+
+struct SegmentDocStream {
+    segment_ord: SegmentOrdinal,
+    scorer: Box<dyn Scorer>,
+    alive_bitset: Option<AliveBitSet>,
+}
+
+impl Iterator for SegmentDocStream {
+    type Item = DocAddress;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let doc_id = self.scorer.doc();
+            if doc_id == TERMINATED {
+                return None;
+            }
+
+            self.scorer.advance();
+
+            let is_alive = match &self.alive_bitset {
+                Some(alive_bitset) => alive_bitset.is_alive(doc_id),
+                None => true,
+            };
+
+            if is_alive {
+                return Some(DocAddress::new(self.segment_ord, doc_id));
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Stops a search from the outside, either once its deadline has passed or when `cancel` is called
+// (e.g. because the client went away). Searches check it between candidates and return whatever
+// they have confirmed so far. Clones share the same cancellation state
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    // Only set by the deadline, so a client going away or a cancelled parent is not a timeout
+    timed_out: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl CancellationToken {
+    pub fn with_timeout(timeout_ms: Option<u64>) -> Self {
+        CancellationToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            timed_out: Arc::new(AtomicBool::new(false)),
+            deadline: timeout_ms
+                .map(|timeout_ms| Instant::now() + Duration::from_millis(timeout_ms)),
+            parent: None,
+        }
+    }
+
+    // A token with its own deadline that is also cancelled along with this one (but not the
+    // other way around), e.g. for the queries of a batch
+    pub fn child(&self, timeout_ms: Option<u64>) -> Self {
+        CancellationToken {
+            parent: Some(self.cancelled.clone()),
+            ..CancellationToken::with_timeout(timeout_ms)
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // A passed deadline is latched here, so `timed_out` only reports deadlines that a search
+    // actually ran into
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let parent_cancelled = match &self.parent {
+            Some(parent) => parent.load(Ordering::Relaxed),
+            None => false,
+        };
+
+        let deadline_passed = match self.deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        };
+
+        if deadline_passed {
+            self.timed_out.store(true, Ordering::Relaxed);
+        }
+
+        if parent_cancelled || deadline_passed {
+            self.cancel();
+        }
+
+        parent_cancelled || deadline_passed
+    }
+
+    // Whether a search checking this token was stopped early, for whatever reason. Its results are
+    // then incomplete, so they are neither cached nor resumed with a cursor
+    pub fn stopped(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Whether a search checking this token ran into its own deadline
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed)
+    }
+
+    // Cancels the token when the returned guard goes out of scope
+    pub fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop(self.clone())
+    }
+}
+
+pub struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
@@ -0,0 +1,318 @@
+use crate::search::basic_search::basic_search_stream;
+use crate::search::cancellation::CancellationToken;
+use crate::search::similarity_search::SimilarityMetric;
+use poem_openapi::Object;
+use rayon::prelude::*;
+use tantivy::schema::OwnedValue;
+use tantivy::{DocAddress, Searcher};
+
+// Butina clusters of the documents matching a basic search query
+#[derive(Object, Debug)]
+pub struct Clustering {
+    /// Number of compounds that were clustered
+    pub compound_count: usize,
+    /// Whether more compounds matched the query than compound_limit allows
+    pub truncated: bool,
+    /// Largest clusters first
+    pub clusters: Vec<Cluster>,
+}
+
+#[derive(Object, Debug)]
+pub struct Cluster {
+    pub id: usize,
+    pub centroid: ClusteredCompound,
+    /// Every compound of the cluster, centroid first
+    pub members: Vec<ClusteredCompound>,
+}
+
+#[derive(Object, Debug, Clone)]
+pub struct ClusteredCompound {
+    pub smiles: String,
+    pub extra_data: serde_json::Value,
+    /// Tanimoto similarity to the centroid of the cluster
+    pub similarity: f32,
+}
+
+// MaxMin picks out of the documents matching a basic search query
+#[derive(Object, Debug)]
+pub struct DiversityPick {
+    /// Number of compounds that were picked from
+    pub compound_count: usize,
+    /// Whether more compounds matched the query than compound_limit allows
+    pub truncated: bool,
+    /// In the order they were picked
+    pub picks: Vec<PickedCompound>,
+}
+
+#[derive(Object, Debug)]
+pub struct PickedCompound {
+    pub smiles: String,
+    pub extra_data: serde_json::Value,
+    /// Tanimoto distance to the closest compound picked before it; not set for the first pick
+    #[oai(skip_serializing_if_is_none)]
+    pub min_distance: Option<f32>,
+}
+
+struct FingerprintedCompound {
+    smiles: String,
+    extra_data: serde_json::Value,
+    fingerprint: Vec<u8>,
+}
+
+// Clusters the stored Morgan fingerprints of the first `compound_limit` matches of `query`.
+// Every pair is compared, so the cost grows with the square of the number of compounds
+pub fn cluster_index(
+    searcher: &Searcher,
+    query: &str,
+    distance_threshold: f32,
+    compound_limit: usize,
+    token: &CancellationToken,
+) -> eyre::Result<Clustering> {
+    if !(0.0..=1.0).contains(&distance_threshold) {
+        return Err(eyre::eyre!("distance_threshold must be between 0 and 1"));
+    }
+
+    let (compounds, truncated) = load_fingerprints(searcher, query, compound_limit)?;
+    let fingerprints = compounds
+        .iter()
+        .map(|compound| compound.fingerprint.as_slice())
+        .collect::<Vec<_>>();
+
+    let clusters = butina_cluster(&fingerprints, distance_threshold, token)?
+        .into_iter()
+        .enumerate()
+        .map(|(id, members)| {
+            let centroid = &compounds[members[0]];
+            let members = members
+                .iter()
+                .map(|member| {
+                    let compound = &compounds[*member];
+                    ClusteredCompound {
+                        smiles: compound.smiles.clone(),
+                        extra_data: compound.extra_data.clone(),
+                        similarity: tanimoto(&centroid.fingerprint, &compound.fingerprint),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            Cluster {
+                id,
+                centroid: members[0].clone(),
+                members,
+            }
+        })
+        .collect();
+
+    Ok(Clustering {
+        compound_count: compounds.len(),
+        truncated,
+        clusters,
+    })
+}
+
+// Picks `count` compounds out of the first `compound_limit` matches of `query`, each as far as
+// possible from the ones picked before it
+pub fn diversity_pick(
+    searcher: &Searcher,
+    query: &str,
+    count: usize,
+    compound_limit: usize,
+    token: &CancellationToken,
+) -> eyre::Result<DiversityPick> {
+    let (compounds, truncated) = load_fingerprints(searcher, query, compound_limit)?;
+    let fingerprints = compounds
+        .iter()
+        .map(|compound| compound.fingerprint.as_slice())
+        .collect::<Vec<_>>();
+
+    let picks = maxmin_pick(&fingerprints, count, token)?
+        .into_iter()
+        .map(|(pick, min_distance)| {
+            let compound = &compounds[pick];
+            PickedCompound {
+                smiles: compound.smiles.clone(),
+                extra_data: compound.extra_data.clone(),
+                min_distance,
+            }
+        })
+        .collect();
+
+    Ok(DiversityPick {
+        compound_count: compounds.len(),
+        truncated,
+        picks,
+    })
+}
+
+// Taylor-Butina: every compound's neighbors are the compounds within `distance_threshold` of it
+// (itself included). Compounds are visited by descending neighbor count, and every one not yet
+// clustered becomes a centroid that takes its unclustered neighbors into its cluster. Ties go to
+// the compound that comes first, so the clustering is deterministic. Returns the clusters as
+// indices into `fingerprints`, centroid first, largest clusters first
+pub fn butina_cluster(
+    fingerprints: &[&[u8]],
+    distance_threshold: f32,
+    token: &CancellationToken,
+) -> eyre::Result<Vec<Vec<usize>>> {
+    let neighbors = fingerprints
+        .par_iter()
+        .enumerate()
+        .map(|(i, fingerprint)| {
+            if token.is_cancelled() {
+                return Err(eyre::eyre!("Clustering was cancelled"));
+            }
+
+            Ok(fingerprints
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| {
+                    i == *j || 1.0 - tanimoto(fingerprint, other) <= distance_threshold
+                })
+                .map(|(j, _)| j)
+                .collect::<Vec<_>>())
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let mut order = (0..fingerprints.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| std::cmp::Reverse(neighbors[*i].len()));
+
+    let mut clustered = vec![false; fingerprints.len()];
+    let mut clusters = Vec::new();
+
+    for centroid in order {
+        if clustered[centroid] {
+            continue;
+        }
+
+        let mut cluster = vec![centroid];
+        clustered[centroid] = true;
+
+        for neighbor in &neighbors[centroid] {
+            if !clustered[*neighbor] {
+                clustered[*neighbor] = true;
+                cluster.push(*neighbor);
+            }
+        }
+
+        clusters.push(cluster);
+    }
+
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.len()));
+
+    Ok(clusters)
+}
+
+// MaxMin: starting from the first compound, repeatedly picks the compound whose distance to its
+// closest pick so far is the largest (ties go to the compound that comes first). Only the
+// distances to the latest pick have to be computed in every round. Returns indices into
+// `fingerprints` together with that distance at the time of picking
+pub fn maxmin_pick(
+    fingerprints: &[&[u8]],
+    count: usize,
+    token: &CancellationToken,
+) -> eyre::Result<Vec<(usize, Option<f32>)>> {
+    let count = count.min(fingerprints.len());
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut picks = vec![(0, None)];
+    let mut picked = vec![false; fingerprints.len()];
+    picked[0] = true;
+
+    let mut min_distances = vec![f32::MAX; fingerprints.len()];
+    let mut latest = 0;
+
+    while picks.len() < count {
+        if token.is_cancelled() {
+            return Err(eyre::eyre!("Diversity picking was cancelled"));
+        }
+
+        let latest_fingerprint = fingerprints[latest];
+        min_distances
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, min_distance)| {
+                let distance = 1.0 - tanimoto(latest_fingerprint, fingerprints[i]);
+                *min_distance = min_distance.min(distance);
+            });
+
+        let mut next: Option<usize> = None;
+        for (i, min_distance) in min_distances.iter().enumerate() {
+            if picked[i] {
+                continue;
+            }
+
+            match next {
+                Some(best) if min_distances[best] >= *min_distance => {}
+                _ => next = Some(i),
+            }
+        }
+
+        latest = match next {
+            Some(next) => next,
+            None => break,
+        };
+
+        picked[latest] = true;
+        picks.push((latest, Some(min_distances[latest])));
+    }
+
+    Ok(picks)
+}
+
+fn tanimoto(fp1: &[u8], fp2: &[u8]) -> f32 {
+    SimilarityMetric::Tanimoto.similarity_from_bytes(fp1, fp2)
+}
+
+// The stored morgan_fingerprint (and smiles and extra_data) of the first `compound_limit`
+// documents matching `query`, and whether there were more
+fn load_fingerprints(
+    searcher: &Searcher,
+    query: &str,
+    compound_limit: usize,
+) -> eyre::Result<(Vec<FingerprintedCompound>, bool)> {
+    let schema = searcher.schema();
+    let smiles_field = schema.get_field("smiles")?;
+    let extra_data_field = schema.get_field("extra_data")?;
+    let fingerprint_field = schema.get_field("morgan_fingerprint")?;
+
+    let mut docs = basic_search_stream(searcher, query)?
+        .take(compound_limit + 1)
+        .collect::<Vec<DocAddress>>();
+
+    let truncated = docs.len() > compound_limit;
+    docs.truncate(compound_limit);
+
+    let compounds = docs
+        .into_par_iter()
+        .map(|docaddr| {
+            let doc = searcher.doc::<tantivy::TantivyDocument>(docaddr)?;
+
+            let smiles = match doc.get_first(smiles_field) {
+                Some(OwnedValue::Str(s)) => s.clone(),
+                other => return Err(eyre::eyre!("expected string, got {:?}", other)),
+            };
+
+            let fingerprint = match doc.get_first(fingerprint_field) {
+                Some(OwnedValue::Bytes(f)) => f.clone(),
+                other => return Err(eyre::eyre!("could not fetch fingerprint, got {:?}", other)),
+            };
+
+            let extra_data = match doc.get_first(extra_data_field) {
+                Some(OwnedValue::Object(obj_map)) => {
+                    serde_json::from_str(&serde_json::to_string(&obj_map)?)?
+                }
+                Some(_) | None => serde_json::Value::Object(Default::default()),
+            };
+
+            Ok(FingerprintedCompound {
+                smiles,
+                extra_data,
+                fingerprint,
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    Ok((compounds, truncated))
+}
@@ -0,0 +1,76 @@
+use crate::rdkit_bridge::mol_draw_ffi;
+use crate::search::{rdkit_sys_mol_in_order, AtomMatch, StructureSearchHit};
+use rayon::prelude::*;
+use rdkit::ROMol;
+use rdkit_sys::mol_ops_ffi;
+
+pub const DEFAULT_DEPICTION_SIZE: u32 = 300;
+
+// Renders the molecule as a standalone SVG document with RDKit's MolDraw2DSVG. Every atom and bond
+// of the given matches (indices into `mol`) is highlighted. Explicit hydrogens are appended after
+// the existing atoms, so match indices stay valid either way
+pub fn depict_svg(
+    mol: &ROMol,
+    width: u32,
+    height: u32,
+    explicit_hydrogens: bool,
+    highlights: &[AtomMatch],
+) -> eyre::Result<String> {
+    if width == 0 || height == 0 {
+        return Err(eyre::eyre!("Depiction width and height must be positive"));
+    }
+
+    let mut rdkit_mol = rdkit_sys_mol_in_order(mol, true)?;
+    if explicit_hydrogens {
+        rdkit_mol = mol_ops_ffi::add_hs(&rdkit_mol, false, true, false);
+    }
+
+    let mut highlight_atoms = highlights
+        .iter()
+        .flat_map(|m| m.atoms.iter().map(|idx| *idx as i32))
+        .collect::<Vec<_>>();
+    highlight_atoms.sort();
+    highlight_atoms.dedup();
+
+    let mut highlight_bonds = highlights
+        .iter()
+        .flat_map(|m| m.bonds.iter().map(|idx| *idx as i32))
+        .collect::<Vec<_>>();
+    highlight_bonds.sort();
+    highlight_bonds.dedup();
+
+    mol_draw_ffi::draw_svg(
+        &rdkit_mol,
+        width,
+        height,
+        &highlight_atoms,
+        &highlight_bonds,
+    )
+    .map_err(|e| eyre::eyre!("{e}"))
+}
+
+// Adds an SVG of its SMILES to every hit. With `highlight`, the hit's atom matches (which must
+// refer to the hit itself, as they do for substructure searches) are highlighted
+pub fn embed_depictions(hits: &mut [StructureSearchHit], highlight: bool) -> eyre::Result<()> {
+    hits.par_iter_mut().try_for_each(|hit| {
+        hit.svg = Some(depict_hit(hit, highlight)?);
+        Ok(())
+    })
+}
+
+pub fn depict_hit(hit: &StructureSearchHit, highlight: bool) -> eyre::Result<String> {
+    let mol = ROMol::from_smiles(&hit.smiles)?;
+
+    let highlights = match &hit.atom_matches {
+        Some(atom_matches) if highlight => atom_matches.as_slice(),
+        _ => &[],
+    };
+
+    depict_svg(
+        &mol,
+        DEFAULT_DEPICTION_SIZE,
+        DEFAULT_DEPICTION_SIZE,
+        false,
+        highlights,
+    )
+}
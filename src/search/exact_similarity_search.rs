@@ -0,0 +1,230 @@
+use crate::search::basic_search::parse_search_query;
+use crate::search::cancellation::CancellationToken;
+use crate::search::fingerprints::fingerprint_field;
+use crate::search::similarity_search::SimilarityMetric;
+use crate::search::{get_smiles_and_extra_data, StructureSearchHit};
+use bitvec::prelude::BitVec;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use tantivy::query::{AllQuery, EnableScoring, Query, Weight};
+use tantivy::{DocAddress, DocSet, Searcher, SegmentOrdinal, SegmentReader, TERMINATED};
+
+// Unlike `neighbor_search`, which only looks inside the similarity clusters predicted for the
+// query, this scans the fingerprint fast field of every document matching `extra_query`. The
+// result is the exact top `limit` (or everything above `similarity_minimum`, whichever is
+// fewer), at the cost of touching every fingerprint in the index. Stored documents are only read
+// for the hits that make the cut. Once `token` is cancelled the scan stops and ranks what it has
+// seen so far
+#[allow(clippy::too_many_arguments)]
+pub fn exact_similarity_search(
+    searcher: &Searcher,
+    taut_fingerprints: &[BitVec<u8>],
+    fingerprint: &str,
+    metric: SimilarityMetric,
+    similarity_minimum: f32,
+    extra_query: &str,
+    query_smiles: &str,
+    limit: usize,
+    after: Option<(f32, DocAddress)>,
+    token: &CancellationToken,
+) -> eyre::Result<Vec<(StructureSearchHit, DocAddress)>> {
+    let schema = searcher.schema();
+    let smiles_field = schema.get_field("smiles")?;
+    let extra_data_field = schema.get_field("extra_data")?;
+
+    let field_name = fingerprint_field(fingerprint);
+    let is_fast = schema
+        .get_field(&field_name)
+        .map(|field| schema.get_field_entry(field).is_fast())
+        .unwrap_or(false);
+
+    if !is_fast {
+        return Err(eyre::eyre!(
+            "Index schema does not store {fingerprint} fingerprints as a fast field; exact similarity search requires a descriptor_v2 index"
+        ));
+    }
+
+    let query: Box<dyn Query> = if extra_query.is_empty() {
+        Box::new(AllQuery)
+    } else {
+        parse_search_query(searcher, extra_query)?
+    };
+
+    let weight = query.weight(EnableScoring::disabled_from_searcher(searcher))?;
+
+    let taut_fingerprints = taut_fingerprints
+        .iter()
+        .map(|fp| fp.as_raw_slice())
+        .collect::<Vec<_>>();
+
+    let scan = ExactScan {
+        taut_fingerprints: &taut_fingerprints,
+        field_name: &field_name,
+        metric,
+        similarity_minimum,
+        limit,
+        after,
+        token,
+    };
+
+    let segment_heaps = searcher
+        .segment_readers()
+        .par_iter()
+        .enumerate()
+        .map(|(segment_ord, segment_reader)| {
+            scan.scan_segment(
+                weight.as_ref(),
+                segment_ord as SegmentOrdinal,
+                segment_reader,
+            )
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let mut top_docs = BinaryHeap::with_capacity(limit.min(1024));
+    for ranked_doc in segment_heaps.into_iter().flatten() {
+        push_bounded(&mut top_docs, ranked_doc, limit);
+    }
+
+    let used_tautomers = taut_fingerprints.len() > 1;
+
+    top_docs
+        .into_sorted_vec()
+        .into_iter()
+        .map(|RankedDoc { score, doc }| {
+            let (smiles, extra_data) =
+                get_smiles_and_extra_data(doc, searcher, smiles_field, extra_data_field)?;
+
+            Ok((
+                StructureSearchHit {
+                    extra_data,
+                    smiles,
+                    score,
+                    query: query_smiles.into(),
+                    used_tautomers,
+                    atom_matches: None,
+                    svg: None,
+                    rgroup_decomposition: None,
+                },
+                doc,
+            ))
+        })
+        .collect()
+}
+
+struct ExactScan<'a> {
+    taut_fingerprints: &'a [&'a [u8]],
+    field_name: &'a str,
+    metric: SimilarityMetric,
+    similarity_minimum: f32,
+    limit: usize,
+    after: Option<(f32, DocAddress)>,
+    token: &'a CancellationToken,
+}
+
+impl ExactScan<'_> {
+    fn scan_segment(
+        &self,
+        weight: &dyn Weight,
+        segment_ord: SegmentOrdinal,
+        segment_reader: &SegmentReader,
+    ) -> eyre::Result<BinaryHeap<RankedDoc>> {
+        let mut top_docs = BinaryHeap::with_capacity(self.limit.min(1024));
+
+        let column = match segment_reader.fast_fields().bytes(self.field_name)? {
+            Some(column) => column,
+            None => return Ok(top_docs),
+        };
+
+        // Bytes columns are dictionary encoded, so every distinct fingerprint in the segment is
+        // scored exactly once and documents only carry an ordinal into these scores
+        let mut term_scores = Vec::with_capacity(column.num_terms());
+        let mut terms = column.dictionary().stream()?;
+        while terms.advance() {
+            let score = self
+                .taut_fingerprints
+                .iter()
+                .map(|fp| self.metric.similarity_from_bytes(fp, terms.key()))
+                .fold(f32::MIN, |max, x| x.max(max));
+            term_scores.push(score);
+        }
+
+        let mut scorer = weight.scorer(segment_reader, 1.0)?;
+        let alive_bitset = segment_reader.alive_bitset();
+        let mut scanned = 0usize;
+
+        while scorer.doc() != TERMINATED {
+            let doc_id = scorer.doc();
+            scorer.advance();
+
+            // Reading the clock for every document would cost more than scoring it
+            scanned += 1;
+            if scanned % 4096 == 0 && self.token.is_cancelled() {
+                break;
+            }
+
+            if let Some(alive_bitset) = alive_bitset {
+                if !alive_bitset.is_alive(doc_id) {
+                    continue;
+                }
+            }
+
+            let score = match column.term_ords(doc_id).next() {
+                Some(term_ord) => term_scores[term_ord as usize],
+                None => continue,
+            };
+
+            if score < self.similarity_minimum {
+                continue;
+            }
+
+            let doc = DocAddress::new(segment_ord, doc_id);
+            if let Some((last_score, last_doc)) = self.after {
+                if !(score < last_score || (score == last_score && doc > last_doc)) {
+                    continue;
+                }
+            }
+
+            push_bounded(&mut top_docs, RankedDoc { score, doc }, self.limit);
+        }
+
+        Ok(top_docs)
+    }
+}
+
+// Hits rank by descending score with ties broken on doc address, the same total order
+// `similarity_search` uses. A RankedDoc compares greater when it ranks worse, so the top of the
+// (max-)heap is always the weakest hit kept so far
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RankedDoc {
+    score: f32,
+    doc: DocAddress,
+}
+
+impl Eq for RankedDoc {}
+
+impl Ord for RankedDoc {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| self.doc.cmp(&other.doc))
+    }
+}
+
+impl PartialOrd for RankedDoc {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn push_bounded(top_docs: &mut BinaryHeap<RankedDoc>, ranked_doc: RankedDoc, limit: usize) {
+    if top_docs.len() < limit {
+        top_docs.push(ranked_doc);
+    } else if let Some(weakest) = top_docs.peek() {
+        if ranked_doc < *weakest {
+            top_docs.pop();
+            top_docs.push(ranked_doc);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::search::prepare_smarts_query;
+use crate::search::structure_matching::substructure_match_fp;
+use bitvec::prelude::{BitSlice, BitVec, Lsb0};
+use rayon::prelude::*;
+use rdkit::{substruct_match, ROMol, SubstructMatchParameters};
+use std::sync::{Arc, Mutex};
+use tantivy::schema::OwnedValue;
+use tantivy::{DocAddress, Searcher};
+
+// A substructure that disqualifies any hit containing it
+pub struct ExcludedPattern {
+    pub smarts: String,
+    pub mol: Arc<Mutex<ROMol>>,
+    pub fp: BitVec<u8>,
+}
+
+pub fn parse_excluded_patterns(exclude_smarts: &[String]) -> eyre::Result<Vec<ExcludedPattern>> {
+    exclude_smarts
+        .iter()
+        .map(|smarts| {
+            let mol = prepare_smarts_query(smarts)
+                .map_err(|e| eyre::eyre!("Invalid exclude_smarts {smarts}: {e}"))?;
+
+            Ok(ExcludedPattern {
+                smarts: smarts.clone(),
+                fp: mol.pattern_fingerprint().0,
+                mol: Arc::new(Mutex::new(mol)),
+            })
+        })
+        .collect()
+}
+
+// The pattern fingerprint screen can only prove that a pattern is absent (the fingerprints of
+// SMARTS leave out their query features, so they never set a bit the match would not), so the
+// molecule is only matched against the patterns that pass it. `mol` is only parsed when one does
+pub fn matches_excluded_pattern(
+    mol: impl FnOnce() -> eyre::Result<ROMol>,
+    pattern_fingerprint: &BitSlice<u8>,
+    excluded_patterns: &[ExcludedPattern],
+) -> eyre::Result<bool> {
+    let candidates = excluded_patterns
+        .iter()
+        .filter(|pattern| substructure_match_fp(pattern.fp.as_bitslice(), pattern_fingerprint))
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        return Ok(false);
+    }
+
+    let mol = mol()?;
+    let params = SubstructMatchParameters::default();
+
+    Ok(candidates
+        .into_iter()
+        .any(|pattern| !substruct_match(&mol, &pattern.mol.lock().unwrap(), &params).is_empty()))
+}
+
+// Same as `matches_excluded_pattern`, for an indexed document through its stored SMILES and
+// pattern fingerprint
+pub fn doc_matches_excluded_pattern(
+    searcher: &Searcher,
+    docaddr: DocAddress,
+    excluded_patterns: &[ExcludedPattern],
+) -> eyre::Result<bool> {
+    let schema = searcher.schema();
+    let smiles_field = schema.get_field("smiles")?;
+    let pattern_fingerprint_field = schema.get_field("pattern_fingerprint")?;
+
+    let doc = searcher.doc::<tantivy::TantivyDocument>(docaddr)?;
+
+    let pattern_fingerprint = match doc.get_first(pattern_fingerprint_field) {
+        Some(OwnedValue::Bytes(b)) => b,
+        other => return Err(eyre::eyre!("expected bytes, got {:?}", other)),
+    };
+
+    let smiles = match doc.get_first(smiles_field) {
+        Some(OwnedValue::Str(s)) => s,
+        other => return Err(eyre::eyre!("expected string, got {:?}", other)),
+    };
+
+    matches_excluded_pattern(
+        || Ok(ROMol::from_smiles(smiles)?),
+        BitSlice::<u8, Lsb0>::from_slice(pattern_fingerprint),
+        excluded_patterns,
+    )
+}
+
+// The first `keep` items (in their order) whose documents match none of the excluded patterns.
+// Items are drawn and checked a chunk at a time, so ranked hits (or streamed candidates) past the
+// ones that are kept are never matched, or even produced
+pub fn retain_unexcluded<T: Send>(
+    searcher: &Searcher,
+    items: impl IntoIterator<Item = T>,
+    docaddr: impl Fn(&T) -> DocAddress + Sync,
+    excluded_patterns: &[ExcludedPattern],
+    keep: usize,
+) -> eyre::Result<Vec<T>> {
+    if excluded_patterns.is_empty() {
+        return Ok(items.into_iter().take(keep).collect());
+    }
+
+    let mut kept = Vec::new();
+    let mut items = items.into_iter().peekable();
+
+    while kept.len() < keep && items.peek().is_some() {
+        let chunk = items
+            .by_ref()
+            .take((keep - kept.len()).clamp(100, 1000))
+            .collect::<Vec<_>>();
+
+        let unexcluded = chunk
+            .into_par_iter()
+            .map(|item| {
+                let excluded =
+                    doc_matches_excluded_pattern(searcher, docaddr(&item), excluded_patterns)?;
+                Ok((!excluded).then_some(item))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        kept.extend(unexcluded.into_iter().flatten());
+    }
+
+    kept.truncate(keep);
+
+    Ok(kept)
+}
@@ -0,0 +1,279 @@
+use crate::indexing::KNOWN_DESCRIPTORS;
+use crate::search::basic_search::parse_search_query;
+use crate::search::cancellation::CancellationToken;
+use poem_openapi::Object;
+use tantivy::aggregation::agg_req::Aggregations;
+use tantivy::aggregation::agg_result::{
+    AggregationResult, AggregationResults, BucketEntries, BucketResult, MetricResult,
+};
+use tantivy::aggregation::{AggregationCollector, AggregationLimits, Key};
+use tantivy::collector::Count;
+use tantivy::query::Query;
+use tantivy::Searcher;
+
+// Aggregations over every document matching a basic search query
+#[derive(Object, Debug)]
+pub struct DescriptorFacets {
+    /// Number of documents matching the query
+    pub count: usize,
+    /// Counts per value of the integer descriptors and extra_data keys, most frequent first
+    pub terms: Vec<TermsFacet>,
+    /// Binned counts of the float descriptors
+    pub histograms: Vec<HistogramFacet>,
+}
+
+#[derive(Object, Debug)]
+pub struct TermsFacet {
+    /// The descriptor, or extra_data.{key} for extra_data keys
+    pub field: String,
+    pub buckets: Vec<FacetBucket>,
+    /// Matching documents whose value did not make it into the buckets
+    pub other_count: u64,
+}
+
+#[derive(Object, Debug)]
+pub struct FacetBucket {
+    pub key: String,
+    pub count: u64,
+}
+
+#[derive(Object, Debug)]
+pub struct HistogramFacet {
+    pub field: String,
+    /// Not set when no matching document has a value for the descriptor
+    #[oai(skip_serializing_if_is_none)]
+    pub min: Option<f64>,
+    #[oai(skip_serializing_if_is_none)]
+    pub max: Option<f64>,
+    #[oai(skip_serializing_if_is_none)]
+    pub mean: Option<f64>,
+    /// Evenly sized bins from min to max; the last one includes max
+    pub buckets: Vec<HistogramBucket>,
+}
+
+#[derive(Object, Debug)]
+pub struct HistogramBucket {
+    pub from: f64,
+    pub to: f64,
+    pub count: u64,
+}
+
+// Integer descriptors (and extra_data keys) are counted per value, float descriptors are binned
+// between their min and max. The bins can only be laid out once min and max are known, so float
+// descriptors take a second pass over the matching documents. Every descriptor has to be a fast
+// field, which only the descriptor_v4 schema provides for all of them. A pass cannot be
+// interrupted, so cancellation is only noticed between the two
+pub fn descriptor_facets(
+    searcher: &Searcher,
+    query: &str,
+    descriptors: &[String],
+    extra_data_keys: &[String],
+    bins: usize,
+    terms_limit: usize,
+    token: &CancellationToken,
+) -> eyre::Result<DescriptorFacets> {
+    let schema = searcher.schema();
+
+    let mut terms_fields = Vec::with_capacity(descriptors.len() + extra_data_keys.len());
+    let mut histogram_fields = Vec::with_capacity(descriptors.len());
+
+    for descriptor in descriptors {
+        if !KNOWN_DESCRIPTORS.contains(&descriptor.as_str()) {
+            return Err(eyre::eyre!("Unknown descriptor: {descriptor}"));
+        }
+
+        let is_fast = schema
+            .get_field(descriptor)
+            .map(|field| schema.get_field_entry(field).is_fast())
+            .unwrap_or(false);
+
+        if !is_fast {
+            return Err(eyre::eyre!(
+                "Index schema does not store {descriptor} as a fast field; descriptor facets require a descriptor_v4 index"
+            ));
+        }
+
+        if descriptor.starts_with("Num") || descriptor.starts_with("lipinski") {
+            terms_fields.push(descriptor.clone());
+        } else {
+            histogram_fields.push(descriptor.clone());
+        }
+    }
+
+    if !extra_data_keys.is_empty() {
+        let is_fast = schema
+            .get_field("extra_data")
+            .map(|field| schema.get_field_entry(field).is_fast())
+            .unwrap_or(false);
+
+        if !is_fast {
+            return Err(eyre::eyre!(
+                "Index schema does not store extra_data as a fast field; extra_data facets require a descriptor_v4 index"
+            ));
+        }
+
+        for key in extra_data_keys {
+            terms_fields.push(format!("extra_data.{key}"));
+        }
+    }
+
+    let query = parse_search_query(searcher, query)?;
+
+    let mut first_pass = serde_json::Map::new();
+    for field in &terms_fields {
+        first_pass.insert(
+            field.clone(),
+            serde_json::json!({"terms": {"field": field, "size": terms_limit}}),
+        );
+    }
+    for field in &histogram_fields {
+        first_pass.insert(
+            field.clone(),
+            serde_json::json!({"stats": {"field": field}}),
+        );
+    }
+
+    let (count, first_pass_results) = run_aggregations(searcher, query.as_ref(), first_pass)?;
+
+    let mut terms = Vec::with_capacity(terms_fields.len());
+    for field in terms_fields {
+        let (buckets, other_count) = match first_pass_results.0.get(&field) {
+            Some(AggregationResult::BucketResult(BucketResult::Terms {
+                buckets,
+                sum_other_doc_count,
+                ..
+            })) => (buckets, *sum_other_doc_count),
+            _ => return Err(eyre::eyre!("Missing terms aggregation for {field}")),
+        };
+
+        let buckets = buckets
+            .iter()
+            .map(|bucket| FacetBucket {
+                key: key_to_string(&bucket.key),
+                count: bucket.doc_count,
+            })
+            .collect();
+
+        terms.push(TermsFacet {
+            field,
+            buckets,
+            other_count,
+        });
+    }
+
+    let mut histogram_stats = Vec::with_capacity(histogram_fields.len());
+    let mut second_pass = serde_json::Map::new();
+    for field in histogram_fields {
+        let stats = match first_pass_results.0.get(&field) {
+            Some(AggregationResult::MetricResult(MetricResult::Stats(stats))) => stats,
+            _ => return Err(eyre::eyre!("Missing stats aggregation for {field}")),
+        };
+
+        if let (Some(min), Some(max)) = (stats.min, stats.max) {
+            let interval = bin_interval(min, max, bins);
+            let bounds = serde_json::json!({"min": min, "max": max});
+            second_pass.insert(
+                field.clone(),
+                serde_json::json!({"histogram": {
+                    "field": field,
+                    "interval": interval,
+                    "offset": min,
+                    "min_doc_count": 0,
+                    "hard_bounds": bounds,
+                    "extended_bounds": bounds,
+                }}),
+            );
+        }
+
+        histogram_stats.push((field, stats.min, stats.max, stats.avg));
+    }
+
+    let second_pass_results = if second_pass.is_empty() {
+        AggregationResults(Default::default())
+    } else if token.is_cancelled() {
+        return Err(eyre::eyre!("Facet search was cancelled"));
+    } else {
+        run_aggregations(searcher, query.as_ref(), second_pass)?.1
+    };
+
+    let mut histograms = Vec::with_capacity(histogram_stats.len());
+    for (field, min, max, mean) in histogram_stats {
+        let buckets = match (min, max) {
+            (Some(min), Some(max)) => {
+                let buckets = match second_pass_results.0.get(&field) {
+                    Some(AggregationResult::BucketResult(BucketResult::Histogram {
+                        buckets: BucketEntries::Vec(buckets),
+                    })) => buckets,
+                    _ => return Err(eyre::eyre!("Missing histogram aggregation for {field}")),
+                };
+
+                let interval = bin_interval(min, max, bins);
+                let mut histogram_buckets: Vec<HistogramBucket> = Vec::with_capacity(bins);
+                for bucket in buckets {
+                    let from = match bucket.key {
+                        Key::F64(from) => from,
+                        Key::Str(_) => continue,
+                    };
+
+                    // max itself opens a bin of its own, which belongs to the last one
+                    if histogram_buckets.len() >= bins.max(1) {
+                        if let Some(last_bucket) = histogram_buckets.last_mut() {
+                            last_bucket.count += bucket.doc_count;
+                        }
+                    } else {
+                        histogram_buckets.push(HistogramBucket {
+                            from,
+                            to: (from + interval).min(max),
+                            count: bucket.doc_count,
+                        });
+                    }
+                }
+
+                histogram_buckets
+            }
+            _ => Vec::new(),
+        };
+
+        histograms.push(HistogramFacet {
+            field,
+            min,
+            max,
+            mean,
+            buckets,
+        });
+    }
+
+    Ok(DescriptorFacets {
+        count,
+        terms,
+        histograms,
+    })
+}
+
+fn run_aggregations(
+    searcher: &Searcher,
+    query: &dyn Query,
+    aggregations: serde_json::Map<String, serde_json::Value>,
+) -> eyre::Result<(usize, AggregationResults)> {
+    let aggregations: Aggregations =
+        serde_json::from_value(serde_json::Value::Object(aggregations))?;
+    let collector = AggregationCollector::from_aggs(aggregations, AggregationLimits::default());
+
+    Ok(searcher.search(query, &(Count, collector))?)
+}
+
+fn bin_interval(min: f64, max: f64, bins: usize) -> f64 {
+    if max > min {
+        (max - min) / bins.max(1) as f64
+    } else {
+        1.0
+    }
+}
+
+// Integer values come back as floats, which print without a fractional part
+fn key_to_string(key: &Key) -> String {
+    match key {
+        Key::Str(s) => s.clone(),
+        Key::F64(v) => v.to_string(),
+    }
+}
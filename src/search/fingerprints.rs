@@ -0,0 +1,86 @@
+use crate::rdkit_bridge::fingerprint_ffi;
+use crate::search::rdkit_sys_mol;
+use bitvec::prelude::BitVec;
+use rdkit::{Fingerprint, ROMol};
+use std::collections::HashMap;
+
+// All of these are RDKit's own fingerprints, computed with the same parameters as their Python
+// counterparts (e.g. GetMorganFingerprintAsBitVect(mol, 2, nBits=2048, useFeatures=True) for
+// "feature_morgan_r2_2048"), so similarities match what RDKit reports. "morgan" (radius 3, 2048
+// bits) is stored by every schema; the others only by schemas from descriptor_v2 on
+pub const FINGERPRINTS: [&str; 8] = [
+    "morgan",
+    "morgan_r2_1024",
+    "morgan_r2_2048",
+    "morgan_r3_4096",
+    "feature_morgan_r2_2048",
+    "atom_pair",
+    "torsion",
+    "maccs",
+];
+
+pub fn fingerprint_field(fingerprint: &str) -> String {
+    format!("{fingerprint}_fingerprint")
+}
+
+pub fn get_fingerprint(mol: &ROMol, fingerprint: &str) -> eyre::Result<BitVec<u8>> {
+    let mut fingerprints = get_fingerprints(mol, &[fingerprint])?;
+    fingerprints
+        .remove(fingerprint)
+        .ok_or(eyre::eyre!("Failed to compute {fingerprint} fingerprint"))
+}
+
+pub fn get_fingerprints(
+    mol: &ROMol,
+    fingerprints: &[&str],
+) -> eyre::Result<HashMap<String, BitVec<u8>>> {
+    let mut sys_mol = None;
+    let mut results = HashMap::with_capacity(fingerprints.len());
+
+    for fingerprint in fingerprints {
+        if !FINGERPRINTS.contains(fingerprint) {
+            return Err(eyre::eyre!("Unknown fingerprint: {fingerprint}"));
+        }
+
+        if *fingerprint == "morgan" {
+            results.insert(fingerprint.to_string(), mol.morgan_fingerprint().0);
+            continue;
+        }
+
+        if sys_mol.is_none() {
+            sys_mol = Some(rdkit_sys_mol(mol)?);
+        }
+        let sys_mol = sys_mol.as_ref().unwrap();
+
+        let ptr = match *fingerprint {
+            "morgan_r2_1024" => {
+                fingerprint_ffi::morgan_fingerprint_mol_with_params(sys_mol, 2, 1024, false)
+            }
+            "morgan_r2_2048" => {
+                fingerprint_ffi::morgan_fingerprint_mol_with_params(sys_mol, 2, 2048, false)
+            }
+            "morgan_r3_4096" => {
+                fingerprint_ffi::morgan_fingerprint_mol_with_params(sys_mol, 3, 4096, false)
+            }
+            "feature_morgan_r2_2048" => {
+                fingerprint_ffi::morgan_fingerprint_mol_with_params(sys_mol, 2, 2048, true)
+            }
+            "atom_pair" => fingerprint_ffi::atom_pair_fingerprint_mol(sys_mol, 2048),
+            "torsion" => fingerprint_ffi::topological_torsion_fingerprint_mol(sys_mol, 2048),
+            _ => fingerprint_ffi::maccs_fingerprint_mol(sys_mol),
+        };
+
+        if ptr.is_null() {
+            return Err(eyre::eyre!("Failed to compute {fingerprint} fingerprint"));
+        }
+
+        // The bits come back in whole 64-bit blocks, which only matters for the 167 MACCS keys
+        let num_bits = fingerprint_ffi::get_num_bits(&ptr) as usize;
+        let mut bits = Fingerprint::new(ptr).0;
+        bits.truncate(num_bits);
+
+        results.insert(fingerprint.to_string(), bits);
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,228 @@
+use rdkit::ROMol;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+
+pub const ELEMENTS: [&str; 118] = [
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S", "Cl",
+    "Ar", "K", "Ca", "Sc", "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As",
+    "Se", "Br", "Kr", "Rb", "Sr", "Y", "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In",
+    "Sn", "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce", "Pr", "Nd", "Pm", "Sm", "Eu", "Gd", "Tb",
+    "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W", "Re", "Os", "Ir", "Pt", "Au", "Hg", "Tl",
+    "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th", "Pa", "U", "Np", "Pu", "Am", "Cm", "Bk",
+    "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db", "Sg", "Bh", "Hs", "Mt", "Ds", "Rg", "Cn", "Nh",
+    "Fl", "Mc", "Lv", "Ts", "Og",
+];
+
+// Every element of a formula query with its lowest and (unless open ended) highest atom count
+pub type ElementRange = (String, u32, Option<u32>);
+
+// Documents only get "at least n atoms" terms up to this count, so large molecules do not carry
+// hundreds of terms per element; higher counts are told apart through the exact counts
+pub const ELEMENT_THRESHOLD_LIMIT: u32 = 32;
+
+// Every count above ELEMENT_THRESHOLD_LIMIT costs the query one clause per count in between
+const MAX_FORMULA_QUERY_COUNT: u32 = 1000;
+
+// Atom counts per element, implicit hydrogens included, and the net formal charge. Dummy atoms
+// are not elements and are left out
+pub fn element_counts(mol: &ROMol) -> (BTreeMap<String, u32>, i32) {
+    let mut mol = mol.clone();
+    let mut counts = BTreeMap::new();
+    let mut charge = 0;
+
+    for idx in 0..mol.num_atoms(true) {
+        let atom = mol.atom_with_idx(idx);
+        if atom.get_atomic_num() == 0 {
+            continue;
+        }
+
+        *counts.entry(atom.symbol()).or_insert(0) += 1;
+        charge += atom.get_formal_charge();
+
+        let num_hs = atom.get_total_num_hs();
+        if num_hs > 0 {
+            *counts.entry("H".to_string()).or_insert(0) += num_hs;
+        }
+    }
+
+    (counts, charge)
+}
+
+// Hill order: carbon first and hydrogen second when there is carbon, everything else (hydrogen
+// included when there is no carbon) alphabetically. The net charge is appended the way RDKit
+// writes it, e.g. C2H3O2- or C5H6N+
+pub fn hill_formula(counts: &BTreeMap<String, u32>, charge: i32) -> String {
+    let mut symbols = counts.keys().map(|s| s.as_str()).collect::<Vec<_>>();
+    if counts.contains_key("C") {
+        symbols.retain(|s| *s != "C" && *s != "H");
+        symbols.insert(0, "C");
+        if counts.contains_key("H") {
+            symbols.insert(1, "H");
+        }
+    }
+
+    let mut formula = symbols
+        .into_iter()
+        .map(|symbol| match counts[symbol] {
+            1 => symbol.to_string(),
+            count => format!("{symbol}{count}"),
+        })
+        .collect::<String>();
+
+    match charge {
+        0 => {}
+        1 => formula.push('+'),
+        -1 => formula.push('-'),
+        c if c > 0 => formula.push_str(&format!("+{c}")),
+        c => formula.push_str(&format!("{c}")),
+    }
+
+    formula
+}
+
+// Parses formula queries such as "C6-8 H* N1-2 Cl0" or "C6H6": every element symbol is followed by
+// an exact count, an inclusive range, an open range ("N2-" for at least two) or "*" for any count
+// (zero included); a bare symbol means one atom. Whitespace between elements is optional
+pub fn parse_formula_query(formula: &str) -> eyre::Result<Vec<ElementRange>> {
+    let re = Regex::new(r"^([A-Z][a-z]?)(\*|(\d+)(-(\d*))?)?").unwrap();
+
+    let mut ranges: Vec<ElementRange> = Vec::new();
+    let mut rest = formula.trim_start();
+
+    while !rest.is_empty() {
+        let captures = match re.captures(rest) {
+            Some(captures) => captures,
+            None => {
+                return Err(eyre::eyre!(
+                    "Invalid formula query at \"{rest}\"; expected an element symbol followed by a count, a range (e.g. C6-8) or *"
+                ))
+            }
+        };
+
+        let symbol = captures[1].to_string();
+        if !ELEMENTS.contains(&symbol.as_str()) {
+            return Err(eyre::eyre!("Unknown element in formula query: {symbol}"));
+        }
+        if ranges.iter().any(|(other, _, _)| *other == symbol) {
+            return Err(eyre::eyre!(
+                "Element given twice in formula query: {symbol}"
+            ));
+        }
+
+        let parse_count = |count: &str| {
+            count
+                .parse::<u32>()
+                .map_err(|e| eyre::eyre!("Invalid atom count {count} for {symbol}: {e}"))
+        };
+
+        let range = match (captures.get(2), captures.get(3), captures.get(4)) {
+            (None, _, _) => (1, Some(1)),
+            (Some(_), None, _) => (0, None),
+            (Some(_), Some(low), None) => {
+                let count = parse_count(low.as_str())?;
+                (count, Some(count))
+            }
+            (Some(_), Some(low), Some(_)) => match captures.get(5).map(|high| high.as_str()) {
+                Some("") | None => (parse_count(low.as_str())?, None),
+                Some(high) => (parse_count(low.as_str())?, Some(parse_count(high)?)),
+            },
+        };
+
+        if let (low, Some(high)) = range {
+            if low > high {
+                return Err(eyre::eyre!(
+                    "Invalid range for {symbol} in formula query: {low}-{high}"
+                ));
+            }
+        }
+
+        ranges.push((symbol, range.0, range.1));
+        rest = rest[captures[0].len()..].trim_start();
+    }
+
+    if ranges.is_empty() {
+        return Err(eyre::eyre!("Empty formula query"));
+    }
+
+    Ok(ranges)
+}
+
+// Expands a formula query into a single parenthesised tantivy query. Documents carry an "at least
+// n atoms" term for every element and every n up to their count (or ELEMENT_THRESHOLD_LIMIT),
+// since tantivy cannot run range queries on JSON fields; a range is then "at least low, but not at
+// least high + 1". Beyond the limit, "at least n" is "at least the limit, but none of the exact
+// counts from the limit to n - 1". Elements the formula does not mention are not constrained (Cl0
+// rules chlorine out)
+pub fn build_formula_query(formula: &str) -> eyre::Result<String> {
+    let ranges = parse_formula_query(formula)?;
+
+    for (symbol, low, high) in &ranges {
+        if high.unwrap_or(*low) > MAX_FORMULA_QUERY_COUNT {
+            return Err(eyre::eyre!(
+                "Atom count for {symbol} in formula query is above {MAX_FORMULA_QUERY_COUNT}, the most supported"
+            ));
+        }
+    }
+
+    let threshold =
+        |symbol: &str, count: u32| format!("other_descriptors.element_thresholds.{symbol}:{count}");
+    let at_least = |symbol: &str, count: u32| {
+        if count <= ELEMENT_THRESHOLD_LIMIT {
+            return threshold(symbol, count);
+        }
+
+        let exact_counts = (ELEMENT_THRESHOLD_LIMIT..count)
+            .map(|count| format!("-other_descriptors.element_counts.{symbol}:{count}"))
+            .collect::<Vec<_>>();
+
+        format!(
+            "(+{} {})",
+            threshold(symbol, ELEMENT_THRESHOLD_LIMIT),
+            exact_counts.join(" ")
+        )
+    };
+
+    let mut clauses = vec!["*".to_string()];
+
+    for (symbol, low, high) in &ranges {
+        match (low, high) {
+            (0, Some(high)) => clauses.push(format!("-{}", at_least(symbol, high + 1))),
+            (0, None) => {}
+            (low, Some(high)) => clauses.push(format!(
+                "+({} -{})",
+                at_least(symbol, *low),
+                at_least(symbol, high + 1)
+            )),
+            (low, None) => clauses.push(format!("+{}", at_least(symbol, *low))),
+        }
+    }
+
+    Ok(format!("({})", clauses.join(" ")))
+}
+
+// Replaces every formula:"..." (or unquoted formula:C6H6) clause of a query with its expansion,
+// leaving the rest of the query untouched
+pub fn expand_formula_queries(query: &str) -> eyre::Result<String> {
+    let re = Regex::new(r#"(^|[\s(+\-])formula:("([^"]*)"|[A-Za-z0-9*\-]+)"#).unwrap();
+
+    let mut error = None;
+    let expanded = re.replace_all(query, |captures: &Captures| {
+        let formula = match captures.get(3) {
+            Some(quoted) => quoted.as_str(),
+            None => &captures[2],
+        };
+
+        match build_formula_query(formula) {
+            Ok(formula_query) => format!("{}{formula_query}", &captures[1]),
+            Err(e) => {
+                error.get_or_insert(e);
+                String::new()
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(expanded.to_string()),
+    }
+}
@@ -0,0 +1,167 @@
+use crate::search::mol_graph::MolGraph;
+use std::collections::HashMap;
+
+// Writes the fragment as SMILES/SMARTS by walking a depth-first spanning tree; bonds outside the
+// tree become ring closures and disconnected parts are separated by dots. Atoms are written by
+// `atom_token` and bonds always get an explicit symbol. Returns the atoms in the order they appear
+// in the output
+pub fn write_fragment(
+    graph: &MolGraph,
+    bonds: &[usize],
+    atom_token: &dyn Fn(usize) -> String,
+) -> (Vec<usize>, String) {
+    let mut fragment_neighbors: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    let mut atoms = Vec::new();
+    for bond_idx in bonds {
+        let bond = &graph.bonds[*bond_idx];
+        for (atom_idx, neighbor) in [(bond.begin, bond.end), (bond.end, bond.begin)] {
+            let neighbors = fragment_neighbors.entry(atom_idx).or_default();
+            if neighbors.is_empty() {
+                atoms.push(atom_idx);
+            }
+            neighbors.push((neighbor, *bond_idx));
+        }
+    }
+
+    // First pass: visiting order, tree children and ring closure bonds
+    let mut roots = Vec::new();
+    let mut visited = HashMap::new();
+    let mut children: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    let mut tree_bonds = vec![false; graph.bonds.len()];
+
+    for root in atoms {
+        if visited.contains_key(&root) {
+            continue;
+        }
+
+        roots.push(root);
+        let mut stack = vec![(root, None)];
+
+        while let Some((atom_idx, parent)) = stack.pop() {
+            if visited.contains_key(&atom_idx) {
+                continue;
+            }
+
+            if let Some((parent_idx, bond_idx)) = parent {
+                children
+                    .entry(parent_idx)
+                    .or_default()
+                    .push((atom_idx, bond_idx));
+                tree_bonds[bond_idx] = true;
+            }
+
+            visited.insert(atom_idx, visited.len());
+
+            for (neighbor, bond_idx) in fragment_neighbors[&atom_idx].iter().rev() {
+                if !visited.contains_key(neighbor) {
+                    stack.push((*neighbor, Some((atom_idx, *bond_idx))));
+                }
+            }
+        }
+    }
+
+    // Second pass: emit the atoms in the same order
+    let mut writer = FragmentWriter {
+        graph,
+        atom_token,
+        fragment_neighbors: &fragment_neighbors,
+        children: &children,
+        tree_bonds: &tree_bonds,
+        visited: &visited,
+        output: String::new(),
+        atom_order: Vec::with_capacity(visited.len()),
+        open_rings: HashMap::new(),
+        free_digits: Vec::new(),
+        next_digit: 1,
+    };
+
+    for (position, root) in roots.into_iter().enumerate() {
+        if position > 0 {
+            writer.output.push('.');
+        }
+        writer.write_atom(root);
+    }
+
+    (writer.atom_order, writer.output)
+}
+
+struct FragmentWriter<'a> {
+    graph: &'a MolGraph,
+    atom_token: &'a dyn Fn(usize) -> String,
+    fragment_neighbors: &'a HashMap<usize, Vec<(usize, usize)>>,
+    children: &'a HashMap<usize, Vec<(usize, usize)>>,
+    tree_bonds: &'a [bool],
+    visited: &'a HashMap<usize, usize>,
+    output: String,
+    atom_order: Vec<usize>,
+    open_rings: HashMap<usize, u32>,
+    free_digits: Vec<u32>,
+    next_digit: u32,
+}
+
+impl FragmentWriter<'_> {
+    fn write_atom(&mut self, atom_idx: usize) {
+        self.atom_order.push(atom_idx);
+        self.output.push_str(&(self.atom_token)(atom_idx));
+
+        // Ring closures open at the atom that comes first and close at the other one
+        let mut ring_bonds = self.fragment_neighbors[&atom_idx]
+            .iter()
+            .filter(|(_, bond_idx)| !self.tree_bonds[*bond_idx])
+            .collect::<Vec<_>>();
+        ring_bonds.sort_by_key(|(neighbor, _)| self.visited[neighbor]);
+
+        // Digits closed here are only reused from the next atom on
+        let mut closed_digits = Vec::new();
+        for (neighbor, bond_idx) in ring_bonds {
+            let digit = if self.visited[neighbor] > self.visited[&atom_idx] {
+                let digit = self.free_digits.pop().unwrap_or_else(|| {
+                    self.next_digit += 1;
+                    self.next_digit - 1
+                });
+                self.open_rings.insert(*bond_idx, digit);
+                digit
+            } else {
+                let digit = self.open_rings.remove(bond_idx).unwrap_or_default();
+                closed_digits.push(digit);
+                digit
+            };
+
+            self.output
+                .push_str(bond_symbol(self.graph.bonds[*bond_idx].order));
+            if digit < 10 {
+                self.output.push_str(&digit.to_string());
+            } else {
+                self.output.push_str(&format!("%{digit}"));
+            }
+        }
+        self.free_digits.extend(closed_digits);
+
+        // All children but the last go into branches
+        let children = self.children.get(&atom_idx).cloned().unwrap_or_default();
+        for (position, (child, bond_idx)) in children.iter().enumerate() {
+            let branch = position + 1 < children.len();
+            if branch {
+                self.output.push('(');
+            }
+
+            self.output
+                .push_str(bond_symbol(self.graph.bonds[*bond_idx].order));
+            self.write_atom(*child);
+
+            if branch {
+                self.output.push(')');
+            }
+        }
+    }
+}
+
+pub fn bond_symbol(order: u8) -> &'static str {
+    match order {
+        1 => "-",
+        2 => "=",
+        3 => "#",
+        4 => ":",
+        _ => "~",
+    }
+}
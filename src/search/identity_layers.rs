@@ -0,0 +1,180 @@
+use crate::rdkit_bridge::mol_ops_ffi;
+use crate::search::graph_fragments::write_fragment;
+use crate::search::mol_graph::MolGraph;
+use crate::search::{fnv1a, rdkit_sys_mol};
+use cxx::SharedPtr;
+use rdkit::{
+    fragment_parent, substruct_match, CleanupParameters, ROMol, RWMol, SubstructMatchParameters,
+};
+use rdkit_sys::ro_mol_ffi;
+use std::collections::HashMap;
+
+// Identity layers, from strictest to loosest match:
+// - full: the standardized parent structure as drawn, i.e. before tautomer canonicalization and
+//   with its charges kept
+// - tautomer: the canonical tautomer (what the index stores as smiles)
+// - stereo: the canonical tautomer without stereochemistry
+// - isotope: the canonical tautomer without isotope labels
+// - connectivity: the heavy-atom skeleton only (no bond orders, hydrogens or charges). Unlike the
+//   other layers this is not a hash of a canonical SMILES, so hits are confirmed with `Skeleton`
+pub const IDENTITY_LEVELS: [&str; 5] = ["full", "tautomer", "stereo", "isotope", "connectivity"];
+
+// Every layer that could be computed, as level -> hash. Layers are stored per document under
+// other_descriptors.identity
+pub fn identity_layers(raw_mol: &ROMol, canon_taut: &ROMol) -> HashMap<String, String> {
+    IDENTITY_LEVELS
+        .iter()
+        .filter_map(|level| match identity_layer(level, raw_mol, canon_taut) {
+            Ok(layer) => Some((level.to_string(), layer)),
+            Err(e) => {
+                log::warn!("Could not compute {level} identity layer: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+// `raw_mol` is the molecule as given, `canon_taut` its standardized canonical tautomer
+pub fn identity_layer(level: &str, raw_mol: &ROMol, canon_taut: &ROMol) -> eyre::Result<String> {
+    let hash = match level {
+        "full" => {
+            let parent = fragment_parent(
+                &raw_mol.as_rw_mol(false, 1),
+                &CleanupParameters::default(),
+                false,
+            );
+            fnv1a(parent.to_ro_mol().as_smiles().as_bytes())
+        }
+        "tautomer" => fnv1a(canon_taut.as_smiles().as_bytes()),
+        "stereo" => {
+            fnv1a(edited_smiles(canon_taut, mol_ops_ffi::remove_stereochemistry)?.as_bytes())
+        }
+        "isotope" => fnv1a(edited_smiles(canon_taut, mol_ops_ffi::remove_isotopes)?.as_bytes()),
+        "connectivity" => connectivity_hash(&MolGraph::from_romol(canon_taut)?),
+        other => {
+            return Err(eyre::eyre!(
+                "Unknown identity level: {other}; expected one of {}",
+                IDENTITY_LEVELS.join(", ")
+            ))
+        }
+    };
+
+    // The letter keeps the query parser from reading all-digit hashes as numbers
+    Ok(format!("h{hash:016x}"))
+}
+
+pub fn identity_layer_query(level: &str, layer: &str) -> String {
+    format!("other_descriptors.identity.{level}:{layer}")
+}
+
+// The canonical SMILES of a molecule after an RDKit operation that returns an edited copy
+fn edited_smiles(
+    mol: &ROMol,
+    edit: fn(&SharedPtr<ro_mol_ffi::ROMol>) -> SharedPtr<ro_mol_ffi::ROMol>,
+) -> eyre::Result<String> {
+    Ok(ro_mol_ffi::mol_to_smiles(&edit(&rdkit_sys_mol(mol)?)))
+}
+
+// Weisfeiler-Lehman style refinement of element labels over the bond graph, so the hash does
+// not depend on atom order. Some non-isomorphic graphs (e.g. certain regular ones) end up with the
+// same labels, so equal hashes only make two skeletons candidates for a match
+fn connectivity_hash(graph: &MolGraph) -> u64 {
+    let mut labels = graph
+        .atoms
+        .iter()
+        .map(|atom| fnv1a(&atom.atomic_num.to_le_bytes()))
+        .collect::<Vec<_>>();
+
+    for _ in 0..graph.num_atoms() {
+        let refined = (0..graph.num_atoms())
+            .map(|atom_idx| {
+                let mut neighbor_labels = graph.neighbors[atom_idx]
+                    .iter()
+                    .map(|(neighbor, _)| labels[*neighbor])
+                    .collect::<Vec<_>>();
+                neighbor_labels.sort();
+
+                let mut bytes = labels[atom_idx].to_le_bytes().to_vec();
+                for label in neighbor_labels {
+                    bytes.extend(label.to_le_bytes());
+                }
+
+                fnv1a(&bytes)
+            })
+            .collect::<Vec<_>>();
+
+        let num_classes = |labels: &[u64]| {
+            let mut labels = labels.to_vec();
+            labels.sort();
+            labels.dedup();
+            labels.len()
+        };
+
+        let stable = num_classes(&refined) == num_classes(&labels);
+        labels = refined;
+        if stable {
+            break;
+        }
+    }
+
+    labels.sort();
+
+    let mut bytes = (graph.bonds.len() as u64).to_le_bytes().to_vec();
+    for label in labels {
+        bytes.extend(label.to_le_bytes());
+    }
+
+    fnv1a(&bytes)
+}
+
+// The element-and-bond skeleton of a molecule as a SMARTS pattern: every atom matches any atom of
+// the same element and every bond any bond. A molecule with as many atoms and bonds that contains
+// the pattern has the same skeleton
+pub struct Skeleton {
+    pattern: ROMol,
+    num_atoms: usize,
+    num_bonds: usize,
+}
+
+impl Skeleton {
+    pub fn new(mol: &ROMol) -> eyre::Result<Skeleton> {
+        let mut graph = MolGraph::from_romol(mol)?;
+        for bond in &mut graph.bonds {
+            bond.order = 0;
+        }
+
+        let atom_token = |atom_idx: usize| format!("[#{}]", graph.atoms[atom_idx].atomic_num);
+        let bonds = (0..graph.bonds.len()).collect::<Vec<_>>();
+        let (_, mut smarts) = write_fragment(&graph, &bonds, &atom_token);
+
+        // write_fragment only walks bonds, so atoms without any are added on their own
+        for atom_idx in 0..graph.num_atoms() {
+            if graph.neighbors[atom_idx].is_empty() {
+                if !smarts.is_empty() {
+                    smarts.push('.');
+                }
+                smarts.push_str(&atom_token(atom_idx));
+            }
+        }
+
+        let pattern = RWMol::from_smarts(&smarts)
+            .map_err(|e| eyre::eyre!("{}", e))?
+            .to_ro_mol();
+
+        Ok(Skeleton {
+            pattern,
+            num_atoms: graph.num_atoms(),
+            num_bonds: graph.bonds.len(),
+        })
+    }
+
+    pub fn matches(&self, mol: &ROMol) -> eyre::Result<bool> {
+        let graph = MolGraph::from_romol(mol)?;
+        if graph.num_atoms() != self.num_atoms || graph.bonds.len() != self.num_bonds {
+            return Ok(false);
+        }
+
+        let params = SubstructMatchParameters::default();
+        Ok(!substruct_match(mol, &self.pattern, &params).is_empty())
+    }
+}
@@ -1,5 +1,10 @@
+use crate::search::cancellation::CancellationToken;
+use crate::search::identity_layers::{identity_layer_query, Skeleton};
 use crate::search::structure_matching::exact_match;
-use crate::search::{basic_search::basic_search, STRUCTURE_MATCH_DESCRIPTORS};
+use crate::search::{
+    basic_search::basic_search_stream, extra_query_clause, get_smiles_and_extra_data,
+    STRUCTURE_MATCH_DESCRIPTORS,
+};
 use bitvec::prelude::{BitSlice, Lsb0};
 use rayon::prelude::*;
 use rdkit::ROMol;
@@ -17,13 +22,50 @@ pub fn identity_search(
     query_descriptors: &HashMap<String, f64>,
     use_chirality: bool,
     extra_query: &str,
+    token: &CancellationToken,
 ) -> eyre::Result<Vec<(String, serde_json::Value, SegmentOrdinal, DocId)>> {
+    let mut filtered_results = Vec::new();
+
+    identity_search_streaming(
+        searcher,
+        query_mol,
+        scaffold_matches,
+        query_pattern_fingerprint,
+        query_descriptors,
+        use_chirality,
+        extra_query,
+        token,
+        |results_subset| {
+            filtered_results.extend(results_subset);
+            true
+        },
+    )?;
+
+    Ok(filtered_results)
+}
+
+// Hands each chunk's confirmed matches (in doc address order) to `on_results` as soon as they are
+// known; returning false from `on_results` or cancelling `token` stops the search
+#[allow(clippy::too_many_arguments)]
+pub fn identity_search_streaming<F>(
+    searcher: &Searcher,
+    query_mol: &ROMol,
+    scaffold_matches: &Option<Vec<i64>>,
+    query_pattern_fingerprint: &BitSlice<u8, Lsb0>,
+    query_descriptors: &HashMap<String, f64>,
+    use_chirality: bool,
+    extra_query: &str,
+    token: &CancellationToken,
+    mut on_results: F,
+) -> eyre::Result<()>
+where
+    F: FnMut(Vec<(String, serde_json::Value, SegmentOrdinal, DocId)>) -> bool,
+{
     let schema = searcher.schema();
 
     let query = build_identity_query(query_descriptors, extra_query, scaffold_matches);
 
-    let tantivy_limit = 100_000;
-    let initial_results = basic_search(searcher, &query, tantivy_limit)?;
+    let mut candidates = basic_search_stream(searcher, &query)?;
 
     let smiles_field = schema.get_field("smiles")?;
     let pattern_fingerprint_field = schema.get_field("pattern_fingerprint")?;
@@ -31,28 +73,118 @@ pub fn identity_search(
 
     let query_mol_mutex = Arc::new(Mutex::new(query_mol.clone()));
 
-    let filtered_results = initial_results
-        .into_par_iter()
-        .filter_map(|result| {
-            let confirmed_match = identity_match(
-                result,
-                smiles_field,
-                pattern_fingerprint_field,
-                extra_data_field,
-                searcher,
-                &query_mol_mutex.lock().unwrap(),
-                query_pattern_fingerprint,
-                use_chirality,
-            );
-
-            confirmed_match.unwrap_or_else(|e| {
-                log::error!("{:?}", e);
-                None
+    loop {
+        let chunk = candidates.by_ref().take(1000).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            break;
+        }
+
+        let mut results_subset = chunk
+            .into_par_iter()
+            .filter_map(|result| {
+                if token.is_cancelled() {
+                    return None;
+                }
+
+                let confirmed_match = identity_match(
+                    result,
+                    smiles_field,
+                    pattern_fingerprint_field,
+                    extra_data_field,
+                    searcher,
+                    &query_mol_mutex.lock().unwrap(),
+                    query_pattern_fingerprint,
+                    use_chirality,
+                );
+
+                confirmed_match.unwrap_or_else(|e| {
+                    log::error!("{:?}", e);
+                    None
+                })
             })
-        })
-        .collect::<Vec<_>>();
+            .collect::<Vec<_>>();
 
-    Ok(filtered_results)
+        results_subset.sort_by_key(|result| (result.2, result.3));
+
+        if !on_results(results_subset) || token.is_cancelled() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Identity at a given layer is a term lookup on the stored layer hash. The canonical SMILES based
+// layers need nothing more, but connectivity hashes can collide, so those hits are confirmed by
+// matching the skeleton of `query_mol` (the standardized query)
+#[allow(clippy::too_many_arguments)]
+pub fn identity_layer_search_streaming<F>(
+    searcher: &Searcher,
+    query_mol: &ROMol,
+    identity_level: &str,
+    layer: &str,
+    extra_query: &str,
+    token: &CancellationToken,
+    mut on_results: F,
+) -> eyre::Result<()>
+where
+    F: FnMut(Vec<(String, serde_json::Value, SegmentOrdinal, DocId)>) -> bool,
+{
+    let schema = searcher.schema();
+
+    let mut query_parts = vec![identity_layer_query(identity_level, layer)];
+    if !extra_query.is_empty() {
+        query_parts.push(extra_query_clause(extra_query));
+    }
+
+    let mut candidates = basic_search_stream(searcher, &query_parts.join(" AND "))?;
+
+    let smiles_field = schema.get_field("smiles")?;
+    let extra_data_field = schema.get_field("extra_data")?;
+
+    let skeleton = match identity_level {
+        "connectivity" => Some(Skeleton::new(query_mol)?),
+        _ => None,
+    };
+
+    loop {
+        let chunk = candidates.by_ref().take(1000).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            break;
+        }
+
+        let mut results_subset = Vec::with_capacity(chunk.len());
+        for docaddr in chunk {
+            if token.is_cancelled() {
+                break;
+            }
+
+            let (smiles, extra_data) =
+                get_smiles_and_extra_data(docaddr, searcher, smiles_field, extra_data_field)?;
+
+            if let Some(skeleton) = &skeleton {
+                let confirmed = ROMol::from_smiles(&smiles)
+                    .map_err(|e| eyre::eyre!("{}", e))
+                    .and_then(|mol| skeleton.matches(&mol))
+                    .unwrap_or_else(|e| {
+                        log::error!("{:?}", e);
+                        false
+                    });
+
+                if !confirmed {
+                    continue;
+                }
+            }
+
+            results_subset.push((smiles, extra_data, docaddr.segment_ord, docaddr.doc_id));
+        }
+
+        if !on_results(results_subset) || token.is_cancelled() {
+            break;
+        }
+    }
+
+    Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -121,9 +253,7 @@ pub fn build_identity_query(
     let mut query_parts = Vec::with_capacity(descriptors.len());
 
     if !extra_query.is_empty() {
-        for subquery in extra_query.split(" AND ") {
-            query_parts.push(subquery.to_string());
-        }
+        query_parts.push(extra_query_clause(extra_query));
     }
 
     if let Some(scaffolds) = matching_scaffolds {
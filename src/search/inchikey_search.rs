@@ -0,0 +1,112 @@
+use crate::rdkit_bridge::inchi_ffi;
+use crate::search::basic_search::basic_search_stream;
+use crate::search::cancellation::CancellationToken;
+use crate::search::{get_smiles_and_extra_data, rdkit_sys_mol, StructureSearchHit};
+use cxx::let_cxx_string;
+use rdkit::ROMol;
+use tantivy::Searcher;
+
+// The standard InChI and InChIKey, as generated by the InChI library that RDKit bundles
+pub fn standard_inchi(mol: &ROMol) -> eyre::Result<(String, String)> {
+    let sys_mol = rdkit_sys_mol(mol)?;
+    let inchi = inchi_ffi::mol_to_inchi(&sys_mol).map_err(|e| eyre::eyre!("{e}"))?;
+
+    let_cxx_string!(cxx_inchi = inchi.as_str());
+    let inchikey = inchi_ffi::inchi_to_inchi_key(&cxx_inchi).map_err(|e| eyre::eyre!("{e}"))?;
+
+    Ok((inchi, inchikey))
+}
+
+// InChIKeys are 14 + 10 + 1 uppercase letters separated by dashes; the first block hashes the
+// connectivity (skeleton) layer of the InChI
+pub fn validate_inchikey(inchikey: &str) -> eyre::Result<String> {
+    let inchikey = inchikey.trim().to_uppercase();
+
+    let blocks = inchikey.split('-').collect::<Vec<_>>();
+    let well_formed = blocks.len() == 3
+        && blocks.iter().zip([14, 10, 1]).all(|(block, len)| {
+            block.len() == len && block.chars().all(|c| c.is_ascii_uppercase())
+        });
+
+    if !well_formed {
+        return Err(eyre::eyre!("Malformed InChIKey: {inchikey}"));
+    }
+
+    Ok(inchikey)
+}
+
+pub fn inchikey_connectivity(inchikey: &str) -> String {
+    inchikey.chars().take(14).collect()
+}
+
+// Looks the InChIKey up in the index. With `connectivity`, only the first block is compared (so
+// stereoisomers, isotopologues and protonation states all match) and it may be passed on its own.
+// Returns the hits in doc address order and whether there were more than `result_limit`. Once
+// `token` is cancelled, the hits loaded so far are returned
+pub fn inchikey_search(
+    searcher: &Searcher,
+    inchikey: &str,
+    connectivity: bool,
+    result_limit: usize,
+    token: &CancellationToken,
+) -> eyre::Result<(Vec<StructureSearchHit>, bool)> {
+    let schema = searcher.schema();
+
+    let (field_name, term_text) = if connectivity {
+        let first_block = inchikey.trim().to_uppercase();
+        let first_block = if first_block.len() == 14 {
+            first_block
+        } else {
+            inchikey_connectivity(&validate_inchikey(inchikey)?)
+        };
+
+        if !first_block.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(eyre::eyre!("Malformed InChIKey block: {first_block}"));
+        }
+
+        ("inchikey_connectivity", first_block)
+    } else {
+        ("inchikey", validate_inchikey(inchikey)?)
+    };
+
+    schema.get_field(field_name).map_err(|_| {
+        eyre::eyre!(
+            "Index schema does not store InChIKeys; InChIKey search requires a descriptor_v3 index"
+        )
+    })?;
+
+    // Documents stream in doc address order, so the first result_limit of them are the page and
+    // one more tells whether it was cut off. The term is quoted for its dashes
+    let query = format!("{field_name}:\"{term_text}\"");
+    let mut results = basic_search_stream(searcher, &query)?
+        .take(result_limit.saturating_add(1))
+        .collect::<Vec<_>>();
+
+    let truncated = results.len() > result_limit;
+    results.truncate(result_limit);
+
+    let smiles_field = schema.get_field("smiles")?;
+    let extra_data_field = schema.get_field("extra_data")?;
+
+    let hits = results
+        .into_iter()
+        .take_while(|_| !token.is_cancelled())
+        .map(|docaddr| {
+            let (smiles, extra_data) =
+                get_smiles_and_extra_data(docaddr, searcher, smiles_field, extra_data_field)?;
+
+            Ok(StructureSearchHit {
+                extra_data,
+                smiles,
+                score: 1.0,
+                query: inchikey.to_string(),
+                used_tautomers: false,
+                atom_matches: None,
+                svg: None,
+                rgroup_decomposition: None,
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    Ok((hits, truncated))
+}
@@ -0,0 +1,184 @@
+use crate::search::basic_search::basic_search_stream;
+use crate::search::cancellation::CancellationToken;
+use crate::search::sorting::SortKeys;
+use crate::search::{extra_query_clause, get_smiles_and_extra_data};
+use poem_openapi::Object;
+use tantivy::Searcher;
+
+// (name, molecules per ion, mass added to them, charge). Masses are monoisotopic and account for
+// the electrons lost or gained, e.g. a proton rather than a hydrogen atom for [M+H]+
+pub const ADDUCTS: [(&str, u32, f64, i32); 20] = [
+    ("[M]+", 1, -0.000549, 1),
+    ("[M+H]+", 1, 1.007276, 1),
+    ("[M+NH4]+", 1, 18.033823, 1),
+    ("[M+Na]+", 1, 22.989218, 1),
+    ("[M+K]+", 1, 38.963158, 1),
+    ("[M+H-H2O]+", 1, -17.003289, 1),
+    ("[2M+H]+", 2, 1.007276, 1),
+    ("[2M+Na]+", 2, 22.989218, 1),
+    ("[M+2H]2+", 1, 2.014552, 2),
+    ("[M+H+Na]2+", 1, 23.996494, 2),
+    ("[M+2Na]2+", 1, 45.978436, 2),
+    ("[M]-", 1, 0.000549, -1),
+    ("[M-H]-", 1, -1.007276, -1),
+    ("[M+Cl]-", 1, 34.969402, -1),
+    ("[M+HCOO]-", 1, 44.998201, -1),
+    ("[M+CH3COO]-", 1, 59.013851, -1),
+    ("[M-H2O-H]-", 1, -19.018389, -1),
+    ("[2M-H]-", 2, -1.007276, -1),
+    ("[M-2H]2-", 1, -2.014552, -2),
+    ("[M+Na-2H]-", 1, 20.974666, -1),
+];
+
+#[derive(Object, Debug, Clone)]
+pub struct MassSearchHit {
+    pub smiles: String,
+    pub extra_data: serde_json::Value,
+    /// Monoisotopic mass of the neutral compound
+    pub exactmw: f64,
+    /// The adduct whose m/z explains the observed one
+    pub adduct: String,
+    pub theoretical_mz: f64,
+    /// Observed minus theoretical m/z, relative to the theoretical m/z
+    pub error_ppm: f64,
+    /// Observed minus theoretical m/z in mDa
+    pub error_mda: f64,
+}
+
+// The adducts with the given names (every known adduct of the charge state when none are given),
+// all of which must carry that charge
+pub fn resolve_adducts(
+    adducts: &[String],
+    charge: i32,
+) -> eyre::Result<Vec<(&'static str, u32, f64, i32)>> {
+    if adducts.is_empty() {
+        return Ok(ADDUCTS
+            .into_iter()
+            .filter(|(_, _, _, adduct_charge)| *adduct_charge == charge)
+            .collect());
+    }
+
+    adducts
+        .iter()
+        .map(|name| {
+            let adduct = ADDUCTS
+                .into_iter()
+                .find(|(adduct_name, _, _, _)| adduct_name == name)
+                .ok_or_else(|| {
+                    eyre::eyre!(
+                        "Unknown adduct: {name}; expected one of {}",
+                        ADDUCTS.map(|(adduct_name, _, _, _)| adduct_name).join(", ")
+                    )
+                })?;
+
+            if adduct.3 != charge {
+                return Err(eyre::eyre!(
+                    "Adduct {name} has charge {}, not the charge state {charge}",
+                    adduct.3
+                ));
+            }
+
+            Ok(adduct)
+        })
+        .collect()
+}
+
+// For every adduct, the neutral mass window that explains the observed m/z is searched as a range
+// on exactmw; hits are then ranked by the absolute m/z error. A compound explained by several
+// adducts is listed once per adduct. The tolerance applies to the observed m/z, in ppm or mDa.
+// Hits can only be ranked once every candidate is known, so a cancelled search fails
+#[allow(clippy::too_many_arguments)]
+pub fn mass_search(
+    searcher: &Searcher,
+    mz: f64,
+    tolerance: f64,
+    tolerance_unit: &str,
+    charge: i32,
+    adducts: &[String],
+    extra_query: &str,
+    result_limit: usize,
+    token: &CancellationToken,
+) -> eyre::Result<Vec<MassSearchHit>> {
+    if mz <= 0.0 {
+        return Err(eyre::eyre!("mz must be positive"));
+    }
+    if tolerance <= 0.0 {
+        return Err(eyre::eyre!("tolerance must be positive"));
+    }
+    if charge == 0 {
+        return Err(eyre::eyre!("charge must not be 0"));
+    }
+
+    let mz_tolerance = match tolerance_unit {
+        "ppm" => mz * tolerance / 1e6,
+        "mda" => tolerance / 1000.0,
+        other => {
+            return Err(eyre::eyre!(
+                "Unknown tolerance_unit: {other}; expected ppm or mda"
+            ))
+        }
+    };
+
+    let adducts = resolve_adducts(adducts, charge)?;
+    let exactmw_keys = SortKeys::new(searcher, "exactmw", "asc")?;
+
+    // (document, adduct, exactmw, theoretical m/z) for every candidate within tolerance
+    let mut candidates = Vec::new();
+    for (name, multimer, shift, adduct_charge) in adducts {
+        let z = adduct_charge.unsigned_abs() as f64;
+        let neutral_mass = |mz: f64| (mz * z - shift) / multimer as f64;
+        let low = neutral_mass(mz - mz_tolerance).max(0.0);
+        let high = neutral_mass(mz + mz_tolerance);
+        if high <= 0.0 {
+            continue;
+        }
+
+        let mut query = format!("exactmw:[{low} TO {high}]");
+        if !extra_query.is_empty() {
+            query = format!("{query} AND {}", extra_query_clause(extra_query));
+        }
+
+        for doc in basic_search_stream(searcher, &query)? {
+            if token.is_cancelled() {
+                return Err(eyre::eyre!("Mass search was cancelled"));
+            }
+
+            let exactmw = match exactmw_keys.value(doc) {
+                Some(exactmw) => exactmw,
+                None => continue,
+            };
+
+            let theoretical_mz = (exactmw * multimer as f64 + shift) / z;
+            if (mz - theoretical_mz).abs() <= mz_tolerance {
+                candidates.push((doc, name, exactmw, theoretical_mz));
+            }
+        }
+    }
+
+    // Stable, so equal errors keep index and adduct order
+    let error_ppm = |theoretical_mz: f64| (mz - theoretical_mz) / theoretical_mz * 1e6;
+    candidates.sort_by(|a, b| error_ppm(a.3).abs().total_cmp(&error_ppm(b.3).abs()));
+    candidates.truncate(result_limit);
+
+    let schema = searcher.schema();
+    let smiles_field = schema.get_field("smiles")?;
+    let extra_data_field = schema.get_field("extra_data")?;
+
+    candidates
+        .into_iter()
+        .map(|(doc, name, exactmw, theoretical_mz)| {
+            let (smiles, extra_data) =
+                get_smiles_and_extra_data(doc, searcher, smiles_field, extra_data_field)?;
+
+            Ok(MassSearchHit {
+                smiles,
+                extra_data,
+                exactmw,
+                adduct: name.to_string(),
+                theoretical_mz,
+                error_ppm: error_ppm(theoretical_mz),
+                error_mda: (mz - theoretical_mz) * 1000.0,
+            })
+        })
+        .collect()
+}
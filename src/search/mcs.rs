@@ -0,0 +1,121 @@
+use crate::rdkit_bridge::fmcs_ffi;
+use crate::search::structure_matching::map_atoms;
+use crate::search::{prepare_smarts_query, rdkit_sys_mol, AtomMatch};
+use cxx::let_cxx_string;
+use poem_openapi::Object;
+use rdkit::ROMol;
+use std::time::Duration;
+
+// The maximum common (connected) substructure of a set of molecules, as found by RDKit's FMCS
+#[derive(Object, Debug, Clone)]
+pub struct Mcs {
+    pub smarts: String,
+    pub num_atoms: usize,
+    pub num_bonds: usize,
+    /// One per input molecule: the atoms matching the SMARTS atoms (in SMARTS order) and the bonds
+    /// matching the SMARTS bonds (in SMARTS order)
+    pub mappings: Vec<AtomMatch>,
+    /// The search ran out of time, so the MCS is the largest common substructure found so far
+    pub timed_out: bool,
+}
+
+pub const MCS_ATOM_COMPARISONS: [&str; 3] = ["elements", "any", "isotopes"];
+pub const MCS_BOND_COMPARISONS: [&str; 3] = ["order", "order_exact", "any"];
+
+// FMCS's parameters, with RDKit's defaults. Atoms match on element ("elements", or "any" or
+// "isotopes") and bonds on order ("order", which lets single and aromatic bonds match each other,
+// "order_exact" or "any")
+#[derive(Debug, Clone)]
+pub struct McsParams {
+    pub timeout: Duration,
+    pub atom_compare: String,
+    pub bond_compare: String,
+    pub ring_matches_ring_only: bool,
+    pub complete_rings_only: bool,
+}
+
+impl McsParams {
+    pub fn new(timeout: Duration) -> McsParams {
+        McsParams {
+            timeout,
+            atom_compare: "elements".to_string(),
+            bond_compare: "order".to_string(),
+            ring_matches_ring_only: false,
+            complete_rings_only: false,
+        }
+    }
+
+    pub fn check(&self) -> eyre::Result<()> {
+        if !MCS_ATOM_COMPARISONS.contains(&self.atom_compare.as_str()) {
+            return Err(eyre::eyre!(
+                "Unknown atom comparison: {}; expected one of {}",
+                self.atom_compare,
+                MCS_ATOM_COMPARISONS.join(", ")
+            ));
+        }
+
+        if !MCS_BOND_COMPARISONS.contains(&self.bond_compare.as_str()) {
+            return Err(eyre::eyre!(
+                "Unknown bond comparison: {}; expected one of {}",
+                self.bond_compare,
+                MCS_BOND_COMPARISONS.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn find_mcs(mols: &[ROMol], params: &McsParams) -> eyre::Result<Mcs> {
+    if mols.is_empty() {
+        return Err(eyre::eyre!("MCS needs at least one molecule"));
+    }
+
+    params.check()?;
+
+    let mut mol_list = fmcs_ffi::new_mol_list();
+    for mol in mols {
+        fmcs_ffi::mol_list_push(mol_list.pin_mut(), &rdkit_sys_mol(mol)?);
+    }
+
+    let_cxx_string!(atom_compare = &params.atom_compare);
+    let_cxx_string!(bond_compare = &params.bond_compare);
+    let result = fmcs_ffi::find_mcs(
+        &mol_list,
+        params.timeout.as_millis() as u64,
+        &atom_compare,
+        &bond_compare,
+        params.ring_matches_ring_only,
+        params.complete_rings_only,
+    )
+    .map_err(|e| eyre::eyre!("{e}"))?;
+
+    // FMCS only reports the SMARTS, so where it sits in every molecule is found by matching it
+    let mappings = if result.smarts.is_empty() {
+        vec![
+            AtomMatch {
+                atoms: Vec::new(),
+                bonds: Vec::new(),
+            };
+            mols.len()
+        ]
+    } else {
+        let pattern = prepare_smarts_query(&result.smarts)?;
+        mols.iter()
+            .map(|mol| {
+                map_atoms(mol, &pattern, false)?
+                    .into_iter()
+                    .next()
+                    .ok_or(eyre::eyre!("MCS does not match one of the molecules"))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?
+    };
+
+    Ok(Mcs {
+        smarts: result.smarts,
+        num_atoms: result.num_atoms as usize,
+        num_bonds: result.num_bonds as usize,
+        mappings,
+        timed_out: result.canceled,
+    })
+}
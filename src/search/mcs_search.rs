@@ -0,0 +1,79 @@
+use crate::search::cancellation::CancellationToken;
+use crate::search::mcs::{find_mcs, McsParams};
+use crate::search::{get_smiles_and_extra_data, StructureSearchHit};
+use rayon::prelude::*;
+use rdkit::ROMol;
+use std::collections::HashSet;
+use tantivy::{DocAddress, Searcher};
+
+// Ranks the candidates (usually the neighbours found by `neighbor_search`) by the size of their
+// MCS with the query, bonds first and atoms second. The score is the number of MCS bonds and
+// atom_matches holds the MCS atoms and bonds within each hit. The FMCS timeout applies to every
+// candidate separately; a candidate whose MCS search runs out of time is ranked by the largest
+// common substructure found until then. Candidates left once `token` is cancelled are skipped
+pub fn mcs_search(
+    searcher: &Searcher,
+    results: HashSet<DocAddress>,
+    query_mol: &ROMol,
+    query_smiles: &str,
+    params: &McsParams,
+    token: &CancellationToken,
+) -> eyre::Result<Vec<(StructureSearchHit, DocAddress)>> {
+    // Checked once here, as errors for single candidates only skip them
+    params.check()?;
+
+    let schema = searcher.schema();
+    let smiles_field = schema.get_field("smiles")?;
+    let extra_data_field = schema.get_field("extra_data")?;
+
+    let mut final_results = results
+        .into_par_iter()
+        .filter_map(|docaddr| {
+            if token.is_cancelled() {
+                return None;
+            }
+
+            let result =
+                get_smiles_and_extra_data(docaddr, searcher, smiles_field, extra_data_field)
+                    .and_then(|(smiles, extra_data)| {
+                        let mol = ROMol::from_smiles(&smiles)?;
+                        let mcs = find_mcs(&[query_mol.clone(), mol], params)?;
+                        Ok((smiles, extra_data, mcs))
+                    });
+
+            match result {
+                Ok((smiles, extra_data, mut mcs)) => Some((
+                    StructureSearchHit {
+                        extra_data,
+                        smiles,
+                        score: mcs.num_bonds as f32,
+                        query: query_smiles.into(),
+                        used_tautomers: false,
+                        atom_matches: Some(vec![mcs.mappings.remove(1)]),
+                        svg: None,
+                        rgroup_decomposition: None,
+                    },
+                    docaddr,
+                    mcs.num_atoms,
+                )),
+                Err(e) => {
+                    log::warn!("Encountered exception in MCS calculation: {e}");
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Ties are broken on doc address so that the ordering is total
+    final_results.sort_by(|a, b| {
+        b.0.score
+            .total_cmp(&a.0.score)
+            .then_with(|| b.2.cmp(&a.2))
+            .then_with(|| a.1.cmp(&b.1))
+    });
+
+    Ok(final_results
+        .into_iter()
+        .map(|(hit, docaddr, _)| (hit, docaddr))
+        .collect())
+}
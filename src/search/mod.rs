@@ -1,21 +1,48 @@
 use std::collections::HashMap;
 
+use crate::rdkit_bridge::smarts_ffi;
 use crate::search::compound_processing::process_cpd;
+use crate::search::rgroup_decomposition::RGroupDecomposition;
+use cxx::{let_cxx_string, SharedPtr};
 use poem_openapi_derive::Object;
 use rayon::prelude::*;
 use rdkit::{
-    detect_chemistry_problems, Fingerprint, MolSanitizeException, ROMol, SmilesParserParams,
+    detect_chemistry_problems, Fingerprint, MolSanitizeException, ROMol, RWMol, SmilesParserParams,
 };
+use rdkit_sys::{ro_mol_ffi, rw_mol_ffi};
+use tantivy::query::Occur;
+use tantivy::query_grammar::{parse_query, UserInputAst};
 use tantivy::schema::Field;
 use tantivy::{DocAddress, DocId, Searcher, SegmentOrdinal};
 
 pub mod basic_search;
+pub mod cancellation;
+pub mod clustering;
 pub mod compound_processing;
+pub mod depiction;
+pub mod exact_similarity_search;
+pub mod excluded_patterns;
+pub mod facets;
+pub mod fingerprints;
+pub mod formula;
+pub mod graph_fragments;
+pub mod identity_layers;
 pub mod identity_search;
+pub mod inchikey_search;
+pub mod mass_search;
+pub mod mcs;
+pub mod mcs_search;
+pub mod mol_graph;
+pub mod pagination;
+pub mod rgroup_decomposition;
 pub mod scaffold_search;
 pub mod similarity_search;
+pub mod sorting;
+pub mod structural_alerts;
+pub mod structure_explain;
 pub mod structure_matching;
 pub mod structure_search;
+pub mod structured_query;
 
 #[derive(Object, Debug)]
 pub struct StructureValidationError {
@@ -44,6 +71,78 @@ pub const STRUCTURE_MATCH_DESCRIPTORS: [&str; 19] = [
     "lipinskiHBA",
 ];
 
+// Whether the documents were indexed with `path` (dot separated) inside the JSON field
+// `field_name`, which indexes built before that data was added lack. An empty index has nothing to
+// miss. Term dictionary keys of a JSON field start with the path, its segments joined by \x01 and
+// ended by \0, so the terms of this path and the paths below it sort between "path\0" and
+// "path\x02"
+pub fn json_path_indexed(searcher: &Searcher, field_name: &str, path: &str) -> eyre::Result<bool> {
+    if searcher.num_docs() == 0 {
+        return Ok(true);
+    }
+
+    let field = searcher.schema().get_field(field_name)?;
+    let path = path.replace('.', "\u{1}");
+    let start = format!("{path}\0");
+    let end = format!("{path}\u{2}");
+
+    for segment_reader in searcher.segment_readers() {
+        let inverted_index = segment_reader.inverted_index(field)?;
+        let mut terms = inverted_index
+            .terms()
+            .range()
+            .ge(&start)
+            .lt(&end)
+            .into_stream()?;
+
+        if terms.advance() {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Stable across builds and platforms, unlike the std hashers
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// The rdkit crate keeps the RDKit molecule behind an ROMol to itself, so the rdkit-sys bindings get
+// their own copy, parsed from its canonical SMILES
+pub fn rdkit_sys_mol(mol: &ROMol) -> eyre::Result<SharedPtr<ro_mol_ffi::ROMol>> {
+    let_cxx_string!(smiles = mol.as_smiles());
+    let ptr = ro_mol_ffi::smiles_to_mol(&smiles).map_err(|e| eyre::eyre!("{e}"))?;
+
+    if ptr.is_null() {
+        return Err(eyre::eyre!("Could not parse {}", mol.as_smiles()));
+    }
+
+    Ok(ptr)
+}
+
+// Same, but going through its molblock, which keeps the atom and bond order (and the 2D layout) of
+// `mol` so that indices into it stay valid. Query molecules do not survive sanitization and are
+// taken as they are
+pub fn rdkit_sys_mol_in_order(
+    mol: &ROMol,
+    sanitize: bool,
+) -> eyre::Result<SharedPtr<ro_mol_ffi::ROMol>> {
+    let_cxx_string!(mol_block = mol.to_molblock());
+    let rw_mol = rw_mol_ffi::rw_mol_from_mol_block(&mol_block, sanitize, false, false);
+
+    if rw_mol.is_null() {
+        return Err(eyre::eyre!(
+            "Could not parse the molblock of {}",
+            mol.as_smiles()
+        ));
+    }
+
+    Ok(rw_mol_ffi::rw_mol_to_ro_mol(rw_mol))
+}
+
 pub fn prepare_query_structure(
     smiles: &str,
 ) -> eyre::Result<(ROMol, Fingerprint, HashMap<String, f64>)> {
@@ -64,6 +163,52 @@ pub fn validate_structure(smiles: &str) -> eyre::Result<Vec<MolSanitizeException
     Ok(detect_chemistry_problems(&mol))
 }
 
+// SMARTS and molblock queries are used as-is (no standardization) so that atom lists, generic
+// atoms and query bonds survive all the way to the substruct_match confirmation step
+pub fn prepare_smarts_query(smarts: &str) -> eyre::Result<ROMol> {
+    if smarts.trim().is_empty() {
+        return Err(eyre::eyre!("Empty SMARTS query"));
+    }
+
+    // RDKit hands back a null molecule (rather than an error) for much of what it cannot parse,
+    // which the rdkit crate would wrap as if it were a molecule
+    let_cxx_string!(cxx_smarts = smarts);
+    smarts_ffi::check_smarts(&cxx_smarts).map_err(|e| eyre::eyre!("{e}"))?;
+
+    let rwmol = RWMol::from_smarts(smarts).map_err(|e| eyre::eyre!("{}", e))?;
+    Ok(rwmol.to_ro_mol())
+}
+
+pub fn prepare_mol_block_query(mol_block: &str) -> eyre::Result<ROMol> {
+    let rwmol = RWMol::from_mol_block(mol_block, true, true, false)
+        .ok_or(eyre::eyre!("Could not parse query molblock"))?;
+    Ok(rwmol.to_ro_mol())
+}
+
+// An extra query as a single clause to AND with the generated ones. The parentheses keep its ORs
+// and negations from binding to the neighbouring clauses. A group of nothing but negations matches
+// nothing, so such a query starts from all documents instead. Queries that do not parse are left
+// for the query parser to report
+pub fn extra_query_clause(extra_query: &str) -> String {
+    fn all_negative(ast: &UserInputAst) -> bool {
+        match ast {
+            UserInputAst::Clause(children) => {
+                !children.is_empty()
+                    && children
+                        .iter()
+                        .all(|(occur, child)| *occur == Some(Occur::MustNot) || all_negative(child))
+            }
+            UserInputAst::Boost(child, _) => all_negative(child),
+            UserInputAst::Leaf(_) => false,
+        }
+    }
+
+    match parse_query(extra_query) {
+        Ok(ast) if all_negative(&ast) => format!("(* {extra_query})"),
+        _ => format!("({extra_query})"),
+    }
+}
+
 #[derive(Object, Debug)]
 pub struct QuerySearchHit {
     pub extra_data: serde_json::Value,
@@ -78,8 +223,21 @@ pub struct StructureSearchHit {
     pub score: f32,
     pub query: String,
     pub used_tautomers: bool,
+    #[oai(skip_serializing_if_is_none)]
+    pub atom_matches: Option<Vec<AtomMatch>>,
+    #[oai(skip_serializing_if_is_none)]
+    pub svg: Option<String>,
+    #[oai(skip_serializing_if_is_none)]
+    pub rgroup_decomposition: Option<RGroupDecomposition>,
+}
+
+#[derive(Object, Debug, Clone, PartialEq)]
+pub struct AtomMatch {
+    pub atoms: Vec<u32>,
+    pub bonds: Vec<u32>,
 }
 
+// Hits come back in the order of `results`, which callers have already sorted
 pub fn aggregate_query_hits(
     searcher: Searcher,
     results: Vec<DocAddress>,
@@ -89,16 +247,18 @@ pub fn aggregate_query_hits(
     let smiles_field = schema.get_field("smiles")?;
     let extra_data_field = schema.get_field("extra_data")?;
 
-    let mut data_results = results
+    let final_results = results
         .into_par_iter()
         .filter_map(|result| {
             let smiles_and_extra_data =
                 get_smiles_and_extra_data(result, &searcher, smiles_field, extra_data_field);
 
             match smiles_and_extra_data {
-                Ok((smiles, extra_data)) => {
-                    Some((smiles, extra_data, result.segment_ord, result.doc_id))
-                }
+                Ok((smiles, extra_data)) => Some(QuerySearchHit {
+                    extra_data,
+                    smiles,
+                    query: query.into(),
+                }),
                 Err(e) => {
                     log::error!("{:?}", e);
                     None
@@ -107,19 +267,10 @@ pub fn aggregate_query_hits(
         })
         .collect::<Vec<_>>();
 
-    let sorted_results = sort_results(&mut data_results)
-        .into_iter()
-        .map(|(smiles, extra_data)| QuerySearchHit {
-            extra_data,
-            smiles,
-            query: query.into(),
-        })
-        .collect::<Vec<_>>();
-
-    Ok(sorted_results)
+    Ok(final_results)
 }
 
-fn get_smiles_and_extra_data(
+pub fn get_smiles_and_extra_data(
     docaddr: DocAddress,
     searcher: &Searcher,
     smiles_field: Field,
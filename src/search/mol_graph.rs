@@ -0,0 +1,233 @@
+use crate::rdkit_bridge::mol_graph_ffi;
+use crate::search::rdkit_sys_mol_in_order;
+use rdkit::ROMol;
+use std::collections::VecDeque;
+
+// The rdkit crate only exposes per-atom properties, so bonds and isotopes are read from an
+// rdkit-sys copy of the molecule that keeps its atom order, and the remaining atom properties from
+// the molecule itself
+#[derive(Debug, Clone)]
+pub struct MolGraph {
+    pub atoms: Vec<GraphAtom>,
+    pub bonds: Vec<GraphBond>,
+    // For every atom: (neighbor atom index, bond index)
+    pub neighbors: Vec<Vec<(usize, usize)>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphAtom {
+    pub symbol: String,
+    pub atomic_num: i32,
+    pub is_aromatic: bool,
+    pub formal_charge: i32,
+    pub total_num_hs: u32,
+    pub isotope: u32,
+    pub in_ring: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphBond {
+    pub begin: usize,
+    pub end: usize,
+    // 1, 2 and 3 for single, double and triple bonds, 4 for aromatic bonds as RDKit perceives them
+    pub order: u8,
+    pub in_ring: bool,
+}
+
+impl MolGraph {
+    pub fn from_romol(mol: &ROMol) -> eyre::Result<MolGraph> {
+        let rdkit_mol = rdkit_sys_mol_in_order(mol, true)?;
+        let isotopes = mol_graph_ffi::atom_isotopes(&rdkit_mol);
+        let num_atoms = isotopes.len();
+
+        let mut mol = mol.clone();
+        let mut atoms = Vec::with_capacity(num_atoms);
+        for (idx, isotope) in isotopes.into_iter().enumerate() {
+            let atom = mol.atom_with_idx(idx as u32);
+            atoms.push(GraphAtom {
+                symbol: atom.symbol(),
+                atomic_num: atom.get_atomic_num(),
+                is_aromatic: atom.get_is_aromatic(),
+                formal_charge: atom.get_formal_charge(),
+                total_num_hs: atom.get_total_num_hs(),
+                isotope,
+                in_ring: false,
+            });
+        }
+
+        let bond_table = mol_graph_ffi::mol_bonds(&rdkit_mol);
+        let mut neighbors = vec![Vec::new(); num_atoms];
+        let mut bonds = Vec::with_capacity(bond_table.len());
+        for (bond_idx, mol_bond) in bond_table.into_iter().enumerate() {
+            let (begin, end) = (mol_bond.begin as usize, mol_bond.end as usize);
+
+            neighbors[begin].push((end, bond_idx));
+            neighbors[end].push((begin, bond_idx));
+            bonds.push(GraphBond {
+                begin,
+                end,
+                order: mol_bond.order,
+                in_ring: false,
+            });
+        }
+
+        let mut graph = MolGraph {
+            atoms,
+            bonds,
+            neighbors,
+        };
+
+        graph.assign_rings();
+
+        Ok(graph)
+    }
+
+    pub fn num_atoms(&self) -> usize {
+        self.atoms.len()
+    }
+
+    pub fn degree(&self, atom_idx: usize) -> usize {
+        self.neighbors[atom_idx].len()
+    }
+
+    // Topological distances from one atom to all others (usize::MAX when disconnected)
+    pub fn distances_from(&self, atom_idx: usize) -> Vec<usize> {
+        let mut distances = vec![usize::MAX; self.num_atoms()];
+        let mut queue = VecDeque::from([atom_idx]);
+        distances[atom_idx] = 0;
+
+        while let Some(current) = queue.pop_front() {
+            for (neighbor, _) in &self.neighbors[current] {
+                if distances[*neighbor] == usize::MAX {
+                    distances[*neighbor] = distances[current] + 1;
+                    queue.push_back(*neighbor);
+                }
+            }
+        }
+
+        distances
+    }
+
+    pub fn num_fragments(&self) -> usize {
+        let mut seen = vec![false; self.num_atoms()];
+        let mut fragments = 0;
+
+        for atom_idx in 0..self.num_atoms() {
+            if seen[atom_idx] {
+                continue;
+            }
+
+            fragments += 1;
+            for (other_idx, distance) in self.distances_from(atom_idx).into_iter().enumerate() {
+                if distance != usize::MAX {
+                    seen[other_idx] = true;
+                }
+            }
+        }
+
+        fragments
+    }
+
+    // Size of the smallest ring going through a ring bond
+    pub fn smallest_ring_size(&self, bond_idx: usize) -> Option<usize> {
+        let bond = &self.bonds[bond_idx];
+        if !bond.in_ring {
+            return None;
+        }
+
+        let mut distances = vec![usize::MAX; self.num_atoms()];
+        let mut queue = VecDeque::from([bond.begin]);
+        distances[bond.begin] = 0;
+
+        while let Some(current) = queue.pop_front() {
+            for (neighbor, other_bond_idx) in &self.neighbors[current] {
+                if *other_bond_idx == bond_idx || distances[*neighbor] != usize::MAX {
+                    continue;
+                }
+
+                distances[*neighbor] = distances[current] + 1;
+                if *neighbor == bond.end {
+                    return Some(distances[*neighbor] + 1);
+                }
+                queue.push_back(*neighbor);
+            }
+        }
+
+        None
+    }
+
+    // Ring bonds are exactly the bonds that are not bridges
+    fn assign_rings(&mut self) {
+        let num_atoms = self.num_atoms();
+        let mut discovery = vec![usize::MAX; num_atoms];
+        let mut low = vec![0; num_atoms];
+        let mut time = 0;
+
+        for root in 0..num_atoms {
+            if discovery[root] != usize::MAX {
+                continue;
+            }
+
+            // Iterative DFS: (atom, bond used to reach it, next neighbor position)
+            let mut stack = vec![(root, usize::MAX, 0)];
+            discovery[root] = time;
+            low[root] = time;
+            time += 1;
+
+            while let Some((atom_idx, parent_bond, position)) = stack.pop() {
+                if position < self.neighbors[atom_idx].len() {
+                    stack.push((atom_idx, parent_bond, position + 1));
+
+                    let (neighbor, bond_idx) = self.neighbors[atom_idx][position];
+                    if bond_idx == parent_bond {
+                        continue;
+                    }
+
+                    if discovery[neighbor] == usize::MAX {
+                        discovery[neighbor] = time;
+                        low[neighbor] = time;
+                        time += 1;
+                        stack.push((neighbor, bond_idx, 0));
+                    } else {
+                        low[atom_idx] = low[atom_idx].min(discovery[neighbor]);
+                    }
+                } else if parent_bond != usize::MAX {
+                    let bond = &self.bonds[parent_bond];
+                    let parent = if bond.begin == atom_idx {
+                        bond.end
+                    } else {
+                        bond.begin
+                    };
+
+                    low[parent] = low[parent].min(low[atom_idx]);
+                    if low[atom_idx] <= discovery[parent] {
+                        self.bonds[parent_bond].in_ring = true;
+                    }
+                }
+            }
+        }
+
+        for bond_idx in 0..self.bonds.len() {
+            let (begin, end, in_ring) = {
+                let bond = &self.bonds[bond_idx];
+                (bond.begin, bond.end, bond.in_ring)
+            };
+
+            if in_ring {
+                self.atoms[begin].in_ring = true;
+                self.atoms[end].in_ring = true;
+            }
+        }
+    }
+}
+
+// Only the (begin, end) atom indices of every bond, so unlike `MolGraph::from_romol` this never
+// asks RDKit for atom properties and also works for SMARTS and molblock query molecules
+pub fn bond_atoms(mol: &ROMol) -> eyre::Result<Vec<(usize, usize)>> {
+    Ok(
+        mol_graph_ffi::mol_bonds(&rdkit_sys_mol_in_order(mol, false)?)
+            .into_iter()
+            .map(|mol_bond| (mol_bond.begin as usize, mol_bond.end as usize))
+            .collect(),
+    )
+}
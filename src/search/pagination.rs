@@ -0,0 +1,117 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use tantivy::{DocAddress, Searcher};
+
+// Cursors are tied to the exact set of segments (and their deletes) a searcher sees; any commit,
+// delete or merge changes that set, which would silently shift the doc addresses we resume from
+pub fn searcher_generation(searcher: &Searcher) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    searcher.generation().segments().hash(&mut hasher);
+    hasher.finish()
+}
+
+// Ties a cursor to the query it was issued for. Only what decides the hits and their order goes in,
+// so the page size (and what hits are annotated with) can still change from one page to the next
+pub fn query_hash(method: &str, query: &str, params: &serde_json::Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (method, query, params.to_string()).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchCursor {
+    pub generation: u64,
+    pub query_hash: u64,
+    pub last_doc: DocAddress,
+    pub last_score: Option<f32>,
+}
+
+impl SearchCursor {
+    pub fn new(
+        searcher: &Searcher,
+        query_hash: u64,
+        last_doc: DocAddress,
+        last_score: Option<f32>,
+    ) -> Self {
+        SearchCursor {
+            generation: searcher_generation(searcher),
+            query_hash,
+            last_doc,
+            last_score,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let cursor = format!(
+            "{:016x}{:016x}{:08x}{:08x}",
+            self.generation, self.query_hash, self.last_doc.segment_ord, self.last_doc.doc_id
+        );
+
+        match self.last_score {
+            Some(score) => format!("{cursor}{:08x}", score.to_bits()),
+            None => cursor,
+        }
+    }
+
+    pub fn decode(cursor: &str) -> eyre::Result<Self> {
+        if !(cursor.len() == 48 || cursor.len() == 56) || !cursor.is_ascii() {
+            return Err(eyre::eyre!("Malformed cursor"));
+        }
+
+        let parse_part = |start: usize, end: usize| {
+            u64::from_str_radix(&cursor[start..end], 16)
+                .map_err(|_| eyre::eyre!("Malformed cursor"))
+        };
+
+        let generation = parse_part(0, 16)?;
+        let query_hash = parse_part(16, 32)?;
+        let segment_ord = parse_part(32, 40)? as u32;
+        let doc_id = parse_part(40, 48)? as u32;
+
+        let last_score = if cursor.len() == 56 {
+            Some(f32::from_bits(parse_part(48, 56)? as u32))
+        } else {
+            None
+        };
+
+        Ok(SearchCursor {
+            generation,
+            query_hash,
+            last_doc: DocAddress::new(segment_ord, doc_id),
+            last_score,
+        })
+    }
+
+    pub fn validate(&self, searcher: &Searcher, query_hash: u64) -> eyre::Result<()> {
+        if self.query_hash != query_hash {
+            return Err(eyre::eyre!(
+                "Cursor was issued for a different query or different search parameters"
+            ));
+        }
+
+        if self.generation != searcher_generation(searcher) {
+            return Err(eyre::eyre!(
+                "Cursor is no longer valid: the index has been modified since it was issued"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Decodes a client-supplied cursor and checks that it was issued for the same query, against the
+// searcher's generation
+pub fn resume_cursor(
+    cursor: Option<&str>,
+    searcher: &Searcher,
+    query_hash: u64,
+) -> eyre::Result<Option<SearchCursor>> {
+    match cursor {
+        Some(cursor) => {
+            let cursor = SearchCursor::decode(cursor)?;
+            cursor.validate(searcher, query_hash)?;
+            Ok(Some(cursor))
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,98 @@
+use crate::rdkit_bridge::rgroup_decomposition_ffi;
+use crate::search::{rdkit_sys_mol, rdkit_sys_mol_in_order};
+use cxx::SharedPtr;
+use poem_openapi::Object;
+use rdkit::ROMol;
+use rdkit_sys::ro_mol_ffi;
+
+#[derive(Object, Debug, Clone, PartialEq)]
+pub struct RGroupDecomposition {
+    /// The part of the molecule matching the core, with the R-groups replaced by labeled
+    /// attachment points
+    pub core: String,
+    /// The molecule's atoms matching the core's atoms (without its attachment points), in core
+    /// atom order
+    pub core_atoms: Vec<u32>,
+    pub rgroups: Vec<RGroup>,
+}
+
+#[derive(Object, Debug, Clone, PartialEq)]
+pub struct RGroup {
+    /// "R1", "R2", …
+    pub label: String,
+    /// The substituent with its attachment point(s) written as [*:n]; [H][*:n] when the position
+    /// is unsubstituted
+    pub smiles: String,
+}
+
+// A core with attachment points, e.g. c1ccc([*:1])cc1[*:2], decomposed by RDKit's
+// RGroupDecomposition. Attachment points are dummy atoms labeled by atom map number (or isotope);
+// RDKit numbers unlabeled ones and labels substituents on core atoms without an attachment point
+// after the core's own. The core is kept as SMILES so that it can be shared between threads
+pub struct RGroupCore {
+    smiles: String,
+}
+
+impl RGroupCore {
+    pub fn from_smiles(core_smiles: &str) -> eyre::Result<RGroupCore> {
+        let core = RGroupCore {
+            smiles: core_smiles.to_string(),
+        };
+
+        // Checks the attachment points
+        core.search_mol()?;
+
+        Ok(core)
+    }
+
+    // What RDKit looks for in a molecule before decomposing it: the core without its attachment
+    // points. Searching with it finds the molecules that decompose (the unsubstituted ones too)
+    pub fn search_mol(&self) -> eyre::Result<ROMol> {
+        let query = rgroup_decomposition_ffi::rgroup_core_query(&self.mol()?)
+            .map_err(|e| eyre::eyre!("{e}"))?;
+
+        ROMol::from_smiles(&ro_mol_ffi::mol_to_smiles(&query))
+            .map_err(|_| eyre::eyre!("Could not parse the core without its attachment points"))
+    }
+
+    pub fn decompose(&self, smiles: &str) -> eyre::Result<RGroupDecomposition> {
+        let mol = ROMol::from_smiles(smiles).map_err(|_| eyre::eyre!("Could not parse SMILES"))?;
+
+        // In order, so that core_atoms index into the molecule as parsed from `smiles`
+        let row = rgroup_decomposition_ffi::rgroup_decompose(
+            &self.mol()?,
+            &rdkit_sys_mol_in_order(&mol, true)?,
+        )
+        .map_err(|e| eyre::eyre!("{e}"))?;
+
+        let mut rgroups = row
+            .rgroups
+            .into_iter()
+            .map(|rgroup| RGroup {
+                label: rgroup.label,
+                smiles: rgroup.smiles,
+            })
+            .collect::<Vec<_>>();
+
+        // RDKit sorts the labels as strings, which puts R10 before R2
+        rgroups.sort_by_key(|rgroup| {
+            rgroup
+                .label
+                .strip_prefix('R')
+                .and_then(|number| number.parse::<u32>().ok())
+                .unwrap_or(u32::MAX)
+        });
+
+        Ok(RGroupDecomposition {
+            core: row.core,
+            core_atoms: row.core_atoms,
+            rgroups,
+        })
+    }
+
+    fn mol(&self) -> eyre::Result<SharedPtr<ro_mol_ffi::ROMol>> {
+        let core = ROMol::from_smiles(&self.smiles)
+            .map_err(|_| eyre::eyre!("Could not parse core SMILES"))?;
+        rdkit_sys_mol(&core)
+    }
+}
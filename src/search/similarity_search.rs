@@ -1,5 +1,7 @@
 use crate::search::basic_search::basic_search;
-use crate::search::StructureSearchHit;
+use crate::search::cancellation::CancellationToken;
+use crate::search::fingerprints::fingerprint_field;
+use crate::search::{extra_query_clause, StructureSearchHit};
 use bitvec::order::Lsb0;
 use bitvec::prelude::{BitSlice, BitVec};
 use cheminee_similarity_model::encoder::{build_encoder_model, NUM_CLUSTERS};
@@ -9,60 +11,176 @@ use std::collections::HashSet;
 use tantivy::schema::{Field, OwnedValue};
 use tantivy::{DocAddress, Searcher};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimilarityMetric {
+    Tanimoto,
+    Dice,
+    Cosine,
+    // Asymmetric: alpha weighs the bits only the query has, beta the bits only the candidate has
+    Tversky { alpha: f32, beta: f32 },
+}
+
+impl SimilarityMetric {
+    pub fn from_name(metric: &str, tversky_alpha: f32, tversky_beta: f32) -> eyre::Result<Self> {
+        match metric {
+            "tanimoto" => Ok(SimilarityMetric::Tanimoto),
+            "dice" => Ok(SimilarityMetric::Dice),
+            "cosine" => Ok(SimilarityMetric::Cosine),
+            "tversky" => {
+                if tversky_alpha < 0.0 || tversky_beta < 0.0 {
+                    return Err(eyre::eyre!("Tversky weights must not be negative"));
+                }
+
+                Ok(SimilarityMetric::Tversky {
+                    alpha: tversky_alpha,
+                    beta: tversky_beta,
+                })
+            }
+            other => Err(eyre::eyre!("Unknown similarity metric: {other}")),
+        }
+    }
+
+    pub fn similarity(&self, query_fp: &BitSlice<u8>, fp: &BitSlice<u8>) -> f32 {
+        self.similarity_from_counts(
+            (query_fp.to_bitvec() & fp).count_ones(),
+            query_fp.count_ones(),
+            fp.count_ones(),
+        )
+    }
+
+    // Same as `similarity`, straight off the raw fingerprint bytes (e.g. from a fast field column)
+    pub fn similarity_from_bytes(&self, query_fp: &[u8], fp: &[u8]) -> f32 {
+        let mut counts = (0, 0, 0);
+        let mut query_chunks = query_fp.chunks(8);
+        let mut fp_chunks = fp.chunks(8);
+
+        loop {
+            let (query_word, fp_word) = match (query_chunks.next(), fp_chunks.next()) {
+                (None, None) => break,
+                (query_chunk, fp_chunk) => (to_word(query_chunk), to_word(fp_chunk)),
+            };
+
+            counts.0 += (query_word & fp_word).count_ones() as usize;
+            counts.1 += query_word.count_ones() as usize;
+            counts.2 += fp_word.count_ones() as usize;
+        }
+
+        self.similarity_from_counts(counts.0, counts.1, counts.2)
+    }
+
+    fn similarity_from_counts(&self, common: usize, query_ones: usize, fp_ones: usize) -> f32 {
+        let (common, query_ones, fp_ones) = (common as f32, query_ones as f32, fp_ones as f32);
+
+        let (numerator, denominator) = match self {
+            SimilarityMetric::Tanimoto => (common, query_ones + fp_ones - common),
+            SimilarityMetric::Dice => (2.0 * common, query_ones + fp_ones),
+            SimilarityMetric::Cosine => (common, (query_ones * fp_ones).sqrt()),
+            SimilarityMetric::Tversky { alpha, beta } => (
+                common,
+                common + alpha * (query_ones - common) + beta * (fp_ones - common),
+            ),
+        };
+
+        if denominator == 0.0 {
+            0.0
+        } else {
+            numerator / denominator
+        }
+    }
+}
+
+fn to_word(chunk: Option<&[u8]>) -> u64 {
+    let mut word = [0u8; 8];
+    if let Some(chunk) = chunk {
+        word[..chunk.len()].copy_from_slice(chunk);
+    }
+
+    u64::from_le_bytes(word)
+}
+
+// `taut_fingerprints` must be of the kind named by `fingerprint`, whose stored field is read
+// from each candidate; `similarity_minimum` applies to the chosen metric. Candidates left once
+// `token` is cancelled are skipped
+#[allow(clippy::too_many_arguments)]
 pub fn similarity_search(
     searcher: &Searcher,
     results: HashSet<DocAddress>,
-    taut_morgan_fingerprints: &[BitVec<u8>],
-    tanimoto_minimum: f32,
+    taut_fingerprints: &[BitVec<u8>],
+    fingerprint: &str,
+    metric: SimilarityMetric,
+    similarity_minimum: f32,
     query_smiles: &str,
-) -> eyre::Result<Vec<StructureSearchHit>> {
+    after: Option<(f32, DocAddress)>,
+    token: &CancellationToken,
+) -> eyre::Result<Vec<(StructureSearchHit, DocAddress)>> {
     let schema = searcher.schema();
     let smiles_field = schema.get_field("smiles")?;
-    let morgan_fingerprint_field = schema.get_field("morgan_fingerprint")?;
+    let fingerprint_field = schema
+        .get_field(&fingerprint_field(fingerprint))
+        .map_err(|_| eyre::eyre!("Index schema does not store {fingerprint} fingerprints"))?;
     let extra_data_field = schema.get_field("extra_data")?;
 
-    let used_tautomers = taut_morgan_fingerprints.len() > 1;
+    let used_tautomers = taut_fingerprints.len() > 1;
 
     let mut final_results = results
         .into_par_iter()
         .filter_map(|docaddr| {
+            if token.is_cancelled() {
+                return None;
+            }
+
             let result = get_best_similarity(
                 searcher,
                 &docaddr,
                 smiles_field,
-                morgan_fingerprint_field,
+                fingerprint_field,
                 extra_data_field,
-                taut_morgan_fingerprints,
+                taut_fingerprints,
+                metric,
             );
 
             match result {
                 Ok(result) => {
-                    if result.2 < tanimoto_minimum {
+                    if result.2 < similarity_minimum {
                         None
                     } else {
-                        Some(StructureSearchHit {
-                            smiles: result.0,
-                            extra_data: result.1,
-                            score: result.2,
-                            query: query_smiles.into(),
-                            used_tautomers,
-                        })
+                        Some((
+                            StructureSearchHit {
+                                smiles: result.0,
+                                extra_data: result.1,
+                                score: result.2,
+                                query: query_smiles.into(),
+                                used_tautomers,
+                                atom_matches: None,
+                                svg: None,
+                                rgroup_decomposition: None,
+                            },
+                            docaddr,
+                        ))
                     }
                 }
                 Err(e) => {
-                    log::warn!("Encountered exception in Tanimoto calculation: {e}");
+                    log::warn!("Encountered exception in similarity calculation: {e}");
                     None
                 }
             }
         })
         .collect::<Vec<_>>();
 
+    // Ties are broken on doc address so that the ordering is total and pages never overlap
     final_results.sort_by(|a, b| {
-        b.score
-            .partial_cmp(&a.score)
+        b.0.score
+            .partial_cmp(&a.0.score)
             .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.1.cmp(&b.1))
     });
 
+    if let Some((last_score, last_doc)) = after {
+        final_results.retain(|(hit, docaddr)| {
+            hit.score < last_score || (hit.score == last_score && *docaddr > last_doc)
+        });
+    }
+
     Ok(final_results)
 }
 
@@ -90,24 +208,20 @@ pub fn get_best_similarity(
     searcher: &Searcher,
     docaddr: &DocAddress,
     smiles_field: Field,
-    morgan_fingerprint_field: Field,
+    fingerprint_field: Field,
     extra_data_field: Field,
     taut_fingerprints: &[BitVec<u8>],
+    metric: SimilarityMetric,
 ) -> eyre::Result<(String, serde_json::Value, f32)> {
     let doc = searcher.doc::<tantivy::TantivyDocument>(*docaddr)?;
 
     let fingerprint = doc
-        .get_first(morgan_fingerprint_field)
-        .ok_or(eyre::eyre!("Tantivy pattern fingerprint retrieval failed"))?;
+        .get_first(fingerprint_field)
+        .ok_or(eyre::eyre!("Tantivy fingerprint retrieval failed"))?;
 
     let fingerprint = match fingerprint {
         OwnedValue::Bytes(f) => f,
-        other => {
-            return Err(eyre::eyre!(
-                "could not fetch pattern_fingerprint, got {:?}",
-                other
-            ))
-        }
+        other => return Err(eyre::eyre!("could not fetch fingerprint, got {:?}", other)),
     };
 
     let fingerprint = BitSlice::<u8, Lsb0>::from_slice(fingerprint);
@@ -128,7 +242,7 @@ pub fn get_best_similarity(
 
     let score = taut_fingerprints
         .iter()
-        .map(|fp| get_tanimoto_similarity(fp, fingerprint))
+        .map(|fp| metric.similarity(fp, fingerprint))
         .fold(f32::MIN, |max, x| x.max(max));
 
     Ok((smiles.to_string(), extra_data, score))
@@ -194,9 +308,7 @@ pub fn build_similarity_query(
     let mut query_parts = vec![format!("({cluster_query})")];
 
     if !extra_query.is_empty() {
-        for subquery in extra_query.split(" AND ") {
-            query_parts.push(subquery.to_string());
-        }
+        query_parts.push(extra_query_clause(extra_query));
     }
 
     Ok(query_parts.join(" AND "))
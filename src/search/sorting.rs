@@ -0,0 +1,124 @@
+use tantivy::columnar::DynamicColumn;
+use tantivy::{DocAddress, Searcher};
+
+// Orders documents by a numeric fast field: a descriptor, or extra_data.{key} when extra_data is a
+// fast field (descriptor_v4 indexes). Only fast fields are read, so keys are cheap to fetch for
+// every matching document, stored documents included or not. Documents without a numeric value
+// for the field come last in either order
+pub struct SortKeys {
+    segment_columns: Vec<Vec<DynamicColumn>>,
+    descending: bool,
+}
+
+impl SortKeys {
+    pub fn new(searcher: &Searcher, sort_by: &str, order: &str) -> eyre::Result<Self> {
+        let descending = match order {
+            "asc" => false,
+            "desc" => true,
+            other => return Err(eyre::eyre!("Unknown order: {other}; expected asc or desc")),
+        };
+
+        let schema = searcher.schema();
+        let field_name = match sort_by.split_once('.') {
+            Some((field_name, _)) => field_name,
+            None => sort_by,
+        };
+
+        let is_fast = schema
+            .get_field(field_name)
+            .map(|field| schema.get_field_entry(field).is_fast())
+            .unwrap_or(false);
+
+        if !is_fast {
+            return Err(eyre::eyre!(
+                "Index schema does not store {sort_by} as a fast field; sorting by anything but exactmw requires a descriptor_v4 index"
+            ));
+        }
+
+        let mut segment_columns = Vec::with_capacity(searcher.segment_readers().len());
+        for segment_reader in searcher.segment_readers() {
+            let mut columns = Vec::new();
+            for handle in segment_reader
+                .fast_fields()
+                .dynamic_column_handles(sort_by)?
+            {
+                columns.push(handle.open()?);
+            }
+            segment_columns.push(columns);
+        }
+
+        Ok(SortKeys {
+            segment_columns,
+            descending,
+        })
+    }
+
+    pub fn value(&self, doc: DocAddress) -> Option<f64> {
+        let columns = self.segment_columns.get(doc.segment_ord as usize)?;
+
+        columns.iter().find_map(|column| match column {
+            DynamicColumn::F64(column) => column.first(doc.doc_id),
+            DynamicColumn::I64(column) => column.first(doc.doc_id).map(|v| v as f64),
+            DynamicColumn::U64(column) => column.first(doc.doc_id).map(|v| v as f64),
+            DynamicColumn::Bool(column) => column.first(doc.doc_id).map(|v| v as u8 as f64),
+            _ => None,
+        })
+    }
+
+    // Stable, so documents with equal keys keep the order they came in
+    pub fn sort<T>(&self, items: &mut [T], docaddr: impl Fn(&T) -> DocAddress) {
+        items.sort_by_cached_key(|item| self.rank_doc(docaddr(item)));
+    }
+
+    // The first `limit` documents in sort order, ties broken on doc address
+    pub fn top_docs(
+        &self,
+        docs: impl Iterator<Item = DocAddress>,
+        limit: usize,
+    ) -> Vec<DocAddress> {
+        let mut ranked_docs = docs
+            .map(|doc| (self.rank_doc(doc), doc))
+            .collect::<Vec<_>>();
+
+        if ranked_docs.len() > limit && limit > 0 {
+            ranked_docs.select_nth_unstable(limit - 1);
+        }
+        ranked_docs.truncate(limit);
+        ranked_docs.sort_unstable();
+
+        ranked_docs.into_iter().map(|(_, doc)| doc).collect()
+    }
+
+    // Ranks compare across SortKeys for the same field and order, e.g. to merge the hits of
+    // several indexes
+    pub fn rank_doc(&self, doc: DocAddress) -> (bool, RankedValue) {
+        self.rank(self.value(doc))
+    }
+
+    // Missing values rank after every present one; present values rank by their total order,
+    // reversed for descending sorts
+    fn rank(&self, value: Option<f64>) -> (bool, RankedValue) {
+        match value {
+            Some(value) if self.descending => (false, RankedValue(-value)),
+            Some(value) => (false, RankedValue(value)),
+            None => (true, RankedValue(0.0)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankedValue(f64);
+
+impl Eq for RankedValue {}
+
+impl Ord for RankedValue {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl PartialOrd for RankedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
@@ -0,0 +1,262 @@
+use crate::rdkit_bridge::filter_catalog_ffi;
+use crate::search::{extra_query_clause, fnv1a, json_path_indexed, rdkit_sys_mol};
+use cxx::{let_cxx_string, SharedPtr};
+use poem_openapi::Object;
+use rdkit::{substruct_match, ROMol, RWMol, SubstructMatchParameters};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::Mutex;
+use tantivy::Index;
+
+const REACTIVE_ALERTS: &str = include_str!("../../assets/reactive_alerts_20261018.json");
+
+// "pains" selects all three PAINS sets at once
+pub const ALERT_SETS: [&str; 5] = ["pains_a", "pains_b", "pains_c", "brenk", "reactive"];
+
+// (set, RDKit filter catalog). The PAINS and Brenk sets are the ones RDKit publishes, under their
+// original names
+const CATALOG_SETS: [(&str, &str); 4] = [
+    ("pains_a", "PAINS_A"),
+    ("pains_b", "PAINS_B"),
+    ("pains_c", "PAINS_C"),
+    ("brenk", "BRENK"),
+];
+
+pub struct StructuralAlert {
+    pub pattern: AlertPattern,
+    pub idx: i64,
+    pub set: String,
+    pub name: String,
+}
+
+pub enum AlertPattern {
+    // Matched by the filter catalog of the alert's set, which also takes care of entries that need
+    // more than one match or combine several patterns
+    Catalog,
+    Smarts(Arc<Mutex<ROMol>>),
+}
+
+#[derive(Object, Debug, Clone, PartialEq)]
+pub struct AlertHit {
+    pub alert_id: i64,
+    /// One of pains_a, pains_b, pains_c, brenk or reactive
+    pub set: String,
+    pub name: String,
+}
+
+struct AlertCatalog {
+    catalog: SharedPtr<filter_catalog_ffi::FilterCatalog>,
+    // Alert id of every catalog entry, by entry index
+    ids: Vec<i64>,
+}
+
+lazy_static::lazy_static! {
+    static ref ALERT_CATALOGS: Vec<AlertCatalog> = CATALOG_SETS
+    .iter()
+    .map(|(set, catalog_name)| {
+        let_cxx_string!(catalog_name = *catalog_name);
+        let catalog = filter_catalog_ffi::new_filter_catalog(&catalog_name)
+            .expect("failed to load RDKit filter catalog");
+        let ids = alert_ids(set, &filter_catalog_ffi::filter_catalog_descriptions(&catalog));
+
+        AlertCatalog { catalog, ids }
+    })
+    .collect();
+
+    pub static ref PARSED_ALERTS: Vec<StructuralAlert> = {
+        let catalog_alerts = CATALOG_SETS
+            .iter()
+            .zip(ALERT_CATALOGS.iter())
+            .flat_map(|((set, _), alert_catalog)| {
+                filter_catalog_ffi::filter_catalog_descriptions(&alert_catalog.catalog)
+                    .into_iter()
+                    .zip(alert_catalog.ids.iter())
+                    .map(move |(name, idx)| StructuralAlert {
+                        pattern: AlertPattern::Catalog,
+                        idx: *idx,
+                        set: set.to_string(),
+                        name,
+                    })
+            });
+
+        let reactive_alerts = REACTIVE_ALERTS
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .map(|v| {
+                let get_str = |key: &str| {
+                    v.get(key)
+                        .unwrap_or_else(|| panic!("failed to get {key} from static data"))
+                        .as_str()
+                        .unwrap()
+                        .to_string()
+                };
+
+                let romol = RWMol::from_smarts(&get_str("smarts"))
+                    .expect("failed to parse static alert SMARTS")
+                    .to_ro_mol();
+
+                StructuralAlert {
+                    pattern: AlertPattern::Smarts(Arc::new(Mutex::new(romol))),
+                    idx: alert_id(&get_str("set"), &get_str("name")),
+                    set: get_str("set"),
+                    name: get_str("name"),
+                }
+            });
+
+        catalog_alerts.chain(reactive_alerts).collect()
+    };
+}
+
+// Alert ids hash the set and the alert's name, so they do not depend on where an entry sits in
+// RDKit's catalog (or in the reactive alerts file) and the ids stored in an index keep pointing at
+// the same alerts across RDKit versions. 31 bits keep them exact for JSON clients that read numbers
+// as doubles
+pub fn alert_id(set: &str, name: &str) -> i64 {
+    (fnv1a(format!("{set}/{name}").as_bytes()) & 0x7fff_ffff) as i64
+}
+
+// The ids of a catalog's entries, given their names in entry order. Repeated names are told apart
+// by how often they occurred before
+fn alert_ids(set: &str, names: &[String]) -> Vec<i64> {
+    let mut occurrences = HashMap::new();
+
+    names
+        .iter()
+        .map(|name| {
+            let occurrence = occurrences.entry(name.as_str()).or_insert(0);
+            *occurrence += 1;
+
+            match *occurrence {
+                1 => alert_id(set, name),
+                n => alert_id(set, &format!("{name}#{n}")),
+            }
+        })
+        .collect()
+}
+
+// Alert SMARTS are not plain structures, so unlike scaffolds there is no pattern fingerprint to
+// screen them with; every catalog and every SMARTS alert is matched against the molecule
+pub fn alert_search(mol: &ROMol, alerts: &[StructuralAlert]) -> eyre::Result<Vec<i64>> {
+    let params = SubstructMatchParameters::default();
+    let rdkit_mol = rdkit_sys_mol(mol)?;
+
+    let catalog_matches = ALERT_CATALOGS
+        .iter()
+        .flat_map(|alert_catalog| {
+            filter_catalog_ffi::filter_catalog_matches(&alert_catalog.catalog, &rdkit_mol)
+                .into_iter()
+                .map(|idx| alert_catalog.ids[idx as usize])
+        })
+        .collect::<HashSet<_>>();
+
+    Ok(alerts
+        .iter()
+        .filter(|alert| match &alert.pattern {
+            AlertPattern::Catalog => catalog_matches.contains(&alert.idx),
+            AlertPattern::Smarts(pattern) => {
+                !substruct_match(mol, &pattern.lock().unwrap(), &params).is_empty()
+            }
+        })
+        .map(|alert| alert.idx)
+        .collect())
+}
+
+pub fn alert_hits(alert_ids: &[i64], alerts: &[StructuralAlert]) -> Vec<AlertHit> {
+    alerts
+        .iter()
+        .filter(|alert| alert_ids.contains(&alert.idx))
+        .map(|alert| AlertHit {
+            alert_id: alert.idx,
+            set: alert.set.clone(),
+            name: alert.name.clone(),
+        })
+        .collect()
+}
+
+// Alert filters on documents indexed without alerts would silently exclude nothing or keep
+// nothing, so searches using them refuse such indexes
+pub fn check_alerts_indexed(index: Index, uses_alerts: bool) -> eyre::Result<Index> {
+    if uses_alerts
+        && !json_path_indexed(&index.reader()?.searcher(), "other_descriptors", "alerts")?
+    {
+        return Err(eyre::eyre!(
+            "Index has no structural alerts; re-index it to filter by alerts"
+        ));
+    }
+
+    Ok(index)
+}
+
+// A comma separated list of "pains", set names, alert names or alert ids
+pub fn resolve_alerts(selection: &str, alerts: &[StructuralAlert]) -> eyre::Result<Vec<i64>> {
+    let mut alert_ids = Vec::new();
+
+    for item in selection.split(',').map(|item| item.trim()) {
+        let selected = alerts
+            .iter()
+            .filter(|alert| match item {
+                "pains" => alert.set.starts_with("pains_"),
+                item if ALERT_SETS.contains(&item) => alert.set == item,
+                item => alert.name == item || alert.idx.to_string() == item,
+            })
+            .map(|alert| alert.idx)
+            .collect::<Vec<_>>();
+
+        if selected.is_empty() {
+            return Err(eyre::eyre!("Unknown structural alert or alert set: {item}"));
+        }
+
+        alert_ids.extend(selected);
+    }
+
+    alert_ids.sort();
+    alert_ids.dedup();
+
+    Ok(alert_ids)
+}
+
+// Query clauses to AND with the rest of a search query. Excluding has to start from all documents,
+// since a query made of nothing but negations matches nothing
+pub fn build_alert_filters(
+    exclude_alerts: Option<&str>,
+    only_alerts: Option<&str>,
+) -> eyre::Result<Vec<String>> {
+    let mut filters = Vec::new();
+
+    if let Some(exclude_alerts) = exclude_alerts {
+        let exclusions = resolve_alerts(exclude_alerts, &PARSED_ALERTS)?
+            .iter()
+            .map(|alert_id| format!("-other_descriptors.alerts:{alert_id}"))
+            .collect::<Vec<_>>();
+
+        filters.push(format!("(* {})", exclusions.join(" ")));
+    }
+
+    if let Some(only_alerts) = only_alerts {
+        let inclusions = resolve_alerts(only_alerts, &PARSED_ALERTS)?
+            .iter()
+            .map(|alert_id| format!("other_descriptors.alerts:{alert_id}"))
+            .collect::<Vec<_>>();
+
+        filters.push(format!("({})", inclusions.join(" OR ")));
+    }
+
+    Ok(filters)
+}
+
+// Adds the alert filters to an extra query (as taken by structure, similarity and identity search)
+pub fn add_alert_filters(
+    extra_query: &str,
+    exclude_alerts: Option<&str>,
+    only_alerts: Option<&str>,
+) -> eyre::Result<String> {
+    let mut query_parts = Vec::new();
+
+    if !extra_query.is_empty() {
+        query_parts.push(extra_query_clause(extra_query));
+    }
+
+    query_parts.extend(build_alert_filters(exclude_alerts, only_alerts)?);
+
+    Ok(query_parts.join(" AND "))
+}
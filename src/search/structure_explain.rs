@@ -0,0 +1,112 @@
+use crate::search::cancellation::CancellationToken;
+use crate::search::structure_search::{structure_search_streaming, StructureSearchStats};
+use poem_openapi::Object;
+use rdkit::ROMol;
+use std::time::Duration;
+use tantivy::Searcher;
+
+// How a substructure or superstructure search narrows the index down to its hits
+#[derive(Object, Debug)]
+pub struct StructureSearchExplanation {
+    /// The tantivy query that selects the candidates for the fingerprint screen
+    pub query: String,
+    /// The scaffolds found in the query; not set when scaffolds are not used for this query
+    #[oai(skip_serializing_if_is_none)]
+    pub matched_scaffolds: Option<Vec<i64>>,
+    /// In the order they run: query_preparation, descriptor_prefilter, scaffold_filter,
+    /// fingerprint_screen and structure_confirmation
+    pub stages: Vec<SearchStage>,
+    /// Confirmation stopped at result_limit hits (or the timeout), so the last two stages did not
+    /// see every candidate
+    pub truncated: bool,
+    /// The timeout stopped the search before it reached result_limit hits
+    pub timed_out: bool,
+}
+
+#[derive(Object, Debug)]
+pub struct SearchStage {
+    pub name: String,
+    /// Candidates left after this stage; not set for query_preparation
+    #[oai(skip_serializing_if_is_none)]
+    pub candidates: Option<usize>,
+    /// Wall time, except for fingerprint_screen and structure_confirmation, which run in
+    /// parallel and report the time summed over all threads
+    pub time_ms: f64,
+}
+
+// Runs the search itself with its stage counters switched on, until result_limit hits are
+// confirmed or `token` is cancelled
+#[allow(clippy::too_many_arguments)]
+pub fn explain_structure_search(
+    searcher: &Searcher,
+    query_mol: &ROMol,
+    method: &str,
+    use_scaffolds: bool,
+    result_limit: usize,
+    use_chirality: bool,
+    extra_query: &str,
+    generic_query: bool,
+    token: &CancellationToken,
+) -> eyre::Result<StructureSearchExplanation> {
+    let mut stats = StructureSearchStats::default();
+    let mut hit_count = 0;
+
+    let truncated = structure_search_streaming(
+        searcher,
+        query_mol,
+        method,
+        use_scaffolds,
+        use_chirality,
+        extra_query,
+        generic_query,
+        &[],
+        None,
+        None,
+        Some(&mut stats),
+        token,
+        |results_subset| {
+            hit_count += results_subset.len();
+            hit_count < result_limit
+        },
+    )?;
+
+    let stages = vec![
+        stage("query_preparation", None, stats.query_preparation),
+        stage(
+            "descriptor_prefilter",
+            Some(stats.descriptor_prefilter.0),
+            stats.descriptor_prefilter.1,
+        ),
+        stage(
+            "scaffold_filter",
+            Some(stats.scaffold_filter.0),
+            stats.scaffold_filter.1,
+        ),
+        stage(
+            "fingerprint_screen",
+            Some(stats.fingerprint_screen.passed()),
+            stats.fingerprint_screen.elapsed(),
+        ),
+        stage(
+            "structure_confirmation",
+            Some(stats.structure_confirmation.passed()),
+            stats.structure_confirmation.elapsed(),
+        ),
+    ];
+
+    Ok(StructureSearchExplanation {
+        query: stats.query,
+        matched_scaffolds: stats.matched_scaffolds,
+        stages,
+        truncated,
+        timed_out: token.timed_out(),
+    })
+}
+
+fn stage(name: &str, candidates: Option<usize>, elapsed: Duration) -> SearchStage {
+    SearchStage {
+        name: name.to_string(),
+        candidates,
+        time_ms: elapsed.as_secs_f64() * 1000.0,
+    }
+}
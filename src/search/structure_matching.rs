@@ -1,5 +1,8 @@
+use crate::search::mol_graph::bond_atoms;
+use crate::search::AtomMatch;
 use bitvec::prelude::BitSlice;
 use rdkit::*;
+use std::collections::HashMap;
 
 pub fn exact_match(romol1: &ROMol, romol2: &ROMol, use_chirality: bool) -> bool {
     let mut params = SubstructMatchParameters::default();
@@ -22,3 +25,72 @@ pub fn substructure_match_fp(
     let and_match = substructure_fp.to_bitvec() & superstructure_fp;
     and_match == substructure_fp
 }
+
+// Every mapping of the query onto a structure search hit. Indices refer to the molecule that was
+// searched within, i.e. the hit (parsed from its SMILES) for substructure searches and the query
+// molecule for superstructure searches. Atoms are listed in the order of the matched pattern's
+// atoms, bonds in the order of its bonds
+pub fn get_atom_matches(
+    query_mol: &ROMol,
+    hit_smiles: &str,
+    method: &str,
+    use_chirality: bool,
+) -> eyre::Result<Vec<AtomMatch>> {
+    let hit_mol = ROMol::from_smiles(hit_smiles)?;
+    let (target, pattern) = if method == "substructure" {
+        (&hit_mol, query_mol)
+    } else {
+        (query_mol, &hit_mol)
+    };
+
+    map_atoms(target, pattern, use_chirality)
+}
+
+// Every mapping of `pattern` onto `target`, as indices into `target`
+pub fn map_atoms(
+    target: &ROMol,
+    pattern: &ROMol,
+    use_chirality: bool,
+) -> eyre::Result<Vec<AtomMatch>> {
+    let mut params = SubstructMatchParameters::default();
+    params.set_use_chirality(use_chirality);
+
+    let target_bonds = bond_atoms(target)?
+        .into_iter()
+        .enumerate()
+        .flat_map(|(bond_idx, (begin, end))| [((begin, end), bond_idx), ((end, begin), bond_idx)])
+        .collect::<HashMap<_, _>>();
+    let pattern_bonds = bond_atoms(pattern)?;
+
+    let atom_matches = substruct_match(target, pattern, &params)
+        .into_iter()
+        .map(|mut mapping| {
+            mapping.sort_by_key(|item| item.query_atom_idx);
+
+            let atom_map = mapping
+                .iter()
+                .map(|item| (item.query_atom_idx as usize, item.mol_atom_idx as usize))
+                .collect::<HashMap<_, _>>();
+
+            let bonds = pattern_bonds
+                .iter()
+                .filter_map(|(begin, end)| {
+                    let target_atoms = (*atom_map.get(begin)?, *atom_map.get(end)?);
+                    target_bonds
+                        .get(&target_atoms)
+                        .map(|bond_idx| *bond_idx as u32)
+                })
+                .collect();
+
+            AtomMatch {
+                atoms: mapping
+                    .iter()
+                    .map(|item| item.mol_atom_idx as u32)
+                    .collect(),
+                bonds,
+            }
+        })
+        .collect();
+
+    Ok(atom_matches)
+}
@@ -1,7 +1,12 @@
+use crate::search::cancellation::CancellationToken;
 use crate::search::compound_processing::get_cpd_properties;
+use crate::search::excluded_patterns::{matches_excluded_pattern, ExcludedPattern};
 use crate::search::scaffold_search::{scaffold_search, PARSED_SCAFFOLDS};
+use crate::search::sorting::SortKeys;
 use crate::search::{
-    basic_search::basic_search, structure_matching::substructure_match_fp,
+    basic_search::{basic_search_count, basic_search_stream_after},
+    extra_query_clause,
+    structure_matching::substructure_match_fp,
     STRUCTURE_MATCH_DESCRIPTORS,
 };
 use bitvec::prelude::{BitSlice, Lsb0};
@@ -9,10 +14,13 @@ use rayon::prelude::*;
 use rdkit::{substruct_match, ROMol, SubstructMatchParameters};
 use regex::Regex;
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tantivy::schema::Field;
 use tantivy::{DocAddress, DocId, Searcher, SegmentOrdinal};
 
+#[allow(clippy::too_many_arguments)]
 pub fn structure_search(
     searcher: &Searcher,
     query_mol: &ROMol,
@@ -21,16 +29,142 @@ pub fn structure_search(
     result_limit: usize,
     use_chirality: bool,
     extra_query: &str,
-) -> eyre::Result<HashSet<(String, serde_json::Value, SegmentOrdinal, DocId)>> {
+    generic_query: bool,
+    excluded_patterns: &[ExcludedPattern],
+    after: Option<DocAddress>,
+    sort_keys: Option<&SortKeys>,
+    token: &CancellationToken,
+) -> eyre::Result<(
+    HashSet<(String, serde_json::Value, SegmentOrdinal, DocId)>,
+    bool,
+)> {
+    let mut filtered_results: HashSet<(String, serde_json::Value, SegmentOrdinal, DocId)> =
+        HashSet::new();
+
+    let mut truncated = structure_search_streaming(
+        searcher,
+        query_mol,
+        method,
+        use_scaffolds,
+        use_chirality,
+        extra_query,
+        generic_query,
+        excluded_patterns,
+        after,
+        sort_keys,
+        None,
+        token,
+        |results_subset| {
+            filtered_results.extend(results_subset);
+            filtered_results.len() < result_limit
+        },
+    )?;
+
+    if filtered_results.len() > result_limit {
+        truncated = true;
+    }
+
+    Ok((filtered_results, truncated))
+}
+
+// Per-stage counters and timers that `structure_search_streaming` fills in when it is given one.
+// The descriptor prefilter and scaffold filter counts are taken with extra count queries; the
+// fingerprint screen and structure confirmation only count the candidates the search got to, and
+// their times add up what every thread spent on them
+#[derive(Debug, Default)]
+pub struct StructureSearchStats {
+    pub query: String,
+    pub matched_scaffolds: Option<Vec<i64>>,
+    pub query_preparation: Duration,
+    pub descriptor_prefilter: (usize, Duration),
+    pub scaffold_filter: (usize, Duration),
+    pub fingerprint_screen: StageCounter,
+    pub structure_confirmation: StageCounter,
+}
+
+#[derive(Debug, Default)]
+pub struct StageCounter {
+    passed: AtomicUsize,
+    nanos: AtomicU64,
+}
+
+impl StageCounter {
+    pub fn record(&self, passed: bool, started: Instant) {
+        if passed {
+            self.passed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.nanos
+            .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn passed(&self) -> usize {
+        self.passed.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+// Confirms candidates one chunk at a time and hands each chunk's matches (in doc address order,
+// or in the order of `sort_keys` when given) to `on_results` as soon as they are known. Returning
+// false from `on_results` or cancelling `token` stops the search; the returned bool tells whether
+// unexamined candidates were left behind at that point. Matches that also contain one of
+// `excluded_patterns` are dropped. With `stats`, every stage is counted and timed along the way
+#[allow(clippy::too_many_arguments)]
+pub fn structure_search_streaming<F>(
+    searcher: &Searcher,
+    query_mol: &ROMol,
+    method: &str,
+    use_scaffolds: bool,
+    use_chirality: bool,
+    extra_query: &str,
+    generic_query: bool,
+    excluded_patterns: &[ExcludedPattern],
+    after: Option<DocAddress>,
+    sort_keys: Option<&SortKeys>,
+    mut stats: Option<&mut StructureSearchStats>,
+    token: &CancellationToken,
+    mut on_results: F,
+) -> eyre::Result<bool>
+where
+    F: FnMut(Vec<(String, serde_json::Value, SegmentOrdinal, DocId)>) -> bool,
+{
     let schema = searcher.schema();
 
-    let (query_pattern_fingerprint, query_descriptors) = get_cpd_properties(query_mol)?;
+    // Generic queries (SMARTS, molblocks with query features) can match atoms and bonds that the
+    // descriptors and scaffolds of the query molecule know nothing about, so we can only rely on
+    // the pattern fingerprint screen, which skips query atoms and bonds when it is generated
+    let timer = Instant::now();
+    let (query_pattern_fingerprint, query_descriptors) = if generic_query {
+        (query_mol.pattern_fingerprint(), None)
+    } else {
+        let (query_pattern_fingerprint, query_descriptors) = get_cpd_properties(query_mol)?;
+        (query_pattern_fingerprint, Some(query_descriptors))
+    };
+
+    if let Some(stats) = stats.as_mut() {
+        stats.query_preparation = timer.elapsed();
+    }
 
-    let query_pattern_fingerprint = query_pattern_fingerprint.0.as_bitslice();
+    let build_query = |scaffold_matches: &Option<Vec<i64>>| match &query_descriptors {
+        None => build_generic_query(extra_query),
+        Some(descriptors) if method == "substructure" => {
+            build_substructure_query(descriptors, extra_query, scaffold_matches)
+        }
+        Some(descriptors) => build_superstructure_query(descriptors, extra_query, scaffold_matches),
+    };
+
+    if let Some(stats) = stats.as_mut() {
+        let timer = Instant::now();
+        let prefilter_count = basic_search_count(searcher, &build_query(&None))?;
+        stats.descriptor_prefilter = (prefilter_count, timer.elapsed());
+    }
 
-    let scaffold_matches = if use_scaffolds {
+    let timer = Instant::now();
+    let scaffold_matches = if use_scaffolds && !generic_query {
         Some(scaffold_search(
-            query_pattern_fingerprint,
+            query_pattern_fingerprint.0.as_bitslice(),
             query_mol,
             &PARSED_SCAFFOLDS,
         )?)
@@ -38,14 +172,29 @@ pub fn structure_search(
         None
     };
 
-    let query = if method == "substructure" {
-        build_substructure_query(&query_descriptors, extra_query, &scaffold_matches)
-    } else {
-        build_superstructure_query(&query_descriptors, extra_query, &scaffold_matches)
-    };
+    let query = build_query(&scaffold_matches);
+
+    if let Some(stats) = stats.as_mut() {
+        let scaffold_count = match scaffold_matches {
+            Some(_) => basic_search_count(searcher, &query)?,
+            None => stats.descriptor_prefilter.0,
+        };
+        stats.scaffold_filter = (scaffold_count, timer.elapsed());
+        stats.query = query.clone();
+        stats.matched_scaffolds = scaffold_matches;
+    }
+
+    let stats = stats.as_deref();
+    let query_pattern_fingerprint = query_pattern_fingerprint.0.as_bitslice();
 
-    let tantivy_limit = 100_000;
-    let initial_results = basic_search(searcher, &query, tantivy_limit)?;
+    // Candidates stream in doc address order, so resuming a paginated search only needs the
+    // address of the last hit on the previous page. A sorted search ranks every candidate up front
+    // (through fast fields only) and confirms them in that order, so its first hits are the top ones
+    let candidates = basic_search_stream_after(searcher, &query, after)?;
+    let mut candidates: Box<dyn Iterator<Item = DocAddress>> = match sort_keys {
+        Some(sort_keys) => Box::new(sort_keys.top_docs(candidates, usize::MAX).into_iter()),
+        None => Box::new(candidates),
+    };
 
     let smiles_field = schema.get_field("smiles")?;
     let pattern_fingerprint_field = schema.get_field("pattern_fingerprint")?;
@@ -53,20 +202,27 @@ pub fn structure_search(
 
     let query_mol_mutex = Arc::new(Mutex::new(query_mol.clone()));
 
-    let mut result_count: usize = 0;
-    let mut filtered_results: HashSet<(String, serde_json::Value, SegmentOrdinal, DocId)> =
-        HashSet::new();
+    let mut stopped = false;
+
+    loop {
+        let chunk = candidates.by_ref().take(1000).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            return Ok(false);
+        }
 
-    for chunk in initial_results.chunks(1000) {
-        if result_count > result_limit {
-            break;
+        if stopped {
+            return Ok(true);
         }
 
-        let results_subset = chunk
+        let mut results_subset = chunk
             .into_par_iter()
             .filter_map(|result| {
+                if token.is_cancelled() {
+                    return None;
+                }
+
                 let struct_match = structure_match(
-                    *result,
+                    result,
                     smiles_field,
                     pattern_fingerprint_field,
                     extra_data_field,
@@ -75,6 +231,8 @@ pub fn structure_search(
                     query_pattern_fingerprint,
                     method,
                     use_chirality,
+                    excluded_patterns,
+                    stats,
                 );
 
                 struct_match.unwrap_or_else(|e| {
@@ -82,13 +240,19 @@ pub fn structure_search(
                     None
                 })
             })
-            .collect::<HashSet<_>>();
+            .collect::<Vec<_>>();
 
-        result_count += results_subset.len();
-        filtered_results.extend(results_subset);
-    }
+        if sort_keys.is_none() {
+            results_subset.sort_by_key(|result| (result.2, result.3));
+        }
+
+        stopped = !on_results(results_subset);
 
-    Ok(filtered_results)
+        // Part of the chunk may have been skipped, so there is no telling what was left behind
+        if token.is_cancelled() {
+            return Ok(true);
+        }
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -102,7 +266,10 @@ pub fn structure_match(
     query_pattern_fingerprint: &BitSlice<u8>,
     method: &str,
     use_chirality: bool,
+    excluded_patterns: &[ExcludedPattern],
+    stats: Option<&StructureSearchStats>,
 ) -> eyre::Result<Option<(String, serde_json::Value, SegmentOrdinal, DocId)>> {
+    let timer = Instant::now();
     let doc = searcher.doc::<tantivy::TantivyDocument>(docaddr)?;
 
     let smiles = doc
@@ -132,17 +299,36 @@ pub fn structure_match(
         substructure_match_fp(pattern_fingerprint_bits, query_pattern_fingerprint)
     };
 
+    if let Some(stats) = stats {
+        stats.fingerprint_screen.record(fp_match, timer);
+    }
+
     if fp_match {
+        let timer = Instant::now();
         let mut params = SubstructMatchParameters::default();
         params.set_use_chirality(use_chirality);
 
+        let hit_mol = ROMol::from_smiles(smiles)?;
         let mol_substruct_match = if method == "substructure" {
-            substruct_match(&ROMol::from_smiles(smiles)?, query_mol, &params)
+            substruct_match(&hit_mol, query_mol, &params)
         } else {
-            substruct_match(query_mol, &ROMol::from_smiles(smiles)?, &params)
+            substruct_match(query_mol, &hit_mol, &params)
         };
 
-        if !mol_substruct_match.is_empty() && query_mol.as_smiles() != *smiles {
+        // Exclusions only run on confirmed matches, which are the fewest candidates to check
+        let confirmed = !mol_substruct_match.is_empty()
+            && query_mol.as_smiles() != *smiles
+            && !matches_excluded_pattern(
+                || Ok(hit_mol),
+                pattern_fingerprint_bits,
+                excluded_patterns,
+            )?;
+
+        if let Some(stats) = stats {
+            stats.structure_confirmation.record(confirmed, timer);
+        }
+
+        if confirmed {
             let extra_data = match doc.get_first(extra_data_field) {
                 Some(extra_data) => serde_json::from_str(&serde_json::to_string(extra_data)?)?,
                 None => serde_json::Value::Object(Default::default()),
@@ -168,9 +354,7 @@ pub fn build_substructure_query(
     let mut query_parts = Vec::with_capacity(descriptors.len());
 
     if !extra_query.is_empty() {
-        for subquery in extra_query.split(" AND ") {
-            query_parts.push(subquery.to_string());
-        }
+        query_parts.push(extra_query_clause(extra_query));
     }
 
     for (k, v) in descriptors {
@@ -193,6 +377,14 @@ pub fn build_substructure_query(
     query_parts.join(" AND ")
 }
 
+pub fn build_generic_query(extra_query: &str) -> String {
+    if extra_query.is_empty() {
+        "*".to_string()
+    } else {
+        extra_query.to_string()
+    }
+}
+
 pub fn build_superstructure_query(
     descriptors: &HashMap<String, f64>,
     extra_query: &str,
@@ -201,9 +393,7 @@ pub fn build_superstructure_query(
     let mut query_parts = Vec::with_capacity(descriptors.len());
 
     if !extra_query.is_empty() {
-        for subquery in extra_query.split(" AND ") {
-            query_parts.push(subquery.to_string());
-        }
+        query_parts.push(extra_query_clause(extra_query));
     }
 
     for (k, v) in descriptors {
@@ -0,0 +1,633 @@
+use crate::indexing::KNOWN_DESCRIPTORS;
+use crate::search::basic_search::basic_search_stream;
+use crate::search::cancellation::CancellationToken;
+use crate::search::fingerprints::{fingerprint_field, get_fingerprint};
+use crate::search::formula::expand_formula_queries;
+use crate::search::identity_search::build_identity_query;
+use crate::search::scaffold_search::{scaffold_search, PARSED_SCAFFOLDS};
+use crate::search::similarity_search::SimilarityMetric;
+use crate::search::structure_matching::{exact_match, substructure_match_fp};
+use crate::search::structure_search::{build_substructure_query, build_superstructure_query};
+use crate::search::{
+    extra_query_clause, prepare_query_structure, prepare_smarts_query, StructureSearchHit,
+};
+use bitvec::prelude::{BitSlice, BitVec, Lsb0};
+use rayon::prelude::*;
+use rdkit::{substruct_match, ROMol, SubstructMatchParameters};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, OwnedValue};
+use tantivy::{DocAddress, Searcher};
+
+// A structured query as JSON, e.g.
+// {"and": [{"substructure": {"smarts": "c1ccccc1"}},
+//          {"not": {"substructure": {"smarts": "[N+](=O)[O-]"}}},
+//          {"or": [{"similar": {"smiles": "CCOc1ccccc1", "min": 0.7}},
+//                  {"descriptor": {"name": "exactmw", "max": 250}}]}]}
+// "query" takes any tantivy clause (formula:"..." included), as an extra query would
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryNode {
+    And(Vec<QueryNode>),
+    Or(Vec<QueryNode>),
+    Not(Box<QueryNode>),
+    Substructure(MoleculeClause),
+    Superstructure(MoleculeClause),
+    Identity(MoleculeClause),
+    Similar(SimilarClause),
+    Descriptor(DescriptorClause),
+    Query(String),
+}
+
+// SMARTS only make sense for substructure clauses; everything else takes SMILES
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MoleculeClause {
+    pub smiles: Option<String>,
+    pub smarts: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SimilarClause {
+    pub smiles: String,
+    // Lowest similarity that matches, under `metric`
+    pub min: f32,
+    pub fingerprint: Option<String>,
+    pub metric: Option<String>,
+}
+
+// Inclusive bounds; at least one of them must be given
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DescriptorClause {
+    pub name: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// A clause that can only be decided on the molecule (or fingerprints) of a candidate
+enum ChemistryClause {
+    Substructure {
+        mol: Arc<Mutex<ROMol>>,
+        fp: BitVec<u8>,
+    },
+    Superstructure {
+        mol: Arc<Mutex<ROMol>>,
+        fp: BitVec<u8>,
+    },
+    Identity {
+        mol: Arc<Mutex<ROMol>>,
+        fp: BitVec<u8>,
+    },
+    Similar {
+        field: Field,
+        fp: BitVec<u8>,
+        metric: SimilarityMetric,
+        min: f32,
+    },
+}
+
+enum PlanNode {
+    // Holds for every candidate, since the prefilter already enforces it
+    True,
+    // A tantivy clause, decided by the set of candidates that match it
+    Filter(String, HashSet<DocAddress>),
+    Chemistry(ChemistryClause),
+    And(Vec<PlanNode>),
+    Or(Vec<PlanNode>),
+    Not(Box<PlanNode>),
+}
+
+// A planned node with the tantivy query that every match of it satisfies (none when that could be
+// any document), whether that query is exactly the node, and how expensive the node is to
+// evaluate on a candidate
+struct Planned {
+    node: PlanNode,
+    prefilter: Option<String>,
+    exact: bool,
+    cost: u8,
+}
+
+pub struct QueryPlan {
+    // Every match of the query satisfies it, so only its documents are evaluated
+    pub prefilter: String,
+    root: PlanNode,
+    use_chirality: bool,
+    smiles_field: Field,
+    pattern_fingerprint_field: Field,
+    extra_data_field: Field,
+}
+
+impl QueryPlan {
+    // Descriptor ranges and tantivy clauses are pushed into the prefilter as they are; structure
+    // clauses add the descriptor and scaffold bounds their matches must meet (only where they are
+    // not negated), and similarity clauses add nothing. Clauses that the prefilter decides on its
+    // own are never evaluated again, the rest are evaluated cheapest first
+    pub fn new(
+        searcher: &Searcher,
+        query: &QueryNode,
+        use_chirality: bool,
+        use_scaffolds: bool,
+    ) -> eyre::Result<Self> {
+        let planned = plan_node(searcher, query, use_scaffolds)?;
+        let prefilter = planned.prefilter.unwrap_or_else(|| "*".to_string());
+
+        let mut root = drop_implied(planned.node);
+        fill_filters(searcher, &mut root, &prefilter)?;
+
+        let schema = searcher.schema();
+
+        Ok(QueryPlan {
+            prefilter,
+            root,
+            use_chirality,
+            smiles_field: schema.get_field("smiles")?,
+            pattern_fingerprint_field: schema.get_field("pattern_fingerprint")?,
+            extra_data_field: schema.get_field("extra_data")?,
+        })
+    }
+
+    // The smiles, extra data and score of a matching document. The score is the highest
+    // similarity among the similarity clauses the document matched, or 1 without any
+    fn evaluate(
+        &self,
+        searcher: &Searcher,
+        docaddr: DocAddress,
+    ) -> eyre::Result<Option<(String, serde_json::Value, f32)>> {
+        let doc = searcher.doc::<tantivy::TantivyDocument>(docaddr)?;
+
+        let smiles = match doc.get_first(self.smiles_field) {
+            Some(OwnedValue::Str(s)) => s.to_string(),
+            other => return Err(eyre::eyre!("expected string, got {:?}", other)),
+        };
+
+        let pattern_fingerprint = match doc.get_first(self.pattern_fingerprint_field) {
+            Some(OwnedValue::Bytes(b)) => b.clone(),
+            other => return Err(eyre::eyre!("expected bytes, got {:?}", other)),
+        };
+
+        let mut candidate = Candidate {
+            docaddr,
+            doc,
+            smiles,
+            pattern_fingerprint,
+            mol: None,
+        };
+
+        let mut score = None;
+        if !self.matches(&self.root, &mut candidate, &mut score)? {
+            return Ok(None);
+        }
+
+        let extra_data = match candidate.doc.get_first(self.extra_data_field) {
+            Some(extra_data) => serde_json::from_str(&serde_json::to_string(extra_data)?)?,
+            None => serde_json::Value::Object(Default::default()),
+        };
+
+        Ok(Some((candidate.smiles, extra_data, score.unwrap_or(1.0))))
+    }
+
+    fn matches(
+        &self,
+        node: &PlanNode,
+        candidate: &mut Candidate,
+        score: &mut Option<f32>,
+    ) -> eyre::Result<bool> {
+        match node {
+            PlanNode::True => Ok(true),
+            PlanNode::Filter(_, docs) => Ok(docs.contains(&candidate.docaddr)),
+            PlanNode::Chemistry(clause) => self.matches_clause(clause, candidate, score),
+            PlanNode::And(children) => {
+                for child in children {
+                    if !self.matches(child, candidate, score)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            PlanNode::Or(children) => {
+                for child in children {
+                    if self.matches(child, candidate, score)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            // Similarities that rule a document out do not score it
+            PlanNode::Not(child) => Ok(!self.matches(child, candidate, &mut None)?),
+        }
+    }
+
+    fn matches_clause(
+        &self,
+        clause: &ChemistryClause,
+        candidate: &mut Candidate,
+        score: &mut Option<f32>,
+    ) -> eyre::Result<bool> {
+        let mut params = SubstructMatchParameters::default();
+        params.set_use_chirality(self.use_chirality);
+
+        match clause {
+            ChemistryClause::Substructure { mol, fp } => {
+                if !substructure_match_fp(fp, candidate.pattern_fingerprint()) {
+                    return Ok(false);
+                }
+
+                let hit_mol = candidate.mol()?;
+                Ok(!substruct_match(hit_mol, &mol.lock().unwrap(), &params).is_empty())
+            }
+            ChemistryClause::Superstructure { mol, fp } => {
+                if !substructure_match_fp(candidate.pattern_fingerprint(), fp) {
+                    return Ok(false);
+                }
+
+                let hit_mol = candidate.mol()?;
+                Ok(!substruct_match(&mol.lock().unwrap(), hit_mol, &params).is_empty())
+            }
+            ChemistryClause::Identity { mol, fp } => {
+                if candidate.pattern_fingerprint() != fp.as_bitslice() {
+                    return Ok(false);
+                }
+
+                let hit_mol = candidate.mol()?;
+                Ok(exact_match(
+                    hit_mol,
+                    &mol.lock().unwrap(),
+                    self.use_chirality,
+                ))
+            }
+            ChemistryClause::Similar {
+                field,
+                fp,
+                metric,
+                min,
+            } => {
+                let fingerprint = match candidate.doc.get_first(*field) {
+                    Some(OwnedValue::Bytes(b)) => b,
+                    other => return Err(eyre::eyre!("expected bytes, got {:?}", other)),
+                };
+
+                let similarity = metric.similarity_from_bytes(fp.as_raw_slice(), fingerprint);
+                if similarity < *min {
+                    return Ok(false);
+                }
+
+                *score = Some(score.map_or(similarity, |best: f32| best.max(similarity)));
+                Ok(true)
+            }
+        }
+    }
+}
+
+// A document under evaluation; its molecule is only parsed once a clause needs it
+struct Candidate {
+    docaddr: DocAddress,
+    doc: tantivy::TantivyDocument,
+    smiles: String,
+    pattern_fingerprint: Vec<u8>,
+    mol: Option<ROMol>,
+}
+
+impl Candidate {
+    fn pattern_fingerprint(&self) -> &BitSlice<u8> {
+        BitSlice::<u8, Lsb0>::from_slice(&self.pattern_fingerprint)
+    }
+
+    fn mol(&mut self) -> eyre::Result<&ROMol> {
+        if self.mol.is_none() {
+            self.mol = Some(ROMol::from_smiles(&self.smiles)?);
+        }
+
+        Ok(self.mol.as_ref().unwrap())
+    }
+}
+
+fn plan_node(searcher: &Searcher, node: &QueryNode, use_scaffolds: bool) -> eyre::Result<Planned> {
+    let scaffolds = |mol: &ROMol, fp: &BitVec<u8>| -> eyre::Result<Option<Vec<i64>>> {
+        match use_scaffolds {
+            true => Ok(Some(scaffold_search(fp, mol, &PARSED_SCAFFOLDS)?)),
+            false => Ok(None),
+        }
+    };
+
+    let planned = match node {
+        QueryNode::Query(query) => {
+            let query = extra_query_clause(&expand_formula_queries(query)?);
+            QueryParser::for_index(searcher.index(), vec![]).parse_query(&query)?;
+            exact_clause(query)
+        }
+        QueryNode::Descriptor(clause) => exact_clause(descriptor_range(clause)?),
+        QueryNode::Substructure(clause) => {
+            let (mol, fp, prefilter) = match (&clause.smiles, &clause.smarts) {
+                (Some(smiles), None) => {
+                    let (mol, fp, descriptors) = prepare_query_structure(smiles)?;
+                    let prefilter =
+                        build_substructure_query(&descriptors, "", &scaffolds(&mol, &fp.0)?);
+                    (mol, fp.0, Some(prefilter))
+                }
+                // SMARTS can match what the descriptors and scaffolds of the pattern know nothing
+                // about, so only the pattern fingerprint screen applies
+                (None, Some(smarts)) => {
+                    let mol = prepare_smarts_query(smarts)?;
+                    let fp = mol.pattern_fingerprint().0;
+                    (mol, fp, None)
+                }
+                _ => {
+                    return Err(eyre::eyre!(
+                        "substructure clauses take exactly one of smiles or smarts"
+                    ))
+                }
+            };
+
+            chemistry_clause(
+                ChemistryClause::Substructure {
+                    mol: Arc::new(Mutex::new(mol)),
+                    fp,
+                },
+                prefilter,
+                3,
+            )
+        }
+        QueryNode::Superstructure(clause) => {
+            let (mol, fp, descriptors) =
+                prepare_query_structure(smiles_only(clause, "superstructure")?)?;
+            let prefilter = build_superstructure_query(&descriptors, "", &scaffolds(&mol, &fp.0)?);
+
+            chemistry_clause(
+                ChemistryClause::Superstructure {
+                    mol: Arc::new(Mutex::new(mol)),
+                    fp: fp.0,
+                },
+                Some(prefilter),
+                3,
+            )
+        }
+        QueryNode::Identity(clause) => {
+            let (mol, fp, descriptors) = prepare_query_structure(smiles_only(clause, "identity")?)?;
+            let prefilter = build_identity_query(&descriptors, "", &scaffolds(&mol, &fp.0)?);
+
+            chemistry_clause(
+                ChemistryClause::Identity {
+                    mol: Arc::new(Mutex::new(mol)),
+                    fp: fp.0,
+                },
+                Some(prefilter),
+                2,
+            )
+        }
+        // Similarity clusters are only a guess at the neighbours of a molecule, so they cannot
+        // narrow down the candidates without losing matches
+        QueryNode::Similar(clause) => {
+            let fingerprint = clause.fingerprint.as_deref().unwrap_or("morgan");
+            let metric = SimilarityMetric::from_name(
+                clause.metric.as_deref().unwrap_or("tanimoto"),
+                0.5,
+                0.5,
+            )?;
+            let field = searcher
+                .schema()
+                .get_field(&fingerprint_field(fingerprint))
+                .map_err(|_| {
+                    eyre::eyre!("Index schema does not store {fingerprint} fingerprints")
+                })?;
+            let (mol, _, _) = prepare_query_structure(&clause.smiles)?;
+
+            chemistry_clause(
+                ChemistryClause::Similar {
+                    field,
+                    fp: get_fingerprint(&mol, fingerprint)?,
+                    metric,
+                    min: clause.min,
+                },
+                None,
+                1,
+            )
+        }
+        QueryNode::And(children) | QueryNode::Or(children) => {
+            let is_and = matches!(node, QueryNode::And(_));
+            if children.is_empty() {
+                return Err(eyre::eyre!(
+                    "{} needs at least one clause",
+                    if is_and { "and" } else { "or" }
+                ));
+            }
+
+            let mut planned = children
+                .iter()
+                .map(|child| plan_node(searcher, child, use_scaffolds))
+                .collect::<eyre::Result<Vec<_>>>()?;
+            planned.sort_by_key(|child| child.cost);
+
+            let exact = planned.iter().all(|child| child.exact);
+            let cost = planned.iter().map(|child| child.cost).max().unwrap_or(0);
+
+            // Any child narrows down the matches of a conjunction, while a disjunction can only be
+            // narrowed down when every one of its children can
+            let prefilters = planned
+                .iter()
+                .filter_map(|child| child.prefilter.clone())
+                .collect::<Vec<_>>();
+            let prefilter = match (is_and, prefilters.len()) {
+                (_, 0) => None,
+                (false, n) if n < planned.len() => None,
+                (true, _) => Some(format!("({})", prefilters.join(" AND "))),
+                (false, _) => Some(format!("({})", prefilters.join(" OR "))),
+            };
+
+            match (exact, prefilter) {
+                (true, Some(prefilter)) => exact_clause(prefilter),
+                (_, prefilter) => {
+                    let children = planned.into_iter().map(|child| child.node).collect();
+                    Planned {
+                        node: if is_and {
+                            PlanNode::And(children)
+                        } else {
+                            PlanNode::Or(children)
+                        },
+                        prefilter,
+                        exact: false,
+                        cost,
+                    }
+                }
+            }
+        }
+        // Structure bounds only hold for the matches of a clause, so they say nothing about the
+        // documents that do not match it
+        QueryNode::Not(child) => {
+            let child = plan_node(searcher, child, use_scaffolds)?;
+
+            match (child.exact, child.prefilter) {
+                (true, Some(prefilter)) => exact_clause(format!("(* -{prefilter})")),
+                _ => Planned {
+                    node: PlanNode::Not(Box::new(child.node)),
+                    prefilter: None,
+                    exact: false,
+                    cost: child.cost,
+                },
+            }
+        }
+    };
+
+    Ok(planned)
+}
+
+fn exact_clause(query: String) -> Planned {
+    Planned {
+        node: PlanNode::Filter(query.clone(), HashSet::new()),
+        prefilter: Some(query),
+        exact: true,
+        cost: 0,
+    }
+}
+
+fn chemistry_clause(clause: ChemistryClause, prefilter: Option<String>, cost: u8) -> Planned {
+    Planned {
+        node: PlanNode::Chemistry(clause),
+        prefilter: prefilter
+            .filter(|prefilter| !prefilter.is_empty())
+            .map(|prefilter| format!("({prefilter})")),
+        exact: false,
+        cost,
+    }
+}
+
+fn smiles_only<'a>(clause: &'a MoleculeClause, method: &str) -> eyre::Result<&'a str> {
+    match (&clause.smiles, &clause.smarts) {
+        (Some(smiles), None) => Ok(smiles),
+        _ => Err(eyre::eyre!("{method} clauses take smiles (and no smarts)")),
+    }
+}
+
+// Integer descriptors are indexed as such, so their bounds are rounded inwards
+fn descriptor_range(clause: &DescriptorClause) -> eyre::Result<String> {
+    let name = clause.name.as_str();
+    if !KNOWN_DESCRIPTORS.contains(&name) {
+        return Err(eyre::eyre!("Unknown descriptor: {name}"));
+    }
+
+    if let (Some(min), Some(max)) = (clause.min, clause.max) {
+        if min > max {
+            return Err(eyre::eyre!("Invalid range for {name}: {min} to {max}"));
+        }
+    }
+
+    let integer = name.starts_with("Num") || name.starts_with("lipinski");
+    let bound = |value: Option<f64>, round: fn(f64) -> f64| match value {
+        Some(value) if integer => format!("{}", round(value) as i64),
+        Some(value) => format!("{value}"),
+        None => "*".to_string(),
+    };
+
+    match (clause.min, clause.max) {
+        (None, None) => Err(eyre::eyre!("descriptor clauses need a min, a max or both")),
+        (min, max) => Ok(format!(
+            "{name}:[{} TO {}]",
+            bound(min, f64::ceil),
+            bound(max, f64::floor)
+        )),
+    }
+}
+
+// Clauses of a conjunction that the whole query is narrowed down by hold for every candidate
+fn drop_implied(node: PlanNode) -> PlanNode {
+    match node {
+        PlanNode::Filter(_, _) => PlanNode::True,
+        PlanNode::And(children) => PlanNode::And(children.into_iter().map(drop_implied).collect()),
+        other => other,
+    }
+}
+
+// Runs every remaining tantivy clause once, within the prefilter, so evaluating it on a candidate
+// is a set lookup
+fn fill_filters(searcher: &Searcher, node: &mut PlanNode, prefilter: &str) -> eyre::Result<()> {
+    match node {
+        PlanNode::Filter(query, docs) => {
+            let query = match prefilter {
+                "*" => query.clone(),
+                prefilter => format!("{prefilter} AND {query}"),
+            };
+            docs.extend(basic_search_stream(searcher, &query)?);
+        }
+        PlanNode::And(children) | PlanNode::Or(children) => {
+            for child in children {
+                fill_filters(searcher, child, prefilter)?;
+            }
+        }
+        PlanNode::Not(child) => fill_filters(searcher, child, prefilter)?,
+        PlanNode::True | PlanNode::Chemistry(_) => {}
+    }
+
+    Ok(())
+}
+
+// Evaluates the plan on the candidates of its prefilter one chunk at a time, in doc address order,
+// until result_limit hits are found. The returned bool tells whether candidates were left behind,
+// at result_limit or because `token` was cancelled
+pub fn structured_search(
+    searcher: &Searcher,
+    plan: &QueryPlan,
+    query: &str,
+    result_limit: usize,
+    token: &CancellationToken,
+) -> eyre::Result<(Vec<StructureSearchHit>, bool)> {
+    let mut candidates = basic_search_stream(searcher, &plan.prefilter)?;
+    let mut hits = Vec::new();
+
+    loop {
+        let chunk = candidates.by_ref().take(1000).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            return Ok((hits, false));
+        }
+
+        if hits.len() >= result_limit {
+            return Ok((hits, true));
+        }
+
+        let mut results_subset = chunk
+            .into_par_iter()
+            .filter_map(|docaddr| {
+                if token.is_cancelled() {
+                    return None;
+                }
+
+                match plan.evaluate(searcher, docaddr) {
+                    Ok(result) => result.map(|result| (docaddr, result)),
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        results_subset.sort_by_key(|(docaddr, _)| (docaddr.segment_ord, docaddr.doc_id));
+
+        hits.extend(
+            results_subset
+                .into_iter()
+                .map(|(_, (smiles, extra_data, score))| StructureSearchHit {
+                    extra_data,
+                    smiles,
+                    score,
+                    query: query.to_string(),
+                    used_tautomers: false,
+                    atom_matches: None,
+                    svg: None,
+                    rgroup_decomposition: None,
+                }),
+        );
+
+        if hits.len() > result_limit {
+            hits.truncate(result_limit);
+            return Ok((hits, true));
+        }
+
+        // Part of the chunk may have been skipped, so there is no telling what was left behind
+        if token.is_cancelled() {
+            return Ok((hits, true));
+        }
+    }
+}
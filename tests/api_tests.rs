@@ -1,6 +1,8 @@
 use cheminee::indexing::index_manager::IndexManager;
-use cheminee::rest_api::openapi_server::{api_service, API_PREFIX};
-use std::collections::HashMap;
+use cheminee::rest_api::api::{ApiV1, ResultCache};
+use cheminee::rest_api::openapi_server::{configured_api_service, API_PREFIX};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use cheminee::indexing::{combine_json_objects, KNOWN_DESCRIPTORS};
 use cheminee::search::compound_processing::process_cpd;
@@ -85,9 +87,15 @@ M  END
 "#;
 
 fn build_test_client() -> eyre::Result<(poem::test::TestClient<impl Endpoint>, IndexManager)> {
+    build_configured_test_client(ApiV1::default())
+}
+
+fn build_configured_test_client(
+    api: ApiV1,
+) -> eyre::Result<(poem::test::TestClient<impl Endpoint>, IndexManager)> {
     let tempdir = TempDir::new("cheminee-api-tests-")?;
     let index_manager = IndexManager::new(tempdir.into_path(), true)?;
-    let test_api = api_service("https://does-not-matter.com", API_PREFIX)?;
+    let test_api = configured_api_service(api, "https://does-not-matter.com", API_PREFIX)?;
     let route = Route::new()
         .nest(API_PREFIX, test_api)
         .data(index_manager.clone());
@@ -301,72 +309,172 @@ async fn test_basic_search() -> eyre::Result<()> {
 }
 
 #[tokio::test]
-async fn test_identity_search() -> eyre::Result<()> {
+async fn test_search_sort_by() -> eyre::Result<()> {
     let index_name = "test-api-index";
-    let schema_name = "descriptor_v1";
+    let schema_name = "descriptor_v4";
     let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
 
-    let tantivy_index = index_manager.create(
-        index_name,
-        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
-        false,
-    )?;
+    index_manager.create(index_name, schema, false)?;
 
-    fill_test_index(tantivy_index)?;
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [
+                {"smiles": "Cc1ccccc1", "extra_data": {"activity": 3}},
+                {"smiles": "CCc1ccccc1", "extra_data": {"activity": 1}},
+                {"smiles": "CCCc1ccccc1", "extra_data": {"activity": 2}},
+                {"smiles": "CCO", "extra_data": {}}
+            ]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
 
+    // Documents without the field come last
     let response = test_client
-        .get(format!("/api/v1/indexes/{index_name}/search/identity"))
-        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"exactmw:[0 TO 10000]")
+        .query("sort_by", &"extra_data.activity")
+        .query("order", &"desc")
         .send()
         .await;
     response.assert_status_is_ok();
-    response
-        .assert_json(&serde_json::json!([{
-            "extra_data": {"extra": "data"},
-            "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
-            "score": 1.0,
-            "smiles": "c1ccc(CCc2ccccc2)cc1",
-            "used_tautomers": false
-        }]))
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(4);
+    hits.get(0)
+        .object()
+        .get("smiles")
+        .assert_string("Cc1ccccc1");
+    hits.get(1)
+        .object()
+        .get("smiles")
+        .assert_string("CCCc1ccccc1");
+    hits.get(2)
+        .object()
+        .get("smiles")
+        .assert_string("CCc1ccccc1");
+    hits.get(3).object().get("smiles").assert_string("CCO");
+
+    // The top hits by the sort field, not the first hits in index order
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"c1ccccc1")
+        .query("result_limit", &2)
+        .query("sort_by", &"exactmw")
+        .query("order", &"desc")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header_is_not_exist("x-cheminee-next-cursor");
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(2);
+    hits.get(0)
+        .object()
+        .get("smiles")
+        .assert_string("CCCc1ccccc1");
+    hits.get(1)
+        .object()
+        .get("smiles")
+        .assert_string("CCc1ccccc1");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"c1ccccc1")
+        .query("sort_by", &"exactmw")
+        .query("order", &"sideways")
+        .send()
         .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_substructure_search() -> eyre::Result<()> {
+async fn test_basic_search_facets() -> eyre::Result<()> {
     let index_name = "test-api-index";
-    let schema_name = "descriptor_v1";
+    let schema_name = "descriptor_v4";
     let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
 
-    let tantivy_index = index_manager.create(
-        index_name,
-        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
-        false,
-    )?;
+    index_manager.create(index_name, schema, false)?;
 
-    fill_test_index(tantivy_index)?;
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [
+                {"smiles": "CCO", "extra_data": {"source": "a"}},
+                {"smiles": "c1ccccc1", "extra_data": {"source": "b"}},
+                {"smiles": "c1ccc(CCc2ccccc2)cc1", "extra_data": {"source": "a"}}
+            ]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
 
     let response = test_client
-        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
-        .query("smiles", &"C1=CC=CC=C1")
+        .get(format!("/api/v1/indexes/{index_name}/search/basic/facets"))
+        .query("query", &"exactmw:[0 TO 10000]")
+        .query("descriptor", &"NumRings")
+        .query("descriptor", &"exactmw")
+        .query("extra_data_key", &"source")
+        .query("bins", &3)
         .send()
         .await;
     response.assert_status_is_ok();
-    response
-        .assert_json(&serde_json::json!([{
-            "extra_data": {"extra": "data"},
-            "query": "C1=CC=CC=C1",
-            "score": 1.0,
-            "smiles": "c1ccc(CCc2ccccc2)cc1",
-            "used_tautomers": false
-        }]))
+    let facets = response.json().await;
+    let facets = facets.value().object();
+    facets.get("count").assert_i64(3);
+
+    let terms = facets.get("terms").array();
+    terms.assert_len(2);
+    terms.get(0).object().get("field").assert_string("NumRings");
+    terms.get(0).object().get("buckets").array().assert_len(3);
+    terms
+        .get(1)
+        .object()
+        .get("field")
+        .assert_string("extra_data.source");
+    let source_buckets = terms.get(1).object().get("buckets").array();
+    source_buckets.assert_len(2);
+    source_buckets.get(0).object().get("key").assert_string("a");
+    source_buckets.get(0).object().get("count").assert_i64(2);
+
+    let histograms = facets.get("histograms").array();
+    histograms.assert_len(1);
+    let exactmw = histograms.get(0).object();
+    exactmw.get("field").assert_string("exactmw");
+    exactmw.get("min").assert_not_null();
+    exactmw.get("mean").assert_not_null();
+    let exactmw_buckets = exactmw.get("buckets").array();
+    exactmw_buckets.assert_len(3);
+    exactmw_buckets.get(0).object().get("count").assert_i64(2);
+    exactmw_buckets.get(1).object().get("count").assert_i64(0);
+    exactmw_buckets.get(2).object().get("count").assert_i64(1);
+
+    // Only descriptor_v4 indexes store every descriptor as a fast field
+    let index_name = "test-api-index-v1";
+    index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get("descriptor_v1").unwrap(),
+        false,
+    )?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic/facets"))
+        .query("query", &"exactmw:[0 TO 10000]")
+        .query("descriptor", &"NumRings")
+        .send()
         .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
     Ok(())
 }
 
 #[tokio::test]
-async fn test_superstructure_search() -> eyre::Result<()> {
+async fn test_identity_search() -> eyre::Result<()> {
     let index_name = "test-api-index";
     let schema_name = "descriptor_v1";
     let (test_client, index_manager) = build_test_client()?;
@@ -380,37 +488,26 @@ async fn test_superstructure_search() -> eyre::Result<()> {
     fill_test_index(tantivy_index)?;
 
     let response = test_client
-        .get(format!(
-            "/api/v1/indexes/{index_name}/search/superstructure"
-        ))
+        .get(format!("/api/v1/indexes/{index_name}/search/identity"))
         .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
         .send()
         .await;
     response.assert_status_is_ok();
     response
-        .assert_json(&serde_json::json!([
-            {
-                "extra_data": {"extra": "data"},
-                "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
-                "score": 1.0,
-                "smiles": "CC",
-                "used_tautomers": false
-            },
-            {
-                "extra_data": {"extra": "data"},
-                "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
-                "score": 1.0,
-                "smiles": "c1ccccc1",
-                "used_tautomers": false
-            }
-        ]))
+        .assert_json(&serde_json::json!([{
+            "extra_data": {"extra": "data"},
+            "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+            "score": 1.0,
+            "smiles": "c1ccc(CCc2ccccc2)cc1",
+            "used_tautomers": false
+        }]))
         .await;
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_similarity_search() -> eyre::Result<()> {
+async fn test_substructure_search() -> eyre::Result<()> {
     let index_name = "test-api-index";
     let schema_name = "descriptor_v1";
     let (test_client, index_manager) = build_test_client()?;
@@ -424,15 +521,16 @@ async fn test_similarity_search() -> eyre::Result<()> {
     fill_test_index(tantivy_index)?;
 
     let response = test_client
-        .get(format!("/api/v1/indexes/{index_name}/search/similarity"))
-        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
         .send()
         .await;
     response.assert_status_is_ok();
+    response.assert_header("x-cheminee-truncated", "false");
     response
         .assert_json(&serde_json::json!([{
             "extra_data": {"extra": "data"},
-            "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+            "query": "C1=CC=CC=C1",
             "score": 1.0,
             "smiles": "c1ccc(CCc2ccccc2)cc1",
             "used_tautomers": false
@@ -442,63 +540,111 @@ async fn test_similarity_search() -> eyre::Result<()> {
 }
 
 #[tokio::test]
-async fn test_list_indices() -> eyre::Result<()> {
+async fn test_result_cache() -> eyre::Result<()> {
     let index_name = "test-api-index";
     let schema_name = "descriptor_v1";
-    let (test_client, index_manager) = build_test_client()?;
+    let (test_client, index_manager) = build_configured_test_client(ApiV1 {
+        result_cache: Arc::new(ResultCache::new(8)),
+        ..Default::default()
+    })?;
 
-    index_manager.create(
+    let tantivy_index = index_manager.create(
         index_name,
         cheminee::schema::LIBRARY.get(schema_name).unwrap(),
         false,
     )?;
 
-    let response = test_client.get(format!("/api/v1/indexes")).send().await;
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .send()
+        .await;
     response.assert_status_is_ok();
-    response
-        .assert_json(&serde_json::json!([{"name": index_name, "schema": schema_name}]))
+    response.json().await.value().array().assert_len(1);
+
+    // Same query molecule, different spelling
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"c1ccccc1")
+        .send()
         .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("query").assert_string("c1ccccc1");
 
-    Ok(())
-}
+    let response = test_client.get("/api/v1/cache").send().await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!({
+            "capacity": 8,
+            "entries": 1,
+            "hits": 1,
+            "misses": 1
+        }))
+        .await;
 
-#[tokio::test]
-async fn test_list_schemas() -> eyre::Result<()> {
-    let (test_client, _) = build_test_client()?;
-    let expected_schema = cheminee::schema::LIBRARY.get("descriptor_v1").unwrap();
+    // The commit moves the index to a new generation, so the cached page is dropped
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [{"smiles": "Cc1ccccc1", "extra_data": {"extra": "more data"}}]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
 
-    let response = test_client.get(format!("/api/v1/schemas")).send().await;
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"c1ccccc1")
+        .send()
+        .await;
     response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(2);
 
+    let response = test_client.get("/api/v1/cache").send().await;
     response
-        .assert_json(&serde_json::json!([{"name": "descriptor_v1", "schema": expected_schema}]))
+        .assert_json(&serde_json::json!({
+            "capacity": 8,
+            "entries": 1,
+            "hits": 1,
+            "misses": 2
+        }))
         .await;
 
-    Ok(())
-}
-
-#[tokio::test]
-async fn test_get_index() -> eyre::Result<()> {
-    let index_name = "test-api-index";
-    let schema_name = "descriptor_v1";
-    let (test_client, index_manager) = build_test_client()?;
-    let expected_schema = cheminee::schema::LIBRARY.get("descriptor_v1").unwrap();
-
-    index_manager.create(
-        index_name,
-        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
-        false,
-    )?;
+    // Similarity queries are keyed on the standardized molecule as well
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/similarity"))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(1);
 
     let response = test_client
-        .get(format!("/api/v1/indexes/{index_name}"))
+        .get(format!("/api/v1/indexes/{index_name}/search/similarity"))
+        .query("smiles", &"c1ccc(CCc2ccccc2)cc1")
         .send()
         .await;
     response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0)
+        .object()
+        .get("query")
+        .assert_string("c1ccc(CCc2ccccc2)cc1");
+
+    let response = test_client.get("/api/v1/cache").send().await;
     response
         .assert_json(&serde_json::json!({
-            "index": index_name,
-            "schema": expected_schema
+            "capacity": 8,
+            "entries": 2,
+            "hits": 2,
+            "misses": 3
         }))
         .await;
 
@@ -506,7 +652,7 @@ async fn test_get_index() -> eyre::Result<()> {
 }
 
 #[tokio::test]
-async fn test_bulk_delete() -> eyre::Result<()> {
+async fn test_cluster_and_diversity_pick() -> eyre::Result<()> {
     let index_name = "test-api-index";
     let schema_name = "descriptor_v1";
     let (test_client, index_manager) = build_test_client()?;
@@ -520,79 +666,1711 @@ async fn test_bulk_delete() -> eyre::Result<()> {
     fill_test_index(tantivy_index)?;
 
     let response = test_client
-        .delete(format!("/api/v1/indexes/{index_name}/bulk_delete"))
-        .body_json(&serde_json::json!({
-            "docs": [
-                {"smiles": "c1ccc(CCc2ccccc2)cc1"}
-            ]
-        }))
+        .get(format!("/api/v1/indexes/{index_name}/cluster"))
+        .query("distance_threshold", &1.0)
         .send()
         .await;
     response.assert_status_is_ok();
-    response
-        .assert_json(&serde_json::json!({
-            "statuses": [{"error": null, "opcode": 4}],
-        }))
+    let json = response.json().await;
+    let clustering = json.value().object();
+    clustering.get("compound_count").assert_i64(3);
+    clustering.get("truncated").assert_bool(false);
+    let clusters = clustering.get("clusters").array();
+    clusters.assert_len(1);
+    clusters
+        .get(0)
+        .object()
+        .get("members")
+        .array()
+        .assert_len(3);
+    clusters
+        .get(0)
+        .object()
+        .get("centroid")
+        .object()
+        .get("smiles")
+        .assert_string("CC");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/cluster"))
+        .query("distance_threshold", &0.0)
+        .query("compound_limit", &2)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let clustering = json.value().object();
+    clustering.get("truncated").assert_bool(true);
+    clustering.get("clusters").array().assert_len(2);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/diversity_pick"))
+        .query("count", &2)
+        .send()
         .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let picks = json.value().object().get("picks").array();
+    picks.assert_len(2);
+    picks.get(0).object().get("smiles").assert_string("CC");
+    picks.get(1).object().get("min_distance").assert_not_null();
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_delete_index() -> eyre::Result<()> {
+async fn test_structural_alerts() -> eyre::Result<()> {
     let index_name = "test-api-index";
     let schema_name = "descriptor_v1";
     let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
 
-    index_manager.create(
-        index_name,
-        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
-        false,
-    )?;
-    assert_eq!(index_manager.list().unwrap().len(), 1);
+    index_manager.create(index_name, schema, false)?;
 
     let response = test_client
-        .delete(format!("/api/v1/indexes/{index_name}"))
+        .post("/api/v1/alerts")
+        .body_json(&serde_json::json!([{"smiles": "O=Cc1ccccc1"}, {"smiles": "CCO"}]))
         .send()
         .await;
     response.assert_status_is_ok();
-    response
-        .assert_json(&serde_json::json!({
-            "name": "test-api-index",
-            "schema": "descriptor_v1",
+    let json = response.json().await;
+    let results = json.value().array();
+    results.assert_len(2);
+    let aldehyde_alerts = results.get(0).object().get("alerts").array();
+    aldehyde_alerts.assert_contains(|alert| {
+        let alert = alert.object();
+        alert.get("name").string() == "aldehyde" && alert.get("set").string() == "brenk"
+    });
+    results.get(1).object().get("alerts").array().assert_len(0);
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [{"smiles": "CCO"}, {"smiles": "O=Cc1ccccc1"}]
         }))
+        .send()
         .await;
-    assert_eq!(index_manager.list().unwrap().len(), 0);
+    response.assert_status_is_ok();
 
-    Ok(())
-}
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[0 TO 1000]")
+        .query("exclude_alerts", &"brenk")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("CCO");
 
-#[tokio::test]
-async fn test_mol_block_to_smiles_with_sanitize() -> eyre::Result<()> {
-    let (test_client, _) = build_test_client()?;
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[0 TO 1000]")
+        .query("only_alerts", &"aldehyde")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0)
+        .object()
+        .get("smiles")
+        .assert_string("O=Cc1ccccc1");
 
     let response = test_client
-        .post("/api/v1/convert/mol_block_to_smiles")
-        .body_json(&serde_json::json!([{"mol_block": MOL_BLOCK}]))
-        .query("sanitize", &"true")
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C=O")
+        .query("exclude_alerts", &"pains,brenk")
         .send()
         .await;
     response.assert_status_is_ok();
-    response
-        .assert_json(&serde_json::json!([{"smiles": "CC(=O)OC(CC(=O)[O-])C[N+](C)(C)C"}]))
+    response.json().await.value().array().assert_len(0);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[0 TO 1000]")
+        .query("exclude_alerts", &"not_an_alert")
+        .send()
         .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
 
     Ok(())
 }
 
 #[tokio::test]
-async fn test_mol_block_to_smiles_without_sanitize() -> eyre::Result<()> {
-    let (test_client, _) = build_test_client()?;
+async fn test_alert_filters_need_indexed_alerts() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
 
-    let response = test_client
-        .post("/api/v1/convert/mol_block_to_smiles")
-        .body_json(&serde_json::json!([{"mol_block": MOL_BLOCK}]))
-        .query("sanitize", &"false")
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    // Written without alerts, like an index built before they were added
+    fill_test_index(tantivy_index)?;
+
+    for (endpoint, param, value) in [
+        ("basic", "query", "NumAtoms:[0 TO 1000]"),
+        ("substructure", "smiles", "C1=CC=CC=C1"),
+    ] {
+        for alert_param in ["exclude_alerts", "only_alerts"] {
+            let response = test_client
+                .get(format!("/api/v1/indexes/{index_name}/search/{endpoint}"))
+                .query(param, &value)
+                .query(alert_param, &"brenk")
+                .send()
+                .await;
+            response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+            let body = response.0.into_body().into_string().await?;
+            assert!(body.contains("no structural alerts"));
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_formula_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
+
+    index_manager.create(index_name, schema, false)?;
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [
+                {"smiles": "CCO"},
+                {"smiles": "c1ccncc1"},
+                {"smiles": "ClCCCl"},
+                {"smiles": "C".repeat(40)}
+            ]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/formula"))
+        .query("formula", &"C2-5 H* N0 Cl0")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("CCO");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/formula"))
+        .query("formula", &"C5H5N")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("c1ccncc1");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"CC")
+        .query("extra_query", &"formula:\"C2-5 H* Cl1-\"")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("ClCCCl");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/formula"))
+        .query("formula", &"C2-x")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    // Counts beyond the indexed thresholds are told apart by the exact counts
+    for (formula, num_hits) in [
+        ("C33-", 1),
+        ("C40H82", 1),
+        ("C39-41", 1),
+        ("C41-", 0),
+        ("C20-39", 0),
+    ] {
+        let response = test_client
+            .get(format!("/api/v1/indexes/{index_name}/search/formula"))
+            .query("formula", &formula)
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.json().await.value().array().assert_len(num_hits);
+    }
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/formula"))
+        .query("formula", &"C2000")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_formula_search_needs_indexed_formulas() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    // Written without element counts, like an index built before they were added
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/formula"))
+        .query("formula", &"C2 Cl0")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    let body = response.0.into_body().into_string().await?;
+    assert!(body.contains("no element counts"));
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("extra_query", &"formula:\"C6-\"")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mass_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
+
+    index_manager.create(index_name, schema, false)?;
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [{"smiles": "CCO"}, {"smiles": "c1ccccc1O"}]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    // Ethanol is 46.041865
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/mass"))
+        .query("mz", &47.04914)
+        .query("adduct", &"[M+H]+")
+        .query("adduct", &"[M+Na]+")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    let hit = hits.get(0).object();
+    hit.get("smiles").assert_string("CCO");
+    hit.get("adduct").assert_string("[M+H]+");
+    assert!(hit.get("error_ppm").f64().abs() < 1.0);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/mass"))
+        .query("mz", &45.03459)
+        .query("charge", &-1)
+        .query("tolerance", &1)
+        .query("tolerance_unit", &"mda")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("adduct").assert_string("[M-H]-");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/mass"))
+        .query("mz", &47.04914)
+        .query("extra_query", &"NumAromaticRings:1")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(0);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/mass"))
+        .query("mz", &47.04914)
+        .query("adduct", &"[M-H]-")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    // Hits cannot be ranked from part of the matches, so running out of time is an error
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/mass"))
+        .query("mz", &47.04914)
+        .query("timeout_ms", &0)
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_exclude_smarts() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("exclude_smarts", &"Cl")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(1);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("exclude_smarts", &"Cl")
+        .query("exclude_smarts", &"[CH2][CH2]")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(0);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/similarity"))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .query("exclude_smarts", &"[CH2][CH2]")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(0);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[0 TO 1000]")
+        .query("exclude_smarts", &"c1ccccc1")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("CC");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[0 TO 1000]")
+        .query("exclude_smarts", &"c1ccccc1(")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_basic_search_exclusions_past_candidate_limit() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
+
+    index_manager.create(index_name, schema, false)?;
+
+    // More excluded compounds than the 10 * limit candidates a plain basic search looks at
+    let mut docs = (1..=12)
+        .map(|n| serde_json::json!({"smiles": format!("c1ccccc1{}", "C".repeat(n))}))
+        .collect::<Vec<_>>();
+    docs.push(serde_json::json!({"smiles": "CCO"}));
+    docs.push(serde_json::json!({"smiles": "CCCO"}));
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({ "docs": docs }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[0 TO 1000]")
+        .query("limit", &1)
+        .query("exclude_smarts", &"c1ccccc1")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("CCO");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_structured_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/search/query"))
+        .body_json(&serde_json::json!({
+            "query": {"and": [
+                {"substructure": {"smiles": "C1=CC=CC=C1"}},
+                {"not": {"substructure": {"smarts": "[CH2][CH2]"}}}
+            ]}
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("c1ccccc1");
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/search/query"))
+        .body_json(&serde_json::json!({
+            "query": {"or": [
+                {"identity": {"smiles": "CC"}},
+                {"descriptor": {"name": "exactmw", "min": 100}}
+            ]}
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(2);
+    hits.get(0).object().get("smiles").assert_string("CC");
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/search/query"))
+        .body_json(&serde_json::json!({
+            "query": {"and": [
+                {"query": "-exactmw:[100 TO *]"},
+                {"not": {"identity": {"smiles": "CC"}}}
+            ]}
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("c1ccccc1");
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/search/query"))
+        .body_json(&serde_json::json!({
+            "query": {"similar": {"smiles": "C1=CC=CC=C1", "min": 0.99}}
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let json = response.json().await;
+    let hits = json.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("score").assert_f64(1.0);
+
+    for query in [
+        serde_json::json!({"nearby": {"smiles": "CC"}}),
+        serde_json::json!({"descriptor": {"name": "NumFoo", "min": 1}}),
+    ] {
+        let response = test_client
+            .post(format!("/api/v1/indexes/{index_name}/search/query"))
+            .body_json(&serde_json::json!({ "query": query }))
+            .send()
+            .await;
+        response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_substructure_search_timeout() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("timeout_ms", &60_000)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-timed-out", "false");
+    response.json().await.value().array().assert_len(1);
+
+    // Out of time before the first candidate
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("timeout_ms", &0)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-timed-out", "true");
+    response.assert_header("x-cheminee-truncated", "true");
+    response.json().await.value().array().assert_is_empty();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_basic_search_timeout() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[13 TO 100]")
+        .query("timeout_ms", &60_000)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-timed-out", "false");
+    response.json().await.value().array().assert_len(1);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/basic"))
+        .query("query", &"NumAtoms:[13 TO 100]")
+        .query("timeout_ms", &0)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-timed-out", "true");
+    response.json().await.value().array().assert_is_empty();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_explain_substructure_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/explain"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let explanation = response.json().await;
+    let explanation = explanation.value().object();
+    explanation.get("query").assert_not_null();
+    explanation.get("truncated").assert_bool(false);
+
+    let stages = explanation.get("stages").array();
+    stages.assert_len(5);
+    stages
+        .get(0)
+        .object()
+        .get("name")
+        .assert_string("query_preparation");
+    let confirmation = stages.get(4).object();
+    confirmation
+        .get("name")
+        .assert_string("structure_confirmation");
+    confirmation.get("candidates").assert_i64(1);
+    explanation.get("timed_out").assert_bool(false);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/explain"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("timeout_ms", &0)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let explanation = response.json().await;
+    let explanation = explanation.value().object();
+    explanation.get("truncated").assert_bool(true);
+    explanation.get("timed_out").assert_bool(true);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/explain"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("method", &"identity")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_substructure_search_with_atom_matches() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"CC")
+        .query("include_atom_matches", &true)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!([{
+            "extra_data": {"extra": "data"},
+            "query": "CC",
+            "score": 1.0,
+            "smiles": "c1ccc(CCc2ccccc2)cc1",
+            "used_tautomers": false,
+            "atom_matches": [{"atoms": [4, 5], "bonds": [4]}]
+        }]))
+        .await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_substructure_search_with_rgroup_decomposition() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"c1ccccc1[*:1]")
+        .query("rgroup_decomposition", &true)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(1);
+
+    let decomposition = hits.get(0).object().get("rgroup_decomposition").object();
+    decomposition.get("core_atoms").array().assert_len(6);
+    let rgroups = decomposition.get("rgroups").array();
+    rgroups.assert_len(1);
+    rgroups.get(0).object().get("label").assert_string("R1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rgroup_decomposition() -> eyre::Result<()> {
+    let (test_client, _) = build_test_client()?;
+
+    let response = test_client
+        .post("/api/v1/rgroup_decomposition")
+        .body_json(&serde_json::json!({
+            "core": "c1ccccc1[*:1]",
+            "smiles": ["Oc1ccccc1", "CCCC"]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let results = response.json().await;
+    let results = results.value().array();
+    results.assert_len(2);
+    results
+        .get(0)
+        .object()
+        .get("decomposition")
+        .object()
+        .get("rgroups")
+        .array()
+        .assert_len(1);
+    results.get(1).object().get("error").assert_not_null();
+
+    let response = test_client
+        .post("/api/v1/rgroup_decomposition")
+        .body_json(&serde_json::json!({"core": "c1ccccc1", "smiles": ["Oc1ccccc1"]}))
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_substructure_search_with_smarts() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smarts", &"c1ccccc1[CH2]")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!([{
+            "extra_data": {"extra": "data"},
+            "query": "c1ccccc1[CH2]",
+            "score": 1.0,
+            "smiles": "c1ccc(CCc2ccccc2)cc1",
+            "used_tautomers": false
+        }]))
+        .await;
+
+    // RDKit parses none of these, which must come back as an error rather than a null molecule
+    for smarts in ["C1CC", "[Zz]", "C&&", "[C;]"] {
+        let response = test_client
+            .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+            .query("smarts", &smarts)
+            .send()
+            .await;
+        response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_superstructure_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!(
+            "/api/v1/indexes/{index_name}/search/superstructure"
+        ))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!([
+            {
+                "extra_data": {"extra": "data"},
+                "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                "score": 1.0,
+                "smiles": "CC",
+                "used_tautomers": false
+            },
+            {
+                "extra_data": {"extra": "data"},
+                "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                "score": 1.0,
+                "smiles": "c1ccccc1",
+                "used_tautomers": false
+            }
+        ]))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_superstructure_search_ndjson() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!(
+            "/api/v1/indexes/{index_name}/search/superstructure"
+        ))
+        .header("accept", "application/x-ndjson")
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_content_type("application/x-ndjson");
+    response.assert_header_is_not_exist("x-cheminee-truncated");
+
+    let body = response.0.into_body().into_string().await?;
+    let hits = body
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+
+    assert_eq!(
+        hits,
+        vec![
+            serde_json::json!({
+                "extra_data": {"extra": "data"},
+                "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                "score": 1.0,
+                "smiles": "CC",
+                "used_tautomers": false
+            }),
+            serde_json::json!({
+                "extra_data": {"extra": "data"},
+                "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                "score": 1.0,
+                "smiles": "c1ccccc1",
+                "used_tautomers": false
+            })
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_superstructure_search_pagination() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!(
+            "/api/v1/indexes/{index_name}/search/superstructure"
+        ))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .query("result_limit", &1)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-truncated", "true");
+    let cursor = response
+        .0
+        .header("x-cheminee-next-cursor")
+        .expect("missing next cursor")
+        .to_string();
+    response
+        .assert_json(&serde_json::json!([{
+            "extra_data": {"extra": "data"},
+            "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+            "score": 1.0,
+            "smiles": "CC",
+            "used_tautomers": false
+        }]))
+        .await;
+
+    let response = test_client
+        .get(format!(
+            "/api/v1/indexes/{index_name}/search/superstructure"
+        ))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .query("result_limit", &1)
+        .query("cursor", &cursor)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-truncated", "false");
+    response.assert_header_is_not_exist("x-cheminee-next-cursor");
+    response
+        .assert_json(&serde_json::json!([{
+            "extra_data": {"extra": "data"},
+            "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+            "score": 1.0,
+            "smiles": "c1ccccc1",
+            "used_tautomers": false
+        }]))
+        .await;
+
+    // A cursor only resumes the query it was issued for
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("result_limit", &1)
+        .query("cursor", &cursor)
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    response
+        .assert_json(&serde_json::json!({
+            "error": "Cursor was issued for a different query or different search parameters"
+        }))
+        .await;
+
+    // Any modification of the index invalidates outstanding cursors
+    fill_test_index(index_manager.open(index_name)?)?;
+
+    let response = test_client
+        .get(format!(
+            "/api/v1/indexes/{index_name}/search/superstructure"
+        ))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .query("result_limit", &1)
+        .query("cursor", &cursor)
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    response
+        .assert_json(&serde_json::json!({
+            "error": "Cursor is no longer valid: the index has been modified since it was issued"
+        }))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_substructure_search_tautomer_pagination() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    // The query's canonical tautomer is 2-pyridone, so these are only found through its
+    // 2-hydroxypyridine tautomer
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [
+                {"smiles": "COc1ccccn1"},
+                {"smiles": "CCOc1ccccn1"},
+                {"smiles": "CCCOc1ccccn1"}
+            ]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let search_page = |cursor: Option<String>| {
+        let mut request = test_client
+            .get(format!("/api/v1/indexes/{index_name}/search/substructure"))
+            .query("smiles", &"Oc1ccccn1")
+            .query("result_limit", &2)
+            .query("tautomer_limit", &10);
+        if let Some(cursor) = cursor {
+            request = request.query("cursor", &cursor);
+        }
+        request.send()
+    };
+
+    let response = search_page(None).await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-truncated", "true");
+    let cursor = response
+        .0
+        .header("x-cheminee-next-cursor")
+        .expect("missing next cursor")
+        .to_string();
+    let first_page = response.json().await;
+    let first_page = first_page.value().array();
+    first_page.assert_len(2);
+
+    let response = search_page(Some(cursor)).await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-truncated", "false");
+    response.assert_header_is_not_exist("x-cheminee-next-cursor");
+    let second_page = response.json().await;
+    let second_page = second_page.value().array();
+    second_page.assert_len(1);
+
+    // Every hit shows up exactly once across the pages
+    let smiles = first_page
+        .iter()
+        .chain(second_page.iter())
+        .map(|hit| {
+            hit.object().get("used_tautomers").assert_bool(true);
+            hit.object().get("smiles").string().to_string()
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(smiles.len(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_similarity_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/similarity"))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!([{
+            "extra_data": {"extra": "data"},
+            "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+            "score": 1.0,
+            "smiles": "c1ccc(CCc2ccccc2)cc1",
+            "used_tautomers": false
+        }]))
+        .await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_similarity_search_pagination() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/similarity"))
+        .query("smiles", &"C1=CC=CC=C1CCC2=CC=CC=C2")
+        .query("result_limit", &1)
+        .query("similarity_minimum", &0.0)
+        .query("exact", &true)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_header("x-cheminee-truncated", "true");
+    let cursor = response
+        .0
+        .header("x-cheminee-next-cursor")
+        .expect("missing next cursor")
+        .to_string();
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(1);
+    hits.get(0)
+        .object()
+        .get("smiles")
+        .assert_string("c1ccc(CCc2ccccc2)cc1");
+
+    // The cursor resumes the same molecule written another way
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/similarity"))
+        .query("smiles", &"c1ccc(CCc2ccccc2)cc1")
+        .query("result_limit", &1)
+        .query("similarity_minimum", &0.0)
+        .query("exact", &true)
+        .query("cursor", &cursor)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(1);
+    let hit = hits.get(0).object();
+    hit.get("smiles").assert_string("c1ccccc1");
+    hit.get("query").assert_string("c1ccc(CCc2ccccc2)cc1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/search/batch"))
+        .body_json(&serde_json::json!({
+            "queries": [
+                {"method": "identity", "smiles": "C1=CC=CC=C1CCC2=CC=CC=C2"},
+                {"method": "superstructure", "smiles": "C1=CC=CC=C1CCC2=CC=CC=C2"},
+                {"method": "similarity", "smiles": "not a smiles"},
+                {"method": "wildcard", "smiles": "CC"}
+            ]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!([
+            {
+                "method": "identity",
+                "smiles": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                "hits": [{
+                    "extra_data": {"extra": "data"},
+                    "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                    "score": 1.0,
+                    "smiles": "c1ccc(CCc2ccccc2)cc1",
+                    "used_tautomers": false
+                }],
+                "truncated": false,
+                "timed_out": false
+            },
+            {
+                "method": "superstructure",
+                "smiles": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                "hits": [
+                    {
+                        "extra_data": {"extra": "data"},
+                        "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                        "score": 1.0,
+                        "smiles": "CC",
+                        "used_tautomers": false
+                    },
+                    {
+                        "extra_data": {"extra": "data"},
+                        "query": "C1=CC=CC=C1CCC2=CC=CC=C2",
+                        "score": 1.0,
+                        "smiles": "c1ccccc1",
+                        "used_tautomers": false
+                    }
+                ],
+                "truncated": false,
+                "timed_out": false
+            },
+            {
+                "method": "similarity",
+                "smiles": "not a smiles",
+                "error": "Failed structure validation"
+            },
+            {
+                "method": "wildcard",
+                "smiles": "CC",
+                "error": "Unknown search method: wildcard"
+            }
+        ]))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_federated_search() -> eyre::Result<()> {
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
+
+    for (index_name, docs) in [
+        ("vendor-a", vec!["CCO", "CCCCCCO"]),
+        ("vendor-b", vec!["CCCO", "CCCCO"]),
+    ] {
+        index_manager.create(index_name, schema, false)?;
+
+        let docs = docs
+            .into_iter()
+            .map(|smiles| serde_json::json!({"smiles": smiles, "extra_data": {}}))
+            .collect::<Vec<_>>();
+
+        let response = test_client
+            .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+            .body_json(&serde_json::json!({ "docs": docs }))
+            .send()
+            .await;
+        response.assert_status_is_ok();
+    }
+
+    let response = test_client
+        .post("/api/v1/search/federated")
+        .body_json(&serde_json::json!({
+            "indexes": ["vendor-*"],
+            "method": "basic",
+            "query": "exactmw:[0 TO 10000]",
+            "result_limit": 3,
+            "sort_by": "exactmw",
+            "order": "desc"
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let result = response.json().await;
+    let result = result.value().object();
+    result.get("truncated").assert_bool(true);
+    result.get("timed_out").assert_bool(false);
+
+    let hits = result.get("hits").array();
+    hits.assert_len(3);
+    hits.get(0).object().get("smiles").assert_string("CCCCCCO");
+    hits.get(0).object().get("index").assert_string("vendor-a");
+    hits.get(1).object().get("smiles").assert_string("CCCCO");
+    hits.get(1).object().get("index").assert_string("vendor-b");
+    hits.get(2).object().get("smiles").assert_string("CCCO");
+    hits.get(2).object().get("index").assert_string("vendor-b");
+
+    let response = test_client
+        .post("/api/v1/search/federated")
+        .body_json(&serde_json::json!({
+            "indexes": ["vendor-a", "vendor-c"],
+            "method": "basic",
+            "query": "exactmw:[0 TO 10000]"
+        }))
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_indices() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    let response = test_client.get(format!("/api/v1/indexes")).send().await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!([{"name": index_name, "schema": schema_name}]))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_schemas() -> eyre::Result<()> {
+    let (test_client, _) = build_test_client()?;
+    let expected_schema = cheminee::schema::LIBRARY.get("descriptor_v1").unwrap();
+    let expected_v2_schema = cheminee::schema::LIBRARY.get("descriptor_v2").unwrap();
+    let expected_v3_schema = cheminee::schema::LIBRARY.get("descriptor_v3").unwrap();
+    let expected_v4_schema = cheminee::schema::LIBRARY.get("descriptor_v4").unwrap();
+
+    let response = test_client.get(format!("/api/v1/schemas")).send().await;
+    response.assert_status_is_ok();
+
+    response
+        .assert_json(&serde_json::json!([
+            {"name": "descriptor_v1", "schema": expected_schema},
+            {"name": "descriptor_v2", "schema": expected_v2_schema},
+            {"name": "descriptor_v3", "schema": expected_v3_schema},
+            {"name": "descriptor_v4", "schema": expected_v4_schema}
+        ]))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_index() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+    let expected_schema = cheminee::schema::LIBRARY.get("descriptor_v1").unwrap();
+
+    index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}"))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!({
+            "index": index_name,
+            "schema": expected_schema
+        }))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_inchikey_search() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v3";
+    let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
+
+    index_manager.create(index_name, schema, false)?;
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [{
+                "smiles": "CCO",
+                "extra_data": {"inchikey": "UHOVQNZJYSORNB-UHFFFAOYSA-N"}
+            }, {
+                "smiles": "C1=CC=CC=C1"
+            }, {
+                "smiles": "C[C@H](N)C(=O)O"
+            }, {
+                "smiles": "C[C@@H](N)C(=O)O"
+            }]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/inchikey"))
+        .query("inchikey", &"LFQSCWFLJHTTHZ-UHFFFAOYSA-N")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("CCO");
+
+    // The InChIKey is generated from the structure, not taken from the extra data
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/inchikey"))
+        .query("inchikey", &"UHOVQNZJYSORNB-UHFFFAOYSA-N")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("c1ccccc1");
+
+    // Connectivity matching takes the first block on its own
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/inchikey"))
+        .query("inchikey", &"UHOVQNZJYSORNB")
+        .query("connectivity", &true)
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(1);
+    hits.get(0).object().get("smiles").assert_string("c1ccccc1");
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/inchikey"))
+        .query("inchikey", &"UHOVQNZJYSORNB")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    // Both alanine enantiomers share the first block; the limit cuts off the second one, and no
+    // limit is too large
+    for (result_limit, num_hits, truncated) in [(1, 1, "true"), (usize::MAX, 2, "false")] {
+        let response = test_client
+            .get(format!("/api/v1/indexes/{index_name}/search/inchikey"))
+            .query("inchikey", &"QNAYBMKLOCPYGJ")
+            .query("connectivity", &true)
+            .query("result_limit", &result_limit)
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response.assert_header("x-cheminee-truncated", truncated);
+        let hits = response.json().await;
+        hits.value().array().assert_len(num_hits);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_identity_search_with_identity_level() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+    let schema = cheminee::schema::LIBRARY.get(schema_name).unwrap();
+
+    index_manager.create(index_name, schema, false)?;
+
+    let response = test_client
+        .post(format!("/api/v1/indexes/{index_name}/bulk_index"))
+        .body_json(&serde_json::json!({
+            "docs": [
+                {"smiles": "C[C@H](N)C(=O)O", "extra_data": {"id": "L"}},
+                {"smiles": "C[C@@H](N)C(=O)O", "extra_data": {"id": "D"}},
+                {"smiles": "CC(N)C(=O)O", "extra_data": {"id": "racemic"}}
+            ]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/identity"))
+        .query("smiles", &"C[C@H](N)C(=O)O")
+        .query("identity_level", &"tautomer")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    let hits = response.json().await;
+    let hits = hits.value().array();
+    hits.assert_len(1);
+    hits.get(0)
+        .object()
+        .get("extra_data")
+        .object()
+        .get("id")
+        .assert_string("L");
+
+    // Without stereochemistry all three forms are the same compound
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/identity"))
+        .query("smiles", &"C[C@H](N)C(=O)O")
+        .query("identity_level", &"stereo")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(3);
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/identity"))
+        .query("smiles", &"C[C@H](N)C(=O)O")
+        .query("identity_level", &"nonsense")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_identity_level_needs_indexed_layers() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    // Written without identity layers, like an index built before they were added
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/identity"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .query("identity_level", &"tautomer")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR);
+    let body = response.0.into_body().into_string().await?;
+    assert!(body.contains("no identity layers"));
+
+    // An exact structure match does not need them
+    let response = test_client
+        .get(format!("/api/v1/indexes/{index_name}/search/identity"))
+        .query("smiles", &"C1=CC=CC=C1")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.json().await.value().array().assert_len(1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bulk_delete() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    let tantivy_index = index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+
+    fill_test_index(tantivy_index)?;
+
+    let response = test_client
+        .delete(format!("/api/v1/indexes/{index_name}/bulk_delete"))
+        .body_json(&serde_json::json!({
+            "docs": [
+                {"smiles": "c1ccc(CCc2ccccc2)cc1"}
+            ]
+        }))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!({
+            "statuses": [{"error": null, "opcode": 4}],
+        }))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_index() -> eyre::Result<()> {
+    let index_name = "test-api-index";
+    let schema_name = "descriptor_v1";
+    let (test_client, index_manager) = build_test_client()?;
+
+    index_manager.create(
+        index_name,
+        cheminee::schema::LIBRARY.get(schema_name).unwrap(),
+        false,
+    )?;
+    assert_eq!(index_manager.list().unwrap().len(), 1);
+
+    let response = test_client
+        .delete(format!("/api/v1/indexes/{index_name}"))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!({
+            "name": "test-api-index",
+            "schema": "descriptor_v1",
+        }))
+        .await;
+    assert_eq!(index_manager.list().unwrap().len(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mol_block_to_smiles_with_sanitize() -> eyre::Result<()> {
+    let (test_client, _) = build_test_client()?;
+
+    let response = test_client
+        .post("/api/v1/convert/mol_block_to_smiles")
+        .body_json(&serde_json::json!([{"mol_block": MOL_BLOCK}]))
+        .query("sanitize", &"true")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response
+        .assert_json(&serde_json::json!([{"smiles": "CC(=O)OC(CC(=O)[O-])C[N+](C)(C)C"}]))
+        .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mol_block_to_smiles_without_sanitize() -> eyre::Result<()> {
+    let (test_client, _) = build_test_client()?;
+
+    let response = test_client
+        .post("/api/v1/convert/mol_block_to_smiles")
+        .body_json(&serde_json::json!([{"mol_block": MOL_BLOCK}]))
+        .query("sanitize", &"false")
         .send()
         .await;
     response.assert_status_is_ok();
@@ -622,6 +2400,67 @@ async fn test_smile_to_mol_block() -> eyre::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_mcs() -> eyre::Result<()> {
+    let (test_client, _) = build_test_client()?;
+
+    let response = test_client
+        .post("/api/v1/mcs")
+        .body_json(&serde_json::json!([{"smiles": "Cc1ccccc1"}, {"smiles": "Oc1ccccc1C"}]))
+        .send()
+        .await;
+    response.assert_status_is_ok();
+
+    let mcs = response.json().await;
+    let mcs = mcs.value().object();
+    mcs.get("num_atoms").assert_i64(7);
+    mcs.get("num_bonds").assert_i64(7);
+    mcs.get("timed_out").assert_bool(false);
+    mcs.get("mappings").array().assert_len(2);
+
+    let response = test_client
+        .post("/api/v1/mcs")
+        .query("bond_compare", &"nonsense")
+        .body_json(&serde_json::json!([{"smiles": "Cc1ccccc1"}, {"smiles": "Oc1ccccc1C"}]))
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_depict() -> eyre::Result<()> {
+    let (test_client, _) = build_test_client()?;
+
+    let response = test_client
+        .get("/api/v1/depict")
+        .query("smiles", &"OCc1ccccc1")
+        .query("width", &200)
+        .query("height", &150)
+        .query("highlight_smarts", &"c1ccccc1")
+        .send()
+        .await;
+    response.assert_status_is_ok();
+    response.assert_content_type("image/svg+xml");
+
+    let body = response.0.into_body().into_string().await?;
+    assert!(body.contains("<svg"));
+    assert!(body.contains("width='200px' height='150px'"));
+    // Six highlighted ring atoms
+    assert_eq!(body.matches("<ellipse").count(), 6);
+
+    let response = test_client
+        .get("/api/v1/depict")
+        .query("smiles", &"CCO")
+        .query("mol_block", &"")
+        .send()
+        .await;
+    response.assert_status(poem::http::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_standardization_without_attempt_fix() -> eyre::Result<()> {
     let (test_client, _) = build_test_client()?;
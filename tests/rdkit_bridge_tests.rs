@@ -0,0 +1,141 @@
+use cheminee::rdkit_bridge::*;
+use cxx::let_cxx_string;
+
+#[test]
+fn test_check_smarts() {
+    let_cxx_string!(smarts = "c1ccccc1[F,Cl,Br,I]");
+    assert!(smarts_ffi::check_smarts(&smarts).is_ok());
+
+    for smarts in ["C1CC", "[Zz]", "C&&", "[C;]"] {
+        let_cxx_string!(smarts = smarts);
+        assert!(smarts_ffi::check_smarts(&smarts).is_err());
+    }
+}
+
+#[test]
+fn test_alternative_fingerprints() {
+    let_cxx_string!(smiles = "c1ccccc1CC(=O)O");
+    let mol = rdkit_sys::ro_mol_ffi::smiles_to_mol(&smiles).unwrap();
+
+    for (fingerprint, num_bits) in [
+        (
+            fingerprint_ffi::morgan_fingerprint_mol_with_params(&mol, 2, 1024, false),
+            1024,
+        ),
+        (
+            fingerprint_ffi::morgan_fingerprint_mol_with_params(&mol, 2, 2048, true),
+            2048,
+        ),
+        (fingerprint_ffi::atom_pair_fingerprint_mol(&mol, 2048), 2048),
+        (
+            fingerprint_ffi::topological_torsion_fingerprint_mol(&mol, 2048),
+            2048,
+        ),
+        (fingerprint_ffi::maccs_fingerprint_mol(&mol), 167),
+    ] {
+        assert!(!fingerprint.is_null());
+        assert_eq!(fingerprint_ffi::get_num_bits(&fingerprint), num_bits);
+    }
+}
+
+#[test]
+fn test_mol_to_inchi() {
+    let_cxx_string!(smiles = "CCO");
+    let mol = rdkit_sys::ro_mol_ffi::smiles_to_mol(&smiles).unwrap();
+
+    let inchi = inchi_ffi::mol_to_inchi(&mol).unwrap();
+    assert_eq!(inchi, "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3");
+
+    let_cxx_string!(inchi = inchi);
+    let inchikey = inchi_ffi::inchi_to_inchi_key(&inchi).unwrap();
+    assert_eq!(inchikey, "LFQSCWFLJHTTHZ-UHFFFAOYSA-N");
+
+    let_cxx_string!(inchi = "not an inchi");
+    assert!(inchi_ffi::inchi_to_inchi_key(&inchi).is_err());
+}
+
+#[test]
+fn test_filter_catalog_descriptions() {
+    for (catalog_name, num_entries) in [
+        ("PAINS_A", 16),
+        ("PAINS_B", 55),
+        ("PAINS_C", 409),
+        ("BRENK", 105),
+    ] {
+        let_cxx_string!(catalog_name = catalog_name);
+        let catalog = filter_catalog_ffi::new_filter_catalog(&catalog_name).unwrap();
+        let descriptions = filter_catalog_ffi::filter_catalog_descriptions(&catalog);
+        assert_eq!(descriptions.len(), num_entries);
+    }
+
+    let_cxx_string!(catalog_name = "NOT_A_CATALOG");
+    assert!(filter_catalog_ffi::new_filter_catalog(&catalog_name).is_err());
+}
+
+#[test]
+fn test_filter_catalog_matches() {
+    let_cxx_string!(catalog_name = "BRENK");
+    let catalog = filter_catalog_ffi::new_filter_catalog(&catalog_name).unwrap();
+    let descriptions = filter_catalog_ffi::filter_catalog_descriptions(&catalog);
+
+    let_cxx_string!(smiles = "O=Cc1ccccc1");
+    let mol = rdkit_sys::ro_mol_ffi::smiles_to_mol(&smiles).unwrap();
+    let matches = filter_catalog_ffi::filter_catalog_matches(&catalog, &mol);
+    assert!(matches
+        .iter()
+        .any(|idx| descriptions[*idx as usize] == "aldehyde"));
+
+    let_cxx_string!(smiles = "CCO");
+    let mol = rdkit_sys::ro_mol_ffi::smiles_to_mol(&smiles).unwrap();
+    assert!(filter_catalog_ffi::filter_catalog_matches(&catalog, &mol).is_empty());
+}
+
+#[test]
+fn test_remove_stereochemistry_and_isotopes() {
+    let_cxx_string!(smiles = "[13CH3][C@H](N)C(=O)O");
+    let mol = rdkit_sys::ro_mol_ffi::smiles_to_mol(&smiles).unwrap();
+
+    let without_stereo = mol_ops_ffi::remove_stereochemistry(&mol);
+    assert_eq!(
+        rdkit_sys::ro_mol_ffi::mol_to_smiles(&without_stereo),
+        "[13CH3]C(N)C(=O)O"
+    );
+
+    let without_isotopes = mol_ops_ffi::remove_isotopes(&mol);
+    assert_eq!(
+        rdkit_sys::ro_mol_ffi::mol_to_smiles(&without_isotopes),
+        "C[C@H](N)C(=O)O"
+    );
+
+    // The given molecule is left as it was
+    assert_eq!(
+        rdkit_sys::ro_mol_ffi::mol_to_smiles(&mol),
+        "[13CH3][C@H](N)C(=O)O"
+    );
+}
+
+#[test]
+fn test_mol_bonds() {
+    // Biphenylene: the two bonds joining the benzene rings close a four-membered ring but are not
+    // aromatic
+    let_cxx_string!(smiles = "c1ccc2c(c1)-c1ccccc1-2");
+    let mol = rdkit_sys::ro_mol_ffi::smiles_to_mol(&smiles).unwrap();
+
+    let bonds = mol_graph_ffi::mol_bonds(&mol);
+    assert_eq!(bonds.len(), 14);
+    assert_eq!(bonds.iter().filter(|bond| bond.order == 4).count(), 12);
+    assert_eq!(bonds.iter().filter(|bond| bond.order == 1).count(), 2);
+
+    assert_eq!(mol_graph_ffi::atom_isotopes(&mol), vec![0; 12]);
+}
+
+#[test]
+fn test_draw_svg() {
+    let_cxx_string!(smiles = "OCc1ccccc1");
+    let mol = rdkit_sys::ro_mol_ffi::smiles_to_mol(&smiles).unwrap();
+
+    let svg = mol_draw_ffi::draw_svg(&mol, 200, 150, &[2, 3, 4, 5, 6, 7], &[]).unwrap();
+    assert!(svg.contains("<svg"));
+    assert!(svg.contains("width='200px' height='150px'"));
+    assert_eq!(svg.matches("<ellipse").count(), 6);
+}
@@ -1,14 +1,33 @@
+use bitvec::prelude::BitVec;
 use bitvec::store::BitStore;
+use cheminee::search::basic_search::{basic_search_stream, basic_search_stream_after};
+use cheminee::search::cancellation::CancellationToken;
+use cheminee::search::clustering::{butina_cluster, maxmin_pick};
 use cheminee::search::compound_processing::{process_cpd, standardize_smiles};
+use cheminee::search::exact_similarity_search::exact_similarity_search;
+use cheminee::search::excluded_patterns::{matches_excluded_pattern, parse_excluded_patterns};
+use cheminee::search::fingerprints::{get_fingerprints, FINGERPRINTS};
+use cheminee::search::formula::{
+    build_formula_query, element_counts, expand_formula_queries, hill_formula, parse_formula_query,
+};
+use cheminee::search::identity_layers::{identity_layer, Skeleton};
 use cheminee::search::identity_search::{build_identity_query, identity_search};
+use cheminee::search::inchikey_search::standard_inchi;
+use cheminee::search::mass_search::resolve_adducts;
+use cheminee::search::pagination::{query_hash, SearchCursor};
 use cheminee::search::scaffold_search::{scaffold_search, PARSED_SCAFFOLDS};
-use cheminee::search::similarity_search::build_similarity_query;
+use cheminee::search::similarity_search::{build_similarity_query, SimilarityMetric};
+use cheminee::search::structural_alerts::{
+    alert_search, build_alert_filters, resolve_alerts, PARSED_ALERTS,
+};
 use cheminee::search::structure_search::{
     build_substructure_query, build_superstructure_query, structure_search,
 };
-use cheminee::search::{sort_docs, sort_results};
+use cheminee::search::structured_query::{QueryNode, QueryPlan};
+use cheminee::search::{extra_query_clause, prepare_smarts_query, sort_docs, sort_results};
+use rdkit::ROMol;
 use serde_json::json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use tantivy::schema::{JsonObjectOptions, TEXT};
 use tantivy::{
     doc,
@@ -45,7 +64,117 @@ fn test_build_similarity_query() {
     let ranked_clusters = vec![0, 2];
     let extra_query = "NumAtoms:[1 TO 100] AND NumAmideBonds: [1 TO 5]";
     let query = build_similarity_query(&ranked_clusters, extra_query, 0.1).unwrap();
-    assert_eq!(query, "(other_descriptors.similarity_cluster:0 OR other_descriptors.similarity_cluster:2) AND NumAtoms:[1 TO 100] AND NumAmideBonds: [1 TO 5]");
+    assert_eq!(query, "(other_descriptors.similarity_cluster:0 OR other_descriptors.similarity_cluster:2) AND (NumAtoms:[1 TO 100] AND NumAmideBonds: [1 TO 5])");
+}
+
+#[test]
+fn test_similarity_metrics() {
+    let query: BitVec<u8> = [true, true, true, true, false, false].into_iter().collect();
+    let candidate: BitVec<u8> = [true, true, false, false, true, false]
+        .into_iter()
+        .collect();
+
+    let tanimoto = SimilarityMetric::from_name("tanimoto", 0.5, 0.5).unwrap();
+    assert_eq!(tanimoto.similarity(&query, &candidate), 2.0 / 5.0);
+
+    let dice = SimilarityMetric::from_name("dice", 0.5, 0.5).unwrap();
+    assert_eq!(dice.similarity(&query, &candidate), 4.0 / 7.0);
+
+    let cosine = SimilarityMetric::from_name("cosine", 0.5, 0.5).unwrap();
+    assert_eq!(cosine.similarity(&query, &candidate), 2.0 / 8f32.sqrt());
+
+    // Only penalizing bits missing from the candidate makes Tversky asymmetric
+    let tversky = SimilarityMetric::from_name("tversky", 1.0, 0.0).unwrap();
+    assert_eq!(tversky.similarity(&query, &candidate), 2.0 / 4.0);
+    assert_eq!(tversky.similarity(&candidate, &query), 2.0 / 3.0);
+
+    let empty: BitVec<u8> = BitVec::repeat(false, 6);
+    assert_eq!(tanimoto.similarity(&empty, &empty), 0.0);
+
+    assert!(SimilarityMetric::from_name("euclidean", 0.5, 0.5).is_err());
+    assert!(SimilarityMetric::from_name("tversky", -1.0, 0.5).is_err());
+}
+
+#[test]
+fn test_alternative_fingerprints() {
+    let mol = standardize_smiles("c1ccccc1CC(=O)O", false).unwrap();
+    let fingerprints = get_fingerprints(&mol, &FINGERPRINTS).unwrap();
+
+    let expected_lengths = [
+        ("morgan", 2048),
+        ("morgan_r2_1024", 1024),
+        ("morgan_r2_2048", 2048),
+        ("morgan_r3_4096", 4096),
+        ("feature_morgan_r2_2048", 2048),
+        ("atom_pair", 2048),
+        ("torsion", 2048),
+        ("maccs", 167),
+    ];
+
+    for (fingerprint, length) in expected_lengths {
+        let bits = &fingerprints[fingerprint];
+        assert_eq!(bits.len(), length);
+        assert!(bits.count_ones() > 0);
+    }
+
+    // Fingerprints end up in the index, so they have to be reproducible
+    let recomputed = get_fingerprints(&mol, &FINGERPRINTS).unwrap();
+    assert_eq!(fingerprints, recomputed);
+
+    // MACCS keys 162 (aromatic atom) and 163 (six-membered ring), but not 166 (multiple fragments)
+    let maccs = &fingerprints["maccs"];
+    assert!(maccs[162]);
+    assert!(maccs[163]);
+    assert!(!maccs[166]);
+
+    assert!(get_fingerprints(&mol, &["ecfp"]).is_err());
+}
+
+#[test]
+fn test_identity_layers() {
+    let layer = |level: &str, smiles: &str| {
+        let raw_mol = ROMol::from_smiles(smiles).unwrap();
+        let canon_taut = standardize_smiles(smiles, false).unwrap();
+        identity_layer(level, &raw_mol, &canon_taut).unwrap()
+    };
+
+    // The full layer keeps charges, which standardization neutralizes
+    assert_ne!(layer("full", "CC(=O)[O-]"), layer("full", "CC(=O)O"));
+    assert_eq!(
+        layer("full", "[Na+].CC(=O)[O-]"),
+        layer("full", "CC(=O)[O-]")
+    );
+    assert_eq!(
+        layer("tautomer", "CC(=O)[O-]"),
+        layer("tautomer", "CC(=O)O")
+    );
+
+    // Both skeletons are 2-regular, so the connectivity hashes collide; the skeleton match tells
+    // them apart
+    let cyclohexane = ROMol::from_smiles("C1CCCCC1").unwrap();
+    let cyclopropanes = ROMol::from_smiles("C1CC1.C1CC1").unwrap();
+    assert_eq!(
+        identity_layer("connectivity", &cyclohexane, &cyclohexane).unwrap(),
+        identity_layer("connectivity", &cyclopropanes, &cyclopropanes).unwrap()
+    );
+
+    let skeleton = Skeleton::new(&cyclohexane).unwrap();
+    assert!(skeleton.matches(&cyclohexane).unwrap());
+    assert!(skeleton
+        .matches(&ROMol::from_smiles("C1=CCCC=C1").unwrap())
+        .unwrap());
+    assert!(!skeleton.matches(&cyclopropanes).unwrap());
+    assert!(!skeleton
+        .matches(&ROMol::from_smiles("C1CCNCC1").unwrap())
+        .unwrap());
+}
+
+#[test]
+fn test_standard_inchi() {
+    let mol = standardize_smiles("OCC", false).unwrap();
+    let (inchi, inchikey) = standard_inchi(&mol).unwrap();
+    assert_eq!(inchi, "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3");
+    assert_eq!(inchikey, "LFQSCWFLJHTTHZ-UHFFFAOYSA-N");
 }
 
 #[test]
@@ -100,6 +229,195 @@ fn test_sort_results() {
     );
 }
 
+#[test]
+fn test_basic_search_stream() {
+    let mut builder = SchemaBuilder::new();
+    let smiles_field = builder.add_text_field("smiles", STRING | STORED);
+    let schema = builder.build();
+
+    let builder = IndexBuilder::new().schema(schema);
+    let index = builder.create_in_ram().unwrap();
+
+    let mut index_writer = index.writer_with_num_threads(1, 50 * 1024 * 1024).unwrap();
+    for smiles in ["CC", "CCC", "CCCC"] {
+        index_writer
+            .add_document(doc!(smiles_field => smiles))
+            .unwrap();
+    }
+    index_writer.commit().unwrap();
+
+    let reader = index.reader().unwrap();
+    let searcher = reader.searcher();
+
+    let results = basic_search_stream(&searcher, "*")
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        vec![
+            DocAddress::new(0, 0),
+            DocAddress::new(0, 1),
+            DocAddress::new(0, 2)
+        ]
+    );
+}
+
+#[test]
+fn test_search_cursor() {
+    let mut builder = SchemaBuilder::new();
+    let smiles_field = builder.add_text_field("smiles", STRING | STORED);
+    let schema = builder.build();
+
+    let builder = IndexBuilder::new().schema(schema);
+    let index = builder.create_in_ram().unwrap();
+
+    let mut index_writer = index.writer_with_num_threads(1, 50 * 1024 * 1024).unwrap();
+    for smiles in ["CC", "CCC", "CCCC"] {
+        index_writer
+            .add_document(doc!(smiles_field => smiles))
+            .unwrap();
+    }
+    index_writer.commit().unwrap();
+
+    let reader = index.reader().unwrap();
+    let searcher = reader.searcher();
+
+    let query = query_hash("substructure", "CC", &json!({"extra_query": ""}));
+    let cursor = SearchCursor::new(&searcher, query, DocAddress::new(0, 0), Some(0.5));
+    let decoded_cursor = SearchCursor::decode(&cursor.encode()).unwrap();
+    assert_eq!(decoded_cursor, cursor);
+    assert!(decoded_cursor.validate(&searcher, query).is_ok());
+    assert!(SearchCursor::decode("not-a-cursor").is_err());
+
+    // A cursor only resumes the query it was issued for
+    for other_query in [
+        query_hash("superstructure", "CC", &json!({"extra_query": ""})),
+        query_hash("substructure", "CCC", &json!({"extra_query": ""})),
+        query_hash("substructure", "CC", &json!({"extra_query": "NumAtoms:8"})),
+    ] {
+        assert!(decoded_cursor.validate(&searcher, other_query).is_err());
+    }
+
+    let results = basic_search_stream_after(&searcher, "*", Some(decoded_cursor.last_doc))
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(results, vec![DocAddress::new(0, 1), DocAddress::new(0, 2)]);
+
+    index_writer
+        .add_document(doc!(smiles_field => "CCCCC"))
+        .unwrap();
+    index_writer.commit().unwrap();
+    reader.reload().unwrap();
+
+    assert!(decoded_cursor.validate(&reader.searcher(), query).is_err());
+}
+
+#[test]
+fn test_exact_similarity_search() {
+    let mut builder = SchemaBuilder::new();
+    let smiles_field = builder.add_text_field("smiles", STRING | STORED);
+    let morgan_fingerprint_field = builder.add_bytes_field("morgan_fingerprint", STORED | FAST);
+    let extra_data_field = builder.add_json_field("extra_data", TEXT | STORED);
+    let schema = builder.build();
+
+    let builder = IndexBuilder::new().schema(schema);
+    let index = builder.create_in_ram().unwrap();
+
+    let indexed_smiles = [
+        "c1ccccc1CC",
+        "c1ccccc1CCC",
+        "c1ccccc1",
+        "CCCCCCCC",
+        "c1ccccc1CC",
+        "c1ccccc1CCO",
+    ];
+
+    let mut fingerprints = Vec::new();
+    let mut index_writer = index.writer_with_num_threads(1, 50 * 1024 * 1024).unwrap();
+    for smiles in indexed_smiles {
+        let fingerprint = standardize_smiles(smiles, false)
+            .unwrap()
+            .morgan_fingerprint()
+            .0;
+        index_writer
+            .add_document(doc!(
+                smiles_field => smiles,
+                morgan_fingerprint_field => fingerprint.as_raw_slice(),
+                extra_data_field => json!({"extra": "data"}),
+            ))
+            .unwrap();
+        fingerprints.push(fingerprint);
+    }
+    index_writer.commit().unwrap();
+
+    let reader = index.reader().unwrap();
+    let searcher = reader.searcher();
+
+    let query_fingerprint = standardize_smiles("c1ccccc1CC", false)
+        .unwrap()
+        .morgan_fingerprint()
+        .0;
+    let metric = SimilarityMetric::Tanimoto;
+
+    // Brute force ranking to compare against
+    let mut expected = fingerprints
+        .iter()
+        .enumerate()
+        .map(|(doc_id, fp)| {
+            (
+                metric.similarity(&query_fingerprint, fp),
+                DocAddress::new(0, doc_id as DocId),
+            )
+        })
+        .filter(|(score, _)| *score >= 0.1)
+        .collect::<Vec<_>>();
+    expected.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then_with(|| a.1.cmp(&b.1)));
+
+    let search = |limit: usize, after: Option<(f32, DocAddress)>| {
+        exact_similarity_search(
+            &searcher,
+            &[query_fingerprint.clone()],
+            "morgan",
+            metric,
+            0.1,
+            "",
+            "c1ccccc1CC",
+            limit,
+            after,
+            &CancellationToken::default(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(hit, docaddr)| (hit.score, docaddr))
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(search(100, None), expected);
+
+    let first_page = search(3, None);
+    assert_eq!(first_page, expected[..3]);
+    assert_eq!(first_page[0], (1.0, DocAddress::new(0, 0)));
+    assert_eq!(first_page[1], (1.0, DocAddress::new(0, 4)));
+
+    let second_page = search(100, first_page.last().copied());
+    assert_eq!(second_page, expected[3..]);
+
+    // Only fast fields can be scanned
+    assert!(exact_similarity_search(
+        &searcher,
+        &[query_fingerprint.clone()],
+        "maccs",
+        metric,
+        0.1,
+        "",
+        "c1ccccc1CC",
+        10,
+        None,
+        &CancellationToken::default(),
+    )
+    .is_err());
+}
+
 #[test]
 fn test_identity_search() {
     let test_smiles = "CC";
@@ -155,6 +473,7 @@ fn test_identity_search() {
         &query_descriptors,
         true,
         &extra_query,
+        &CancellationToken::default(),
     )
     .unwrap();
     assert_eq!(result.len(), 1);
@@ -217,7 +536,7 @@ fn test_substructure_search() {
 
     let extra_query = "".to_string();
 
-    let results = structure_search(
+    let (results, truncated) = structure_search(
         &searcher,
         &query_mol,
         "substructure",
@@ -225,10 +544,85 @@ fn test_substructure_search() {
         10,
         true,
         &extra_query,
+        false,
+        &[],
+        None,
+        None,
+        &CancellationToken::default(),
     )
     .unwrap();
 
     assert_eq!(results.len(), 1);
+    assert!(!truncated);
+
+    // A hit that also contains an excluded pattern is dropped
+    for (exclude_smarts, expected_hits) in [("Cl", 1), ("[CH2](c)c", 0)] {
+        let excluded_patterns = parse_excluded_patterns(&[exclude_smarts.to_string()]).unwrap();
+        let (results, _) = structure_search(
+            &searcher,
+            &query_mol,
+            "substructure",
+            true,
+            10,
+            true,
+            &extra_query,
+            false,
+            &excluded_patterns,
+            None,
+            None,
+            &CancellationToken::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), expected_hits);
+    }
+
+    // A search that is out of time confirms nothing and reports what it left behind
+    let token = CancellationToken::with_timeout(Some(0));
+    let (results, truncated) = structure_search(
+        &searcher,
+        &query_mol,
+        "substructure",
+        true,
+        10,
+        true,
+        &extra_query,
+        false,
+        &[],
+        None,
+        None,
+        &token,
+    )
+    .unwrap();
+
+    assert!(results.is_empty());
+    assert!(truncated);
+    assert!(token.stopped());
+    assert!(token.timed_out());
+}
+
+#[test]
+fn test_cancellation_token() {
+    let token = CancellationToken::with_timeout(None);
+    assert!(!token.is_cancelled());
+
+    // Cancelling from the outside, or through a parent, stops the search without timing it out
+    let child = token.child(None);
+    token.cancel();
+    assert!(token.is_cancelled());
+    assert!(token.stopped());
+    assert!(!token.timed_out());
+    assert!(child.is_cancelled());
+    assert!(child.stopped());
+    assert!(!child.timed_out());
+
+    // A child's own deadline does not reach its parent
+    let token = CancellationToken::with_timeout(None);
+    let child = token.child(Some(0));
+    assert!(child.is_cancelled());
+    assert!(child.timed_out());
+    assert!(!token.is_cancelled());
+    assert!(!token.timed_out());
 }
 
 #[test]
@@ -288,7 +682,7 @@ fn test_superstructure_search() {
 
     let extra_query = "".to_string();
 
-    let results = structure_search(
+    let (results, truncated) = structure_search(
         &searcher,
         &query_mol,
         "superstructure-search",
@@ -296,8 +690,364 @@ fn test_superstructure_search() {
         10,
         true,
         &extra_query,
+        false,
+        &[],
+        None,
+        None,
+        &CancellationToken::default(),
     )
     .unwrap();
 
     assert_eq!(results.len(), 1);
+    assert!(!truncated);
+}
+
+#[test]
+fn test_smarts_substructure_search() {
+    let index_smiles = "Clc1ccccc1CC";
+    let (index_mol, index_pattern_fingerprint, _) = process_cpd(index_smiles, false).unwrap();
+
+    let query_mol = prepare_smarts_query("c1ccccc1[F,Cl,Br,I]").unwrap();
+
+    for smarts in ["", "C1CC", "[Zz]", "C&&", "[C;]", "c1ccccc1("] {
+        assert!(prepare_smarts_query(smarts).is_err(), "{smarts}");
+    }
+
+    let mut builder = SchemaBuilder::new();
+    let smiles_field = builder.add_text_field("smiles", STRING | STORED);
+    let pattern_fingerprint_field = builder.add_bytes_field("pattern_fingerprint", FAST | STORED);
+
+    let extra_data_options: JsonObjectOptions =
+        JsonObjectOptions::from(TEXT | STORED).set_expand_dots_enabled();
+    let _extra_data_field = builder.add_json_field("extra_data", extra_data_options);
+
+    let doc = doc!(
+        smiles_field => index_mol.as_smiles(),
+        pattern_fingerprint_field => index_pattern_fingerprint.0.as_raw_slice(),
+    );
+
+    let schema = builder.build();
+
+    let builder = IndexBuilder::new().schema(schema);
+    let index = builder.create_in_ram().unwrap();
+
+    let mut index_writer = index.writer_with_num_threads(1, 50 * 1024 * 1024).unwrap();
+
+    index_writer.add_document(doc).unwrap();
+    index_writer.commit().unwrap();
+
+    let reader = index.reader().unwrap();
+    let searcher = reader.searcher();
+
+    let (results, truncated) = structure_search(
+        &searcher,
+        &query_mol,
+        "substructure",
+        true,
+        10,
+        false,
+        "",
+        true,
+        &[],
+        None,
+        None,
+        &CancellationToken::default(),
+    )
+    .unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert!(!truncated);
+}
+
+#[test]
+fn test_butina_cluster() {
+    // Two tight pairs and a loner
+    let fingerprints: Vec<&[u8]> = vec![
+        &[0b1111_0000],
+        &[0b0000_1111],
+        &[0b1110_0000],
+        &[0b0000_0111],
+        &[0b1111_1111],
+    ];
+
+    let clusters = butina_cluster(&fingerprints, 0.3, &CancellationToken::default()).unwrap();
+    assert_eq!(clusters, vec![vec![0, 2], vec![1, 3], vec![4]]);
+
+    let clusters = butina_cluster(&fingerprints, 0.0, &CancellationToken::default()).unwrap();
+    assert_eq!(clusters.len(), 5);
+
+    let clusters = butina_cluster(&fingerprints, 1.0, &CancellationToken::default()).unwrap();
+    assert_eq!(clusters, vec![vec![0, 1, 2, 3, 4]]);
+}
+
+#[test]
+fn test_maxmin_pick() {
+    let fingerprints: Vec<&[u8]> = vec![
+        &[0b1111_0000],
+        &[0b1110_0000],
+        &[0b0000_1111],
+        &[0b0011_1100],
+    ];
+
+    let picks = maxmin_pick(&fingerprints, 3, &CancellationToken::default()).unwrap();
+    let picked = picks.iter().map(|(pick, _)| *pick).collect::<Vec<_>>();
+    assert_eq!(picked, vec![0, 2, 3]);
+    assert_eq!(picks[0].1, None);
+    assert_eq!(picks[1].1, Some(1.0));
+    assert!((picks[2].1.unwrap() - 2.0 / 3.0).abs() < 1e-6);
+
+    let picks = maxmin_pick(&fingerprints, 10, &CancellationToken::default()).unwrap();
+    assert_eq!(picks.len(), 4);
+}
+
+#[test]
+fn test_build_alert_filters() {
+    let set_ids = |set: &str| {
+        PARSED_ALERTS
+            .iter()
+            .filter(|alert| alert.set == set)
+            .map(|alert| alert.idx)
+            .collect::<Vec<_>>()
+    };
+    let alert_id = |name: &str| {
+        PARSED_ALERTS
+            .iter()
+            .find(|alert| alert.name == name)
+            .unwrap()
+            .idx
+    };
+
+    // The PAINS and Brenk sets as RDKit publishes them
+    assert_eq!(set_ids("pains_a").len(), 16);
+    assert_eq!(set_ids("pains_b").len(), 55);
+    assert_eq!(set_ids("pains_c").len(), 409);
+    assert_eq!(set_ids("brenk").len(), 105);
+
+    // Ids are derived from the set and alert name, so they are unique and do not move when RDKit
+    // reorders its catalogs
+    let ids = PARSED_ALERTS
+        .iter()
+        .map(|alert| alert.idx)
+        .collect::<HashSet<_>>();
+    assert_eq!(ids.len(), PARSED_ALERTS.len());
+    assert_eq!(alert_id("aldehyde"), 1211508454);
+
+    let pains = resolve_alerts("pains", &PARSED_ALERTS).unwrap();
+    assert_eq!(pains.len(), 480);
+
+    let aldehyde = alert_id("aldehyde");
+    let pains_c = set_ids("pains_c")[1];
+    let alerts = resolve_alerts(&format!("aldehyde, {pains_c},pains_a"), &PARSED_ALERTS).unwrap();
+    let mut expected = set_ids("pains_a");
+    expected.extend([pains_c, aldehyde]);
+    expected.sort();
+    assert_eq!(alerts, expected);
+
+    assert!(resolve_alerts("not_an_alert", &PARSED_ALERTS).is_err());
+
+    let mut reactive = set_ids("reactive");
+    reactive.sort();
+    let filters = build_alert_filters(Some("aldehyde"), Some("reactive")).unwrap();
+    assert_eq!(
+        filters,
+        vec![
+            format!("(* -other_descriptors.alerts:{aldehyde})"),
+            format!(
+                "({})",
+                reactive
+                    .iter()
+                    .map(|alert_id| format!("other_descriptors.alerts:{alert_id}"))
+                    .collect::<Vec<_>>()
+                    .join(" OR ")
+            ),
+        ]
+    );
+
+    let benzaldehyde = ROMol::from_smiles("O=Cc1ccccc1").unwrap();
+    assert!(alert_search(&benzaldehyde, &PARSED_ALERTS)
+        .unwrap()
+        .contains(&aldehyde));
+
+    let ethanol = ROMol::from_smiles("CCO").unwrap();
+    assert!(alert_search(&ethanol, &PARSED_ALERTS).unwrap().is_empty());
+}
+
+#[test]
+fn test_hill_formula() {
+    let formula = |smiles: &str| {
+        let (canon_taut, _, _) = process_cpd(smiles, false).unwrap();
+        let (counts, charge) = element_counts(&canon_taut);
+        hill_formula(&counts, charge)
+    };
+
+    assert_eq!(formula("CCO"), "C2H6O");
+    assert_eq!(formula("c1ccncc1"), "C5H5N");
+    assert_eq!(formula("ClCCCl"), "C2H4Cl2");
+    assert_eq!(formula("O"), "H2O");
+}
+
+#[test]
+fn test_formula_query() {
+    let ranges = parse_formula_query("C6-8 H* N1-2 Cl0 O2- S").unwrap();
+    assert_eq!(
+        ranges,
+        vec![
+            ("C".to_string(), 6, Some(8)),
+            ("H".to_string(), 0, None),
+            ("N".to_string(), 1, Some(2)),
+            ("Cl".to_string(), 0, Some(0)),
+            ("O".to_string(), 2, None),
+            ("S".to_string(), 1, Some(1)),
+        ]
+    );
+    assert_eq!(parse_formula_query("C6H6").unwrap().len(), 2);
+
+    assert!(parse_formula_query("Xy2").is_err());
+    assert!(parse_formula_query("C2 C3").is_err());
+    assert!(parse_formula_query("C8-6").is_err());
+    assert!(parse_formula_query("c6").is_err());
+
+    assert_eq!(
+        build_formula_query("C6-8 H* Cl0").unwrap(),
+        "(* +(other_descriptors.element_thresholds.C:6 -other_descriptors.element_thresholds.C:9) -other_descriptors.element_thresholds.Cl:1)"
+    );
+    assert_eq!(
+        build_formula_query("C34-").unwrap(),
+        "(* +(+other_descriptors.element_thresholds.C:32 -other_descriptors.element_counts.C:32 -other_descriptors.element_counts.C:33))"
+    );
+    assert!(build_formula_query("C2000").is_err());
+
+    let expanded =
+        expand_formula_queries(r#"NumRings:1 AND formula:"N1-" AND -formula:O"#).unwrap();
+    assert_eq!(
+        expanded,
+        "NumRings:1 AND (* +other_descriptors.element_thresholds.N:1) AND -(* +(other_descriptors.element_thresholds.O:1 -other_descriptors.element_thresholds.O:2))"
+    );
+    assert_eq!(
+        expand_formula_queries("other_descriptors.formula:C6H6").unwrap(),
+        "other_descriptors.formula:C6H6"
+    );
+    assert!(expand_formula_queries("formula:Q").is_err());
+}
+
+#[test]
+fn test_resolve_adducts() {
+    let positive = resolve_adducts(&[], 1).unwrap();
+    assert!(positive.iter().all(|(_, _, _, charge)| *charge == 1));
+    assert!(positive.iter().any(|(name, _, _, _)| *name == "[M+H]+"));
+    assert!(!positive.iter().any(|(name, _, _, _)| *name == "[M+2H]2+"));
+
+    let negative = resolve_adducts(&["[M-H]-".to_string(), "[M+Cl]-".to_string()], -1).unwrap();
+    assert_eq!(
+        negative
+            .iter()
+            .map(|(name, _, _, _)| *name)
+            .collect::<Vec<_>>(),
+        vec!["[M-H]-", "[M+Cl]-"]
+    );
+
+    assert!(resolve_adducts(&["[M+H]+".to_string()], -1).is_err());
+    assert!(resolve_adducts(&["[M+Xe]+".to_string()], 1).is_err());
+}
+
+#[test]
+fn test_excluded_patterns() {
+    assert!(parse_excluded_patterns(&["c1ccccc1(".to_string()]).is_err());
+
+    let excluded_patterns =
+        parse_excluded_patterns(&["[N+](=O)[O-]".to_string(), "C(=O)[OH]".to_string()]).unwrap();
+
+    for (smiles, expected) in [
+        ("O=[N+]([O-])c1ccccc1", true),
+        ("OC(=O)c1ccccc1", true),
+        ("Nc1ccccc1", false),
+    ] {
+        let (mol, pattern_fingerprint, _) = process_cpd(smiles, false).unwrap();
+        let excluded = matches_excluded_pattern(
+            || Ok(mol.clone()),
+            &pattern_fingerprint.0,
+            &excluded_patterns,
+        )
+        .unwrap();
+
+        assert_eq!(excluded, expected, "{smiles}");
+    }
+
+    // Nothing is parsed when there is nothing to exclude
+    assert!(!matches_excluded_pattern(
+        || Err(eyre::eyre!("not parsed")),
+        &BitVec::<u8>::repeat(true, 2048),
+        &[],
+    )
+    .unwrap());
+}
+
+#[test]
+fn test_extra_query_clause() {
+    assert_eq!(
+        extra_query_clause("NumAtoms:[1 TO 10]"),
+        "(NumAtoms:[1 TO 10])"
+    );
+    assert_eq!(
+        extra_query_clause("exactmw:[0 TO 100] OR NumRings:0"),
+        "(exactmw:[0 TO 100] OR NumRings:0)"
+    );
+    assert_eq!(
+        extra_query_clause("-extra_data.vendor:acme -NumRings:0"),
+        "(* -extra_data.vendor:acme -NumRings:0)"
+    );
+}
+
+#[test]
+fn test_structured_query_plan() {
+    let index = IndexBuilder::new()
+        .schema(
+            cheminee::schema::LIBRARY
+                .get("descriptor_v1")
+                .unwrap()
+                .clone(),
+        )
+        .create_in_ram()
+        .unwrap();
+    let reader = index.reader().unwrap();
+    let searcher = reader.searcher();
+
+    let plan = |query: serde_json::Value| -> eyre::Result<QueryPlan> {
+        let query = serde_json::from_value::<QueryNode>(query)?;
+        QueryPlan::new(&searcher, &query, false, true)
+    };
+
+    let descriptors = plan(json!({"and": [
+        {"descriptor": {"name": "NumAtoms", "min": 1.5, "max": 10.2}},
+        {"not": {"descriptor": {"name": "exactmw", "max": 100}}},
+    ]}))
+    .unwrap();
+    assert_eq!(
+        descriptors.prefilter,
+        "(NumAtoms:[2 TO 10] AND (* -exactmw:[* TO 100]))"
+    );
+
+    // Similarity clauses cannot narrow down an or, and negated structures narrow down nothing
+    let similar = plan(json!({"or": [
+        {"similar": {"smiles": "CCO", "min": 0.7}},
+        {"descriptor": {"name": "exactmw", "max": 100}},
+    ]}))
+    .unwrap();
+    assert_eq!(similar.prefilter, "*");
+
+    let negated = plan(json!({"not": {"substructure": {"smiles": "c1ccccc1"}}})).unwrap();
+    assert_eq!(negated.prefilter, "*");
+
+    let substructure = plan(json!({"substructure": {"smiles": "c1ccccc1"}})).unwrap();
+    assert_ne!(substructure.prefilter, "*");
+
+    assert!(plan(json!({"descriptor": {"name": "NumFoo", "min": 1}})).is_err());
+    assert!(plan(json!({"descriptor": {"name": "NumAtoms"}})).is_err());
+    assert!(plan(json!({"and": []})).is_err());
+    assert!(plan(json!({"substructure": {"smiles": "CC", "smarts": "CC"}})).is_err());
+    assert!(plan(json!({"identity": {"smarts": "CC"}})).is_err());
+    assert!(
+        plan(json!({"similar": {"smiles": "CC", "min": 0.5, "fingerprint": "maccs"}})).is_err()
+    );
+    assert!(plan(json!({"nearby": {"smiles": "CC"}})).is_err());
 }
@@ -42,3 +42,85 @@ fn test_substructure_match_fp() {
     assert!(substructure_match_fp(sub_fp, super_fp));
     assert!(!substructure_match_fp(super_fp, sub_fp));
 }
+
+#[test]
+fn test_find_mcs() {
+    use cheminee::search::mcs::{find_mcs, McsParams};
+    use std::time::Duration;
+
+    let toluene = ROMol::from_smiles("Cc1ccccc1").unwrap();
+    let ethylbenzene = ROMol::from_smiles("CCc1ccccc1").unwrap();
+    let phenol = ROMol::from_smiles("Oc1ccccc1").unwrap();
+    let params = McsParams::new(Duration::from_secs(10));
+
+    let mcs = find_mcs(&[toluene.clone(), ethylbenzene.clone()], &params).unwrap();
+    assert_eq!((mcs.num_atoms, mcs.num_bonds), (7, 7));
+    assert!(!mcs.timed_out);
+    assert_eq!(mcs.mappings.len(), 2);
+    assert!(mcs
+        .mappings
+        .iter()
+        .all(|m| m.atoms.len() == 7 && m.bonds.len() == 7));
+
+    // The SMARTS finds the MCS in every input molecule
+    let pattern = RWMol::from_smarts(&mcs.smarts).unwrap().to_ro_mol();
+    let params = SubstructMatchParameters::default();
+    assert!(!substruct_match(&toluene, &pattern, &params).is_empty());
+    assert!(!substruct_match(&ethylbenzene, &pattern, &params).is_empty());
+
+    let params = McsParams::new(Duration::from_secs(10));
+    let mcs = find_mcs(&[toluene.clone(), ethylbenzene, phenol.clone()], &params).unwrap();
+    assert_eq!((mcs.num_atoms, mcs.num_bonds), (6, 6));
+    assert_eq!(mcs.mappings.len(), 3);
+
+    // Any atom matches any other, so the methyl carbon and the hydroxyl oxygen line up
+    let params = McsParams {
+        atom_compare: "any".to_string(),
+        ..McsParams::new(Duration::from_secs(10))
+    };
+    let mcs = find_mcs(&[toluene.clone(), phenol.clone()], &params).unwrap();
+    assert_eq!((mcs.num_atoms, mcs.num_bonds), (7, 7));
+
+    let params = McsParams {
+        bond_compare: "nonsense".to_string(),
+        ..McsParams::new(Duration::from_secs(10))
+    };
+    assert!(find_mcs(&[toluene, phenol], &params).is_err());
+}
+
+#[test]
+fn test_rgroup_decomposition() {
+    use cheminee::search::rgroup_decomposition::RGroupCore;
+
+    let canonical = |smiles: &str| ROMol::from_smiles(smiles).unwrap().as_smiles();
+
+    let core = RGroupCore::from_smiles("c1ccccc1[*:1]").unwrap();
+    assert_eq!(core.search_mol().unwrap().as_smiles(), "c1ccccc1");
+
+    let decomposition = core.decompose("Oc1ccccc1").unwrap();
+    assert_eq!(decomposition.core_atoms.len(), 6);
+    assert_eq!(decomposition.rgroups.len(), 1);
+    assert_eq!(decomposition.rgroups[0].label, "R1");
+    assert_eq!(decomposition.rgroups[0].smiles, canonical("O[*:1]"));
+
+    // Substituents off the attachment points get new labels
+    let decomposition = core.decompose("Cc1ccc(O)cc1").unwrap();
+    let labels = decomposition
+        .rgroups
+        .iter()
+        .map(|rgroup| rgroup.label.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(labels, vec!["R1", "R2"]);
+
+    // Attachment points without a substituent carry a hydrogen
+    let core = RGroupCore::from_smiles("c1ccc([*:1])cc1[*:2]").unwrap();
+    let decomposition = core.decompose("Cc1ccccc1").unwrap();
+    assert_eq!(decomposition.rgroups.len(), 2);
+    assert!(decomposition
+        .rgroups
+        .iter()
+        .any(|rgroup| rgroup.smiles.starts_with("[H]")));
+
+    assert!(core.decompose("CCCC").is_err());
+    assert!(RGroupCore::from_smiles("c1ccccc1").is_err());
+}
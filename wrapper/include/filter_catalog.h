@@ -0,0 +1,15 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+#include <GraphMol/FilterCatalog/FilterCatalog.h>
+
+namespace cheminee {
+using RDKit::FilterCatalog;
+using RDKit::ROMol;
+
+std::shared_ptr<FilterCatalog> new_filter_catalog(const std::string &catalog_name);
+rust::Vec<rust::String> filter_catalog_descriptions(const std::shared_ptr<FilterCatalog> &catalog);
+rust::Vec<uint32_t> filter_catalog_matches(const std::shared_ptr<FilterCatalog> &catalog,
+                                           const std::shared_ptr<ROMol> &mol);
+} // namespace cheminee
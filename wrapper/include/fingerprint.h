@@ -0,0 +1,23 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <DataStructs/ExplicitBitVect.h>
+#include <GraphMol/GraphMol.h>
+
+// rdkit-sys declares the bit vector inside the RDKit namespace
+namespace RDKit {
+using ExplicitBitVect = ::ExplicitBitVect;
+} // namespace RDKit
+
+namespace cheminee {
+using RDKit::ROMol;
+
+std::shared_ptr<ExplicitBitVect> morgan_fingerprint_mol_with_params(const std::shared_ptr<ROMol> &mol,
+                                                                    unsigned int radius, unsigned int num_bits,
+                                                                    bool use_features);
+std::shared_ptr<ExplicitBitVect> atom_pair_fingerprint_mol(const std::shared_ptr<ROMol> &mol, unsigned int num_bits);
+std::shared_ptr<ExplicitBitVect> topological_torsion_fingerprint_mol(const std::shared_ptr<ROMol> &mol,
+                                                                     unsigned int num_bits);
+std::shared_ptr<ExplicitBitVect> maccs_fingerprint_mol(const std::shared_ptr<ROMol> &mol);
+unsigned int get_num_bits(const std::shared_ptr<ExplicitBitVect> &bitvect);
+} // namespace cheminee
@@ -0,0 +1,20 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+#include <memory>
+#include <vector>
+
+namespace cheminee {
+using RDKit::ROMol;
+
+struct McsResult;
+using MolList = std::vector<std::shared_ptr<ROMol>>;
+
+std::unique_ptr<MolList> new_mol_list();
+void mol_list_push(MolList &mols, const std::shared_ptr<ROMol> &mol);
+
+McsResult find_mcs(const MolList &mols, uint64_t timeout_ms, const std::string &atom_compare,
+                   const std::string &bond_compare, bool ring_matches_ring_only, bool complete_rings_only);
+} // namespace cheminee
@@ -0,0 +1,11 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+namespace cheminee {
+using RDKit::ROMol;
+
+rust::String mol_to_inchi(const std::shared_ptr<ROMol> &mol);
+rust::String inchi_to_inchi_key(const std::string &inchi);
+} // namespace cheminee
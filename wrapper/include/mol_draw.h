@@ -0,0 +1,11 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+namespace cheminee {
+using RDKit::ROMol;
+
+rust::String draw_svg(const std::shared_ptr<ROMol> &mol, uint32_t width, uint32_t height,
+                      rust::Slice<const int32_t> highlight_atoms, rust::Slice<const int32_t> highlight_bonds);
+} // namespace cheminee
@@ -0,0 +1,13 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+namespace cheminee {
+using RDKit::ROMol;
+
+struct MolBond;
+
+rust::Vec<MolBond> mol_bonds(const std::shared_ptr<ROMol> &mol);
+rust::Vec<uint32_t> atom_isotopes(const std::shared_ptr<ROMol> &mol);
+} // namespace cheminee
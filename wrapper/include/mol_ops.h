@@ -0,0 +1,11 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+namespace cheminee {
+using RDKit::ROMol;
+
+std::shared_ptr<ROMol> remove_stereochemistry(const std::shared_ptr<ROMol> &mol);
+std::shared_ptr<ROMol> remove_isotopes(const std::shared_ptr<ROMol> &mol);
+} // namespace cheminee
@@ -0,0 +1,15 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+#include <memory>
+
+namespace cheminee {
+using RDKit::ROMol;
+
+struct RGroupRow;
+
+std::shared_ptr<ROMol> rgroup_core_query(const std::shared_ptr<ROMol> &core);
+RGroupRow rgroup_decompose(const std::shared_ptr<ROMol> &core, const std::shared_ptr<ROMol> &mol);
+} // namespace cheminee
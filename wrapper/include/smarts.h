@@ -0,0 +1,8 @@
+#pragma once
+
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+namespace cheminee {
+void check_smarts(const std::string &smarts);
+} // namespace cheminee
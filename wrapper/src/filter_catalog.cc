@@ -0,0 +1,45 @@
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+#include <GraphMol/FilterCatalog/FilterCatalog.h>
+
+#include <stdexcept>
+
+namespace cheminee {
+using namespace RDKit;
+
+// One of the catalogs that ship with RDKit, by the name of its FilterCatalogParams value
+std::shared_ptr<FilterCatalog> new_filter_catalog(const std::string &catalog_name) {
+	FilterCatalogParams::FilterCatalogs catalog;
+	if (catalog_name == "PAINS_A") {
+		catalog = FilterCatalogParams::PAINS_A;
+	} else if (catalog_name == "PAINS_B") {
+		catalog = FilterCatalogParams::PAINS_B;
+	} else if (catalog_name == "PAINS_C") {
+		catalog = FilterCatalogParams::PAINS_C;
+	} else if (catalog_name == "BRENK") {
+		catalog = FilterCatalogParams::BRENK;
+	} else {
+		throw std::invalid_argument("unknown filter catalog: " + catalog_name);
+	}
+	return std::make_shared<FilterCatalog>(catalog);
+}
+
+// Entry descriptions in entry index order
+rust::Vec<rust::String> filter_catalog_descriptions(const std::shared_ptr<FilterCatalog> &catalog) {
+	rust::Vec<rust::String> descriptions;
+	for (unsigned int idx = 0; idx < catalog->getNumEntries(); ++idx) {
+		descriptions.push_back(catalog->getEntryWithIdx(idx)->getDescription());
+	}
+	return descriptions;
+}
+
+// Indices of the entries that match the molecule
+rust::Vec<uint32_t> filter_catalog_matches(const std::shared_ptr<FilterCatalog> &catalog,
+                                           const std::shared_ptr<ROMol> &mol) {
+	rust::Vec<uint32_t> idxs;
+	for (const auto &entry : catalog->getMatches(*mol)) {
+		idxs.push_back(catalog->getIdxForEntry(entry.get()));
+	}
+	return idxs;
+}
+} // namespace cheminee
@@ -0,0 +1,39 @@
+#include "rust/cxx.h"
+#include <DataStructs/ExplicitBitVect.h>
+#include <GraphMol/Fingerprints/AtomPairs.h>
+#include <GraphMol/Fingerprints/MACCS.h>
+#include <GraphMol/Fingerprints/MorganFingerprints.h>
+#include <GraphMol/GraphMol.h>
+
+namespace cheminee {
+using namespace RDKit;
+
+// Same as GetMorganFingerprintAsBitVect(mol, radius, nBits=num_bits, useFeatures=use_features) in Python
+std::shared_ptr<ExplicitBitVect> morgan_fingerprint_mol_with_params(const std::shared_ptr<ROMol> &mol,
+                                                                    unsigned int radius, unsigned int num_bits,
+                                                                    bool use_features) {
+	if (!use_features) {
+		return std::shared_ptr<ExplicitBitVect>(MorganFingerprints::getFingerprintAsBitVect(*mol, radius, num_bits));
+	}
+
+	std::vector<std::uint32_t> invariants(mol->getNumAtoms());
+	MorganFingerprints::getFeatureInvariants(*mol, invariants);
+	return std::shared_ptr<ExplicitBitVect>(
+	    MorganFingerprints::getFingerprintAsBitVect(*mol, radius, num_bits, &invariants));
+}
+
+std::shared_ptr<ExplicitBitVect> atom_pair_fingerprint_mol(const std::shared_ptr<ROMol> &mol, unsigned int num_bits) {
+	return std::shared_ptr<ExplicitBitVect>(AtomPairs::getHashedAtomPairFingerprintAsBitVect(*mol, num_bits));
+}
+
+std::shared_ptr<ExplicitBitVect> topological_torsion_fingerprint_mol(const std::shared_ptr<ROMol> &mol,
+                                                                     unsigned int num_bits) {
+	return std::shared_ptr<ExplicitBitVect>(AtomPairs::getHashedTopologicalTorsionFingerprintAsBitVect(*mol, num_bits));
+}
+
+std::shared_ptr<ExplicitBitVect> maccs_fingerprint_mol(const std::shared_ptr<ROMol> &mol) {
+	return std::shared_ptr<ExplicitBitVect>(MACCSFingerprints::getFingerprintAsBitVect(*mol));
+}
+
+unsigned int get_num_bits(const std::shared_ptr<ExplicitBitVect> &bitvect) { return bitvect->getNumBits(); }
+} // namespace cheminee
@@ -0,0 +1,61 @@
+#include "cheminee/src/rdkit_bridge/fmcs.rs.h"
+#include "rust/cxx.h"
+#include <GraphMol/FMCS/FMCS.h>
+#include <GraphMol/GraphMol.h>
+
+#include <chrono>
+#include <stdexcept>
+
+namespace cheminee {
+using namespace RDKit;
+
+std::unique_ptr<MolList> new_mol_list() { return std::make_unique<MolList>(); }
+
+void mol_list_push(MolList &mols, const std::shared_ptr<ROMol> &mol) { mols.push_back(mol); }
+
+// FMCS only takes whole seconds as its timeout, so the deadline is checked by the progress
+// callback instead, which stops the search as soon as it returns false
+static bool before_deadline(const MCSProgressData &, const MCSParameters &, void *user_data) {
+	auto deadline = static_cast<const std::chrono::steady_clock::time_point *>(user_data);
+	return std::chrono::steady_clock::now() < *deadline;
+}
+
+McsResult find_mcs(const MolList &mols, uint64_t timeout_ms, const std::string &atom_compare,
+                   const std::string &bond_compare, bool ring_matches_ring_only, bool complete_rings_only) {
+	MCSParameters params;
+
+	if (atom_compare == "elements") {
+		params.setMCSAtomTyperFromEnum(AtomCompareElements);
+	} else if (atom_compare == "any") {
+		params.setMCSAtomTyperFromEnum(AtomCompareAny);
+	} else if (atom_compare == "isotopes") {
+		params.setMCSAtomTyperFromEnum(AtomCompareIsotopes);
+	} else {
+		throw std::invalid_argument("unknown atom comparison: " + atom_compare +
+		                            "; expected one of elements, any, isotopes");
+	}
+
+	if (bond_compare == "order") {
+		params.setMCSBondTyperFromEnum(BondCompareOrder);
+	} else if (bond_compare == "order_exact") {
+		params.setMCSBondTyperFromEnum(BondCompareOrderExact);
+	} else if (bond_compare == "any") {
+		params.setMCSBondTyperFromEnum(BondCompareAny);
+	} else {
+		throw std::invalid_argument("unknown bond comparison: " + bond_compare +
+		                            "; expected one of order, order_exact, any");
+	}
+
+	params.AtomCompareParameters.RingMatchesRingOnly = ring_matches_ring_only;
+	params.BondCompareParameters.RingMatchesRingOnly = ring_matches_ring_only;
+	params.BondCompareParameters.CompleteRingsOnly = complete_rings_only;
+
+	auto deadline = std::chrono::steady_clock::now() + std::chrono::milliseconds(timeout_ms);
+	params.Timeout = static_cast<unsigned int>((timeout_ms + 999) / 1000);
+	params.ProgressCallback = before_deadline;
+	params.ProgressCallbackUserData = &deadline;
+
+	MCSResult result = findMCS(mols, &params);
+	return McsResult{result.SmartsString, result.NumAtoms, result.NumBonds, result.Canceled};
+}
+} // namespace cheminee
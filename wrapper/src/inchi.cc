@@ -0,0 +1,33 @@
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+#include <GraphMol/inchi.h>
+
+#include <cstdlib>
+#include <stdexcept>
+
+namespace cheminee {
+using namespace RDKit;
+
+// The InChI API signals failure with an empty string; surface it as an error instead
+rust::String mol_to_inchi(const std::shared_ptr<ROMol> &mol) {
+	ExtraInchiReturnValues rv;
+	std::string inchi = MolToInchi(*mol, rv);
+	std::string message = rv.messagePtr ? rv.messagePtr : "";
+	// the return values are malloc'd copies owned by the caller
+	free(rv.messagePtr);
+	free(rv.logPtr);
+	free(rv.auxInfoPtr);
+	if (inchi.empty()) {
+		throw std::invalid_argument("could not generate InChI: " + message);
+	}
+	return inchi;
+}
+
+rust::String inchi_to_inchi_key(const std::string &inchi) {
+	std::string inchikey = InchiToInchiKey(inchi);
+	if (inchikey.empty()) {
+		throw std::invalid_argument("could not generate InChIKey for " + inchi);
+	}
+	return inchikey;
+}
+} // namespace cheminee
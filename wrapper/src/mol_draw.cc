@@ -0,0 +1,23 @@
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+#include <GraphMol/MolDraw2D/MolDraw2DSVG.h>
+#include <GraphMol/MolDraw2D/MolDraw2DUtils.h>
+
+#include <vector>
+
+namespace cheminee {
+using namespace RDKit;
+
+// prepareAndDrawMolecule kekulizes a copy of the molecule and lays it out in 2D unless it already
+// has coordinates
+rust::String draw_svg(const std::shared_ptr<ROMol> &mol, uint32_t width, uint32_t height,
+                      rust::Slice<const int32_t> highlight_atoms, rust::Slice<const int32_t> highlight_bonds) {
+	std::vector<int> atoms(highlight_atoms.begin(), highlight_atoms.end());
+	std::vector<int> bonds(highlight_bonds.begin(), highlight_bonds.end());
+
+	MolDraw2DSVG drawer(width, height);
+	MolDraw2DUtils::prepareAndDrawMolecule(drawer, *mol, "", &atoms, &bonds);
+	drawer.finishDrawing();
+	return drawer.getDrawingText();
+}
+} // namespace cheminee
@@ -0,0 +1,42 @@
+#include "cheminee/src/rdkit_bridge/mol_graph.rs.h"
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+
+namespace cheminee {
+using namespace RDKit;
+
+// In bond index order
+rust::Vec<MolBond> mol_bonds(const std::shared_ptr<ROMol> &mol) {
+	rust::Vec<MolBond> bonds;
+	for (const auto bond : mol->bonds()) {
+		uint8_t order = 0;
+		switch (bond->getBondType()) {
+		case Bond::SINGLE:
+			order = 1;
+			break;
+		case Bond::DOUBLE:
+			order = 2;
+			break;
+		case Bond::TRIPLE:
+			order = 3;
+			break;
+		case Bond::AROMATIC:
+			order = 4;
+			break;
+		default:
+			break;
+		}
+		bonds.push_back(MolBond{bond->getBeginAtomIdx(), bond->getEndAtomIdx(), order});
+	}
+	return bonds;
+}
+
+// In atom index order, 0 for atoms without an isotope label
+rust::Vec<uint32_t> atom_isotopes(const std::shared_ptr<ROMol> &mol) {
+	rust::Vec<uint32_t> isotopes;
+	for (const auto atom : mol->atoms()) {
+		isotopes.push_back(atom->getIsotope());
+	}
+	return isotopes;
+}
+} // namespace cheminee
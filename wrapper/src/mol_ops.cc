@@ -0,0 +1,22 @@
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+#include <GraphMol/MolOps.h>
+
+namespace cheminee {
+using namespace RDKit;
+
+// Both work on a copy and leave the given molecule alone
+std::shared_ptr<ROMol> remove_stereochemistry(const std::shared_ptr<ROMol> &mol) {
+	std::shared_ptr<ROMol> stripped(new ROMol(*mol));
+	MolOps::removeStereochemistry(*stripped);
+	return stripped;
+}
+
+std::shared_ptr<ROMol> remove_isotopes(const std::shared_ptr<ROMol> &mol) {
+	std::shared_ptr<ROMol> stripped(new ROMol(*mol));
+	for (auto atom : stripped->atoms()) {
+		atom->setIsotope(0);
+	}
+	return stripped;
+}
+} // namespace cheminee
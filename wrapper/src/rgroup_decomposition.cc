@@ -0,0 +1,93 @@
+#include "cheminee/src/rdkit_bridge/rgroup_decomposition.rs.h"
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+#include <GraphMol/MolOps.h>
+#include <GraphMol/RGroupDecomposition/RGroupDecomp.h>
+#include <GraphMol/SmilesParse/SmilesWrite.h>
+#include <GraphMol/Substruct/SubstructMatch.h>
+
+#include <stdexcept>
+#include <vector>
+
+namespace cheminee {
+using namespace RDKit;
+
+// The core without its attachment points (its dummy atoms). Hydrogens are not part of a molecule
+// query, so positions that carry a substituent in a hit still match
+std::shared_ptr<ROMol> rgroup_core_query(const std::shared_ptr<ROMol> &core) {
+	RWMol query(*core);
+
+	std::vector<Atom *> attachments;
+	for (auto atom : query.atoms()) {
+		if (atom->getAtomicNum() == 0) {
+			attachments.push_back(atom);
+		}
+	}
+
+	if (attachments.empty()) {
+		throw std::invalid_argument("Core has no attachment points");
+	}
+
+	query.beginBatchEdit();
+	for (auto atom : attachments) {
+		query.removeAtom(atom);
+	}
+	query.commitBatchEdit();
+	query.updatePropertyCache(false);
+
+	if (query.getNumAtoms() == 0) {
+		throw std::invalid_argument("Core has no atoms besides its attachment points");
+	}
+
+	std::vector<int> fragments;
+	if (MolOps::getMolFrags(query, fragments) > 1) {
+		throw std::invalid_argument("Core atoms must be connected");
+	}
+
+	return std::make_shared<ROMol>(query);
+}
+
+// Every molecule is decomposed on its own, so that a hit's decomposition does not depend on the
+// other hits on its page. That also means an R-group that is only hydrogen is kept (RDKit drops
+// R-groups that are hydrogen in every molecule decomposed together), which shows the attachment
+// points left unsubstituted
+RGroupRow rgroup_decompose(const std::shared_ptr<ROMol> &core, const std::shared_ptr<ROMol> &mol) {
+	RGroupDecompositionParameters params;
+	params.removeAllHydrogenRGroups = false;
+	params.removeAllHydrogenRGroupsAndLabels = false;
+
+	RGroupDecomposition decomposition(*core, params);
+	if (decomposition.add(*mol) < 0) {
+		throw std::runtime_error("Molecule does not contain the core");
+	}
+
+	if (!decomposition.process()) {
+		throw std::runtime_error("Could not decompose the molecule");
+	}
+
+	auto rows = decomposition.getRGroupsAsRows();
+	if (rows.empty()) {
+		throw std::runtime_error("Could not decompose the molecule");
+	}
+
+	RGroupRow result;
+	for (const auto &[label, rgroup] : rows.front()) {
+		if (label == "Core") {
+			result.core = MolToSmiles(*rgroup);
+		} else {
+			result.rgroups.push_back(RGroupSmiles{label, MolToSmiles(*rgroup)});
+		}
+	}
+
+	// RDKit does not say which atoms it took for the core, so they come from the first match of
+	// the core query, which for a symmetric core may be a different (but equivalent) mapping
+	MatchVectType match;
+	if (SubstructMatch(*mol, *rgroup_core_query(core), match)) {
+		for (const auto &[core_atom, mol_atom] : match) {
+			result.core_atoms.push_back(static_cast<uint32_t>(mol_atom));
+		}
+	}
+
+	return result;
+}
+} // namespace cheminee
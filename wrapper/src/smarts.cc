@@ -0,0 +1,18 @@
+#include "rust/cxx.h"
+#include <GraphMol/GraphMol.h>
+#include <GraphMol/SmilesParse/SmilesParse.h>
+
+#include <memory>
+#include <stdexcept>
+
+namespace cheminee {
+
+// SmartsToMol returns a nullptr (without throwing) for much of what it cannot parse, e.g. unclosed
+// rings or unknown elements; throwing turns that into an Err on the Rust side like any other failure
+void check_smarts(const std::string &smarts) {
+	std::unique_ptr<RDKit::RWMol> mol(RDKit::SmartsToMol(smarts));
+	if (mol == nullptr) {
+		throw std::invalid_argument("could not parse SMARTS: " + smarts);
+	}
+}
+} // namespace cheminee